@@ -1,4 +1,4 @@
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
 use monotree::database::*;
 use monotree::hasher::*;
 use monotree::utils::*;
@@ -10,15 +10,30 @@ fn insert<D: Database, H: Hasher>(
     root: Option<Hash>,
     keys: &mut [Hash],
     leaves: &[Hash],
+    _proofs: &[Proof],
 ) -> Option<Hash> {
     tree.inserts(root.as_ref(), keys, &leaves).expect("insert")
 }
 
+fn insert_one<D: Database, H: Hasher>(
+    tree: &mut Monotree<D, H>,
+    mut root: Option<Hash>,
+    keys: &mut [Hash],
+    leaves: &[Hash],
+    _proofs: &[Proof],
+) -> Option<Hash> {
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        root = tree.insert(root.as_ref(), key, leaf).expect("insert_one");
+    }
+    root
+}
+
 fn get<D: Database, H: Hasher>(
     tree: &mut Monotree<D, H>,
     root: Option<Hash>,
     keys: &mut [Hash],
     _leaves: &[Hash],
+    _proofs: &[Proof],
 ) {
     keys.iter().for_each(|key| {
         tree.get(root.as_ref(), key).expect("get");
@@ -30,15 +45,41 @@ fn remove<D: Database, H: Hasher>(
     root: Option<Hash>,
     keys: &mut [Hash],
     _leaves: &[Hash],
+    _proofs: &[Proof],
 ) {
     tree.removes(root.as_ref(), keys).expect("remove");
 }
 
+fn prove<D: Database, H: Hasher>(
+    tree: &mut Monotree<D, H>,
+    root: Option<Hash>,
+    keys: &mut [Hash],
+    _leaves: &[Hash],
+    _proofs: &[Proof],
+) {
+    keys.iter().for_each(|key| {
+        tree.get_merkle_proof(root.as_ref(), key).expect("prove");
+    });
+}
+
+fn verify<D: Database, H: Hasher>(
+    tree: &mut Monotree<D, H>,
+    root: Option<Hash>,
+    _keys: &mut [Hash],
+    leaves: &[Hash],
+    proofs: &[Proof],
+) {
+    leaves.iter().zip(proofs.iter()).for_each(|(leaf, proof)| {
+        assert!(verify_proof(&tree.hasher, root.as_ref(), leaf, Some(proof)));
+    });
+}
+
 macro_rules! impl_bench_group {
     ($n:expr) => {
         paste::item_with_macros! {
             fn [<bench_group_ $n>](c: &mut Criterion) {
                 let mut group = c.benchmark_group(format!("entry_num_{}", stringify!($n)));
+                group.throughput(Throughput::Elements($n));
                 let mut keys = random_hashes($n);
                 let leaves = random_hashes($n);
 
@@ -50,9 +91,11 @@ macro_rules! impl_bench_group {
                         ("blake2s", Blake2s),
                         ("blake2b", Blake2b),
                         ("sha2", Sha2),
-                        ("sha3", Sha3)
+                        ("sha256d", Sha256d),
+                        ("sha3", Sha3),
+                        ("poseidon-bn254", PoseidonBn254)
                     ],
-                    [insert, get, remove]
+                    [insert, insert_one, get, remove, prove, verify]
                 );
                 group.finish();
             }
@@ -95,21 +138,29 @@ macro_rules! impl_bench_fn {
             let mut tree = Monotree::<$db, $hasher>::new(&dbname);
             let mut root: Option<Hash> = None;
             let mut keys = $k.clone();
+            let mut proofs: Vec<Proof> = Vec::new();
             root = match stringify!($fn) {
-                "get" | "remove" => insert(
-                    black_box(&mut tree),
-                    black_box(root),
-                    black_box(&mut keys),
-                    black_box($v),
-                ),
+                "get" | "remove" | "prove" | "verify" => {
+                    insert(&mut tree, root, &mut keys, $v, &proofs)
+                }
                 _ => root,
             };
+            if stringify!($fn) == "verify" {
+                proofs = keys
+                    .iter()
+                    .map(|key| {
+                        let proof = tree.get_merkle_proof(root.as_ref(), key).expect("proof");
+                        proof.expect("proof")
+                    })
+                    .collect();
+            }
             b.iter(|| {
                 $fn(
                     black_box(&mut tree),
                     black_box(root),
                     black_box(&mut keys),
                     black_box($v),
+                    black_box(&proofs),
                 )
             })
         });
@@ -0,0 +1,164 @@
+//! A module for anchoring roots of `monotree` to external services.
+//!
+//! Publishing a root to a service such as `Bitcoin` via `Mainstay`, another chain
+//! or an `RFC3161` timestamping authority proves the tree existed by the time it was published.
+use crate::utils::*;
+use crate::*;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Tag prepended to a root to derive the key its `Receipt` is stored under.
+const ANCHOR_TAG: u8 = 0xfe;
+
+/// A trait defining external services roots are anchored to.
+pub trait Anchor {
+    /// Publish the given root, returning service-specific data that proves it.
+    fn anchor(&mut self, root: &Hash) -> Result<Vec<u8>>;
+    /// Check the data returned by `anchor()` against the service for the given root.
+    fn verify(&self, root: &Hash, data: &[u8]) -> Result<bool>;
+}
+
+/// A record of a root anchored to an external service.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Receipt {
+    pub root: Hash,
+    /// Seconds since `UNIX_EPOCH` when the root was anchored.
+    pub time: u64,
+    /// Data returned by `Anchor::anchor()`.
+    pub data: Vec<u8>,
+}
+
+impl Receipt {
+    /// Serialize `Receipt` into bytes: `root`, `time` of 8 bytes, then `data`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.root[..], &self.time.to_be_bytes(), &self.data].concat()
+    }
+
+    /// Construct `Receipt` by deserializing bytes slice.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HASH_LEN + 8 {
+            return Err(Errors::Decode(
+                "Receipt::from_bytes(): too short".to_string(),
+            ));
+        }
+        Ok(Receipt {
+            root: slice_to_hash(&bytes[..HASH_LEN]),
+            time: bytes_to_int(&bytes[HASH_LEN..HASH_LEN + 8]),
+            data: bytes[HASH_LEN + 8..].to_vec(),
+        })
+    }
+}
+
+/// Policies deciding when the current root is anchored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Schedule {
+    /// Anchor every given number of commits.
+    Commits(u64),
+    /// Anchor on the first commit after the given interval has passed since the last anchoring.
+    Interval(Duration),
+}
+
+/// A scheduler anchoring roots of a tree as commits are made, recording receipts in its database.
+pub struct Anchoring<A> {
+    anchor: A,
+    schedule: Schedule,
+    commits: u64,
+    last: Option<Instant>,
+}
+
+impl<A: Anchor> Anchoring<A> {
+    pub fn new(anchor: A, schedule: Schedule) -> Self {
+        Anchoring {
+            anchor,
+            schedule,
+            commits: 0,
+            last: None,
+        }
+    }
+
+    /// Notify a commit resulting in the given root. Anchors the root if the schedule is due.
+    ///
+    /// Returns the `Receipt` if anchored. An empty tree has nothing to anchor.
+    pub fn commit<D, H, C>(
+        &mut self,
+        tree: &mut Monotree<D, H, C>,
+        root: Option<&Hash>,
+    ) -> Result<Option<Receipt>>
+    where
+        D: Database,
+        H: Hasher,
+        C: NodeCodec,
+    {
+        self.commits += 1;
+        let due = match self.schedule {
+            Schedule::Commits(n) => self.commits >= n,
+            Schedule::Interval(interval) => match self.last {
+                None => true,
+                Some(last) => last.elapsed() >= interval,
+            },
+        };
+        match root {
+            Some(root) if due => self.anchor_now(tree, root).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Anchor the given root regardless of the schedule, and record its `Receipt`.
+    pub fn anchor_now<D, H, C>(
+        &mut self,
+        tree: &mut Monotree<D, H, C>,
+        root: &Hash,
+    ) -> Result<Receipt>
+    where
+        D: Database,
+        H: Hasher,
+        C: NodeCodec,
+    {
+        let data = self.anchor.anchor(root)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Errors::new("anchor_now(): time"))?
+            .as_secs();
+        let receipt = Receipt {
+            root: *root,
+            time,
+            data,
+        };
+        let key = receipt_key(tree, root);
+        tree.db.put(&key, receipt.to_bytes())?;
+        self.commits = 0;
+        self.last = Some(Instant::now());
+        Ok(receipt)
+    }
+
+    /// Verify the given root was anchored, checking its recorded `Receipt` against the service.
+    pub fn verify<D, H, C>(&self, tree: &mut Monotree<D, H, C>, root: &Hash) -> Result<bool>
+    where
+        D: Database,
+        H: Hasher,
+        C: NodeCodec,
+    {
+        match receipt(tree, root)? {
+            Some(receipt) if &receipt.root == root => self.anchor.verify(root, &receipt.data),
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Get the key the `Receipt` of a root is stored under.
+pub fn receipt_key<D, H: Hasher, C>(tree: &Monotree<D, H, C>, root: &Hash) -> Hash {
+    tree.hasher.digest(&[&[ANCHOR_TAG], &root[..]].concat())
+}
+
+/// Get the `Receipt` recorded for the given root, if anchored.
+pub fn receipt<D, H, C>(tree: &mut Monotree<D, H, C>, root: &Hash) -> Result<Option<Receipt>>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let key = receipt_key(tree, root);
+    match tree.db.get(&key)? {
+        None => Ok(None),
+        Some(bytes) => Ok(Some(Receipt::from_bytes(&bytes)?)),
+    }
+}
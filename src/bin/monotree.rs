@@ -0,0 +1,525 @@
+//! Command-line tool for `monotree`.
+// without a database backend, commands working on a tree only report that none is enabled
+#![cfg_attr(
+    not(any(feature = "db-rocks", feature = "db-sled")),
+    allow(unused_imports, unused_variables, dead_code)
+)]
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use monotree::database::*;
+use monotree::import::{Format, Importer};
+use monotree::{verify_proof, Database, DefaultHasher, Hash, Monotree, Proof, ProofBytes, Result};
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+fn main() {
+    let matches = App::new("monotree")
+        .about("Rust implementation of an optimized Sparse Merkle Tree")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Builds a tree from CSV or JSON-lines records, or restores a snapshot, and prints its root")
+                .arg(
+                    Arg::with_name("db")
+                        .long("db")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .takes_value(true)
+                        .possible_values(&["rocksdb", "sled"])
+                        .default_value("rocksdb"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["csv", "jsonl", "snapshot"])
+                        .default_value("csv"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .takes_value(true)
+                        .help("Field of records hashed into keys, except for snapshots"),
+                )
+                .arg(
+                    Arg::with_name("value")
+                        .long("value")
+                        .takes_value(true)
+                        .help("Field of records hashed into leaves, except for snapshots"),
+                )
+                .arg(Arg::with_name("quiet").long("quiet").short("q"))
+                .arg(Arg::with_name("FILE").help("Reads stdin if omitted")),
+        )
+        .subcommand(
+            tree_command("insert", "Inserts a key with its leaf and prints the new root")
+                .arg(Arg::with_name("KEY").required(true))
+                .arg(Arg::with_name("LEAF").required(true)),
+        )
+        .subcommand(
+            tree_command("get", "Prints the leaf of a key").arg(Arg::with_name("KEY").required(true)),
+        )
+        .subcommand(
+            tree_command("remove", "Removes a key and prints the new root")
+                .arg(Arg::with_name("KEY").required(true)),
+        )
+        .subcommand(
+            tree_command("proof", "Prints the Merkle proof of a key, encoded by ProofBytes")
+                .arg(Arg::with_name("KEY").required(true)),
+        )
+        .subcommand(
+            tree_command("verify", "Verifies the Merkle proof of a key with its leaf")
+                .arg(Arg::with_name("LEAF").required(true))
+                .arg(Arg::with_name("PROOF").required(true)),
+        )
+        .subcommand(
+            tree_command("export", "Writes a snapshot of the tree to restore by import")
+                .arg(Arg::with_name("FILE").help("Writes stdout if omitted")),
+        )
+        .subcommand(tree_command(
+            "stats",
+            "Prints the numbers of nodes and leaves, the bytes and the depth of the tree",
+        ))
+        .subcommand(
+            SubCommand::with_name("shell")
+                .about("Opens an interactive shell to inspect trees in a database")
+                .arg(
+                    Arg::with_name("db")
+                        .long("db")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .takes_value(true)
+                        .possible_values(&["rocksdb", "sled"])
+                        .default_value("rocksdb"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serves leaves and proofs of trees in a database read-only over HTTP")
+                .arg(
+                    Arg::with_name("db")
+                        .long("db")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .takes_value(true)
+                        .possible_values(&["rocksdb", "sled"])
+                        .default_value("rocksdb"),
+                )
+                .arg(
+                    Arg::with_name("addr")
+                        .long("addr")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:8080"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("grpc")
+                .about("Serves updates, leaves and proofs of trees in a database over gRPC")
+                .arg(
+                    Arg::with_name("db")
+                        .long("db")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("backend")
+                        .long("backend")
+                        .takes_value(true)
+                        .possible_values(&["rocksdb", "sled"])
+                        .default_value("rocksdb"),
+                )
+                .arg(
+                    Arg::with_name("addr")
+                        .long("addr")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:50051"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vectors")
+                .about("Exports test vectors as JSON, or checks fixtures of the same schema")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Prints vectors for every hasher and order of traversal")
+                        .arg(
+                            Arg::with_name("entries")
+                                .long("entries")
+                                .short("n")
+                                .takes_value(true)
+                                .default_value("20"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("Checks fixtures against this implementation, one line per vector")
+                        .arg(Arg::with_name("FILE").help("Reads stdin if omitted")),
+                ),
+        )
+        .get_matches();
+
+    let run = match matches.subcommand() {
+        ("import", Some(m)) => import(m),
+        ("shell", Some(m)) => shell(m),
+        (name, Some(m)) if TREE_COMMANDS.contains(&name) => on_tree(name, m),
+        ("serve", Some(m)) => serve(m),
+        ("grpc", Some(m)) => grpc(m),
+        ("vectors", Some(m)) => vectors(m),
+        _ => unreachable!(),
+    };
+    if let Err(err) = run {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Subcommands working on the tree of a root in a database, built by `tree_command()`.
+const TREE_COMMANDS: [&str; 7] = [
+    "insert", "get", "remove", "proof", "verify", "export", "stats",
+];
+
+/// Build a subcommand working on the tree of `--root` in a database, or of the root recorded.
+fn tree_command<'a, 'b>(name: &'a str, about: &'b str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            Arg::with_name("db")
+                .long("db")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .takes_value(true)
+                .possible_values(&["rocksdb", "sled"])
+                .default_value("rocksdb"),
+        )
+        .arg(
+            Arg::with_name("root")
+                .long("root")
+                .takes_value(true)
+                .help("Root of the tree, `-` for an empty tree, the one recorded if omitted"),
+        )
+}
+
+fn import(m: &ArgMatches) -> Result<()> {
+    let format = match m.value_of("format") {
+        Some("snapshot") => None,
+        Some("jsonl") => Some(Format::JsonLines),
+        _ => Some(Format::Csv),
+    };
+    let importer = match format {
+        None => None,
+        Some(format) => match (m.value_of("key"), m.value_of("value")) {
+            (Some(key), Some(value)) => Some(Importer::new(format, key, value)),
+            _ => return Err(monotree::Errors::new("--key and --value are required")),
+        },
+    };
+    let reader: Box<dyn Read> = match m.value_of("FILE") {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(io::stdin()),
+    };
+    let dbpath = m.value_of("db").unwrap();
+    let quiet = m.is_present("quiet");
+    match m.value_of("backend") {
+        #[cfg(feature = "db-sled")]
+        Some("sled") => {
+            let mut tree = Monotree::<Sled, DefaultHasher>::try_new(dbpath)?;
+            import_into(&mut tree, importer.as_ref(), reader, quiet)?;
+            tree.db.flush()
+        }
+        #[cfg(feature = "db-rocks")]
+        Some("rocksdb") => {
+            let mut tree = Monotree::<RocksDB, DefaultHasher>::try_new(dbpath)?;
+            tree.db.set_bulk_load(true);
+            import_into(&mut tree, importer.as_ref(), reader, quiet)
+        }
+        Some(backend) => Err(monotree::Errors::new(&format!(
+            "backend {} is not enabled",
+            backend
+        ))),
+        None => unreachable!(),
+    }
+}
+
+fn import_into<D: Database>(
+    tree: &mut Monotree<D, DefaultHasher>,
+    importer: Option<&Importer>,
+    reader: Box<dyn Read>,
+    quiet: bool,
+) -> Result<()> {
+    let importer = match importer {
+        Some(importer) => importer,
+        None => {
+            println!("{}", hex_or_dash(tree.import(reader)?.as_ref()));
+            return Ok(());
+        }
+    };
+    let root = importer.import_with_progress(tree, None, reader, &mut |imported, _| {
+        if !quiet {
+            eprint!("\rimported {} records", imported);
+            io::stderr().flush().ok();
+        }
+    })?;
+    if !quiet {
+        eprintln!();
+    }
+    println!("{}", hex_or_dash(root.as_ref()));
+    Ok(())
+}
+
+fn on_tree(name: &str, m: &ArgMatches) -> Result<()> {
+    let dbpath = m.value_of("db").unwrap();
+    match m.value_of("backend") {
+        #[cfg(feature = "db-sled")]
+        Some("sled") => {
+            let mut tree = Monotree::<Sled, DefaultHasher>::try_new(dbpath)?;
+            run_on(&mut tree, name, m)?;
+            tree.db.flush()
+        }
+        #[cfg(feature = "db-rocks")]
+        Some("rocksdb") => run_on(
+            &mut Monotree::<RocksDB, DefaultHasher>::try_new(dbpath)?,
+            name,
+            m,
+        ),
+        Some(backend) => Err(monotree::Errors::new(&format!(
+            "backend {} is not enabled",
+            backend
+        ))),
+        None => unreachable!(),
+    }
+}
+
+/// Run a subcommand of `TREE_COMMANDS`. Without `--root`, `insert` and `remove`
+/// record the new root in place of the one recorded, so that the next command works on it.
+fn run_on<D: Database>(
+    tree: &mut Monotree<D, DefaultHasher>,
+    name: &str,
+    m: &ArgMatches,
+) -> Result<()> {
+    let root = match m.value_of("root") {
+        Some("-") => None,
+        Some(root) => Some(parse_hash(root)?),
+        None => tree.load_root()?,
+    };
+    let arg = |arg| parse_hash(m.value_of(arg).unwrap());
+    let updated = match name {
+        "insert" => tree.insert(root.as_ref(), &arg("KEY")?, &arg("LEAF")?)?,
+        "remove" => tree.remove(root.as_ref(), &arg("KEY")?)?,
+        "get" => {
+            let leaf = tree.get(root.as_ref(), &arg("KEY")?)?;
+            println!("{}", hex_or_dash(leaf.as_ref()));
+            return Ok(());
+        }
+        "proof" => match tree.get_merkle_proof(root.as_ref(), &arg("KEY")?)? {
+            Some(proof) => {
+                println!("{}", hex::encode(proof.to_bytes()));
+                return Ok(());
+            }
+            None => return Err(monotree::Errors::new("key not in the tree")),
+        },
+        "verify" => {
+            let proof = hex::decode(m.value_of("PROOF").unwrap())
+                .map_err(|_| monotree::Errors::new("invalid proof"))?;
+            let proof = Proof::from_bytes(&proof)?;
+            if !verify_proof(&tree.hasher, root.as_ref(), &arg("LEAF")?, Some(&proof)) {
+                let msg = "proof does not verify against the root".to_string();
+                return Err(monotree::Errors::ProofInvalid(msg));
+            }
+            println!("ok");
+            return Ok(());
+        }
+        "export" => {
+            let nodes = match m.value_of("FILE") {
+                Some(path) => tree.export(root.as_ref(), File::create(path)?)?,
+                None => tree.export(root.as_ref(), io::stdout().lock())?,
+            };
+            eprintln!("exported {} nodes", nodes);
+            return Ok(());
+        }
+        "stats" => {
+            let stats = tree.stats(root.as_ref())?;
+            println!("nodes   {}", stats.nodes);
+            println!("leaves  {}", stats.leaves);
+            println!("bytes   {}", stats.bytes);
+            println!("depth   {}", stats.depth());
+            return Ok(());
+        }
+        _ => unreachable!(),
+    };
+    if m.value_of("root").is_none() && !tree.swap_root(root.as_ref(), updated.as_ref())? {
+        return Err(monotree::Errors::new("the root recorded changed meanwhile"));
+    }
+    println!("{}", hex_or_dash(updated.as_ref()));
+    Ok(())
+}
+
+fn parse_hash(arg: &str) -> Result<Hash> {
+    match hex::decode(arg) {
+        Ok(bytes) if bytes.len() == monotree::HASH_LEN => {
+            Ok(monotree::utils::slice_to_hash(&bytes))
+        }
+        _ => Err(monotree::Errors::new(&format!("invalid hash: {}", arg))),
+    }
+}
+
+fn hex_or_dash(hash: Option<&Hash>) -> String {
+    hash.map_or("-".to_string(), hex::encode)
+}
+
+#[cfg(feature = "shell")]
+fn shell(m: &ArgMatches) -> Result<()> {
+    let dbpath = m.value_of("db").unwrap();
+    match m.value_of("backend") {
+        #[cfg(feature = "db-sled")]
+        Some("sled") => shell_on(&mut Monotree::<Sled, DefaultHasher>::try_new(dbpath)?),
+        #[cfg(feature = "db-rocks")]
+        Some("rocksdb") => shell_on(&mut Monotree::<RocksDB, DefaultHasher>::try_new(dbpath)?),
+        Some(backend) => Err(monotree::Errors::new(&format!(
+            "backend {} is not enabled",
+            backend
+        ))),
+        None => unreachable!(),
+    }
+}
+
+#[cfg(feature = "shell")]
+fn shell_on<D: Database>(tree: &mut Monotree<D, DefaultHasher>) -> Result<()> {
+    let stdin = io::stdin();
+    monotree::shell::Shell::new(tree)?.run(stdin.lock(), &mut io::stdout())
+}
+
+#[cfg(not(feature = "shell"))]
+fn shell(_m: &ArgMatches) -> Result<()> {
+    Err(monotree::Errors::new("feature shell is not enabled"))
+}
+
+#[cfg(feature = "http")]
+fn serve(m: &ArgMatches) -> Result<()> {
+    let dbpath = m.value_of("db").unwrap();
+    let listener = std::net::TcpListener::bind(m.value_of("addr").unwrap())?;
+    match m.value_of("backend") {
+        #[cfg(feature = "db-sled")]
+        Some("sled") => serve_on(
+            &mut Monotree::with_db(Sled::open_read_only(dbpath)?)?,
+            &listener,
+        ),
+        #[cfg(feature = "db-rocks")]
+        Some("rocksdb") => serve_on(
+            &mut Monotree::with_db(RocksDB::open_read_only(dbpath)?)?,
+            &listener,
+        ),
+        Some(backend) => Err(monotree::Errors::new(&format!(
+            "backend {} is not enabled",
+            backend
+        ))),
+        None => unreachable!(),
+    }
+}
+
+#[cfg(feature = "http")]
+fn serve_on<D: Database>(
+    tree: &mut Monotree<D, DefaultHasher>,
+    listener: &std::net::TcpListener,
+) -> Result<()> {
+    eprintln!("serving on {}", listener.local_addr()?);
+    monotree::http::Server::new(tree).serve(listener)
+}
+
+#[cfg(not(feature = "http"))]
+fn serve(_m: &ArgMatches) -> Result<()> {
+    Err(monotree::Errors::new("feature http is not enabled"))
+}
+
+#[cfg(feature = "grpc")]
+fn grpc(m: &ArgMatches) -> Result<()> {
+    let dbpath = m.value_of("db").unwrap();
+    let listener = std::net::TcpListener::bind(m.value_of("addr").unwrap())?;
+    match m.value_of("backend") {
+        #[cfg(feature = "db-sled")]
+        Some("sled") => grpc_on(Monotree::<Sled, DefaultHasher>::try_new(dbpath)?, listener),
+        #[cfg(feature = "db-rocks")]
+        Some("rocksdb") => grpc_on(
+            Monotree::<RocksDB, DefaultHasher>::try_new(dbpath)?,
+            listener,
+        ),
+        Some(backend) => Err(monotree::Errors::new(&format!(
+            "backend {} is not enabled",
+            backend
+        ))),
+        None => unreachable!(),
+    }
+}
+
+#[cfg(feature = "grpc")]
+fn grpc_on<D: Database + Send + 'static>(
+    tree: Monotree<D, DefaultHasher>,
+    listener: std::net::TcpListener,
+) -> Result<()> {
+    eprintln!("serving on {}", listener.local_addr()?);
+    monotree::grpc::Service::new(tree).serve(listener)
+}
+
+#[cfg(not(feature = "grpc"))]
+fn grpc(_m: &ArgMatches) -> Result<()> {
+    Err(monotree::Errors::new("feature grpc is not enabled"))
+}
+
+#[cfg(feature = "test-vectors")]
+fn vectors(m: &ArgMatches) -> Result<()> {
+    use monotree::vectors;
+    match m.subcommand() {
+        ("export", Some(m)) => {
+            let n = m
+                .value_of("entries")
+                .unwrap()
+                .parse()
+                .map_err(|_| monotree::Errors::new("invalid number of entries"))?;
+            let json = vectors::export(&vectors::generate_all(n)?);
+            println!("{}", serde_json::to_string_pretty(&json)?);
+            Ok(())
+        }
+        ("check", Some(m)) => {
+            let mut json = String::new();
+            match m.value_of("FILE") {
+                Some(path) => File::open(path)?.read_to_string(&mut json)?,
+                None => io::stdin().read_to_string(&mut json)?,
+            };
+            let fixtures = vectors::load(&json)?;
+            let mut failed = 0;
+            for (i, vector) in fixtures.iter().enumerate() {
+                let traversal = format!("{:?}/{:?}", vector.traversal.bits, vector.traversal.bytes);
+                match vectors::check(vector) {
+                    Ok(()) => println!("{} {} {}: ok", i, vector.hasher, traversal),
+                    Err(err) => {
+                        failed += 1;
+                        println!("{} {} {}: {}", i, vector.hasher, traversal, err);
+                    }
+                }
+            }
+            if failed > 0 {
+                let msg = format!("{} of {} vectors failed", failed, fixtures.len());
+                return Err(monotree::Errors::new(&msg));
+            }
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(not(feature = "test-vectors"))]
+fn vectors(_m: &ArgMatches) -> Result<()> {
+    Err(monotree::Errors::new("feature test-vectors is not enabled"))
+}
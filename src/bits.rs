@@ -1,7 +1,57 @@
 //! A module for representing `BitVec` in terms of bytes slice.
 use crate::utils::*;
 use crate::*;
-use std::ops::Range;
+use alloc::borrow::Cow;
+use core::convert::TryFrom;
+use core::ops::Range;
+
+/// Orders in which bits within a byte of keys are traversed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BitOrder {
+    /// From the most significant bit. This is the default.
+    #[default]
+    MsbFirst,
+    /// From the least significant bit.
+    LsbFirst,
+}
+
+/// Orders in which bytes of keys are traversed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ByteOrder {
+    /// From the first byte. This is the default.
+    #[default]
+    BigEndian,
+    /// From the last byte.
+    LittleEndian,
+}
+
+/// An order in which `monotree` traverses bits of keys from the root.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Traversal {
+    pub bits: BitOrder,
+    pub bytes: ByteOrder,
+}
+
+impl Traversal {
+    /// Rearrange a key so that traversing it MSB-first from the first byte follows this order.
+    /// Keys are borrowed as they are with the default order.
+    pub fn arrange<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        if *self == Traversal::default() {
+            return Cow::Borrowed(key);
+        }
+        let mut key = key.to_vec();
+        if self.bytes == ByteOrder::LittleEndian {
+            key.reverse();
+        }
+        if self.bits == BitOrder::LsbFirst {
+            key.iter_mut().for_each(|b| *b = b.reverse_bits());
+        }
+        Cow::Owned(key)
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 /// `BitVec` implementation based on bytes slice.
@@ -20,7 +70,7 @@ impl<'a> Bits<'a> {
 
     /// Construct `Bits` instance by deserializing bytes slice.
     pub fn from_bytes(bytes: &'a [u8]) -> Self {
-        let u = std::mem::size_of::<BitsLen>();
+        let u = core::mem::size_of::<BitsLen>();
         let start: BitsLen = bytes_to_int(&bytes[..u]);
         let end: BitsLen = bytes_to_int(&bytes[u..2 * u]);
         Self {
@@ -29,14 +79,47 @@ impl<'a> Bits<'a> {
         }
     }
 
-    /// Serialize `Bits` into bytes.
+    /// The same as `from_bytes()`, but of bytes by `to_compact_bytes()`.
+    /// Bytes of malformed varints or ranges over `BitsLen` fail with `Errors::Decode`.
+    pub fn from_compact_bytes(bytes: &'a [u8]) -> Result<Self> {
+        let narrow = |int: u64| {
+            BitsLen::try_from(int).map_err(|_| {
+                Errors::Decode(format!("from_compact_bytes(): range over BitsLen: {}", int))
+            })
+        };
+        let (start, n) = varint_to_int(bytes)?;
+        let (end, m) = varint_to_int(&bytes[n..])?;
+        Ok(Self {
+            path: &bytes[n + m..],
+            range: narrow(start)?..narrow(end)?,
+        })
+    }
+
+    /// Serialize `Bits` into bytes: `range_start` and `range_end` of `BitsLen` each, then `path`.
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
-        Ok([
-            &self.range.start.to_be_bytes(),
-            &self.range.end.to_be_bytes(),
-            &self.path[..],
-        ]
-        .concat())
+        let mut bytes = Vec::with_capacity(self.path.len() + 4);
+        self.write_bytes(&mut bytes, true);
+        Ok(bytes)
+    }
+
+    /// The same as `to_bytes()`, but with `range_start` and `range_end` as varints.
+    pub fn to_compact_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(self.path.len() + 6);
+        self.write_bytes(&mut bytes, false);
+        Ok(bytes)
+    }
+
+    /// Append the serialized `Bits` to the given bytes, by `to_bytes()` if `fixed`,
+    /// or else by `to_compact_bytes()`, so that nodes are serialized into a single buffer.
+    pub fn write_bytes(&self, bytes: &mut Vec<u8>, fixed: bool) {
+        if fixed {
+            bytes.extend_from_slice(&self.range.start.to_be_bytes());
+            bytes.extend_from_slice(&self.range.end.to_be_bytes());
+        } else {
+            push_varint(bytes, self.range.start as u64);
+            push_varint(bytes, self.range.end as u64);
+        }
+        bytes.extend_from_slice(self.path);
     }
 
     /// Get the very first bit.
@@ -68,6 +151,18 @@ impl<'a> Bits<'a> {
         }
     }
 
+    /// Get bytes and a range representing the given two `Bits` concatenated.
+    /// The resulting range starts where the range of `a` does.
+    pub fn concat(a: &Self, b: &Self) -> (Vec<u8>, Range<BitsLen>) {
+        let start = a.range.start;
+        let mut bits = vec![false; start as usize];
+        bits.extend(bytes_to_slicebit(a.path, &a.range));
+        bits.extend(bytes_to_slicebit(b.path, &b.range));
+        let end = bits.len() as BitsLen;
+        bits.resize(nbytes_across(0, end) as usize * 8, false);
+        (bits_to_bytes(&bits), start..end)
+    }
+
     /// Get length of the longest common prefix bits for the given two `Bits`.
     pub fn len_common_bits(a: &Self, b: &Self) -> BitsLen {
         len_lcp(&a.path, &a.range, &b.path, &b.range)
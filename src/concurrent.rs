@@ -0,0 +1,160 @@
+//! A module sharing a tree of `monotree` between threads, with readers running concurrently.
+//!
+//! `Database::get()` takes `&mut self` for the caches of backends, so a single `Monotree`
+//! serves one operation at a time. `SyncMonotree` runs writers one at a time on a tree,
+//! while every reader takes a tree of its own over another handle of the same database,
+//! opened on demand and pooled once done, so that reads wait neither for each other nor for writes.
+//! Nodes never change once written, so a reader only ever misses those not committed yet.
+//!
+//! With the feature `model-checking`, the handle is built over the primitives of `shuttle`
+//! instead, for tests exploring the interleavings of its threads, and is of no use outside them.
+use crate::*;
+#[cfg(feature = "model-checking")]
+use shuttle::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+#[cfg(not(feature = "model-checking"))]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(not(feature = "model-checking"))]
+use std::sync::Mutex;
+
+/// A function opening another handle of the database of a tree, e.g. `RocksDB::from_db()`
+/// over the same `Arc<DB>`, or `Sled::from_db()` over a clone of the same `sled::Db`.
+/// Handles have to observe whatever was committed through any other,
+/// which rules out `MemoryDB` and backends like it, each handle of which has data of its own.
+pub type Opener<D> = Box<dyn Fn() -> Result<D> + Send + Sync>;
+
+/// Trees idle for readers, each with the number of writes it was opened after.
+struct Readers<D, H, C> {
+    hasher: H,
+    idle: Vec<(u64, Monotree<D, H, C>)>,
+}
+
+/// A handle of `Monotree` shared between threads, which is `Send + Sync` for backends that are `Send`.
+///
+/// Readers are given a tree opened after the last write, so that they observe the settings
+/// recorded by writers, such as the `BloomFilter` enabled by `Monotree::enable_filter()`.
+pub struct SyncMonotree<D = DefaultDatabase, H = DefaultHasher, C = DefaultCodec> {
+    writer: Mutex<Monotree<D, H, C>>,
+    readers: Mutex<Readers<D, H, C>>,
+    open: Opener<D>,
+    writes: AtomicU64,
+}
+
+impl<D, H, C> SyncMonotree<D, H, C>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    /// Construct `SyncMonotree` over the database of the handles opened by `open`.
+    pub fn new<F>(open: F) -> Result<Self>
+    where
+        F: Fn() -> Result<D> + Send + Sync + 'static,
+    {
+        let tree = Monotree::with_db(open()?)?;
+        Ok(SyncMonotree::with_tree(tree, Box::new(open)))
+    }
+
+    /// Construct `SyncMonotree` from an existing tree, taken by writers,
+    /// with readers over the handles of its database opened by `open`.
+    pub fn with_tree(tree: Monotree<D, H, C>, open: Opener<D>) -> Self {
+        SyncMonotree {
+            readers: Mutex::new(Readers {
+                hasher: tree.hasher.clone(),
+                idle: Vec::new(),
+            }),
+            writer: Mutex::new(tree),
+            open,
+            writes: AtomicU64::new(0),
+        }
+    }
+
+    /// Run the given closure with the tree taken by writers, one at a time.
+    pub fn write<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Monotree<D, H, C>) -> Result<R>,
+    {
+        let mut tree = self
+            .writer
+            .lock()
+            .map_err(|_| Errors::new("SyncMonotree::write(): poisoned"))?;
+        let result = f(&mut tree);
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+
+    /// Run the given closure with a tree of its own, concurrently with other readers and writers.
+    ///
+    /// Anything written through the tree given is not seen by other readers until the next write.
+    pub fn read<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Monotree<D, H, C>) -> Result<R>,
+    {
+        let writes = self.writes.load(Ordering::SeqCst);
+        let (idle, hasher) = {
+            let mut readers = self
+                .readers
+                .lock()
+                .map_err(|_| Errors::new("SyncMonotree::read(): poisoned"))?;
+            readers.idle.retain(|(n, _)| *n == writes);
+            (readers.idle.pop(), readers.hasher.clone())
+        };
+        let mut tree = match idle {
+            Some((_, tree)) => tree,
+            None => {
+                let mut tree = Monotree::with_db((self.open)()?)?;
+                tree.hasher = hasher;
+                tree
+            }
+        };
+        let result = f(&mut tree);
+        if let Ok(mut readers) = self.readers.lock() {
+            if self.writes.load(Ordering::SeqCst) == writes {
+                readers.idle.push((writes, tree));
+            }
+        }
+        result
+    }
+
+    /// Insert key-leaf entry into the tree. Returns a new root hash.
+    pub fn insert(&self, root: Option<&Hash>, key: &Hash, leaf: &Hash) -> Result<Option<Hash>> {
+        self.write(|tree| tree.insert(root, key, leaf))
+    }
+
+    /// Insert key-leaf entries in batch. Returns a new root hash.
+    pub fn inserts(
+        &self,
+        root: Option<&Hash>,
+        keys: &[Hash],
+        leaves: &[Hash],
+    ) -> Result<Option<Hash>> {
+        self.write(|tree| tree.inserts(root, keys, leaves))
+    }
+
+    /// Remove the entry of a key from the tree. Returns a new root hash.
+    pub fn remove(&self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        self.write(|tree| tree.remove(root, key))
+    }
+
+    /// Remove the entries of keys in batch. Returns a new root hash.
+    pub fn removes(&self, root: Option<&Hash>, keys: &[Hash]) -> Result<Option<Hash>> {
+        self.write(|tree| tree.removes(root, keys))
+    }
+
+    /// Get the leaf matched with a key.
+    pub fn get(&self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        self.read(|tree| tree.get(root, key))
+    }
+
+    /// Get the leaves matched with keys in batch.
+    pub fn gets(&self, root: Option<&Hash>, keys: &[Hash]) -> Result<Vec<Option<Hash>>> {
+        self.read(|tree| tree.gets(root, keys))
+    }
+
+    /// Generate a Merkle proof for the given root and key.
+    pub fn get_merkle_proof(&self, root: Option<&Hash>, key: &Hash) -> Result<Option<Proof>> {
+        self.read(|tree| tree.get_merkle_proof(root, key))
+    }
+}
@@ -1,260 +1,3102 @@
 //! A module for implementing database supporting `monotree`.
 use crate::*;
 use hashbrown::{HashMap, HashSet};
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::fs;
+#[cfg(any(feature = "db-rocks", feature = "db-sled"))]
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
 use utils::*;
 
+#[cfg(feature = "db-object-store")]
+use futures::stream::{self, StreamExt, TryStreamExt};
+#[cfg(feature = "db-object-store")]
+use futures::FutureExt;
+#[cfg(feature = "db-object-store")]
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+#[cfg(feature = "db-object-store")]
+use object_store::path::Path as ObjectPath;
+#[cfg(feature = "db-object-store")]
+use object_store::{ObjectMeta, ObjectStore, ObjectStoreExt, PutPayload};
+#[cfg(feature = "testing")]
+use rand::{rngs::StdRng, Rng, SeedableRng};
+#[cfg(feature = "db-redis")]
+use redis::Commands;
+/// The `rocksdb` crate `RocksDB` is built on, to construct `Options` for `RocksDB::with_options()`.
 #[cfg(feature = "db-rocks")]
-use rocksdb::{WriteBatch, DB};
+pub use rocksdb;
+#[cfg(feature = "db-rocks")]
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode, Options, WriteBatch,
+    WriteOptions, DB,
+};
+/// The `sled` crate `Sled` is built on, to construct `Config` for `Sled::with_config()`.
+#[cfg(feature = "db-sled")]
+pub use sled;
+#[cfg(feature = "db-object-store")]
+use std::future::Future;
+use std::time::Duration;
+#[cfg(any(feature = "db-rocks", feature = "db-sled"))]
+use std::time::Instant;
+
+/// Default number of entries `MemCache` holds at most.
+pub const DEFAULT_CACHE_ENTRIES: usize = 1 << 16;
+
+/// Default number of bytes `MemCache` holds at most, each entry counted as its key plus its value.
+pub const DEFAULT_CACHE_BYTES: usize = 1 << 26;
 
+/// A cache of values of a database, evicting those least recently used over its capacity,
+/// as used by `CachedDb`.
+///
+/// Values written within an open batch are pinned, so that reads in the batch observe them
+/// however many there are, and are dropped unless the batch is committed.
+/// Other values are kept across batches, so that nodes near roots stay cached.
+/// Deletions are cached as well, as known to be absent.
 pub struct MemCache {
-    set: HashSet<Hash>,
-    map: HashMap<Hash, Vec<u8>>,
+    entries: HashMap<Vec<u8>, CacheEntry>,
+    /// Keys of entries not pinned, by the tick they were last used at.
+    lru: BTreeMap<u64, Vec<u8>>,
+    /// Keys of entries written within the open batch, which are pinned.
+    pending: HashSet<Vec<u8>>,
+    batch_on: bool,
+    tick: u64,
+    bytes: usize,
+    max_entries: usize,
+    max_bytes: usize,
+    hits: u64,
+    misses: u64,
+    /// Lookups hit and missed since the capacity was last tuned.
+    window: (u64, u64),
+    tuning: Option<CacheTuning>,
+}
+
+struct CacheEntry {
+    /// The value, or `None` if known to be absent.
+    value: Option<Vec<u8>>,
+    /// The tick the entry was last used at.
+    tick: u64,
+    pinned: bool,
 }
 
 impl MemCache {
     pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CACHE_ENTRIES, DEFAULT_CACHE_BYTES)
+    }
+
+    /// Construct `MemCache` holding up to `entries` entries and `bytes` bytes,
+    /// except for writes pinned by an open batch.
+    pub fn with_capacity(entries: usize, bytes: usize) -> Self {
         MemCache {
-            set: HashSet::new(),
-            map: HashMap::with_capacity(1 << 12),
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            pending: HashSet::new(),
+            batch_on: false,
+            tick: 0,
+            bytes: 0,
+            max_entries: entries,
+            max_bytes: bytes,
+            hits: 0,
+            misses: 0,
+            window: (0, 0),
+            tuning: None,
+        }
+    }
+
+    /// Change the capacity, evicting entries over it right away.
+    pub fn set_capacity(&mut self, entries: usize, bytes: usize) {
+        self.max_entries = entries;
+        self.max_bytes = bytes;
+        self.evict();
+    }
+
+    /// Get the number of entries and bytes held.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.entries.len(), self.bytes)
+    }
+
+    /// Tune the capacity in bytes by the hit rate observed, or keep it fixed with `None`.
+    /// The capacity in entries is left as set. See `CacheTuning`.
+    pub fn set_tuning(&mut self, tuning: Option<CacheTuning>) {
+        self.tuning = tuning;
+        self.window = (0, 0);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
         }
     }
 
     pub fn clear(&mut self) {
-        self.set.clear();
-        self.map.clear();
+        self.entries.clear();
+        self.lru.clear();
+        self.pending.clear();
+        self.bytes = 0;
     }
 
     pub fn contains(&self, key: &[u8]) -> bool {
-        self.set.contains(key) || self.map.contains_key(key)
+        self.entries.contains_key(key)
     }
 
     pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        match self.map.get(key) {
-            Some(v) => Ok(Some(v.to_owned())),
+        let tick = self.next_tick();
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                if !entry.pinned {
+                    self.lru.remove(&entry.tick);
+                    self.lru.insert(tick, key.to_vec());
+                }
+                entry.tick = tick;
+                Ok(entry.value.to_owned())
+            }
             None => Ok(None),
         }
     }
 
-    pub fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.map.insert(slice_to_hash(key), value);
-        if self.set.contains(key) {
-            self.set.remove(key);
+    /// Get the value of a key if cached, `Some(None)` if known to be absent,
+    /// counting a hit or a miss to tune the capacity by.
+    pub fn lookup(&mut self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let value = if self.contains(key) {
+            self.hits += 1;
+            self.window.0 += 1;
+            self.get(key).ok()
+        } else {
+            self.misses += 1;
+            self.window.1 += 1;
+            None
+        };
+        if self.tuning.is_some() {
+            self.tune();
         }
+        value
+    }
+
+    /// Cache a value written, which is pinned within a batch.
+    pub fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.insert(key, Some(value), self.batch_on);
         Ok(())
     }
 
+    /// Cache a deletion, which is pinned within a batch.
     pub fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.map.remove(key);
-        self.set.insert(slice_to_hash(key));
+        self.insert(key, None, self.batch_on);
         Ok(())
     }
+
+    /// Cache a value read from the backend.
+    pub fn fill(&mut self, key: &[u8], value: Vec<u8>) {
+        self.insert(key, Some(value), false);
+    }
+
+    /// Drop a key from the cache, so that it is read from the backend next time.
+    pub fn forget(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= key.len() + entry.value.map_or(0, |value| value.len());
+            if entry.pinned {
+                self.pending.remove(key);
+            } else {
+                self.lru.remove(&entry.tick);
+            }
+        }
+    }
+
+    /// Start pinning writes, dropping those of a batch left open.
+    pub fn init_batch(&mut self) {
+        self.discard_batch();
+        self.batch_on = true;
+    }
+
+    /// Keep the writes of the batch, which are evictable from now on.
+    pub fn finish_batch(&mut self) {
+        self.batch_on = false;
+        for key in std::mem::take(&mut self.pending) {
+            if let Some(entry) = self.entries.get_mut(&key) {
+                entry.pinned = false;
+                self.lru.insert(entry.tick, key);
+            }
+        }
+        self.tune();
+        self.evict();
+    }
+
+    /// Drop the writes of the batch, such as of a batch failed to commit.
+    pub fn discard_batch(&mut self) {
+        self.batch_on = false;
+        for key in std::mem::take(&mut self.pending) {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= key.len() + entry.value.map_or(0, |value| value.len());
+            }
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn insert(&mut self, key: &[u8], value: Option<Vec<u8>>, pinned: bool) {
+        self.forget(key);
+        let key = key.to_vec();
+        self.bytes += key.len() + value.as_ref().map_or(0, |value| value.len());
+        let tick = self.next_tick();
+        if pinned {
+            self.pending.insert(key.clone());
+        } else {
+            self.lru.insert(tick, key.clone());
+        }
+        let entry = CacheEntry {
+            value,
+            tick,
+            pinned,
+        };
+        self.entries.insert(key, entry);
+        self.evict();
+    }
+
+    /// Grow a full cache missing over the target, or shrink one mostly unused, once every
+    /// window of lookups, then keep the capacity within the bounds and budget of the tuning.
+    fn tune(&mut self) {
+        let tuning = match self.tuning.as_ref() {
+            Some(tuning) => tuning,
+            None => return,
+        };
+        let (hits, misses) = self.window;
+        let mut bytes = self.max_bytes;
+        if hits + misses >= tuning.window.max(1) {
+            let rate = hits as f64 / (hits + misses) as f64;
+            let full =
+                self.bytes >= self.max_bytes / 10 * 9 || self.entries.len() >= self.max_entries;
+            if rate < tuning.target_hit_rate && full {
+                bytes = bytes.saturating_mul(2);
+            } else if self.bytes < self.max_bytes / 2 {
+                bytes = self.bytes.saturating_mul(2);
+            }
+            bytes = bytes.clamp(tuning.min_bytes, tuning.max_bytes.max(tuning.min_bytes));
+            self.window = (0, 0);
+        }
+        if let Some(budget) = tuning.budget.as_ref() {
+            bytes = bytes.min(budget());
+        }
+        if bytes != self.max_bytes {
+            self.max_bytes = bytes;
+            self.evict();
+        }
+    }
+
+    /// Evict entries least recently used until the cache fits in its capacity.
+    fn evict(&mut self) {
+        while self.entries.len() > self.max_entries || self.bytes > self.max_bytes {
+            let key = match self.lru.iter().next() {
+                Some((_, key)) => key.clone(),
+                None => break,
+            };
+            self.forget(&key);
+        }
+    }
+}
+
+/// Bytes `CacheTuning` may give the cache at most at the moment,
+/// such as what is left of the memory budget of the process.
+pub type MemoryBudget = Arc<dyn Fn() -> usize + Send + Sync>;
+
+/// Settings of `MemCache::set_tuning()`, adjusting the capacity in bytes of the cache
+/// once every `window` lookups: a full cache hitting less than `target_hit_rate` doubles,
+/// while one holding less than half of it shrinks to twice what it holds.
+#[derive(Clone)]
+pub struct CacheTuning {
+    pub min_bytes: usize,
+    pub max_bytes: usize,
+    pub target_hit_rate: f64,
+    pub window: u64,
+    /// Polled on every lookup, so that the cache shrinks under memory pressure right away,
+    /// even below `min_bytes`. Keep it cheap, such as reading an atomic updated by a monitor.
+    pub budget: Option<MemoryBudget>,
+}
+
+impl Default for CacheTuning {
+    fn default() -> Self {
+        CacheTuning {
+            min_bytes: 1 << 20,
+            max_bytes: 1 << 30,
+            target_hit_rate: 0.9,
+            window: 1 << 12,
+            budget: None,
+        }
+    }
+}
+
+/// Counters of a `MemCache`, given by `MemCache::stats()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_entries: usize,
+    pub max_bytes: usize,
+}
+
+impl CacheStats {
+    /// Get the ratio of lookups hit, or `0.0` before any lookup.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+/// A type representing entries scanned by `Database::iter_prefix()`, as pairs of key and value.
+pub type Entries<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>;
+
+/// Durability modes applied by `Database::finish_batch()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Durability {
+    /// Leave it to the backend when to persist written data. This is the default.
+    #[default]
+    Lazy,
+    /// Call `flush()` at the end of every `finish_batch()`.
+    Flush,
+    /// Persist the writes of every batch before `finish_batch()` returns, by the cheapest means
+    /// of the backend, such as syncing the write-ahead log of `RocksDB` instead of flushing memtables.
+    Sync,
+    /// Call `flush()` at the end of every given number of `finish_batch()`.
+    FlushEvery(u32),
+    /// Call `flush()` at the end of the first `finish_batch()` once the given time has passed
+    /// since the last flush, so that no more than that time of writes is lost in a crash.
+    FlushInterval(Duration),
+}
+
+/// Batches finished and time passed since the last flush of a backend,
+/// telling when its `Durability` asks for a flush.
+#[cfg(any(feature = "db-rocks", feature = "db-sled"))]
+#[derive(Debug)]
+struct FlushClock {
+    batches: u32,
+    last: Instant,
+}
+
+#[cfg(any(feature = "db-rocks", feature = "db-sled"))]
+impl Default for FlushClock {
+    fn default() -> Self {
+        FlushClock {
+            batches: 0,
+            last: Instant::now(),
+        }
+    }
+}
+
+#[cfg(any(feature = "db-rocks", feature = "db-sled"))]
+impl FlushClock {
+    /// Count a batch finished, and tell whether to flush at the end of it.
+    fn batch_finished(&mut self, durability: Durability) -> bool {
+        self.batches += 1;
+        let due = match durability {
+            Durability::Lazy | Durability::Sync => false,
+            Durability::Flush => true,
+            Durability::FlushEvery(batches) => self.batches >= batches,
+            Durability::FlushInterval(interval) => self.last.elapsed() >= interval,
+        };
+        if due {
+            *self = FlushClock::default();
+        }
+        due
+    }
 }
 
 /// A trait defining databases used for `monotree`.
 pub trait Database {
-    fn new(dbpath: &str) -> Self;
+    /// Open the database at the given path.
+    fn try_new(dbpath: &str) -> Result<Self>
+    where
+        Self: Sized;
+    /// The same as `try_new()`, but panics if the database fails to open.
+    fn new(dbpath: &str) -> Self
+    where
+        Self: Sized,
+    {
+        Self::try_new(dbpath).expect("new()")
+    }
+    /// Open the existing database at the given path for reads only, such as by replicas
+    /// serving proofs. `put()`, `delete()` and `cas()` fail, so that nothing is ever written.
+    /// As this is not supported by default, backends without such a mode fail to open.
+    fn open_read_only(_dbpath: &str) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Err(Errors::new("open_read_only(): not supported"))
+    }
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// Get the values of many keys at once, in the order of the keys.
+    ///
+    /// This calls `get()` for each key by default. Backends reading over a network override it
+    /// to read all of them in a single round trip.
+    fn get_many(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
     fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()>;
     fn delete(&mut self, key: &[u8]) -> Result<()>;
+    /// Open a batch, whose writes are committed at once by `finish_batch()`.
+    ///
+    /// Within a batch, `get()` observes the writes pending in it as if already committed,
+    /// while other handles of the database observe none of them until `finish_batch()`.
+    /// Writes pending in a batch left open, e.g. by an operation failed midway, are discarded.
     fn init_batch(&mut self) -> Result<()>;
+    /// Commit the writes pending in the batch. If this fails, they are discarded altogether,
+    /// so that `get()` never observes writes that were not committed.
+    ///
+    /// Nodes of a root are committed by this before the root is returned by `Monotree`,
+    /// so with a `Durability` other than `Lazy` set, e.g. by `RocksDB::set_durability()`,
+    /// they are persisted before the root is recorded anywhere, by `Monotree::swap_root()`
+    /// or by the application, and are never missing after a crash.
     fn finish_batch(&mut self) -> Result<()>;
+    /// Get the total size in bytes the backend currently occupies.
+    ///
+    /// As this is not supported by default, backends unable to tell their size fail,
+    /// in which case `Monotree::estimated_size()` still tells that of a tree.
+    fn total_size(&self) -> Result<u64> {
+        Err(Errors::new("total_size(): not supported"))
+    }
+    /// Persist all data written so far. Backends without durable storage do nothing.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Reclaim the space still taken by entries deleted, such as by `Monotree::prune()`,
+    /// which backends of log-structured storage return to the disk only once compacted.
+    /// Backends giving space back on deletion do nothing.
+    fn compact(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Replace the value of a key with `new` only if it currently is `expected`,
+    /// where `None` stands for no value. Returns whether the value was replaced.
+    ///
+    /// This is emulated with `get()` followed by `put()` or `delete()` by default, which is
+    /// not atomic against other writers. Backends with a native primitive override it.
+    /// Not to be called within a batch.
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => self.put(key, value)?,
+            None => self.delete(key)?,
+        }
+        Ok(true)
+    }
+    /// Get all keys in the database, in no particular order.
+    ///
+    /// Used to find stale entries by `Monotree::prune()`. As this is not supported by default,
+    /// backends unable to enumerate their keys keep the tree from being pruned.
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        Err(Errors::new("keys(): not supported"))
+    }
+    /// Scan the entries of keys starting with `prefix`, of all keys for an empty one,
+    /// without loading them into memory at once, such as for maintenance over every node.
+    ///
+    /// This gets the values of `keys()` one by one by default, so backends unable to enumerate
+    /// their keys fail. Backends of ordered storage override it to scan a range of keys.
+    /// Not to be called within a batch.
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        let keys = self.keys()?;
+        Ok(Box::new(
+            keys.into_iter()
+                .filter(move |key| key.starts_with(prefix))
+                .filter_map(move |key| match self.get(&key) {
+                    Ok(Some(value)) => Some(Ok((key, value))),
+                    Ok(None) => None,
+                    Err(err) => Some(Err(err)),
+                }),
+        ))
+    }
 }
 
 /// A database using `HashMap`.
+///
+/// Opened by `with_file()`, entries are kept in a file across runs as well,
+/// for tests and tools wanting persistence without the weight of `RocksDB` or `Sled`.
 pub struct MemoryDB {
-    db: HashMap<Hash, Vec<u8>>,
+    db: HashMap<Vec<u8>, Vec<u8>>,
+    /// Writes pending in the batch, where `None` stands for a deletion.
+    batch: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    batch_on: bool,
+    /// File the entries are saved to by `flush()`, if opened by `with_file()`.
+    path: Option<String>,
+}
+
+impl MemoryDB {
+    /// Open `MemoryDB` backed by a file, starting with the entries saved in it, if it exists.
+    /// Entries are saved back to it, as a whole, only by `flush()`, e.g. before exit.
+    pub fn with_file(path: &str) -> Result<Self> {
+        let mut db = match fs::metadata(path) {
+            Ok(_) => Self::load(path)?,
+            Err(_) => Self::try_new("")?,
+        };
+        db.path = Some(path.to_string());
+        Ok(db)
+    }
+
+    /// Dump all entries into a file in a compact binary form, that is, a sequence of
+    /// `len`(4, big-endian) + `key`(`len`) + `len`(4, big-endian) + `value`(`len`),
+    /// so that keys of any length are kept, such as those prefixed by `NamespacedDB`.
+    ///
+    /// The entries are written to a temporary file next to it first, then renamed over it,
+    /// so that a crash while saving leaves the file as it was.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut bytes = Vec::new();
+        for (key, value) in self.db.iter() {
+            for field in [key, value].iter() {
+                let len = u32::try_from(field.len())
+                    .map_err(|_| Errors::new("save(): entry over u32::MAX bytes"))?;
+                bytes.extend_from_slice(&len.to_be_bytes());
+                bytes.extend_from_slice(field);
+            }
+        }
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, bytes)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Construct `MemoryDB` from a file written by `save()`.
+    pub fn load(path: &str) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut db = HashMap::new();
+        let mut i = 0;
+        let field = |i: &mut usize| -> Result<Vec<u8>> {
+            if bytes.len() < *i + 4 {
+                return Err(Errors::Decode("load(): truncated entry".to_string()));
+            }
+            let len: usize = bytes_to_int(&bytes[*i..*i + 4]);
+            *i += 4;
+            if bytes.len() - *i < len {
+                return Err(Errors::Decode("load(): truncated entry".to_string()));
+            }
+            *i += len;
+            Ok(bytes[*i - len..*i].to_vec())
+        };
+        while i < bytes.len() {
+            let key = field(&mut i)?;
+            db.insert(key, field(&mut i)?);
+        }
+        Ok(MemoryDB {
+            db,
+            batch: HashMap::new(),
+            batch_on: false,
+            path: None,
+        })
+    }
+}
+
+impl Database for MemoryDB {
+    fn try_new(_dbname: &str) -> Result<Self> {
+        Ok(MemoryDB {
+            db: HashMap::new(),
+            batch: HashMap::new(),
+            batch_on: false,
+            path: None,
+        })
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.batch.get(key) {
+            return Ok(value.to_owned());
+        }
+        match self.db.get(key) {
+            Some(v) => Ok(Some(v.to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        if self.batch_on {
+            self.batch.insert(key.to_vec(), Some(value));
+        } else {
+            self.db.insert(key.to_vec(), value);
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if self.batch_on {
+            self.batch.insert(key.to_vec(), None);
+        } else {
+            self.db.remove(key);
+        }
+        Ok(())
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.batch.clear();
+        self.batch_on = true;
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.batch_on = false;
+        for (key, value) in self.batch.drain() {
+            match value {
+                Some(value) => self.db.insert(key, value),
+                None => self.db.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        Ok(self
+            .db
+            .iter()
+            .map(|(k, v)| (k.len() + v.len()) as u64)
+            .sum())
+    }
+
+    /// Save the entries to the file opened by `with_file()`, if any.
+    fn flush(&mut self) -> Result<()> {
+        match &self.path {
+            Some(path) => self.save(path),
+            None => Ok(()),
+        }
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        Ok(self.db.keys().cloned().collect())
+    }
+
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        Ok(Box::new(
+            self.db
+                .iter()
+                .filter(move |(key, _)| key.starts_with(prefix))
+                .map(|(key, value)| Ok((key.clone(), value.clone()))),
+        ))
+    }
+}
+
+/// Number of shards used when `ShardedDB` is constructed by `Database::try_new()`.
+pub const DEFAULT_SHARDS: usize = 4;
+
+/// A database partitioning keys across multiple underlying databases.
+///
+/// Each key is deterministically routed to a shard by a simple digest of its bytes,
+/// so the same set of underlying databases must be given in the same order every time.
+pub struct ShardedDB<D> {
+    shards: Vec<D>,
+}
+
+impl<D: Database> ShardedDB<D> {
+    /// Construct `ShardedDB` from the given databases, for example, stores on separate disks.
+    /// Fails if no database is given, as keys would have no shard to be routed to.
+    pub fn with_shards(shards: Vec<D>) -> Result<Self> {
+        if shards.is_empty() {
+            return Err(Errors::new("with_shards(): no shard given"));
+        }
+        Ok(ShardedDB { shards })
+    }
+
+    fn shard(&mut self, key: &[u8]) -> &mut D {
+        let digest = key
+            .iter()
+            .fold(0u32, |h, &b| h.wrapping_mul(31).wrapping_add(b as u32));
+        let n = self.shards.len();
+        &mut self.shards[digest as usize % n]
+    }
+}
+
+impl<D: Database> Database for ShardedDB<D> {
+    /// Open `DEFAULT_SHARDS` databases under the given path, `dbpath/0`, `dbpath/1`, ...
+    fn try_new(dbpath: &str) -> Result<Self> {
+        if !dbpath.is_empty() {
+            fs::create_dir_all(dbpath)?;
+        }
+        let shards = (0..DEFAULT_SHARDS)
+            .map(|i| D::try_new(&format!("{}/{}", dbpath, i)))
+            .collect::<Result<_>>()?;
+        Ok(ShardedDB { shards })
+    }
+
+    /// Open the existing `DEFAULT_SHARDS` databases under the given path for reads only.
+    fn open_read_only(dbpath: &str) -> Result<Self> {
+        let shards = (0..DEFAULT_SHARDS)
+            .map(|i| D::open_read_only(&format!("{}/{}", dbpath, i)))
+            .collect::<Result<_>>()?;
+        Ok(ShardedDB { shards })
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.shard(key).get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.shard(key).put(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.shard(key).delete(key)
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.shards.iter_mut().try_for_each(|db| db.init_batch())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.shards.iter_mut().try_for_each(|db| db.finish_batch())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        self.shards.iter().map(|db| db.total_size()).sum()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.shards.iter_mut().try_for_each(|db| db.flush())
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.shards.iter_mut().try_for_each(|db| db.compact())
+    }
+
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        self.shard(key).cas(key, expected, new)
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        for db in self.shards.iter_mut() {
+            keys.extend(db.keys()?);
+        }
+        Ok(keys)
+    }
+
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        let shards = self
+            .shards
+            .iter_mut()
+            .map(|db| db.iter_prefix(prefix))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(shards.into_iter().flatten()))
+    }
+}
+
+/// Failover behaviors of `MirroredDB` when an operation on the primary fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Failover {
+    /// Return the error of the primary as it is. This is the default.
+    #[default]
+    FailFast,
+    /// Serve reads from the secondary, while writes still fail.
+    ReadSecondary,
+    /// Serve reads from the secondary and keep applying writes to it,
+    /// queueing up to the given number of writes to replay onto the primary when `probe()` succeeds.
+    QueueWrites(usize),
+}
+
+/// Key used by `MirroredDB::probe()` to check if the primary responds.
+const PROBE_KEY: [u8; HASH_LEN] = [0x00; HASH_LEN];
+
+/// A database mirroring every write of a primary database into a secondary one.
+///
+/// In lagging mode, writes to the secondary are queued and applied at the end of
+/// `finish_batch()` or by `sync_secondary()`. Once `promote()` is called,
+/// the secondary takes over all reads and writes, for example, when the primary failed.
+///
+/// How failures of the primary are handled otherwise is decided by `Failover`.
+/// Failures when committing a batch on the primary are always returned.
+pub struct MirroredDB<P, S> {
+    primary: P,
+    secondary: S,
+    lagging: bool,
+    promoted: bool,
+    queue: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    failover: Failover,
+    degraded: bool,
+    backlog: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl<P: Database, S: Database> MirroredDB<P, S> {
+    pub fn with_dbs(primary: P, secondary: S) -> Self {
+        MirroredDB {
+            primary,
+            secondary,
+            lagging: false,
+            promoted: false,
+            queue: Vec::new(),
+            failover: Failover::default(),
+            degraded: false,
+            backlog: Vec::new(),
+        }
+    }
+
+    pub fn set_lagging(&mut self, lagging: bool) {
+        self.lagging = lagging;
+    }
+
+    pub fn set_failover(&mut self, failover: Failover) {
+        self.failover = failover;
+    }
+
+    pub fn is_promoted(&self) -> bool {
+        self.promoted
+    }
+
+    /// Check if the primary has failed since the last successful `probe()`.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Apply all the writes queued in lagging mode to the secondary.
+    pub fn sync_secondary(&mut self) -> Result<()> {
+        for (key, value) in std::mem::take(&mut self.queue) {
+            match value {
+                Some(value) => self.secondary.put(&key, value)?,
+                None => self.secondary.delete(&key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Catch up the secondary, then make it serve all the following operations.
+    pub fn promote(&mut self) -> Result<()> {
+        self.sync_secondary()?;
+        self.promoted = true;
+        Ok(())
+    }
+
+    /// Health probe of the primary. Returns `true` if it responds.
+    ///
+    /// When it does, the writes queued by `Failover::QueueWrites` are replayed onto it
+    /// and the mirror is no longer degraded.
+    pub fn probe(&mut self) -> bool {
+        if self.primary.get(&PROBE_KEY).is_err() {
+            return false;
+        }
+        while !self.backlog.is_empty() {
+            let (key, value) = &self.backlog[0];
+            let replayed = match value {
+                Some(value) => self.primary.put(key, value.to_owned()),
+                None => self.primary.delete(key),
+            };
+            if replayed.is_err() {
+                return false;
+            }
+            self.backlog.remove(0);
+        }
+        self.degraded = false;
+        true
+    }
+
+    fn read_secondary(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.sync_secondary()?;
+        self.secondary.get(key)
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+        if self.promoted {
+            return match value {
+                Some(value) => self.secondary.put(key, value),
+                None => self.secondary.delete(key),
+            };
+        }
+        let written = match (self.failover, self.degraded) {
+            (Failover::QueueWrites(_), true) => {
+                Err(Errors::Db("write(): primary degraded".to_string()))
+            }
+            _ => match &value {
+                Some(value) => self.primary.put(key, value.to_owned()),
+                None => self.primary.delete(key),
+            },
+        };
+        if let Err(err) = written {
+            match self.failover {
+                Failover::QueueWrites(n) if self.backlog.len() < n => {
+                    self.degraded = true;
+                    self.backlog.push((key.to_vec(), value.to_owned()));
+                    self.sync_secondary()?;
+                    return match value {
+                        Some(value) => self.secondary.put(key, value),
+                        None => self.secondary.delete(key),
+                    };
+                }
+                _ => return Err(err),
+            }
+        }
+        if self.lagging {
+            self.queue.push((key.to_vec(), value));
+            return Ok(());
+        }
+        match value {
+            Some(value) => self.secondary.put(key, value),
+            None => self.secondary.delete(key),
+        }
+    }
 }
 
-impl Database for MemoryDB {
-    fn new(_dbname: &str) -> Self {
-        MemoryDB { db: HashMap::new() }
+impl<P: Database, S: Database> Database for MirroredDB<P, S> {
+    /// Open the primary at `dbpath/primary` and the secondary at `dbpath/secondary`.
+    fn try_new(dbpath: &str) -> Result<Self> {
+        if !dbpath.is_empty() {
+            fs::create_dir_all(dbpath)?;
+        }
+        Ok(MirroredDB::with_dbs(
+            P::try_new(&format!("{}/primary", dbpath))?,
+            S::try_new(&format!("{}/secondary", dbpath))?,
+        ))
+    }
+
+    /// Open the existing primary and secondary for reads only.
+    fn open_read_only(dbpath: &str) -> Result<Self> {
+        Ok(MirroredDB::with_dbs(
+            P::open_read_only(&format!("{}/primary", dbpath))?,
+            S::open_read_only(&format!("{}/secondary", dbpath))?,
+        ))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.promoted {
+            return self.secondary.get(key);
+        }
+        if self.degraded && self.failover != Failover::FailFast {
+            return self.read_secondary(key);
+        }
+        match self.primary.get(key) {
+            Err(err) if self.failover == Failover::FailFast => Err(err),
+            Err(_) => {
+                self.degraded = true;
+                self.read_secondary(key)
+            }
+            found => found,
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.write(key, Some(value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.write(key, None)
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        if !self.promoted {
+            self.primary.init_batch()?;
+        }
+        self.secondary.init_batch()
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        if !self.promoted {
+            self.primary.finish_batch()?;
+        }
+        self.sync_secondary()?;
+        self.secondary.finish_batch()
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        if self.promoted {
+            self.secondary.total_size()
+        } else {
+            self.primary.total_size()
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.promoted {
+            self.primary.flush()?;
+        }
+        self.secondary.flush()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        if !self.promoted {
+            self.primary.compact()?;
+        }
+        self.secondary.compact()
+    }
+
+    /// Swap on the primary, which decides for both, then mirror the new value into the secondary.
+    /// Unlike other writes, this never falls over to the secondary unless promoted.
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        if self.promoted {
+            return self.secondary.cas(key, expected, new);
+        }
+        if !self.primary.cas(key, expected, new.to_owned())? {
+            return Ok(false);
+        }
+        if self.lagging {
+            self.queue.push((key.to_vec(), new));
+            return Ok(true);
+        }
+        match new {
+            Some(value) => self.secondary.put(key, value)?,
+            None => self.secondary.delete(key)?,
+        }
+        Ok(true)
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        if self.promoted {
+            return self.secondary.keys();
+        }
+        self.primary.keys()
+    }
+
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        if self.promoted {
+            return self.secondary.iter_prefix(prefix);
+        }
+        self.primary.iter_prefix(prefix)
+    }
+}
+
+/// A database keeping writes in memory over a borrowed database, which is only ever read.
+///
+/// Writes are dropped along with `OverlayDB` or by `discard()`, so that updates are tried out,
+/// e.g. by `Monotree::replay_and_verify()`, over a database opened read-only as well,
+/// unless `commit()` writes them to the borrowed database. Batches are no-ops, as nothing is committed.
+///
+/// To evaluate several candidates against the same state at once, such as blocks proposed
+/// to a consensus engine, each takes an overlay over a handle of its own of the same database,
+/// e.g. `RocksDB::from_db()`, and the one chosen is committed, while the others are discarded.
+pub struct OverlayDB<'a, D> {
+    db: &'a mut D,
+    /// Writes over the borrowed database, where `None` stands for a deletion.
+    writes: HashMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a, D: Database> OverlayDB<'a, D> {
+    pub fn with_db(db: &'a mut D) -> Self {
+        OverlayDB {
+            db,
+            writes: HashMap::new(),
+        }
+    }
+
+    /// Write the writes kept to the borrowed database in a single batch.
+    /// If this fails, nothing is written.
+    pub fn commit(self) -> Result<()> {
+        self.db.init_batch()?;
+        for (key, value) in self.writes {
+            match value {
+                Some(value) => self.db.put(&key, value)?,
+                None => self.db.delete(&key)?,
+            }
+        }
+        self.db.finish_batch()
+    }
+
+    /// Drop the writes kept, so that reads observe the borrowed database alone again.
+    pub fn discard(&mut self) {
+        self.writes.clear();
+    }
+}
+
+impl<D: Database> Database for OverlayDB<'_, D> {
+    fn try_new(_dbpath: &str) -> Result<Self> {
+        Err(Errors::new("OverlayDB::try_new(): use with_db()"))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.writes.get(key) {
+            Some(value) => Ok(value.to_owned()),
+            None => self.db.get(key),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.writes.insert(key.to_vec(), Some(value));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.writes.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        self.db.total_size()
+    }
+}
+
+/// Identifier of a snapshot taken by `SnapshotDB::snapshot()`.
+pub type SnapshotId = usize;
+
+/// A database staging writes in memory over an underlying database, layer by layer.
+///
+/// Each `snapshot()` starts a copy-on-write layer taking every following write,
+/// while reads fall through the layers down to the underlying database.
+/// `rollback()` then discards a layer and those above it without anything written to disk,
+/// whereas `release()` keeps their writes, writing them through once no layer is left below.
+/// Without any layer, this is the same as the underlying database.
+pub struct SnapshotDB<D> {
+    db: D,
+    layers: Vec<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+    /// Whether a batch is open on the underlying database, which outlives snapshots taken within it.
+    batch_on: bool,
+}
+
+impl<D: Database> SnapshotDB<D> {
+    pub fn with_db(db: D) -> Self {
+        SnapshotDB {
+            db,
+            layers: Vec::new(),
+            batch_on: false,
+        }
+    }
+
+    /// Take a snapshot of the current state. Returns its identifier.
+    pub fn snapshot(&mut self) -> SnapshotId {
+        self.layers.push(HashMap::new());
+        self.layers.len() - 1
+    }
+
+    /// Restore the state at the given snapshot, discarding it and every snapshot taken later.
+    pub fn rollback(&mut self, id: SnapshotId) -> Result<()> {
+        if id >= self.layers.len() {
+            return Err(Errors::new("rollback(): unknown snapshot"));
+        }
+        self.layers.truncate(id);
+        Ok(())
+    }
+
+    /// Keep the writes since the given snapshot, discarding it and every snapshot taken later.
+    ///
+    /// The writes are merged into the layer below, if any, or written to the underlying database
+    /// in a single batch.
+    pub fn release(&mut self, id: SnapshotId) -> Result<()> {
+        if id >= self.layers.len() {
+            return Err(Errors::new("release(): unknown snapshot"));
+        }
+        let mut merged = HashMap::new();
+        for layer in self.layers.drain(id..) {
+            merged.extend(layer);
+        }
+        if let Some(below) = self.layers.last_mut() {
+            below.extend(merged);
+            return Ok(());
+        }
+        // writes join the batch open on the underlying database, if any
+        if !self.batch_on {
+            self.db.init_batch()?;
+        }
+        for (key, value) in merged {
+            match value {
+                Some(value) => self.db.put(&key, value)?,
+                None => self.db.delete(&key)?,
+            }
+        }
+        if self.batch_on {
+            return Ok(());
+        }
+        self.db.finish_batch()
+    }
+
+    /// Get the number of snapshots currently taken.
+    pub fn depth(&self) -> usize {
+        self.layers.len()
+    }
+}
+
+impl<D: Database> Database for SnapshotDB<D> {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(SnapshotDB::with_db(D::try_new(dbpath)?))
+    }
+
+    fn open_read_only(dbpath: &str) -> Result<Self> {
+        Ok(SnapshotDB::with_db(D::open_read_only(dbpath)?))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        for layer in self.layers.iter().rev() {
+            if let Some(value) = layer.get(key) {
+                return Ok(value.to_owned());
+            }
+        }
+        self.db.get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        match self.layers.last_mut() {
+            Some(layer) => {
+                layer.insert(key.to_vec(), Some(value));
+                Ok(())
+            }
+            None => self.db.put(key, value),
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        match self.layers.last_mut() {
+            Some(layer) => {
+                layer.insert(key.to_vec(), None);
+                Ok(())
+            }
+            None => self.db.delete(key),
+        }
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        if self.layers.is_empty() {
+            self.batch_on = true;
+            return self.db.init_batch();
+        }
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        if self.batch_on {
+            self.batch_on = false;
+            return self.db.finish_batch();
+        }
+        Ok(())
+    }
+
+    /// Size of the underlying database. Writes staged in layers are not counted.
+    fn total_size(&self) -> Result<u64> {
+        self.db.total_size()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.db.compact()
+    }
+
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        if self.layers.is_empty() {
+            return self.db.cas(key, expected, new);
+        }
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => self.put(key, value)?,
+            None => self.delete(key)?,
+        }
+        Ok(true)
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut keys: HashSet<Vec<u8>> = self.db.keys()?.into_iter().collect();
+        for layer in self.layers.iter() {
+            for (key, value) in layer.iter() {
+                match value {
+                    Some(_) => keys.insert(key.clone()),
+                    None => keys.remove(key),
+                };
+            }
+        }
+        Ok(keys.into_iter().collect())
+    }
+}
+
+/// A database shared by several trees, each keeping its keys under a namespace of its own,
+/// as opened by `Monotree::with_namespace()`.
+///
+/// Handles of other namespaces of the same database are taken by `namespace()`.
+/// Keys are prefixed with the length of the namespace as a varint, then the namespace,
+/// so that no key of a namespace is ever that of another.
+/// Writes within a batch are held by the handle, then written to the shared database
+/// in a single batch of its own by `finish_batch()`, so batches of trees never mix.
+///
+/// To commit updates of several trees at once, each handle `hold()`s the writes of its batches
+/// instead, until `commit_held()` writes those of all of them in a single batch,
+/// so that the database never has the updates of one tree without those of the others.
+pub struct NamespacedDB<D> {
+    db: Arc<Mutex<D>>,
+    prefix: Vec<u8>,
+    /// Writes of the batch open, where `None` stands for a deletion.
+    batch: Option<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+    /// Writes held since `hold()`, including those of batches finished, to be committed by `commit_held()`.
+    held: Option<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl<D: Database> NamespacedDB<D> {
+    pub fn with_db(db: D, namespace: &[u8]) -> Self {
+        NamespacedDB {
+            db: Arc::new(Mutex::new(db)),
+            prefix: [&int_to_varint(namespace.len() as u64)[..], namespace].concat(),
+            batch: None,
+            held: None,
+        }
+    }
+
+    /// Get a handle of the same database under another namespace.
+    pub fn namespace(&self, namespace: &[u8]) -> Self {
+        NamespacedDB {
+            db: Arc::clone(&self.db),
+            prefix: [&int_to_varint(namespace.len() as u64)[..], namespace].concat(),
+            batch: None,
+            held: None,
+        }
+    }
+
+    /// Hold all writes from now on, including those of batches finished, until `commit_held()`.
+    /// Reads of the handle observe them, while other handles observe none of them.
+    pub fn hold(&mut self) {
+        if self.held.is_none() {
+            self.held = Some(HashMap::new());
+        }
+    }
+
+    pub fn is_holding(&self) -> bool {
+        self.held.is_some()
+    }
+
+    /// Write all writes held by the given handles of the same database in a single batch,
+    /// then stop holding. If this fails, the writes held are discarded altogether.
+    pub fn commit_held(handles: &mut [&mut NamespacedDB<D>]) -> Result<()> {
+        let db = match handles.first() {
+            Some(handle) => Arc::clone(&handle.db),
+            None => return Ok(()),
+        };
+        if handles.iter().any(|handle| !Arc::ptr_eq(&handle.db, &db)) {
+            return Err(Errors::new(
+                "NamespacedDB::commit_held(): handles of other databases",
+            ));
+        }
+        let held: Vec<_> = handles
+            .iter_mut()
+            .map(|handle| (handle.prefix.clone(), handle.held.take()))
+            .collect();
+        let mut db = db
+            .lock()
+            .map_err(|_| Errors::new("NamespacedDB::lock(): poisoned"))?;
+        db.init_batch()?;
+        for (prefix, writes) in held {
+            Self::write(&mut *db, &prefix, writes.unwrap_or_default())?;
+        }
+        db.finish_batch()
+    }
+
+    fn write(db: &mut D, prefix: &[u8], writes: HashMap<Vec<u8>, Option<Vec<u8>>>) -> Result<()> {
+        for (key, value) in writes {
+            let key = [prefix, &key].concat();
+            match value {
+                Some(value) => db.put(&key, value)?,
+                None => db.delete(&key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the writes pending in the handle, from the oldest, where `None` stands for a deletion.
+    fn pending(&self) -> impl Iterator<Item = (&Vec<u8>, &Option<Vec<u8>>)> {
+        self.held.iter().chain(self.batch.iter()).flatten()
+    }
+
+    fn key(&self, key: &[u8]) -> Vec<u8> {
+        [&self.prefix[..], key].concat()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, D>> {
+        self.db
+            .lock()
+            .map_err(|_| Errors::new("NamespacedDB::lock(): poisoned"))
+    }
+}
+
+impl<D: Database> Database for NamespacedDB<D> {
+    fn try_new(_dbpath: &str) -> Result<Self> {
+        Err(Errors::new("NamespacedDB::try_new(): use with_db()"))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        for writes in self.batch.iter().chain(self.held.iter()) {
+            if let Some(value) = writes.get(key) {
+                return Ok(value.to_owned());
+            }
+        }
+        let key = self.key(key);
+        self.lock()?.get(&key)
+    }
+
+    fn get_many(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        if self.batch.is_some() || self.held.is_some() {
+            return keys.iter().map(|key| self.get(key)).collect();
+        }
+        let keys: Vec<Vec<u8>> = keys.iter().map(|key| self.key(key)).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|key| &key[..]).collect();
+        self.lock()?.get_many(&keys)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        match self.batch.as_mut().or(self.held.as_mut()) {
+            Some(writes) => {
+                writes.insert(key.to_vec(), Some(value));
+                Ok(())
+            }
+            None => {
+                let key = self.key(key);
+                self.lock()?.put(&key, value)
+            }
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        match self.batch.as_mut().or(self.held.as_mut()) {
+            Some(writes) => {
+                writes.insert(key.to_vec(), None);
+                Ok(())
+            }
+            None => {
+                let key = self.key(key);
+                self.lock()?.delete(&key)
+            }
+        }
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.batch = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        let batch = match self.batch.take() {
+            Some(batch) => batch,
+            None => return Ok(()),
+        };
+        if let Some(held) = &mut self.held {
+            held.extend(batch);
+            return Ok(());
+        }
+        let mut db = self.lock()?;
+        db.init_batch()?;
+        Self::write(&mut db, &self.prefix, batch)?;
+        db.finish_batch()
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        self.lock()?.total_size()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.lock()?.flush()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.lock()?.compact()
+    }
+
+    /// Compare and swap natively with the shared database, unless holding writes,
+    /// in which case the swap is held as well, as atomic as `commit_held()` is.
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        if self.held.is_some() {
+            if self.get(key)?.as_deref() != expected {
+                return Ok(false);
+            }
+            match new {
+                Some(value) => self.put(key, value)?,
+                None => self.delete(key)?,
+            }
+            return Ok(true);
+        }
+        let key = self.key(key);
+        self.lock()?.cas(&key, expected, new)
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        let keys = self.lock()?.keys()?;
+        let mut keys: HashSet<Vec<u8>> = keys
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.prefix[..]).map(|key| key.to_vec()))
+            .collect();
+        for (key, value) in self.pending() {
+            match value {
+                Some(_) => keys.insert(key.to_owned()),
+                None => keys.remove(key),
+            };
+        }
+        Ok(keys.into_iter().collect())
+    }
+}
+
+/// A database caching values of an underlying database in a `MemCache`,
+/// read through and written back.
+///
+/// Reads missing the cache fall through to the underlying database and fill the cache.
+/// Writes within a batch are held in the cache alone, pinned until `finish_batch()` writes them
+/// to the underlying database in a single batch of its own, so that a key written many times
+/// is written there once. Writes outside a batch are written through right away.
+pub struct CachedDb<D> {
+    db: D,
+    cache: MemCache,
+    batch_on: bool,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl<D: Database> CachedDb<D> {
+    pub fn with_db(db: D) -> Self {
+        CachedDb {
+            db,
+            cache: MemCache::new(),
+            batch_on: false,
+            metrics: None,
+        }
+    }
+
+    /// Set metrics receiving every lookup of the cache, whether it hit or missed.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Look up the cache, counting the lookup by the metrics, if set.
+    fn lookup(&mut self, key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let value = self.cache.lookup(key);
+        if let Some(metrics) = &self.metrics {
+            metrics.cache_lookup(value.is_some());
+        }
+        value
+    }
+
+    /// Set the capacity of the cache, `DEFAULT_CACHE_ENTRIES` and `DEFAULT_CACHE_BYTES`
+    /// by default. See `MemCache`.
+    pub fn set_cache_capacity(&mut self, entries: usize, bytes: usize) {
+        self.cache.set_capacity(entries, bytes);
+    }
+
+    /// Tune the capacity in bytes of the cache, see `MemCache::set_tuning()`.
+    pub fn set_cache_tuning(&mut self, tuning: Option<CacheTuning>) {
+        self.cache.set_tuning(tuning);
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Get the underlying database, e.g. to set options of the backend.
+    /// Writes made through it bypass the cache, which may serve stale values afterwards.
+    pub fn db_mut(&mut self) -> &mut D {
+        &mut self.db
+    }
+
+    /// Write the writes pinned in the cache to the underlying database in a single batch.
+    fn write_back(&mut self) -> Result<()> {
+        self.db.init_batch()?;
+        for key in self.cache.pending.iter() {
+            match self
+                .cache
+                .entries
+                .get(key)
+                .and_then(|entry| entry.value.as_ref())
+            {
+                Some(value) => self.db.put(key, value.to_owned())?,
+                None => self.db.delete(key)?,
+            }
+        }
+        self.db.finish_batch()
+    }
+}
+
+impl<D: Database> Database for CachedDb<D> {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(CachedDb::with_db(D::try_new(dbpath)?))
+    }
+
+    fn open_read_only(dbpath: &str) -> Result<Self> {
+        Ok(CachedDb::with_db(D::open_read_only(dbpath)?))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.lookup(key) {
+            return Ok(value);
+        }
+        let value = self.db.get(key)?;
+        if let Some(value) = value.as_ref() {
+            self.cache.fill(key, value.to_owned());
+        }
+        Ok(value)
+    }
+
+    /// Read the keys missing the cache with a single `get_many()` of the underlying database.
+    fn get_many(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut values = Vec::with_capacity(keys.len());
+        let mut missed = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            match self.lookup(key) {
+                Some(value) => values.push(value),
+                None => {
+                    values.push(None);
+                    missed.push(i);
+                }
+            }
+        }
+        if missed.is_empty() {
+            return Ok(values);
+        }
+        let misses: Vec<&[u8]> = missed.iter().map(|&i| keys[i]).collect();
+        for (i, value) in missed.into_iter().zip(self.db.get_many(&misses)?) {
+            if let Some(value) = value.as_ref() {
+                self.cache.fill(keys[i], value.to_owned());
+            }
+            values[i] = value;
+        }
+        Ok(values)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        if !self.batch_on {
+            self.db.put(key, value.to_owned())?;
+        }
+        self.cache.put(key, value)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if !self.batch_on {
+            self.db.delete(key)?;
+        }
+        self.cache.delete(key)
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.cache.init_batch();
+        self.batch_on = true;
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.batch_on = false;
+        if !self.cache.pending.is_empty() {
+            if let Err(err) = self.write_back() {
+                // the cache holds writes of the batch, which are not to be read any longer
+                self.cache.discard_batch();
+                return Err(err);
+            }
+        }
+        self.cache.finish_batch();
+        Ok(())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        self.db.total_size()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.db.compact()
+    }
+
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        self.cache.forget(key);
+        self.db.cas(key, expected, new)
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        self.db.keys()
+    }
+}
+
+/// Writes of a batch handed over to the flusher of `TieredDb`, or a request to flush
+/// the cold database once those handed over before are written, answered through the channel.
+enum Flush {
+    Writes(HashMap<Vec<u8>, Option<Vec<u8>>>),
+    Sync(mpsc::Sender<Result<()>>),
+}
+
+/// Write batches handed over to the cold database, in order, until all of them are written
+/// or one of them fails, which is then kept to be tried again.
+fn flush_in_background<Cold: Database>(
+    cold: Arc<Mutex<Cold>>,
+    failure: Arc<Mutex<Option<String>>>,
+    receiver: mpsc::Receiver<Flush>,
+) {
+    let mut backlog = VecDeque::new();
+    for flush in receiver {
+        let reply = match flush {
+            Flush::Writes(writes) => {
+                backlog.push_back(writes);
+                None
+            }
+            Flush::Sync(reply) => Some(reply),
+        };
+        let written = lock_cold(&cold).and_then(|mut cold| {
+            while let Some(writes) = backlog.front() {
+                cold.init_batch()?;
+                for (key, value) in writes.iter() {
+                    match value {
+                        Some(value) => cold.put(key, value.to_owned())?,
+                        None => cold.delete(key)?,
+                    }
+                }
+                cold.finish_batch()?;
+                backlog.pop_front();
+            }
+            Ok(cold)
+        });
+        match reply {
+            Some(reply) => {
+                let _ = reply.send(written.and_then(|mut cold| cold.flush()));
+            }
+            None => {
+                if let (Err(err), Ok(mut failure)) = (written, failure.lock()) {
+                    *failure = Some(err.to_string());
+                }
+            }
+        }
+    }
+}
+
+fn lock_cold<Cold>(cold: &Mutex<Cold>) -> Result<MutexGuard<'_, Cold>> {
+    cold.lock()
+        .map_err(|_| Errors::new("TieredDb::lock(): poisoned"))
+}
+
+/// A database writing to a hot database, such as `MemoryDB`, and flushing the writes
+/// to a cold one, such as `RocksDB`, in the background, so that bursts of writes never stall on disk.
+///
+/// Writes are applied to the hot database right away, then handed over batch by batch
+/// to a thread writing them to the cold database in batches of the same.
+/// Reads missing the hot database fall through to the cold one, such as those of entries
+/// written before the hot database was opened. Batches failing to be written are kept and tried
+/// again along with the next one, while the failure is returned by the next `finish_batch()`.
+/// `flush()` waits for every batch handed over to be written, then flushes the cold database,
+/// as dropping `TieredDb` waits for them to be written as well.
+///
+/// The hot database keeps every entry written through it, so it is meant for bursts
+/// between restarts rather than for the whole state of a long-running node.
+pub struct TieredDb<Hot, Cold> {
+    hot: Hot,
+    cold: Arc<Mutex<Cold>>,
+    /// Writes of the batch open, where `None` stands for a deletion.
+    batch: Option<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+    /// Keys deleted from the hot database, not to be read from the cold one any longer.
+    deleted: HashSet<Vec<u8>>,
+    sender: Option<mpsc::Sender<Flush>>,
+    flusher: Option<thread::JoinHandle<()>>,
+    /// Failure of the flusher not returned yet.
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl<Hot: Database, Cold: Database + Send + 'static> TieredDb<Hot, Cold> {
+    pub fn with_dbs(hot: Hot, cold: Cold) -> Self {
+        let cold = Arc::new(Mutex::new(cold));
+        let failure = Arc::new(Mutex::new(None));
+        let (sender, receiver) = mpsc::channel();
+        let flusher = {
+            let (cold, failure) = (Arc::clone(&cold), Arc::clone(&failure));
+            thread::spawn(move || flush_in_background(cold, failure, receiver))
+        };
+        TieredDb {
+            hot,
+            cold,
+            batch: None,
+            deleted: HashSet::new(),
+            sender: Some(sender),
+            flusher: Some(flusher),
+            failure,
+        }
+    }
+
+    /// Get the hot database, e.g. to set options of the backend.
+    /// Writes made through it are never flushed to the cold database.
+    pub fn hot_mut(&mut self) -> &mut Hot {
+        &mut self.hot
+    }
+
+    fn send(&mut self, flush: Flush) -> Result<()> {
+        self.sender
+            .as_ref()
+            .and_then(|sender| sender.send(flush).ok())
+            .ok_or_else(|| Errors::Db("TieredDb::send(): flusher gone".to_string()))
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+        match &value {
+            Some(value) => self.hot.put(key, value.to_owned())?,
+            None => self.hot.delete(key)?,
+        }
+        let mut writes = HashMap::new();
+        writes.insert(key.to_vec(), value);
+        match self.batch.as_mut() {
+            Some(batch) => {
+                batch.extend(writes);
+                Ok(())
+            }
+            None => self.hand_over(writes),
+        }
+    }
+
+    /// Hand over writes committed to the hot database to the flusher.
+    fn hand_over(&mut self, writes: HashMap<Vec<u8>, Option<Vec<u8>>>) -> Result<()> {
+        for (key, value) in writes.iter() {
+            match value {
+                Some(_) => self.deleted.remove(key),
+                None => self.deleted.insert(key.to_owned()),
+            };
+        }
+        self.send(Flush::Writes(writes))
+    }
+}
+
+impl<Hot: Database, Cold: Database + Send + 'static> Database for TieredDb<Hot, Cold> {
+    /// Open the hot database at `dbpath/hot` and the cold one at `dbpath/cold`.
+    fn try_new(dbpath: &str) -> Result<Self> {
+        if !dbpath.is_empty() {
+            fs::create_dir_all(dbpath)?;
+        }
+        Ok(TieredDb::with_dbs(
+            Hot::try_new(&format!("{}/hot", dbpath))?,
+            Cold::try_new(&format!("{}/cold", dbpath))?,
+        ))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.batch.as_ref().and_then(|batch| batch.get(key)) {
+            return Ok(value.to_owned());
+        }
+        if let Some(value) = self.hot.get(key)? {
+            return Ok(Some(value));
+        }
+        if self.deleted.contains(key) {
+            return Ok(None);
+        }
+        lock_cold(&self.cold)?.get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.write(key, Some(value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.write(key, None)
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.hot.init_batch()?;
+        self.batch = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        let writes = self.batch.take().unwrap_or_default();
+        self.hot.finish_batch()?;
+        if !writes.is_empty() {
+            self.hand_over(writes)?;
+        }
+        match self
+            .failure
+            .lock()
+            .ok()
+            .and_then(|mut failure| failure.take())
+        {
+            Some(msg) => Err(Errors::Db(format!("TieredDb::finish_batch(): {}", msg))),
+            None => Ok(()),
+        }
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        lock_cold(&self.cold)?.total_size()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.hot.flush()?;
+        let (reply, received) = mpsc::channel();
+        self.send(Flush::Sync(reply))?;
+        received
+            .recv()
+            .map_err(|_| Errors::Db("TieredDb::flush(): flusher gone".to_string()))?
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        self.hot.compact()?;
+        lock_cold(&self.cold)?.compact()
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut keys: HashSet<Vec<u8>> = lock_cold(&self.cold)?.keys()?.into_iter().collect();
+        keys.retain(|key| !self.deleted.contains(key));
+        keys.extend(self.hot.keys()?);
+        Ok(keys.into_iter().collect())
+    }
+}
+
+impl<Hot, Cold> Drop for TieredDb<Hot, Cold> {
+    /// Wait for the batches handed over to be written to the cold database.
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+    }
+}
+
+/// Faults `FaultyDB` injects, each by the probability of an operation suffering it.
+#[cfg(feature = "testing")]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Faults {
+    /// Probability of an operation failing with `Errors::Db` before reaching the database.
+    pub error_rate: f64,
+    /// Delay added to every operation.
+    pub latency: Duration,
+    /// Probability of `finish_batch()` succeeding with none of the writes of the batch written.
+    pub drop_rate: f64,
+    /// Probability of `finish_batch()` writing only a leading part of the writes of the batch,
+    /// then failing.
+    pub tear_rate: f64,
+}
+
+/// A database injecting `Faults` into an underlying database, for applications to test how
+/// they recover from storage misbehaving.
+///
+/// Writes of a batch are held until `finish_batch()`, which drops or tears them as configured.
+/// Faults are drawn from a generator seeded by `set_seed()`, so that a failing run is repeated.
+#[cfg(feature = "testing")]
+pub struct FaultyDB<D> {
+    db: D,
+    faults: Faults,
+    rng: StdRng,
+    /// Writes of the open batch in order, where `None` stands for a deletion.
+    batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// Index of the last write of each key in `batch`.
+    written: HashMap<Vec<u8>, usize>,
+    batch_on: bool,
+    injected: u64,
+}
+
+#[cfg(feature = "testing")]
+impl<D: Database> FaultyDB<D> {
+    pub fn with_db(db: D, faults: Faults) -> Self {
+        FaultyDB {
+            db,
+            faults,
+            rng: StdRng::from_entropy(),
+            batch: Vec::new(),
+            written: HashMap::new(),
+            batch_on: false,
+            injected: 0,
+        }
+    }
+
+    /// Change the faults injected from now on, e.g. `Faults::default()` to heal the database.
+    pub fn set_faults(&mut self, faults: Faults) {
+        self.faults = faults;
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Get the number of faults injected so far.
+    pub fn injected(&self) -> u64 {
+        self.injected
+    }
+
+    /// Get the underlying database, e.g. to check what reached it.
+    pub fn db_mut(&mut self) -> &mut D {
+        &mut self.db
+    }
+
+    fn strike(&mut self, rate: f64) -> bool {
+        let struck = rate > 0.0 && self.rng.gen::<f64>() < rate;
+        if struck {
+            self.injected += 1;
+        }
+        struck
+    }
+
+    /// Delay the operation, then fail it by the error rate.
+    fn enter(&mut self, op: &str) -> Result<()> {
+        if self.faults.latency > Duration::default() {
+            std::thread::sleep(self.faults.latency);
+        }
+        if self.strike(self.faults.error_rate) {
+            return Err(Errors::Db(format!("faulty: {}() failed", op)));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+        if !self.batch_on {
+            return match value {
+                Some(value) => self.db.put(key, value),
+                None => self.db.delete(key),
+            };
+        }
+        let key = key.to_vec();
+        self.written.insert(key.clone(), self.batch.len());
+        self.batch.push((key, value));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "testing")]
+impl<D: Database> Database for FaultyDB<D> {
+    /// Open the underlying database without faults, until `set_faults()`.
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(FaultyDB::with_db(D::try_new(dbpath)?, Faults::default()))
+    }
+
+    fn open_read_only(dbpath: &str) -> Result<Self> {
+        Ok(FaultyDB::with_db(
+            D::open_read_only(dbpath)?,
+            Faults::default(),
+        ))
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.enter("get")?;
+        match self.written.get(key) {
+            Some(&i) => Ok(self.batch[i].1.to_owned()),
+            None => self.db.get(key),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.enter("put")?;
+        self.write(key, Some(value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.enter("delete")?;
+        self.write(key, None)
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.batch.clear();
+        self.written.clear();
+        self.batch_on = true;
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.batch_on = false;
+        self.written.clear();
+        let batch = std::mem::take(&mut self.batch);
+        self.enter("finish_batch")?;
+        if self.strike(self.faults.drop_rate) {
+            return Ok(());
+        }
+        let torn = self.strike(self.faults.tear_rate);
+        let n = match torn {
+            true => self.rng.gen_range(0, batch.len().max(1)),
+            false => batch.len(),
+        };
+        self.db.init_batch()?;
+        for (key, value) in batch.into_iter().take(n) {
+            match value {
+                Some(value) => self.db.put(&key, value)?,
+                None => self.db.delete(&key)?,
+            }
+        }
+        self.db.finish_batch()?;
+        if torn {
+            return Err(Errors::Db("faulty: finish_batch() torn".to_string()));
+        }
+        Ok(())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        self.db.total_size()
     }
 
-    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        match self.db.get(key) {
-            Some(v) => Ok(Some(v.to_owned())),
-            None => Ok(None),
-        }
+    fn flush(&mut self) -> Result<()> {
+        self.enter("flush")?;
+        self.db.flush()
     }
 
-    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.db.insert(slice_to_hash(key), value);
-        Ok(())
+    fn compact(&mut self) -> Result<()> {
+        self.enter("compact")?;
+        self.db.compact()
     }
 
-    fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.db.remove(key);
-        Ok(())
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        self.enter("cas")?;
+        self.db.cas(key, expected, new)
     }
 
-    fn init_batch(&mut self) -> Result<()> {
-        Ok(())
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        self.enter("keys")?;
+        self.db.keys()
     }
 
-    fn finish_batch(&mut self) -> Result<()> {
-        Ok(())
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        self.enter("iter_prefix")?;
+        self.db.iter_prefix(prefix)
     }
 }
 
 #[cfg(feature = "db-rocks")]
 /// A database using rust wrapper for `RocksDB`.
 pub struct RocksDB {
-    db: Arc<Mutex<DB>>,
+    db: Arc<DB>,
     batch: WriteBatch,
-    cache: MemCache,
+    /// Writes pending in the batch, where `None` stands for a deletion, read back until committed.
+    pending: HashMap<Vec<u8>, Option<Vec<u8>>>,
     batch_on: bool,
+    durability: Durability,
+    clock: FlushClock,
+    bulk_load: bool,
+    /// Name of the column family the tree is kept in, or `None` for the default one.
+    cf: Option<String>,
+    read_only: bool,
 }
 #[cfg(feature = "db-rocks")]
 impl From<rocksdb::Error> for Errors {
     fn from(err: rocksdb::Error) -> Self {
-        Errors::new(&err.to_string())
+        Errors::Db(err.to_string())
     }
 }
 #[cfg(feature = "db-rocks")]
-impl Database for RocksDB {
-    fn new(dbpath: &str) -> Self {
-        let db = Arc::new(Mutex::new(
-            DB::open_default(Path::new(dbpath)).expect("new(): rocksdb"),
-        ));
+impl RocksDB {
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Turn on/off the bulk-load mode, intended for the initial construction of a large tree.
+    ///
+    /// In this mode, batches skip the write-ahead log and
+    /// the memtables are flushed into SST files right after every `finish_batch()`.
+    pub fn set_bulk_load(&mut self, bulk_load: bool) {
+        self.bulk_load = bulk_load;
+    }
+
+    /// Open the database at the given path with the given options, instead of the defaults
+    /// `try_new()` opens it with, e.g. to set the block cache, bloom filters or compression.
+    pub fn with_options(dbpath: &str, opts: Options) -> Result<Self> {
+        Ok(Self::with_db(DB::open(&opts, Path::new(dbpath))?))
+    }
+
+    /// Open the database at the given path, keeping the tree in the named column family,
+    /// so that it lives along with other data of an application in the same database.
+    ///
+    /// The database and the column family are created if missing, and every other column family
+    /// of the database is opened as well. As this version of `RocksDB` flushes the memtables
+    /// of the default column family only, batches keep to the write-ahead log even in
+    /// the bulk-load mode, and `flush()` syncs the log instead.
+    pub fn with_cf(dbpath: &str, name: &str) -> Result<Self> {
+        Self::with_cf_options(dbpath, name, Options::default())
+    }
+
+    /// The same as `with_cf()`, but with the given options for the column family of the tree.
+    /// Other column families and the database itself are opened with the defaults.
+    pub fn with_cf_options(dbpath: &str, name: &str, opts: Options) -> Result<Self> {
+        let mut db_opts = Options::default();
+        db_opts.create_if_missing(true);
+        db_opts.create_missing_column_families(true);
+        let cfs = DB::list_cf(&db_opts, dbpath).unwrap_or_default();
+        let mut descriptors: Vec<_> = cfs
+            .iter()
+            .filter(|cf| *cf != name)
+            .map(|cf| ColumnFamilyDescriptor::new(cf.as_str(), Options::default()))
+            .collect();
+        descriptors.push(ColumnFamilyDescriptor::new(name, opts));
+        let db = DB::open_cf_descriptors(&db_opts, Path::new(dbpath), descriptors)?;
+        Self::from_db_cf(Arc::new(db), name)
+    }
+
+    /// The same as `from_db()`, but keeping the tree in the named column family,
+    /// which the application has to have created.
+    pub fn from_db_cf(db: Arc<DB>, name: &str) -> Result<Self> {
+        if db.cf_handle(name).is_none() {
+            return Err(Errors::Db(format!("rocksdb: no column family {}", name)));
+        }
+        Ok(RocksDB {
+            cf: Some(name.to_string()),
+            ..Self::from_db(db)
+        })
+    }
+
+    fn with_db(db: DB) -> Self {
+        Self::from_db(Arc::new(db))
+    }
+
+    /// Construct `RocksDB` over a database the application has opened already,
+    /// keeping the tree in the default column family, so that no other instance is opened.
+    pub fn from_db(db: Arc<DB>) -> Self {
         RocksDB {
             db,
             batch: WriteBatch::default(),
-            cache: MemCache::new(),
+            pending: HashMap::new(),
             batch_on: false,
+            durability: Durability::default(),
+            clock: FlushClock::default(),
+            bulk_load: false,
+            cf: None,
+            read_only: false,
         }
     }
 
-    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if self.cache.contains(key) {
-            return self.cache.get(key);
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Errors::Db("rocksdb: opened read-only".to_string()));
         }
-        let db = self.db.lock().expect("get(): rocksdb");
-        match db.get(key)? {
-            Some(value) => {
-                self.cache.put(key, value.to_owned())?;
-                Ok(Some(value))
-            }
+        Ok(())
+    }
+
+    /// Get the handle of the column family the tree is kept in, or `None` for the default one.
+    fn column<'a>(&self, db: &'a DB) -> Result<Option<&'a ColumnFamily>> {
+        match &self.cf {
             None => Ok(None),
+            Some(name) => match db.cf_handle(name) {
+                Some(cf) => Ok(Some(cf)),
+                None => Err(Errors::Db(format!("rocksdb: no column family {}", name))),
+            },
+        }
+    }
+}
+#[cfg(feature = "db-rocks")]
+impl Database for RocksDB {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(Self::with_db(DB::open_default(Path::new(dbpath))?))
+    }
+
+    /// As this version of `RocksDB` has no read-only mode of its own, writes are refused here,
+    /// but the database is still locked against other processes, a writer included.
+    fn open_read_only(dbpath: &str) -> Result<Self> {
+        let db = DB::open(&Options::default(), Path::new(dbpath))?;
+        Ok(RocksDB {
+            read_only: true,
+            ..Self::with_db(db)
+        })
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.pending.get(key) {
+            return Ok(value.to_owned());
+        }
+        let db = Arc::clone(&self.db);
+        match self.column(&db)? {
+            Some(cf) => Ok(db.get_cf(cf, key)?),
+            None => Ok(db.get(key)?),
         }
     }
 
     fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.cache.put(key, value.to_owned())?;
+        self.check_writable()?;
         if self.batch_on {
-            Ok(self.batch.put(key, value)?)
-        } else {
-            let db = self.db.lock().expect("put(): rocksdb");
-            Ok(db.put(key, value)?)
+            self.pending.insert(key.to_vec(), Some(value.to_owned()));
+        }
+        let db = Arc::clone(&self.db);
+        match (self.column(&db)?, self.batch_on) {
+            (Some(cf), true) => Ok(self.batch.put_cf(cf, key, value)?),
+            (Some(cf), false) => Ok(db.put_cf(cf, key, value)?),
+            (None, true) => Ok(self.batch.put(key, value)?),
+            (None, false) => Ok(db.put(key, value)?),
         }
     }
 
     fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.cache.delete(key)?;
+        self.check_writable()?;
         if self.batch_on {
-            Ok(self.batch.delete(key)?)
-        } else {
-            let db = self.db.lock().expect("remove(): rocksdb");
-            Ok(db.delete(key)?)
+            self.pending.insert(key.to_vec(), None);
+        }
+        let db = Arc::clone(&self.db);
+        match (self.column(&db)?, self.batch_on) {
+            (Some(cf), true) => Ok(self.batch.delete_cf(cf, key)?),
+            (Some(cf), false) => Ok(db.delete_cf(cf, key)?),
+            (None, true) => Ok(self.batch.delete(key)?),
+            (None, false) => Ok(db.delete(key)?),
         }
     }
 
     fn init_batch(&mut self) -> Result<()> {
         self.batch = WriteBatch::default();
-        self.cache.clear();
+        self.pending.clear();
         self.batch_on = true;
         Ok(())
     }
 
     fn finish_batch(&mut self) -> Result<()> {
         self.batch_on = false;
+        self.pending.clear();
         if !self.batch.is_empty() {
             let batch = std::mem::take(&mut self.batch);
-            let db = self.db.lock().expect("write_batch(): rocksdb");
-            db.write(batch)?;
+            let db = Arc::clone(&self.db);
+            if self.bulk_load && self.cf.is_none() {
+                let mut opts = WriteOptions::default();
+                opts.disable_wal(true);
+                db.write_opt(batch, &opts)?;
+                db.flush()?;
+            } else if self.durability == Durability::Sync {
+                let mut opts = WriteOptions::default();
+                opts.set_sync(true);
+                db.write_opt(batch, &opts)?;
+            } else {
+                db.write(batch)?;
+            }
+        }
+        if self.clock.batch_finished(self.durability) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Sum of the SST files and memtables, as reported by `RocksDB` properties.
+    /// With a column family, only those of the column family are counted.
+    fn total_size(&self) -> Result<u64> {
+        let db = Arc::clone(&self.db);
+        let (sst, mem) = match self.column(&db)? {
+            Some(cf) => (
+                db.property_int_value_cf(cf, "rocksdb.total-sst-files-size")?,
+                db.property_int_value_cf(cf, "rocksdb.size-all-mem-tables")?,
+            ),
+            None => (
+                db.property_int_value("rocksdb.total-sst-files-size")?,
+                db.property_int_value("rocksdb.size-all-mem-tables")?,
+            ),
+        };
+        Ok(sst.unwrap_or(0) + mem.unwrap_or(0))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        if self.cf.is_some() {
+            let mut opts = WriteOptions::default();
+            opts.set_sync(true);
+            return Ok(db.write_opt(WriteBatch::default(), &opts)?);
+        }
+        Ok(db.flush()?)
+    }
+
+    /// Compact the whole key range, of the column family of the tree if any,
+    /// so that SST files holding entries deleted are rewritten without them.
+    fn compact(&mut self) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        match self.column(&db)? {
+            Some(cf) => db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>),
+            None => db.compact_range(None::<&[u8]>, None::<&[u8]>),
+        }
+        Ok(())
+    }
+
+    /// Compare and swap by a read and a write of the database.
+    /// As `RocksDB` is opened by a single process at a time, this is atomic against others,
+    /// but handles sharing a database by `from_db()` are not to swap the same key concurrently.
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        self.check_writable()?;
+        let db = Arc::clone(&self.db);
+        let cf = self.column(&db)?;
+        let current = match cf {
+            Some(cf) => db.get_cf(cf, key)?,
+            None => db.get(key)?,
+        };
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        match (cf, new) {
+            (Some(cf), Some(value)) => db.put_cf(cf, key, value)?,
+            (Some(cf), None) => db.delete_cf(cf, key)?,
+            (None, Some(value)) => db.put(key, value)?,
+            (None, None) => db.delete(key)?,
+        }
+        Ok(true)
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        let db = Arc::clone(&self.db);
+        let iter = match self.column(&db)? {
+            Some(cf) => db.iterator_cf(cf, IteratorMode::Start)?,
+            None => db.iterator(IteratorMode::Start),
+        };
+        let keys = iter.map(|(key, _)| key.to_vec()).collect();
+        Ok(keys)
+    }
+
+    /// Scan the range of keys from `prefix` on, a page of entries at a time.
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        Ok(Box::new(RocksPrefix {
+            db: Arc::clone(&self.db),
+            cf: self.cf.clone(),
+            prefix: prefix.to_vec(),
+            from: Some(prefix.to_vec()),
+            page: VecDeque::new(),
+        }))
+    }
+}
+
+/// Number of entries `RocksDB::iter_prefix()` reads at a time.
+#[cfg(feature = "db-rocks")]
+const ROCKS_SCAN_PAGE: usize = 1024;
+
+/// Entries of `RocksDB` under a prefix, read a page at a time by an iterator of its own,
+/// so that neither the whole range is loaded nor the database is borrowed in between.
+#[cfg(feature = "db-rocks")]
+struct RocksPrefix {
+    db: Arc<DB>,
+    cf: Option<String>,
+    prefix: Vec<u8>,
+    /// Key the next page starts from, or `None` once the range is read up.
+    from: Option<Vec<u8>>,
+    page: VecDeque<(Vec<u8>, Vec<u8>)>,
+}
+
+#[cfg(feature = "db-rocks")]
+impl RocksPrefix {
+    fn fill(&mut self) -> Result<()> {
+        let from = match self.from.take() {
+            None => return Ok(()),
+            Some(from) => from,
+        };
+        let mode = IteratorMode::From(&from, Direction::Forward);
+        let iter = match &self.cf {
+            Some(name) => match self.db.cf_handle(name) {
+                Some(cf) => self.db.iterator_cf(cf, mode)?,
+                None => return Err(Errors::Db(format!("rocksdb: no column family {}", name))),
+            },
+            None => self.db.iterator(mode),
+        };
+        for (key, value) in iter {
+            if !key.starts_with(&self.prefix) {
+                break;
+            }
+            if self.page.len() == ROCKS_SCAN_PAGE {
+                self.from = Some(key.to_vec());
+                break;
+            }
+            self.page.push_back((key.to_vec(), value.to_vec()));
         }
         Ok(())
     }
 }
 
+#[cfg(feature = "db-rocks")]
+impl Iterator for RocksPrefix {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() {
+            if let Err(err) = self.fill() {
+                return Some(Err(err));
+            }
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
+
 #[cfg(feature = "db-sled")]
 /// A database using `Sled`, a pure-rust-implmented DB.
 pub struct Sled {
-    db: sled::Db,
+    /// The database, or `None` for a tree given alone by `from_tree()`.
+    db: Option<sled::Db>,
+    /// The tree of the database entries are kept in, its default tree unless given.
+    tree: sled::Tree,
     batch: sled::Batch,
-    cache: MemCache,
+    /// Writes pending in the batch, where `None` stands for a deletion, read back until committed.
+    pending: HashMap<Vec<u8>, Option<Vec<u8>>>,
     batch_on: bool,
+    durability: Durability,
+    clock: FlushClock,
+    read_only: bool,
 }
 #[cfg(feature = "db-sled")]
 impl From<sled::Error> for Errors {
     fn from(err: sled::Error) -> Self {
-        Errors::new(&err.to_string())
+        Errors::Db(err.to_string())
     }
 }
 #[cfg(feature = "db-sled")]
 impl Sled {
-    pub fn flush(&self) -> Result<()> {
-        self.db.flush()?;
+    /// Construct `Sled` over a database the application has opened already,
+    /// such as a clone of its handle, so that no other instance is opened.
+    pub fn from_db(db: sled::Db) -> Self {
+        let tree = sled::Tree::clone(&db);
+        Sled {
+            db: Some(db),
+            ..Self::from_tree(tree)
+        }
+    }
+
+    /// Construct `Sled` over a tree of a database, such as one opened by `sled::Db::open_tree()`,
+    /// so that it lives along with other trees of an application in the same database.
+    ///
+    /// Without the database, `total_size()` sums up the entries of the tree instead.
+    pub fn from_tree(tree: sled::Tree) -> Self {
+        Sled {
+            db: None,
+            tree,
+            batch: sled::Batch::default(),
+            pending: HashMap::new(),
+            batch_on: false,
+            durability: Durability::default(),
+            clock: FlushClock::default(),
+            read_only: false,
+        }
+    }
+
+    /// Open the database by the given config, instead of the defaults `try_new()` opens it with,
+    /// e.g. to set the cache capacity, compression, the flush interval or the temporary mode.
+    pub fn with_config(config: sled::Config) -> Result<Self> {
+        Ok(Self::from_db(config.open()?))
+    }
+
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Errors::Db("sled: opened read-only".to_string()));
+        }
         Ok(())
     }
 }
 #[cfg(feature = "db-sled")]
 impl Database for Sled {
-    fn new(dbpath: &str) -> Self {
-        let db = sled::open(dbpath).expect("new(): sledDB");
-        Sled {
-            db,
-            batch: sled::Batch::default(),
-            cache: MemCache::new(),
-            batch_on: false,
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(Self::from_db(sled::open(dbpath)?))
+    }
+
+    /// As the read-only mode of this version of `Sled` fails to open files, writes are refused
+    /// here, but the database is still locked against other processes, a writer included.
+    fn open_read_only(dbpath: &str) -> Result<Self> {
+        if !Path::new(dbpath).exists() {
+            return Err(Errors::Db(format!("sled: no database at {}", dbpath)));
         }
+        let db = sled::open(dbpath)?;
+        Ok(Sled {
+            read_only: true,
+            ..Self::from_db(db)
+        })
     }
 
     fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        if self.cache.contains(key) {
-            return self.cache.get(key);
-        }
-        match self.db.get(key)? {
-            Some(value) => {
-                self.cache.put(key, value.to_vec())?;
-                Ok(Some(value.to_vec()))
-            }
-            None => Ok(None),
+        if let Some(value) = self.pending.get(key) {
+            return Ok(value.to_owned());
         }
+        Ok(self.tree.get(key)?.map(|value| value.to_vec()))
     }
 
     fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
-        self.cache.put(key, value.to_owned())?;
+        self.check_writable()?;
         if self.batch_on {
+            self.pending.insert(key.to_vec(), Some(value.to_owned()));
             self.batch.insert(key, value);
         } else {
-            self.db.insert(key, value)?;
+            self.tree.insert(key, value)?;
         }
         Ok(())
     }
 
     fn delete(&mut self, key: &[u8]) -> Result<()> {
-        self.cache.delete(key)?;
+        self.check_writable()?;
         if self.batch_on {
+            self.pending.insert(key.to_vec(), None);
             self.batch.remove(key);
         } else {
-            self.db.remove(key)?;
+            self.tree.remove(key)?;
         }
         Ok(())
     }
 
     fn init_batch(&mut self) -> Result<()> {
         self.batch = sled::Batch::default();
-        self.cache.clear();
+        self.pending.clear();
+        self.batch_on = true;
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.batch_on = false;
+        self.pending.clear();
+        let batch = std::mem::take(&mut self.batch);
+        self.tree.apply_batch(batch)?;
+        // `sled` persists by flushing only, so syncing is the same as flushing
+        let due = self.clock.batch_finished(self.durability);
+        if due || self.durability == Durability::Sync {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        match &self.db {
+            Some(db) => Ok(db.size_on_disk()?),
+            None => {
+                let mut size = 0;
+                for entry in self.tree.iter() {
+                    let (key, value) = entry?;
+                    size += (key.len() + value.len()) as u64;
+                }
+                Ok(size)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// `sled` reclaims the segments of entries deleted once they are flushed,
+    /// so compacting is the same as flushing.
+    fn compact(&mut self) -> Result<()> {
+        self.flush()
+    }
+
+    /// Compare and swap natively with `sled`, which is atomic against every handle.
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        self.check_writable()?;
+        Ok(self.tree.compare_and_swap(key, expected, new)?.is_ok())
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        for key in self.tree.iter().keys() {
+            keys.push(key?.to_vec());
+        }
+        Ok(keys)
+    }
+
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        Ok(Box::new(self.tree.scan_prefix(prefix).map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.to_vec(), value.to_vec()))
+        })))
+    }
+}
+
+#[cfg(feature = "db-redis")]
+/// A database using `Redis` by the `redis` crate, so that multiple service instances
+/// can share a single tree store.
+///
+/// The database path is the address of the server, `redis://host:port/db` or `host:port`.
+/// Writes within a batch are kept in memory and sent at once in `MULTI`/`EXEC`,
+/// so that other instances never see a batch partially applied. Reads are not cached,
+/// as values such as the root recorded by `Monotree::swap_root()` may change by others.
+pub struct Redis {
+    // behind a lock for `total_size()` to query the server by a shared reference
+    connection: Mutex<redis::Connection>,
+    batch: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    batch_on: bool,
+}
+#[cfg(feature = "db-redis")]
+impl Redis {
+    /// Open the database as `try_new()`, but failing with `Errors::Timeout` when connecting
+    /// or any request to the server is not answered within `timeout`, instead of waiting on
+    /// a hung connection forever.
+    pub fn with_timeout(dbpath: &str, timeout: Duration) -> Result<Self> {
+        Self::open(dbpath, Some(timeout))
+    }
+
+    fn open(dbpath: &str, timeout: Option<Duration>) -> Result<Self> {
+        let url = match dbpath.starts_with("redis://") {
+            true => dbpath.to_string(),
+            false => format!("redis://{}", dbpath),
+        };
+        let client = redis::Client::open(url)?;
+        let connection = match timeout {
+            Some(timeout) => client.get_connection_with_timeout(timeout)?,
+            None => client.get_connection()?,
+        };
+        connection.set_read_timeout(timeout)?;
+        connection.set_write_timeout(timeout)?;
+        Ok(Redis {
+            connection: Mutex::new(connection),
+            batch: HashMap::new(),
+            batch_on: false,
+        })
+    }
+
+    fn connection(&mut self) -> &mut redis::Connection {
+        self.connection
+            .get_mut()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn write_command(pipe: &mut redis::Pipeline, key: &[u8], value: &Option<Vec<u8>>) {
+        match value {
+            Some(value) => pipe.set(key, value).ignore(),
+            None => pipe.del(key).ignore(),
+        };
+    }
+}
+#[cfg(feature = "db-redis")]
+impl Database for Redis {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Self::open(dbpath, None)
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.batch.get(key) {
+            return Ok(value.to_owned());
+        }
+        Ok(self.connection().get(key)?)
+    }
+
+    /// Read keys not pending in the batch by a single `MGET`.
+    fn get_many(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let unread: Vec<&[u8]> = keys
+            .iter()
+            .filter(|key| !self.batch.contains_key(**key))
+            .cloned()
+            .collect();
+        let mut read = match unread.is_empty() {
+            true => Vec::new().into_iter(),
+            false => redis::cmd("MGET")
+                .arg(&unread)
+                .query::<Vec<Option<Vec<u8>>>>(self.connection())?
+                .into_iter(),
+        };
+        keys.iter()
+            .map(|key| match self.batch.get(*key) {
+                Some(value) => Ok(value.to_owned()),
+                None => read
+                    .next()
+                    .ok_or_else(|| Errors::Db("redis: too few values of MGET".to_string())),
+            })
+            .collect()
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        if self.batch_on {
+            self.batch.insert(key.to_vec(), Some(value));
+            return Ok(());
+        }
+        Ok(self.connection().set(key, value)?)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if self.batch_on {
+            self.batch.insert(key.to_vec(), None);
+            return Ok(());
+        }
+        Ok(self.connection().del(key)?)
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.batch.clear();
         self.batch_on = true;
         Ok(())
     }
 
     fn finish_batch(&mut self) -> Result<()> {
         self.batch_on = false;
+        if self.batch.is_empty() {
+            return Ok(());
+        }
         let batch = std::mem::take(&mut self.batch);
-        self.db.apply_batch(batch)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (key, value) in batch.iter() {
+            Self::write_command(&mut pipe, key, value);
+        }
+        Ok(pipe.query(self.connection())?)
+    }
+
+    /// Memory used by the whole server, as reported by `INFO memory`.
+    fn total_size(&self) -> Result<u64> {
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        let info: redis::InfoDict = redis::cmd("INFO").arg("memory").query(&mut *connection)?;
+        info.get("used_memory")
+            .ok_or_else(|| Errors::Db("redis: no used_memory in INFO".to_string()))
+    }
+
+    /// Compare and swap with `WATCH` and `MULTI`/`EXEC`, which is atomic against every instance.
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        let connection = self.connection();
+        redis::cmd("WATCH").arg(key).query::<()>(connection)?;
+        let current: Option<Vec<u8>> = connection.get(key)?;
+        if current.as_deref() != expected {
+            redis::cmd("UNWATCH").query::<()>(connection)?;
+            return Ok(false);
+        }
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        Self::write_command(&mut pipe, key, &new);
+        // `EXEC` replies nil if the key changed since `WATCH`
+        let applied: Option<()> = pipe.query(connection)?;
+        Ok(applied.is_some())
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        let keys = self.connection().scan()?;
+        Ok(keys.collect::<redis::RedisResult<_>>()?)
+    }
+}
+
+#[cfg(feature = "db-object-store")]
+/// A database keeping entries as objects of an S3-compatible object store by the `object_store`
+/// crate, such as S3, GCS by its interoperability API or MinIO, to archive old roots
+/// and serve their proofs cheaply, e.g. as the cold database of `TieredDb`.
+///
+/// The database path is the endpoint and the bucket, `https://host:port/bucket` or over plain
+/// `http://`, optionally followed by a prefix of object names, each entry being an object named
+/// by the prefix and its key in hex. Requests are signed by AWS Signature Version 4 with the
+/// credentials given to `with_credentials()`, or for `try_new()` those of `AWS_ACCESS_KEY_ID`,
+/// `AWS_SECRET_ACCESS_KEY` and `AWS_REGION` in the environment, or sent unsigned without them.
+///
+/// Values read are cached in a `MemCache`, as nodes never change under their hashes.
+/// Writes within a batch are kept in memory and sent by `finish_batch()`, the objects written
+/// first and concurrently, then the ones deleted, as object stores have no atomic batches.
+/// A batch failing midway leaves some of its objects written, but none deleted: those are nodes
+/// under their own hashes no root recorded refers to yet, removed by `Monotree::prune()`,
+/// so that the batch is retried as a whole by inserting the same entries again.
+/// With a timeout given to `with_timeout()`, each request not answered in time fails
+/// with `Errors::Timeout`, leaving a batch partially written the same way.
+pub struct ObjectStoreDb {
+    store: AmazonS3,
+    prefix: ObjectPath,
+    runtime: tokio_rt::runtime::Runtime,
+    timeout: Option<Duration>,
+    cache: MemCache,
+    batch: HashMap<Vec<u8>, Option<Vec<u8>>>,
+    batch_on: bool,
+}
+
+#[cfg(feature = "db-object-store")]
+/// Credentials signing requests of `ObjectStoreDb`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Credentials {
+    pub access_key: String,
+    pub secret_key: String,
+    /// Region of the bucket, such as `us-east-1`, which is also taken by most other stores.
+    pub region: String,
+}
+
+#[cfg(feature = "db-object-store")]
+impl Credentials {
+    /// Read credentials from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_REGION`,
+    /// the region `us-east-1` if unset, or `None` without keys.
+    pub fn from_env() -> Option<Self> {
+        let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+        Some(Credentials {
+            access_key: var("AWS_ACCESS_KEY_ID")?,
+            secret_key: var("AWS_SECRET_ACCESS_KEY")?,
+            region: var("AWS_REGION").unwrap_or_else(|| "us-east-1".to_string()),
+        })
+    }
+}
+
+/// Number of requests `ObjectStoreDb` sends at once when finishing a batch.
+#[cfg(feature = "db-object-store")]
+const CONCURRENT_REQUESTS: usize = 16;
+
+/// Await a request of `ObjectStoreDb`, failing with `Errors::Timeout` past `timeout` if any.
+#[cfg(feature = "db-object-store")]
+async fn timed<T>(
+    timeout: Option<Duration>,
+    request: impl Future<Output = object_store::Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio_rt::time::timeout(timeout, request)
+            .await
+            .map_err(|_| Errors::Timeout(format!("object store: no answer within {:?}", timeout)))?
+            .map_err(Errors::from),
+        None => Ok(request.await?),
+    }
+}
+
+#[cfg(feature = "db-object-store")]
+impl ObjectStoreDb {
+    /// Open the bucket of `dbpath` with the given credentials, or unsigned if `None`.
+    pub fn with_credentials(dbpath: &str, credentials: Option<Credentials>) -> Result<Self> {
+        Self::open(dbpath, credentials, None)
+    }
+
+    /// Open the bucket as `with_credentials()`, but failing with `Errors::Timeout` when any
+    /// request, retries included, is not answered within `timeout`.
+    pub fn with_timeout(
+        dbpath: &str,
+        credentials: Option<Credentials>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        Self::open(dbpath, credentials, Some(timeout))
+    }
+
+    fn open(
+        dbpath: &str,
+        credentials: Option<Credentials>,
+        timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let malformed = || Errors::Db(format!("object store: malformed path: {}", dbpath));
+        let (scheme, path) = match dbpath.split_once("://") {
+            Some((scheme, path)) if scheme == "http" || scheme == "https" => (scheme, path),
+            Some(_) => return Err(malformed()),
+            None => ("http", dbpath),
+        };
+        let (host, path) = path.split_once('/').ok_or_else(malformed)?;
+        let (bucket, prefix) = match path.trim_matches('/').split_once('/') {
+            Some((bucket, prefix)) => (bucket, prefix),
+            None => (path.trim_matches('/'), ""),
+        };
+        if host.is_empty() || bucket.is_empty() {
+            return Err(malformed());
+        }
+        let mut builder = AmazonS3Builder::new()
+            .with_endpoint(format!("{}://{}", scheme, host))
+            .with_bucket_name(bucket)
+            .with_allow_http(scheme == "http");
+        builder = match credentials {
+            Some(credentials) => builder
+                .with_access_key_id(credentials.access_key)
+                .with_secret_access_key(credentials.secret_key)
+                .with_region(credentials.region),
+            None => builder.with_skip_signature(true),
+        };
+        let db = ObjectStoreDb {
+            store: builder.build()?,
+            prefix: ObjectPath::from(prefix),
+            runtime: tokio_rt::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?,
+            timeout,
+            cache: MemCache::new(),
+            batch: HashMap::new(),
+            batch_on: false,
+        };
+        // a bucket missing or denied fails the first page of the listing
+        db.block_on(
+            db.store
+                .list(Some(&db.prefix))
+                .next()
+                .map(Option::transpose),
+        )?;
+        Ok(db)
+    }
+
+    /// Set the capacity of the cache of values read, `DEFAULT_CACHE_ENTRIES` entries
+    /// and `DEFAULT_CACHE_BYTES` bytes by default.
+    pub fn set_cache_capacity(&mut self, entries: usize, bytes: usize) {
+        self.cache.set_capacity(entries, bytes);
+    }
+
+    /// Counters of the cache of values read.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    fn object(&self, key: &[u8]) -> ObjectPath {
+        self.prefix.clone().join(hex::encode(key))
+    }
+
+    /// Run a request on the runtime of the database, within its timeout.
+    fn block_on<T>(&self, request: impl Future<Output = object_store::Result<T>>) -> Result<T> {
+        self.runtime.block_on(timed(self.timeout, request))
+    }
+
+    /// Write or delete objects of the entries given, `CONCURRENT_REQUESTS` at a time.
+    fn write(&mut self, entries: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<()> {
+        let requests = entries.iter().map(|(key, value)| {
+            let (store, object) = (&self.store, self.object(key));
+            timed(self.timeout, async move {
+                match value {
+                    Some(value) => store
+                        .put(&object, PutPayload::from(value.to_owned()))
+                        .await
+                        .map(|_| ()),
+                    None => match store.delete(&object).await {
+                        Err(object_store::Error::NotFound { .. }) => Ok(()),
+                        result => result,
+                    },
+                }
+            })
+        });
+        let written = stream::iter(requests)
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .try_collect::<Vec<()>>();
+        let result = self.runtime.block_on(written);
+        for (key, value) in entries {
+            self.cache.forget(&key);
+            if let (Ok(_), Some(value)) = (&result, value) {
+                self.cache.fill(&key, value);
+            }
+        }
+        result.map(|_| ())
+    }
+
+    /// List objects under the prefix, page by page, with their names and sizes.
+    fn list(&self) -> Result<Vec<ObjectMeta>> {
+        let mut listing = self.store.list(Some(&self.prefix));
+        let mut metas = Vec::new();
+        while let Some(meta) = self.block_on(listing.next().map(Option::transpose))? {
+            metas.push(meta);
+        }
+        Ok(metas)
+    }
+}
+
+#[cfg(feature = "db-object-store")]
+impl Database for ObjectStoreDb {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Self::with_credentials(dbpath, Credentials::from_env())
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.batch.get(key) {
+            return Ok(value.to_owned());
+        }
+        if let Some(value) = self.cache.lookup(key) {
+            return Ok(value);
+        }
+        let object = self.object(key);
+        let read = async {
+            match self.store.get(&object).await {
+                Ok(result) => result.bytes().await.map(Some),
+                Err(object_store::Error::NotFound { .. }) => Ok(None),
+                Err(err) => Err(err),
+            }
+        };
+        let value = self.block_on(read)?.map(|bytes| bytes.to_vec());
+        if let Some(value) = &value {
+            self.cache.fill(key, value.to_owned());
+        }
+        Ok(value)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        if self.batch_on {
+            self.batch.insert(key.to_vec(), Some(value));
+            return Ok(());
+        }
+        self.write(vec![(key.to_vec(), Some(value))])
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if self.batch_on {
+            self.batch.insert(key.to_vec(), None);
+            return Ok(());
+        }
+        self.write(vec![(key.to_vec(), None)])
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.batch.clear();
+        self.batch_on = true;
         Ok(())
     }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.batch_on = false;
+        let (puts, deletes) = std::mem::take(&mut self.batch)
+            .into_iter()
+            .partition(|(_, value)| value.is_some());
+        self.write(puts)?;
+        self.write(deletes)
+    }
+
+    /// Total size of the objects under the prefix, as listed by the store.
+    fn total_size(&self) -> Result<u64> {
+        Ok(self.list()?.iter().map(|meta| meta.size).sum())
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        self.list()?
+            .iter()
+            .map(|meta| {
+                let name = meta.location.filename().unwrap_or_default();
+                hex::decode(name)
+                    .map_err(|_| Errors::Decode(format!("object store: not a key: {}", name)))
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,76 @@
+//! A module implementing a bloom filter over keys of `monotree`.
+//!
+//! With a filter enabled by `Monotree::enable_filter()`, lookups of keys never inserted
+//! are answered without walking down the tree. A filter never forgets a key,
+//! so removed keys are still walked for, yet keys present are never rejected.
+use crate::utils::*;
+use crate::*;
+
+/// Offset bases of the two `FNV-1a` digests the probed bits are derived from.
+const OFFSETS: [u64; 2] = [0xcbf2_9ce4_8422_2325, 0x8422_2325_cbf2_9ce4];
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// A bloom filter telling keys definitely absent from those possibly present.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    /// Number of bits probed per key.
+    probes: u32,
+}
+
+impl BloomFilter {
+    /// Construct an empty filter sized for `capacity` keys
+    /// with a false positive rate of `rate` at most, e.g. `0.01`.
+    pub fn new(capacity: usize, rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let n = capacity.max(1) as f64;
+        let len = (-n * rate.ln() / (ln2 * ln2)).ceil().max(8.0) as usize;
+        let probes = ((len as f64 / n) * ln2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0u8; len.div_ceil(8)],
+            probes,
+        }
+    }
+
+    /// Add a key to the filter.
+    pub fn insert(&mut self, key: &[u8]) {
+        for i in self.indices(key) {
+            self.bits[i / 8] |= 1 << (i % 8);
+        }
+    }
+
+    /// Check if a key may have been added. `false` means it definitely has not.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.indices(key)
+            .all(|i| self.bits[i / 8] & (1 << (i % 8)) != 0)
+    }
+
+    /// Serialize `BloomFilter` into bytes: number of probes of 4 bytes, then the bits.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.probes.to_be_bytes()[..], &self.bits].concat()
+    }
+
+    /// Construct `BloomFilter` by deserializing bytes slice.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 5 {
+            return Err(Errors::Decode(
+                "BloomFilter::from_bytes(): too short".to_string(),
+            ));
+        }
+        Ok(BloomFilter {
+            bits: bytes[4..].to_vec(),
+            probes: bytes_to_int(&bytes[..4]),
+        })
+    }
+
+    /// Get the bits probed for a key, by double hashing with two digests of it.
+    fn indices(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let digest = |offset: u64| {
+            key.iter()
+                .fold(offset, |h, &b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
+        };
+        let (h1, h2) = (digest(OFFSETS[0]), digest(OFFSETS[1]) | 1);
+        let len = self.bits.len() as u64 * 8;
+        (0..self.probes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
@@ -0,0 +1,560 @@
+//! A module serving trees of `monotree` over gRPC, with proofs encoded in protobuf,
+//! to run a tree as a service of its own rather than linking it into every process.
+//!
+//! ```text
+//! Root(RootRequest) -> RootReply          the root recorded by `SwapRoot`
+//! SwapRoot(SwapRootRequest) -> SwapRootReply
+//! Get(GetRequest) -> GetReply             Gets(GetsRequest) -> GetsReply
+//! Insert(InsertRequest) -> RootReply      Inserts(InsertsRequest) -> RootReply
+//! Remove(RemoveRequest) -> RootReply      Removes(RemovesRequest) -> RootReply
+//! Prove(ProveRequest) -> ProveReply       the leaf of a key with its proof
+//! ```
+//!
+//! The messages here are those of the service `monotree.Monotree` of `proto/monotree.proto`,
+//! for clients in other languages to be generated from. Hashes are bytes of `HASH_LEN`,
+//! or empty for an empty tree or a key not in the tree. As with `Monotree`, updates return
+//! a new root without recording it, which `SwapRoot` does.
+use crate::utils::*;
+use crate::*;
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::codegen::{http, Body, BoxFuture, Context, Poll, StdError};
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Response, Status};
+use tonic_prost::ProstCodec;
+
+/// Name of the service, prefixing the paths of its methods.
+pub const SERVICE_NAME: &str = "monotree.Monotree";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RootRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RootReply {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SwapRootRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub expected: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub new: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SwapRootReply {
+    #[prost(bool, tag = "1")]
+    pub swapped: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetReply {
+    #[prost(bytes = "vec", tag = "1")]
+    pub leaf: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetsRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub keys: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetsReply {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub leaves: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InsertRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub leaf: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InsertsRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub keys: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", repeated, tag = "3")]
+    pub leaves: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RemoveRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct RemovesRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub keys: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProveRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub root: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProveReply {
+    #[prost(bytes = "vec", tag = "1")]
+    pub leaf: Vec<u8>,
+    /// `None` if the key is not in the tree.
+    #[prost(message, optional, tag = "2")]
+    pub proof: Option<Proof>,
+}
+
+/// A Merkle proof in protobuf, converted from and into `crate::Proof`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Proof {
+    #[prost(message, repeated, tag = "1")]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Step {
+    #[prost(bool, tag = "1")]
+    pub right: bool,
+    #[prost(bytes = "vec", tag = "2")]
+    pub cut: Vec<u8>,
+}
+
+impl From<&crate::Proof> for Proof {
+    fn from(proof: &crate::Proof) -> Self {
+        let steps = proof
+            .iter()
+            .map(|(right, cut)| Step {
+                right: *right,
+                cut: cut.to_vec(),
+            })
+            .collect();
+        Proof { steps }
+    }
+}
+
+impl From<Proof> for crate::Proof {
+    fn from(proof: Proof) -> Self {
+        proof
+            .steps
+            .into_iter()
+            .map(|step| (step.right, step.cut))
+            .collect()
+    }
+}
+
+/// Get a hash from bytes, `None` for empty ones. Fails on bytes of any length but `HASH_LEN`.
+fn parse_hash(bytes: &[u8]) -> std::result::Result<Option<Hash>, Status> {
+    match bytes.len() {
+        0 => Ok(None),
+        HASH_LEN => Ok(Some(slice_to_hash(bytes))),
+        _ => Err(Status::invalid_argument("invalid hash")),
+    }
+}
+
+/// The same as `parse_hash()`, but fails on empty bytes as well, such as of keys.
+fn parse_key(bytes: &[u8]) -> std::result::Result<Hash, Status> {
+    parse_hash(bytes)?.ok_or_else(|| Status::invalid_argument("invalid hash"))
+}
+
+fn parse_keys(keys: &[Vec<u8>]) -> std::result::Result<Vec<Hash>, Status> {
+    keys.iter().map(|key| parse_key(key)).collect()
+}
+
+fn hash_bytes(hash: Option<Hash>) -> Vec<u8> {
+    hash.map_or_else(Vec::new, |hash| hash.to_vec())
+}
+
+/// Answer errors of the tree with the status of the nearest code.
+fn status(err: Errors) -> Status {
+    let code = match err {
+        Errors::NotFound(_) => Code::NotFound,
+        Errors::Cancelled => Code::Cancelled,
+        Errors::Vetoed(_) => Code::FailedPrecondition,
+        Errors::Timeout(_) => Code::DeadlineExceeded,
+        _ => Code::Internal,
+    };
+    Status::new(code, err.to_string())
+}
+
+/// A service answering calls over gRPC from a tree, one at a time.
+///
+/// Calls run on the threads for blocking of its runtime, so that backends reading the disk
+/// or the network never hold up the connections being served.
+pub struct Service<D, H, C> {
+    tree: Arc<Mutex<Monotree<D, H, C>>>,
+}
+
+impl<D, H, C> Clone for Service<D, H, C> {
+    fn clone(&self) -> Self {
+        Service {
+            tree: Arc::clone(&self.tree),
+        }
+    }
+}
+
+impl<D, H, C> Service<D, H, C>
+where
+    D: Database + Send + 'static,
+    H: Hasher + Send + 'static,
+    C: NodeCodec + Send + 'static,
+{
+    pub fn new(tree: Monotree<D, H, C>) -> Self {
+        Service {
+            tree: Arc::new(Mutex::new(tree)),
+        }
+    }
+
+    /// Answer calls over connections accepted by `listener`, on a runtime of its own,
+    /// until accepting fails.
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        let runtime = tokio_rt::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        listener.set_nonblocking(true)?;
+        let listener = {
+            let _guard = runtime.enter();
+            tokio_rt::net::TcpListener::from_std(listener)?
+        };
+        runtime
+            .block_on(
+                tonic::transport::Server::builder()
+                    .add_service(self.clone())
+                    .serve_with_incoming(TcpIncoming::from(listener)),
+            )
+            .map_err(|err| Errors::Db(format!("grpc: {}", err)))
+    }
+
+    /// Answer a call decoded as `Req` by running `f` on the tree, then encoding its reply.
+    fn unary<B, Req, Reply, F>(&self, request: http::Request<B>, f: F) -> Call
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+        Req: prost::Message + Default + Send + 'static,
+        Reply: prost::Message + Send + 'static,
+        F: FnOnce(&mut Monotree<D, H, C>, Req) -> std::result::Result<Reply, Status>
+            + Send
+            + 'static,
+    {
+        let method = Method {
+            tree: Arc::clone(&self.tree),
+            f: Some(f),
+        };
+        Box::pin(async move {
+            let mut grpc = tonic::server::Grpc::new(ProstCodec::<Reply, Req>::default());
+            Ok(grpc.unary(method, request).await)
+        })
+    }
+}
+
+/// A call being answered by `Service`.
+type Call = BoxFuture<http::Response<tonic::body::Body>, Infallible>;
+
+/// A method of `Service`, running `f` on the tree for the single request it is called with.
+struct Method<D, H, C, F> {
+    tree: Arc<Mutex<Monotree<D, H, C>>>,
+    f: Option<F>,
+}
+
+impl<D, H, C, F, Req, Reply> tonic::codegen::Service<Request<Req>> for Method<D, H, C, F>
+where
+    D: Database + Send + 'static,
+    H: Hasher + Send + 'static,
+    C: NodeCodec + Send + 'static,
+    Req: Send + 'static,
+    Reply: Send + 'static,
+    F: FnOnce(&mut Monotree<D, H, C>, Req) -> std::result::Result<Reply, Status> + Send + 'static,
+{
+    type Response = Response<Reply>;
+    type Error = Status;
+    type Future = BoxFuture<Response<Reply>, Status>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let (tree, f) = (Arc::clone(&self.tree), self.f.take());
+        Box::pin(async move {
+            let f = f.ok_or_else(|| Status::internal("method called twice"))?;
+            let reply = tokio_rt::task::spawn_blocking(move || {
+                let mut tree = tree
+                    .lock()
+                    .map_err(|_| Status::internal("grpc::Service: poisoned"))?;
+                f(&mut tree, request.into_inner())
+            })
+            .await
+            .map_err(|err| Status::internal(err.to_string()))??;
+            Ok(Response::new(reply))
+        })
+    }
+}
+
+impl<D, H, C, B> tonic::codegen::Service<http::Request<B>> for Service<D, H, C>
+where
+    D: Database + Send + 'static,
+    H: Hasher + Send + 'static,
+    C: NodeCodec + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = Call;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Call {
+        let method = request.uri().path().strip_prefix("/monotree.Monotree/");
+        match method {
+            Some("Root") => self.unary(request, |tree, _: RootRequest| {
+                let root = tree.load_root().map_err(status)?;
+                Ok(RootReply {
+                    root: hash_bytes(root),
+                })
+            }),
+            Some("SwapRoot") => self.unary(request, |tree, req: SwapRootRequest| {
+                let (expected, new) = (parse_hash(&req.expected)?, parse_hash(&req.new)?);
+                let swapped = tree
+                    .swap_root(expected.as_ref(), new.as_ref())
+                    .map_err(status)?;
+                Ok(SwapRootReply { swapped })
+            }),
+            Some("Get") => self.unary(request, |tree, req: GetRequest| {
+                let (root, key) = (parse_hash(&req.root)?, parse_key(&req.key)?);
+                let leaf = tree.get(root.as_ref(), &key).map_err(status)?;
+                Ok(GetReply {
+                    leaf: hash_bytes(leaf),
+                })
+            }),
+            Some("Gets") => self.unary(request, |tree, req: GetsRequest| {
+                let (root, keys) = (parse_hash(&req.root)?, parse_keys(&req.keys)?);
+                let leaves = tree.gets(root.as_ref(), &keys).map_err(status)?;
+                Ok(GetsReply {
+                    leaves: leaves.into_iter().map(hash_bytes).collect(),
+                })
+            }),
+            Some("Insert") => self.unary(request, |tree, req: InsertRequest| {
+                let (root, key) = (parse_hash(&req.root)?, parse_key(&req.key)?);
+                let leaf = parse_key(&req.leaf)?;
+                let root = tree.insert(root.as_ref(), &key, &leaf).map_err(status)?;
+                Ok(RootReply {
+                    root: hash_bytes(root),
+                })
+            }),
+            Some("Inserts") => self.unary(request, |tree, req: InsertsRequest| {
+                let (root, keys) = (parse_hash(&req.root)?, parse_keys(&req.keys)?);
+                let leaves = parse_keys(&req.leaves)?;
+                if keys.len() != leaves.len() {
+                    return Err(Status::invalid_argument("keys and leaves differ in number"));
+                }
+                let root = tree
+                    .inserts(root.as_ref(), &keys, &leaves)
+                    .map_err(status)?;
+                Ok(RootReply {
+                    root: hash_bytes(root),
+                })
+            }),
+            Some("Remove") => self.unary(request, |tree, req: RemoveRequest| {
+                let (root, key) = (parse_hash(&req.root)?, parse_key(&req.key)?);
+                let root = tree.remove(root.as_ref(), &key).map_err(status)?;
+                Ok(RootReply {
+                    root: hash_bytes(root),
+                })
+            }),
+            Some("Removes") => self.unary(request, |tree, req: RemovesRequest| {
+                let (root, keys) = (parse_hash(&req.root)?, parse_keys(&req.keys)?);
+                let root = tree.removes(root.as_ref(), &keys).map_err(status)?;
+                Ok(RootReply {
+                    root: hash_bytes(root),
+                })
+            }),
+            Some("Prove") => self.unary(request, |tree, req: ProveRequest| {
+                let (root, key) = (parse_hash(&req.root)?, parse_key(&req.key)?);
+                let leaf = tree.get(root.as_ref(), &key).map_err(status)?;
+                let proof = tree.get_merkle_proof(root.as_ref(), &key).map_err(status)?;
+                Ok(ProveReply {
+                    leaf: hash_bytes(leaf),
+                    proof: proof.as_ref().map(Proof::from),
+                })
+            }),
+            _ => Box::pin(async { Ok(Status::unimplemented("no such method").into_http()) }),
+        }
+    }
+}
+
+impl<D, H, C> tonic::server::NamedService for Service<D, H, C> {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+/// A client calling `Service` over gRPC, blocking on a runtime of its own,
+/// with methods named after those of `Monotree`.
+pub struct Client {
+    runtime: tokio_rt::runtime::Runtime,
+    grpc: tonic::client::Grpc<Channel>,
+}
+
+impl Client {
+    /// Connect to a service at the given URI, such as `http://127.0.0.1:50051`.
+    pub fn connect(uri: &str) -> Result<Self> {
+        let runtime = tokio_rt::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let endpoint = Endpoint::from_shared(uri.to_string())
+            .map_err(|err| Errors::new(&format!("Client::connect(): {}", err)))?;
+        let channel = runtime
+            .block_on(endpoint.connect())
+            .map_err(|err| Errors::Db(format!("grpc: {}", err)))?;
+        Ok(Client {
+            runtime,
+            grpc: tonic::client::Grpc::new(channel),
+        })
+    }
+
+    fn call<Req, Reply>(&mut self, method: &'static str, request: Req) -> Result<Reply>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Reply: prost::Message + Default + Send + Sync + 'static,
+    {
+        let grpc = &mut self.grpc;
+        let reply = self.runtime.block_on(async {
+            grpc.ready()
+                .await
+                .map_err(|err| Status::unavailable(err.to_string()))?;
+            let path = PathAndQuery::from_static(method);
+            let codec = ProstCodec::<Req, Reply>::default();
+            grpc.unary(Request::new(request), path, codec).await
+        })?;
+        Ok(reply.into_inner())
+    }
+
+    /// Get the root recorded by the service.
+    pub fn load_root(&mut self) -> Result<Option<Hash>> {
+        let reply: RootReply = self.call("/monotree.Monotree/Root", RootRequest {})?;
+        reply_hash(&reply.root)
+    }
+
+    /// Record a new root only if the recorded one still is `expected`.
+    pub fn swap_root(&mut self, expected: Option<&Hash>, new: Option<&Hash>) -> Result<bool> {
+        let request = SwapRootRequest {
+            expected: hash_bytes(expected.copied()),
+            new: hash_bytes(new.copied()),
+        };
+        let reply: SwapRootReply = self.call("/monotree.Monotree/SwapRoot", request)?;
+        Ok(reply.swapped)
+    }
+
+    pub fn get(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        let request = GetRequest {
+            root: hash_bytes(root.copied()),
+            key: key.to_vec(),
+        };
+        let reply: GetReply = self.call("/monotree.Monotree/Get", request)?;
+        reply_hash(&reply.leaf)
+    }
+
+    pub fn gets(&mut self, root: Option<&Hash>, keys: &[Hash]) -> Result<Vec<Option<Hash>>> {
+        let request = GetsRequest {
+            root: hash_bytes(root.copied()),
+            keys: keys.iter().map(|key| key.to_vec()).collect(),
+        };
+        let reply: GetsReply = self.call("/monotree.Monotree/Gets", request)?;
+        reply.leaves.iter().map(|leaf| reply_hash(leaf)).collect()
+    }
+
+    pub fn insert(&mut self, root: Option<&Hash>, key: &Hash, leaf: &Hash) -> Result<Option<Hash>> {
+        let request = InsertRequest {
+            root: hash_bytes(root.copied()),
+            key: key.to_vec(),
+            leaf: leaf.to_vec(),
+        };
+        let reply: RootReply = self.call("/monotree.Monotree/Insert", request)?;
+        reply_hash(&reply.root)
+    }
+
+    pub fn inserts(
+        &mut self,
+        root: Option<&Hash>,
+        keys: &[Hash],
+        leaves: &[Hash],
+    ) -> Result<Option<Hash>> {
+        let request = InsertsRequest {
+            root: hash_bytes(root.copied()),
+            keys: keys.iter().map(|key| key.to_vec()).collect(),
+            leaves: leaves.iter().map(|leaf| leaf.to_vec()).collect(),
+        };
+        let reply: RootReply = self.call("/monotree.Monotree/Inserts", request)?;
+        reply_hash(&reply.root)
+    }
+
+    pub fn remove(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        let request = RemoveRequest {
+            root: hash_bytes(root.copied()),
+            key: key.to_vec(),
+        };
+        let reply: RootReply = self.call("/monotree.Monotree/Remove", request)?;
+        reply_hash(&reply.root)
+    }
+
+    pub fn removes(&mut self, root: Option<&Hash>, keys: &[Hash]) -> Result<Option<Hash>> {
+        let request = RemovesRequest {
+            root: hash_bytes(root.copied()),
+            keys: keys.iter().map(|key| key.to_vec()).collect(),
+        };
+        let reply: RootReply = self.call("/monotree.Monotree/Removes", request)?;
+        reply_hash(&reply.root)
+    }
+
+    /// Get the leaf of a key along with its Merkle proof, verified by `verify_proof()`,
+    /// or `None` if the key is not in the tree.
+    pub fn get_merkle_proof(
+        &mut self,
+        root: Option<&Hash>,
+        key: &Hash,
+    ) -> Result<Option<(Hash, crate::Proof)>> {
+        let request = ProveRequest {
+            root: hash_bytes(root.copied()),
+            key: key.to_vec(),
+        };
+        let reply: ProveReply = self.call("/monotree.Monotree/Prove", request)?;
+        match (reply_hash(&reply.leaf)?, reply.proof) {
+            (Some(leaf), Some(proof)) => Ok(Some((leaf, proof.into()))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Get a hash from bytes answered by the service, which are never of any length but `HASH_LEN`.
+fn reply_hash(bytes: &[u8]) -> Result<Option<Hash>> {
+    parse_hash(bytes).map_err(|_| Errors::Decode("grpc: invalid hash answered".to_string()))
+}
@@ -1,12 +1,55 @@
 //! A module for implementing hash functions supporting `monotree`.
 use crate::utils::*;
 use crate::*;
+#[cfg(any(feature = "hasher-sha2", feature = "hasher-sha3"))]
 use digest::Digest;
 
+/// Tag `Separated` prepends to bytes to be hashed into a leaf, as in RFC 6962.
+pub const LEAF_TAG: u8 = 0x00;
+
+/// Tag `Separated` prepends to serialized nodes to be hashed.
+pub const NODE_TAG: u8 = 0x01;
+
 /// A trait defining hashers used for `monotree`
-pub trait Hasher {
+///
+/// Hashers are cloned into trees rebuilt to verify or replay updates,
+/// so that those keyed carry their keys along.
+/// Digests are of `HASH_LEN` unless given another length `N`, which trees and nodes take as well.
+pub trait Hasher<const N: usize = HASH_LEN>: Clone {
     fn new() -> Self;
-    fn digest(&self, bytes: &[u8]) -> Hash;
+    fn digest(&self, bytes: &[u8]) -> Hash<N>;
+    /// Hash a serialized node of a tree. The same as `digest()` unless separated by `Separated`.
+    fn digest_node(&self, bytes: &[u8]) -> Hash<N> {
+        self.digest(bytes)
+    }
+    /// Hash a value into a leaf, as by `LeafPolicy`. The same as `digest()` unless separated.
+    fn digest_leaf(&self, bytes: &[u8]) -> Hash<N> {
+        self.digest(bytes)
+    }
+}
+
+/// A hasher separating the domains of leaves and nodes of the given hasher,
+/// tagging what is hashed by `LEAF_TAG` or `NODE_TAG`, so that no node is ever taken as a leaf
+/// of a value in adversarial settings. Roots differ from those of the hasher alone.
+#[derive(Clone, Debug)]
+pub struct Separated<H>(pub H);
+impl<H: Hasher<N>, const N: usize> Hasher<N> for Separated<H> {
+    fn new() -> Self {
+        Separated(H::new())
+    }
+
+    /// Keys derived by `digest()` are left untagged.
+    fn digest(&self, bytes: &[u8]) -> Hash<N> {
+        self.0.digest(bytes)
+    }
+
+    fn digest_node(&self, bytes: &[u8]) -> Hash<N> {
+        self.0.digest(&[&[NODE_TAG], bytes].concat())
+    }
+
+    fn digest_leaf(&self, bytes: &[u8]) -> Hash<N> {
+        self.0.digest(&[&[LEAF_TAG], bytes].concat())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -45,6 +88,59 @@ impl Hasher for Blake2b {
     }
 }
 
+#[derive(Clone, Debug)]
+#[cfg(feature = "hasher-blake2")]
+/// A hasher using `Blake2b` hash function in its keyed mode, as a MAC
+/// only those holding the key compute roots and verify proofs with.
+pub struct Blake2bKeyed {
+    key: Vec<u8>,
+}
+#[cfg(feature = "hasher-blake2")]
+impl Blake2bKeyed {
+    /// Construct `Blake2bKeyed` with a key of up to 64 bytes.
+    pub fn with_key(key: &[u8]) -> Result<Self> {
+        if key.len() > 64 {
+            return Err(Errors::new("with_key(): key over 64 bytes"));
+        }
+        Ok(Blake2bKeyed { key: key.to_vec() })
+    }
+}
+#[cfg(feature = "hasher-blake2")]
+impl Hasher for Blake2bKeyed {
+    /// Construct `Blake2bKeyed` with no key, the same as `Blake2b`.
+    /// Set `Monotree::hasher` to one given by `with_key()` instead.
+    fn new() -> Self {
+        Blake2bKeyed { key: Vec::new() }
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Hash {
+        let mut hasher = blake2_rfc::blake2b::Blake2b::with_key(HASH_LEN, &self.key);
+        hasher.update(bytes);
+        let hash = hasher.finalize();
+        slice_to_hash(hash.as_bytes())
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg(feature = "hasher-blake2")]
+/// A hasher using `Blake2b` hash function with digests of `N` bytes, from 1 to 64,
+/// such as of 20 bytes for trees of shorter keys and hashes, `Monotree<D, Blake2bVar<20>, C, 20>`.
+pub struct Blake2bVar<const N: usize>;
+#[cfg(feature = "hasher-blake2")]
+impl<const N: usize> Hasher<N> for Blake2bVar<N> {
+    fn new() -> Self {
+        assert!((1..=64).contains(&N), "Blake2bVar::new(): out of range");
+        Blake2bVar
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Hash<N> {
+        let mut hasher = blake2_rfc::blake2b::Blake2b::new(N);
+        hasher.update(bytes);
+        let hash = hasher.finalize();
+        slice_to_array(hash.as_bytes())
+    }
+}
+
 #[derive(Clone, Debug)]
 #[cfg(feature = "hasher-blake3")]
 /// A hasher using `Blake3` hash function
@@ -64,6 +160,34 @@ impl Hasher for Blake3 {
     }
 }
 
+#[derive(Clone, Debug)]
+#[cfg(feature = "hasher-blake3")]
+/// A hasher using `Blake3` hash function in its keyed mode, as a MAC
+/// only those holding the key compute roots and verify proofs with.
+pub struct Blake3Keyed {
+    key: Hash,
+}
+#[cfg(feature = "hasher-blake3")]
+impl Blake3Keyed {
+    pub fn with_key(key: &Hash) -> Self {
+        Blake3Keyed { key: *key }
+    }
+}
+#[cfg(feature = "hasher-blake3")]
+impl Hasher for Blake3Keyed {
+    /// Construct `Blake3Keyed` with the key of all zeros.
+    /// Set `Monotree::hasher` to one given by `with_key()` instead.
+    fn new() -> Self {
+        Blake3Keyed {
+            key: [0u8; HASH_LEN],
+        }
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Hash {
+        slice_to_hash(blake3::keyed_hash(&self.key, bytes).as_bytes())
+    }
+}
+
 #[derive(Clone, Debug)]
 #[cfg(feature = "hasher-sha2")]
 /// A hasher using `SHA2` hash function
@@ -83,6 +207,40 @@ impl Hasher for Sha2 {
     }
 }
 
+#[derive(Clone, Debug)]
+#[cfg(feature = "hasher-sha2")]
+/// A hasher using double `SHA-256` as `Bitcoin` does, `SHA-256(SHA-256(bytes))`
+pub struct Sha256d;
+#[cfg(feature = "hasher-sha2")]
+impl Hasher for Sha256d {
+    fn new() -> Self {
+        Sha256d
+    }
+
+    /// Digests are in the internal byte order of `Bitcoin`,
+    /// the reverse of how it displays hashes of transactions and blocks.
+    fn digest(&self, bytes: &[u8]) -> Hash {
+        let hash = sha2::Sha256::digest(bytes);
+        slice_to_hash(sha2::Sha256::digest(&hash).as_slice())
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg(feature = "hasher-sha2")]
+/// A hasher using `SHA-512` hash function, for trees of 64-byte keys and hashes,
+/// `Monotree<D, Sha512, C, 64>`.
+pub struct Sha512;
+#[cfg(feature = "hasher-sha2")]
+impl Hasher<64> for Sha512 {
+    fn new() -> Self {
+        Sha512
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Hash<64> {
+        slice_to_array(sha2::Sha512::digest(bytes).as_slice())
+    }
+}
+
 #[derive(Clone, Debug)]
 #[cfg(feature = "hasher-sha3")]
 /// A hasher using `SHA3` or `Keccak` hash function
@@ -101,3 +259,291 @@ impl Hasher for Sha3 {
         slice_to_hash(hash.as_slice())
     }
 }
+
+#[cfg(feature = "hasher-poseidon")]
+use num::{BigUint, One, Zero};
+#[cfg(feature = "hasher-poseidon")]
+use std::sync::OnceLock;
+
+/// Bytes of input taken as a single field element by the Poseidon hashers.
+#[cfg(feature = "hasher-poseidon")]
+pub const POSEIDON_CHUNK_LEN: usize = 31;
+
+/// Parameters of the Poseidon permutation of width 3 with the S-box `x^5` over a prime field,
+/// generated by the Grain LFSR of the reference implementation of Poseidon.
+#[cfg(feature = "hasher-poseidon")]
+#[derive(Debug)]
+struct PoseidonParams {
+    prime: BigUint,
+    full_rounds: usize,
+    partial_rounds: usize,
+    constants: Vec<BigUint>,
+    mds: Vec<Vec<BigUint>>,
+}
+
+#[cfg(feature = "hasher-poseidon")]
+impl PoseidonParams {
+    const WIDTH: usize = 3;
+
+    fn generate(prime: &str, bits: usize, full_rounds: usize, partial_rounds: usize) -> Self {
+        let prime = BigUint::parse_bytes(prime.as_bytes(), 10).expect("generate(): prime");
+        let mut grain = Grain::new(bits, Self::WIDTH, full_rounds, partial_rounds);
+        let constants = (0..(full_rounds + partial_rounds) * Self::WIDTH)
+            .map(|_| loop {
+                let constant = grain.next_uint(bits);
+                if constant < prime {
+                    break constant;
+                }
+            })
+            .collect();
+        // a Cauchy matrix of distinct elements, whose sums are never zero
+        let mds = loop {
+            let elements: Vec<BigUint> = (0..Self::WIDTH * 2)
+                .map(|_| grain.next_uint(bits) % &prime)
+                .collect();
+            let distinct = (0..elements.len()).all(|i| (0..i).all(|j| elements[i] != elements[j]));
+            let (xs, ys) = elements.split_at(Self::WIDTH);
+            let sums: Vec<Vec<BigUint>> = xs
+                .iter()
+                .map(|x| ys.iter().map(|y| (x + y) % &prime).collect())
+                .collect();
+            if distinct && sums.iter().flatten().all(|sum| !sum.is_zero()) {
+                let exp = &prime - BigUint::from(2u8);
+                break sums
+                    .iter()
+                    .map(|row| row.iter().map(|sum| sum.modpow(&exp, &prime)).collect())
+                    .collect();
+            }
+        };
+        PoseidonParams {
+            prime,
+            full_rounds,
+            partial_rounds,
+            constants,
+            mds,
+        }
+    }
+
+    fn sbox(&self, x: &BigUint) -> BigUint {
+        let square = x * x % &self.prime;
+        &square * &square % &self.prime * x % &self.prime
+    }
+
+    fn permute(&self, state: &mut [BigUint; 3]) {
+        let half = self.full_rounds / 2;
+        for round in 0..self.full_rounds + self.partial_rounds {
+            for (i, x) in state.iter_mut().enumerate() {
+                *x = (&*x + &self.constants[round * Self::WIDTH + i]) % &self.prime;
+            }
+            if round < half || round >= half + self.partial_rounds {
+                for x in state.iter_mut() {
+                    *x = self.sbox(x);
+                }
+            } else {
+                state[0] = self.sbox(&state[0]);
+            }
+            let mixed: Vec<BigUint> = self
+                .mds
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .zip(state.iter())
+                        .fold(BigUint::zero(), |acc, (m, x)| acc + m * x)
+                        % &self.prime
+                })
+                .collect();
+            state.clone_from_slice(&mixed);
+        }
+    }
+
+    /// Hash two field elements as the `Poseidon(2)` template of `circomlib` does.
+    fn hash2(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        let mut state = [BigUint::zero(), a % &self.prime, b % &self.prime];
+        self.permute(&mut state);
+        let [out, _, _] = state;
+        out
+    }
+
+    /// Fold the chunks of bytes into a field element, starting from the length of the bytes.
+    fn digest(&self, bytes: &[u8]) -> Hash {
+        let mut acc = BigUint::from(bytes.len());
+        let mut chunks: Vec<&[u8]> = bytes.chunks(POSEIDON_CHUNK_LEN).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        for chunk in chunks {
+            acc = self.hash2(&acc, &BigUint::from_bytes_be(chunk));
+        }
+        element_to_hash(&acc)
+    }
+}
+
+/// The Grain LFSR generating parameters of Poseidon, an 80-bit state seeded by the parameters.
+#[cfg(feature = "hasher-poseidon")]
+struct Grain(u128);
+
+#[cfg(feature = "hasher-poseidon")]
+impl Grain {
+    fn new(bits: usize, width: usize, full_rounds: usize, partial_rounds: usize) -> Self {
+        // a prime field (2 bits), the S-box `x^alpha` (4 bits), then the sizes, padded by ones
+        let fields = [(1, 2), (0, 4), (bits, 12), (width, 12), (full_rounds, 10)];
+        let mut seed = Vec::with_capacity(80);
+        for &(value, len) in fields.iter().chain([(partial_rounds, 10)].iter()) {
+            seed.extend((0..len).rev().map(|i| (value >> i) & 1 == 1));
+        }
+        seed.resize(80, true);
+        let state = seed
+            .iter()
+            .enumerate()
+            .fold(0u128, |state, (i, &bit)| state | ((bit as u128) << i));
+        let mut grain = Grain(state);
+        for _ in 0..160 {
+            grain.step();
+        }
+        grain
+    }
+
+    fn step(&mut self) -> bool {
+        let bit = [62, 51, 38, 23, 13, 0]
+            .iter()
+            .fold(0, |acc, &i| acc ^ (self.0 >> i) & 1);
+        self.0 = (self.0 >> 1) | (bit << 79);
+        bit == 1
+    }
+
+    /// Get the next bit, of those the generator keeps by the bit before each.
+    fn next_bit(&mut self) -> bool {
+        while !self.step() {
+            self.step();
+        }
+        self.step()
+    }
+
+    fn next_uint(&mut self, bits: usize) -> BigUint {
+        (0..bits).fold(BigUint::zero(), |acc, _| {
+            let bit = if self.next_bit() {
+                BigUint::one()
+            } else {
+                BigUint::zero()
+            };
+            (acc << 1) + bit
+        })
+    }
+}
+
+#[cfg(feature = "hasher-poseidon")]
+fn element_to_hash(element: &BigUint) -> Hash {
+    let bytes = element.to_bytes_be();
+    let mut hash = [0u8; HASH_LEN];
+    hash[HASH_LEN - bytes.len()..].copy_from_slice(&bytes);
+    hash
+}
+
+#[cfg(feature = "hasher-poseidon")]
+fn bn254_params() -> &'static PoseidonParams {
+    static PARAMS: OnceLock<PoseidonParams> = OnceLock::new();
+    PARAMS.get_or_init(|| {
+        PoseidonParams::generate(
+            "21888242871839275222246405745257275088548364400416034343698204186575808495617",
+            254,
+            8,
+            57,
+        )
+    })
+}
+
+#[cfg(feature = "hasher-poseidon")]
+fn bls12_381_params() -> &'static PoseidonParams {
+    static PARAMS: OnceLock<PoseidonParams> = OnceLock::new();
+    PARAMS.get_or_init(|| {
+        PoseidonParams::generate(
+            "52435875175126190479447740508185965837690552500527637822603658699938581184513",
+            255,
+            8,
+            57,
+        )
+    })
+}
+
+#[derive(Clone, Debug)]
+#[cfg(feature = "hasher-poseidon")]
+/// A hasher using `Poseidon` over the scalar field of `BN254`, the same as that of `circomlib`,
+/// so that proofs of `monotree` are verified cheaply within arithmetic circuits.
+///
+/// Bytes are taken as big-endian field elements of `POSEIDON_CHUNK_LEN` bytes each, and folded
+/// by `hash2()` from the length of the bytes: `h = len`, then `h = hash2(h, chunk)` for each,
+/// where no bytes are taken as a single chunk of zero.
+pub struct PoseidonBn254;
+#[cfg(feature = "hasher-poseidon")]
+impl PoseidonBn254 {
+    /// Hash two field elements given in big-endian, reduced into the field.
+    pub fn hash2(&self, a: &Hash, b: &Hash) -> Hash {
+        let (a, b) = (BigUint::from_bytes_be(a), BigUint::from_bytes_be(b));
+        element_to_hash(&bn254_params().hash2(&a, &b))
+    }
+}
+#[cfg(feature = "hasher-poseidon")]
+impl Hasher for PoseidonBn254 {
+    fn new() -> Self {
+        PoseidonBn254
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Hash {
+        bn254_params().digest(bytes)
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg(feature = "hasher-poseidon")]
+/// The same as `PoseidonBn254`, but over the scalar field of `BLS12-381`.
+pub struct PoseidonBls12381;
+#[cfg(feature = "hasher-poseidon")]
+impl PoseidonBls12381 {
+    /// Hash two field elements given in big-endian, reduced into the field.
+    pub fn hash2(&self, a: &Hash, b: &Hash) -> Hash {
+        let (a, b) = (BigUint::from_bytes_be(a), BigUint::from_bytes_be(b));
+        element_to_hash(&bls12_381_params().hash2(&a, &b))
+    }
+}
+#[cfg(feature = "hasher-poseidon")]
+impl Hasher for PoseidonBls12381 {
+    fn new() -> Self {
+        PoseidonBls12381
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Hash {
+        bls12_381_params().digest(bytes)
+    }
+}
+
+/// Policies of how values are folded into leaves of `monotree`.
+///
+/// Recorded in `Metadata`, the policy of a tree is applied by `Monotree::insert_value()`
+/// and `verify_value_proof()` to turn a value into the leaf committed to.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LeafPolicy {
+    /// Take a value of the length of hashes as the leaf as it is. This is the default.
+    #[default]
+    Raw,
+    /// Take the hash of a value.
+    Hash,
+    /// Take the hash of a key followed by its value, binding the leaf to the key.
+    KeyedHash,
+}
+
+impl LeafPolicy {
+    /// Get the leaf a value of a key is folded into.
+    pub fn leaf<H: Hasher<N>, const N: usize>(
+        &self,
+        hasher: &H,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Hash<N>> {
+        match self {
+            LeafPolicy::Raw if value.len() == N => Ok(slice_to_array(value)),
+            LeafPolicy::Raw => Err(Errors::new("leaf(): raw value not of the length of hashes")),
+            LeafPolicy::Hash => Ok(hasher.digest_leaf(value)),
+            LeafPolicy::KeyedHash => Ok(hasher.digest_leaf(&[key, value].concat())),
+        }
+    }
+}
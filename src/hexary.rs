@@ -0,0 +1,380 @@
+//! A module implementing a radix-16 variant of `monotree`, walking keys down a nibble at a time.
+//!
+//! Every node branches into up to 16 cells, one for each nibble, so a key is found in about
+//! a quarter of the nodes read by a binary tree, at the cost of proofs carrying whole nodes.
+//! As in `monotree`, each cell also holds the nibbles walked down to the next node or the leaf,
+//! so that paths not shared by other keys take no nodes. Every node but the root has two cells
+//! at least, so a tree of the same entries has the same root however they were inserted.
+use crate::utils::*;
+use crate::*;
+
+/// Number of nibbles of keys, which is the depth of leaves.
+const DEPTH: usize = HASH_LEN * 2;
+
+/// A type representing a proof of `HexaryTree`: serialized nodes walked down along a key.
+///
+/// The same proof shows either the leaf of a key or that the key is absent,
+/// see `verify_hexary_proof()`.
+pub type HexaryProof = Vec<Vec<u8>>;
+
+/// A cell of a node: the rest of the nibbles walked down to a child, and the hash of it.
+#[derive(Clone, Debug, PartialEq)]
+struct Cell {
+    nibbles: Vec<u8>,
+    hash: Hash,
+}
+
+/// A node of up to 16 cells, indexed by the nibble they are walked down by.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct HexNode {
+    cells: [Option<Cell>; 16],
+}
+
+impl HexNode {
+    /// Serialize into a bitmap of cells (2, big-endian) followed by every cell in order of
+    /// nibbles, as the number of nibbles (1) + the nibbles packed into bytes + `hash`(`HASH_LEN`).
+    fn to_bytes(&self) -> Vec<u8> {
+        let bitmap = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.is_some())
+            .fold(0u16, |bitmap, (i, _)| bitmap | (1 << i));
+        let mut bytes = bitmap.to_be_bytes().to_vec();
+        for cell in self.cells.iter().flatten() {
+            bytes.push(cell.nibbles.len() as u8);
+            bytes.extend(cell.nibbles.chunks(2).map(|pair| {
+                let low = pair.get(1).copied().unwrap_or(0);
+                (pair[0] << 4) | low
+            }));
+            bytes.extend_from_slice(&cell.hash);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let malformed = || Errors::Decode("HexNode::from_bytes(): malformed node".to_string());
+        if bytes.len() < 2 {
+            return Err(malformed());
+        }
+        let bitmap = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let mut node = HexNode::default();
+        let mut i = 2;
+        for slot in 0..16 {
+            if bitmap & (1 << slot) == 0 {
+                continue;
+            }
+            let len = *bytes.get(i).ok_or_else(malformed)? as usize;
+            let packed = bytes
+                .get(i + 1..i + 1 + len.div_ceil(2))
+                .ok_or_else(malformed)?;
+            let nibbles: Vec<u8> = packed
+                .iter()
+                .flat_map(|byte| vec![byte >> 4, byte & 0x0f])
+                .take(len)
+                .collect();
+            i += 1 + packed.len();
+            let hash = bytes.get(i..i + HASH_LEN).ok_or_else(malformed)?;
+            i += HASH_LEN;
+            node.cells[slot] = Some(Cell {
+                nibbles,
+                hash: slice_to_hash(hash),
+            });
+        }
+        if i != bytes.len() || bitmap == 0 {
+            return Err(malformed());
+        }
+        Ok(node)
+    }
+
+    fn count(&self) -> usize {
+        self.cells.iter().flatten().count()
+    }
+}
+
+/// Split a key into its nibbles, the higher of each byte first.
+fn nibbles_of(key: &[u8]) -> Vec<u8> {
+    key.iter()
+        .flat_map(|byte| vec![byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// Get the number of nibbles two slices have in common from the start.
+fn len_common(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count()
+}
+
+/// A radix-16 tree of `(key, leaf)` entries over a database, the same as `Monotree` in use.
+///
+/// Roots differ from those of `Monotree` with the same entries, and its proofs are verified
+/// by `verify_hexary_proof()` instead.
+#[derive(Debug)]
+pub struct HexaryTree<D = DefaultDatabase, H = DefaultHasher> {
+    pub db: D,
+    pub hasher: H,
+}
+
+impl<D, H> HexaryTree<D, H>
+where
+    D: Database,
+    H: Hasher,
+{
+    pub fn new(dbpath: &str) -> Self {
+        Self::try_new(dbpath).expect("new()")
+    }
+
+    /// The same as `new()`, but returns an error instead of panicking if the database fails to open.
+    pub fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(Self::with_db(Database::try_new(dbpath)?))
+    }
+
+    /// Construct `HexaryTree` over a database already opened.
+    pub fn with_db(db: D) -> Self {
+        HexaryTree {
+            db,
+            hasher: Hasher::new(),
+        }
+    }
+
+    fn read_node(&mut self, hash: &[u8]) -> Result<HexNode> {
+        match self.db.get(hash)? {
+            Some(bytes) => HexNode::from_bytes(&bytes),
+            None => Err(Errors::NotFound(hash.to_vec())),
+        }
+    }
+
+    fn put_node(&mut self, node: &HexNode) -> Result<Hash> {
+        let bytes = node.to_bytes();
+        let hash = self.hasher.digest_node(&bytes);
+        self.db.put(&hash, bytes)?;
+        Ok(hash)
+    }
+
+    /// Insert key-leaf entry into the tree. Returns a new root hash.
+    pub fn insert(&mut self, root: Option<&Hash>, key: &Hash, leaf: &Hash) -> Result<Option<Hash>> {
+        let path = nibbles_of(key);
+        let node = match root {
+            None => HexNode::default(),
+            Some(root) => self.read_node(root)?,
+        };
+        self.put(node, &path, 0, leaf).map(Some)
+    }
+
+    /// Put a leaf into a node at `depth` in nibbles, returning the hash of the node updated.
+    fn put(&mut self, mut node: HexNode, path: &[u8], depth: usize, leaf: &Hash) -> Result<Hash> {
+        let (slot, rest) = (path[depth] as usize, &path[depth + 1..]);
+        let cell = match node.cells[slot].take() {
+            None => Cell {
+                nibbles: rest.to_vec(),
+                hash: *leaf,
+            },
+            Some(cell) => {
+                let n = len_common(&cell.nibbles, rest);
+                if n == cell.nibbles.len() && depth + 1 + n == DEPTH {
+                    Cell {
+                        hash: *leaf,
+                        ..cell
+                    }
+                } else if n == cell.nibbles.len() {
+                    let child = self.read_node(&cell.hash)?;
+                    let hash = self.put(child, path, depth + 1 + n, leaf)?;
+                    Cell { hash, ..cell }
+                } else {
+                    // the cell splits where the key leaves it, into a node of both
+                    let mut split = HexNode::default();
+                    split.cells[cell.nibbles[n] as usize] = Some(Cell {
+                        nibbles: cell.nibbles[n + 1..].to_vec(),
+                        hash: cell.hash,
+                    });
+                    split.cells[rest[n] as usize] = Some(Cell {
+                        nibbles: rest[n + 1..].to_vec(),
+                        hash: *leaf,
+                    });
+                    Cell {
+                        nibbles: cell.nibbles[..n].to_vec(),
+                        hash: self.put_node(&split)?,
+                    }
+                }
+            }
+        };
+        node.cells[slot] = Some(cell);
+        self.put_node(&node)
+    }
+
+    /// Insert key-leaf entries into the tree in a single batch. Returns a new root hash.
+    pub fn inserts(
+        &mut self,
+        root: Option<&Hash>,
+        keys: &[Hash],
+        leaves: &[Hash],
+    ) -> Result<Option<Hash>> {
+        let mut root = root.cloned();
+        self.db.init_batch()?;
+        for (key, leaf) in keys.iter().zip(leaves.iter()) {
+            root = self.insert(root.as_ref(), key, leaf)?;
+        }
+        self.db.finish_batch()?;
+        Ok(root)
+    }
+
+    /// Get the leaf matched with a key, or `None` if the key is absent.
+    pub fn get(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        let path = nibbles_of(key);
+        let mut hash = match root {
+            None => return Ok(None),
+            Some(root) => *root,
+        };
+        let mut depth = 0;
+        loop {
+            let node = self.read_node(&hash)?;
+            let cell = match &node.cells[path[depth] as usize] {
+                None => return Ok(None),
+                Some(cell) => cell,
+            };
+            let rest = &path[depth + 1..];
+            if !rest.starts_with(&cell.nibbles) {
+                return Ok(None);
+            }
+            depth += 1 + cell.nibbles.len();
+            if depth == DEPTH {
+                return Ok(Some(cell.hash));
+            }
+            hash = cell.hash;
+        }
+    }
+
+    /// Remove the entry of a key from the tree. Returns a new root hash.
+    pub fn remove(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        let root = match root {
+            None => return Ok(None),
+            Some(root) => root,
+        };
+        let path = nibbles_of(key);
+        let node = self.read_node(root)?;
+        match self.delete(node, &path, 0)? {
+            None => Ok(Some(*root)),
+            Some(node) if node.count() == 0 => Ok(None),
+            Some(node) => self.put_node(&node).map(Some),
+        }
+    }
+
+    /// Remove a key from a node, returning the node updated, or `None` if the key is absent.
+    /// Nodes left with a single cell are merged into their parents.
+    fn delete(&mut self, mut node: HexNode, path: &[u8], depth: usize) -> Result<Option<HexNode>> {
+        let (slot, rest) = (path[depth] as usize, &path[depth + 1..]);
+        let cell = match &node.cells[slot] {
+            Some(cell) if rest.starts_with(&cell.nibbles) => cell.clone(),
+            _ => return Ok(None),
+        };
+        let depth = depth + 1 + cell.nibbles.len();
+        if depth == DEPTH {
+            node.cells[slot] = None;
+            return Ok(Some(node));
+        }
+        let child = self.read_node(&cell.hash)?;
+        let child = match self.delete(child, path, depth)? {
+            None => return Ok(None),
+            Some(child) => child,
+        };
+        node.cells[slot] = match child.count() {
+            0 => None,
+            1 => {
+                let (i, only) = child
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, cell)| cell.as_ref().map(|cell| (i, cell)))
+                    .expect("delete(): only cell");
+                let mut nibbles = cell.nibbles;
+                nibbles.push(i as u8);
+                nibbles.extend_from_slice(&only.nibbles);
+                Some(Cell {
+                    nibbles,
+                    hash: only.hash,
+                })
+            }
+            _ => Some(Cell {
+                hash: self.put_node(&child)?,
+                ..cell
+            }),
+        };
+        Ok(Some(node))
+    }
+
+    /// Remove the entries of keys from the tree in a single batch. Returns a new root hash.
+    pub fn removes(&mut self, root: Option<&Hash>, keys: &[Hash]) -> Result<Option<Hash>> {
+        let mut root = root.cloned();
+        self.db.init_batch()?;
+        for key in keys.iter() {
+            root = self.remove(root.as_ref(), key)?;
+        }
+        self.db.finish_batch()?;
+        Ok(root)
+    }
+
+    /// Get the nodes walked down along a key, proving the leaf of the key or that it is absent.
+    /// Returns `None` for an empty tree.
+    pub fn get_proof(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<HexaryProof>> {
+        let path = nibbles_of(key);
+        let mut hash = match root {
+            None => return Ok(None),
+            Some(root) => *root,
+        };
+        let (mut proof, mut depth) = (Vec::new(), 0);
+        loop {
+            let bytes = self
+                .db
+                .get(&hash)?
+                .ok_or_else(|| Errors::NotFound(hash.to_vec()))?;
+            let node = HexNode::from_bytes(&bytes)?;
+            proof.push(bytes);
+            let cell = match &node.cells[path[depth] as usize] {
+                Some(cell) if path[depth + 1..].starts_with(&cell.nibbles) => cell,
+                _ => return Ok(Some(proof)),
+            };
+            depth += 1 + cell.nibbles.len();
+            if depth == DEPTH {
+                return Ok(Some(proof));
+            }
+            hash = cell.hash;
+        }
+    }
+}
+
+/// Verify a `HexaryProof` that a key has the given leaf in the tree of `root`,
+/// or is absent from it if `leaf` is `None`.
+pub fn verify_hexary_proof<H: Hasher>(
+    hasher: &H,
+    root: Option<&Hash>,
+    key: &Hash,
+    leaf: Option<&Hash>,
+    proof: Option<&HexaryProof>,
+) -> bool {
+    let (root, proof) = match (root, proof) {
+        (None, None) => return leaf.is_none(),
+        (Some(root), Some(proof)) => (root, proof),
+        _ => return false,
+    };
+    let path = nibbles_of(key);
+    let (mut expected, mut depth) = (*root, 0);
+    for (i, bytes) in proof.iter().enumerate() {
+        if hasher.digest_node(bytes) != expected {
+            return false;
+        }
+        let node = match HexNode::from_bytes(bytes) {
+            Ok(node) => node,
+            Err(_) => return false,
+        };
+        let last = i == proof.len() - 1;
+        let cell = match &node.cells[path[depth] as usize] {
+            Some(cell) if path[depth + 1..].starts_with(&cell.nibbles) => cell,
+            _ => return last && leaf.is_none(),
+        };
+        depth += 1 + cell.nibbles.len();
+        if depth == DEPTH {
+            return last && leaf == Some(&cell.hash);
+        }
+        expected = cell.hash;
+    }
+    false
+}
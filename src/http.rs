@@ -0,0 +1,142 @@
+//! A module serving trees of `monotree` read-only over HTTP, with responses in JSON,
+//! for light clients and block explorers fetching proofs without a protocol of their own.
+//!
+//! ```text
+//! GET /root                       {"root": <hash>}, the root recorded by `Monotree::swap_root()`
+//! GET /leaf/<key>?root=<hash>     {"root": <hash>, "key": <hash>, "leaf": <hash>}
+//! GET /proof/<key>?root=<hash>    {"root": <hash>, "key": <hash>, "leaf": <hash>, "proof": <proof>}
+//! ```
+//!
+//! Hashes are in hex, or `null` for an empty tree or a key not in the tree.
+//! Without `root`, keys are looked up in the tree of the recorded root.
+//! A proof is an array of `[right, cut]` as in `TestVector::to_json()`, verified by
+//! `verify_proof()` with the leaf, or `null` if the key is not in the tree.
+//! Failures are answered with a status other than `200` and `{"error": <message>}`.
+use crate::utils::*;
+use crate::*;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+
+/// A server answering requests over HTTP from a tree, one connection at a time.
+pub struct Server<'a, D, H, C> {
+    tree: &'a mut Monotree<D, H, C>,
+}
+
+impl<'a, D, H, C> Server<'a, D, H, C>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    pub fn new(tree: &'a mut Monotree<D, H, C>) -> Self {
+        Server { tree }
+    }
+
+    /// Answer connections accepted by `listener`, a request each, until accepting fails.
+    /// A connection failing midway is dropped, so that the server goes on.
+    pub fn serve(&mut self, listener: &TcpListener) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept()?;
+            let _ = self.handle(stream);
+        }
+    }
+
+    /// Read a request from a connection and write the response to it.
+    pub fn handle<S: Read + Write>(&mut self, mut stream: S) -> Result<()> {
+        let mut reader = BufReader::new(&mut stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        // headers are of no use to read-only requests, but have to be read off the connection
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+        let (status, body) = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [method, target, _] => self.respond(method, target),
+            _ => (400, json!({ "error": "malformed request" })),
+        };
+        let body = body.to_string();
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            status,
+            reason(status),
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// Answer a request of the given method and target, such as `/proof/<key>?root=<hash>`,
+    /// with the status and the body in JSON.
+    pub fn respond(&mut self, method: &str, target: &str) -> (u16, Value) {
+        if method != "GET" {
+            return (405, json!({ "error": "method not allowed" }));
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (target, ""),
+        };
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let (key, proof) = match segments.as_slice() {
+            ["root"] => {
+                return match self.tree.load_root() {
+                    Ok(root) => (200, json!({ "root": root.map(hex::encode) })),
+                    Err(err) => (500, json!({ "error": err.to_string() })),
+                }
+            }
+            ["leaf", key] => (key, false),
+            ["proof", key] => (key, true),
+            _ => return (404, json!({ "error": "not found" })),
+        };
+        let root = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("root="));
+        let (key, root) = match (parse_hash(key), root.map(parse_hash)) {
+            (Some(key), None) => (key, None),
+            (Some(key), Some(Some(root))) => (key, Some(root)),
+            _ => return (400, json!({ "error": "invalid hash" })),
+        };
+        match self.leaf(&key, root, proof) {
+            Ok(body) => (200, body),
+            Err(err) => (500, json!({ "error": err.to_string() })),
+        }
+    }
+
+    /// Get the leaf of a key in the tree of `root`, or of the recorded root if `None`,
+    /// along with its proof if `proof`.
+    fn leaf(&mut self, key: &Hash, root: Option<Hash>, proof: bool) -> Result<Value> {
+        let root = match root {
+            Some(root) => Some(root),
+            None => self.tree.load_root()?,
+        };
+        let leaf = self.tree.get(root.as_ref(), key)?;
+        let mut body = json!({
+            "root": root.map(hex::encode),
+            "key": hex::encode(key),
+            "leaf": leaf.map(hex::encode),
+        });
+        if proof {
+            let proof = self.tree.get_merkle_proof(root.as_ref(), key)?;
+            body["proof"] = json!(proof.map(|proof| proof.to_json()));
+        }
+        Ok(body)
+    }
+}
+
+fn parse_hash(arg: &str) -> Option<Hash> {
+    hex_to_hash(arg).ok()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
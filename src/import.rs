@@ -0,0 +1,146 @@
+//! A module for importing records from exported files into `monotree`.
+use crate::*;
+use std::io::{BufRead, BufReader, Read};
+
+/// Number of records read before they are inserted into the tree at once, by default.
+pub const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+/// Formats of files the `Importer` reads.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Comma-separated values with a header row naming the columns.
+    Csv,
+    /// A JSON object per line.
+    JsonLines,
+}
+
+/// An importer streaming records into `monotree`.
+///
+/// A configured key column and value column are taken from every record.
+/// Both are encoded the way `MerkleMap` encodes its keys and values,
+/// so that an imported tree is read with `MerkleMap<String, String>` for CSV files,
+/// or `MerkleMap<serde_json::Value, serde_json::Value>` for JSON-lines files.
+#[derive(Debug)]
+pub struct Importer {
+    format: Format,
+    key: String,
+    value: String,
+    chunk_size: usize,
+}
+
+impl Importer {
+    pub fn new(format: Format, key: &str, value: &str) -> Self {
+        Importer {
+            format,
+            key: key.to_string(),
+            value: value.to_string(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the number of records inserted into the tree at once.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    /// Import all records from `reader` into the tree. Returns a new root hash.
+    pub fn import<D: Database, H: Hasher, C: NodeCodec, R: Read>(
+        &self,
+        tree: &mut Monotree<D, H, C>,
+        root: Option<&Hash>,
+        reader: R,
+    ) -> Result<Option<Hash>> {
+        self.import_with_progress(tree, root, reader, &mut |_, _| {})
+    }
+
+    /// The same as `import()`, but reports `(imported, read)` records to `progress`
+    /// each time a chunk of records is inserted.
+    pub fn import_with_progress<D: Database, H: Hasher, C: NodeCodec, R: Read>(
+        &self,
+        tree: &mut Monotree<D, H, C>,
+        root: Option<&Hash>,
+        reader: R,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Option<Hash>> {
+        let mut root = root.cloned();
+        let mut chunk: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(self.chunk_size);
+        let (mut imported, mut read) = (0, 0);
+        match self.format {
+            Format::Csv => {
+                let mut rdr = csv::Reader::from_reader(reader);
+                let headers = rdr.headers()?.clone();
+                let column = |name: &str| {
+                    headers
+                        .iter()
+                        .position(|h| h == name)
+                        .ok_or_else(|| Errors::Decode(format!("no column named {}", name)))
+                };
+                let (k, v) = (column(&self.key)?, column(&self.value)?);
+                for record in rdr.records() {
+                    let record = record?;
+                    let field = |i: usize| {
+                        record.get(i).ok_or_else(|| {
+                            Errors::Decode(format!("record {}: missing field", read))
+                        })
+                    };
+                    let key = serde_json::to_vec(field(k)?)?;
+                    let value = serde_json::to_vec(field(v)?)?;
+                    chunk.push((key, value));
+                    read += 1;
+                    if chunk.len() == self.chunk_size {
+                        imported += chunk.len();
+                        root = Importer::insert_chunk(tree, root.as_ref(), &mut chunk)?;
+                        progress(imported, read);
+                    }
+                }
+            }
+            Format::JsonLines => {
+                for line in BufReader::new(reader).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let record: serde_json::Value = serde_json::from_str(&line)?;
+                    let field = |name: &str| {
+                        record.get(name).ok_or_else(|| {
+                            Errors::Decode(format!("record {}: no field {}", read, name))
+                        })
+                    };
+                    let key = serde_json::to_vec(field(&self.key)?)?;
+                    let value = serde_json::to_vec(field(&self.value)?)?;
+                    chunk.push((key, value));
+                    read += 1;
+                    if chunk.len() == self.chunk_size {
+                        imported += chunk.len();
+                        root = Importer::insert_chunk(tree, root.as_ref(), &mut chunk)?;
+                        progress(imported, read);
+                    }
+                }
+            }
+        }
+        if !chunk.is_empty() {
+            imported += chunk.len();
+            root = Importer::insert_chunk(tree, root.as_ref(), &mut chunk)?;
+            progress(imported, read);
+        }
+        Ok(root)
+    }
+
+    fn insert_chunk<D: Database, H: Hasher, C: NodeCodec>(
+        tree: &mut Monotree<D, H, C>,
+        root: Option<&Hash>,
+        chunk: &mut Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<Option<Hash>> {
+        let mut keys = Vec::with_capacity(chunk.len());
+        let mut leaves = Vec::with_capacity(chunk.len());
+        tree.db.init_batch()?;
+        for (key, value) in chunk.drain(..) {
+            let leaf = tree.hasher.digest_leaf(&value);
+            tree.db.put(&leaf, value)?;
+            keys.push(tree.hasher.digest(&key));
+            leaves.push(leaf);
+        }
+        tree.db.finish_batch()?;
+        tree.inserts(root, &keys, &leaves)
+    }
+}
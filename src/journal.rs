@@ -0,0 +1,247 @@
+//! A module implementing an append-only journal of the updates of `monotree`, with replay.
+//!
+//! Every insert or remove made through a `Journal` is recorded under the next version,
+//! along with the root it results in, in the same batch of writes as the nodes it writes,
+//! so that the journal and the tree never disagree after a crash. `Journal::replay()` applies
+//! the updates recorded from a version on again, checking each root, to rebuild a tree
+//! from an earlier state or catch up another one, and the entries are an audit trail
+//! of how each root was derived.
+use crate::tree::METADATA_KEY;
+use crate::utils::*;
+use crate::*;
+
+/// Tag following `METADATA_KEY` in the keys the journal and its entries are stored under.
+const JOURNAL_TAG: u8 = 0xf8;
+
+/// Get the key the number of versions, or the entry of `version`, is stored under.
+///
+/// Keys extend `METADATA_KEY`, so that entries are enumerated by `Database::iter_prefix()`,
+/// in the order of versions for ordered backends, and are never taken for nodes.
+fn journal_key(version: Option<u64>) -> Vec<u8> {
+    let mut key = [&METADATA_KEY[..], &[JOURNAL_TAG]].concat();
+    if let Some(version) = version {
+        key.extend_from_slice(&version.to_be_bytes());
+    }
+    key
+}
+
+/// An update recorded in the journal: a key with its new leaf, or `None` if it was removed,
+/// and the root resulting from it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JournalEntry<const N: usize = HASH_LEN> {
+    pub key: Hash<N>,
+    pub leaf: Option<Hash<N>>,
+    pub root: Option<Hash<N>>,
+}
+
+impl<const N: usize> JournalEntry<N> {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.key.to_vec();
+        for hash in [&self.leaf, &self.root].iter() {
+            match hash {
+                Some(hash) => {
+                    bytes.push(0x01);
+                    bytes.extend_from_slice(hash);
+                }
+                None => bytes.push(0x00),
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let invalid = || Errors::Decode("JournalEntry::from_bytes(): invalid entry".to_string());
+        let key = slice_to_array(bytes.get(..N).ok_or_else(invalid)?);
+        let mut rest = &bytes[N..];
+        let mut hashes = [None, None];
+        for hash in hashes.iter_mut() {
+            match rest.first() {
+                Some(0x00) => rest = &rest[1..],
+                Some(0x01) => {
+                    *hash = Some(slice_to_array(rest.get(1..N + 1).ok_or_else(invalid)?));
+                    rest = &rest[N + 1..];
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        if !rest.is_empty() {
+            return Err(invalid());
+        }
+        Ok(JournalEntry {
+            key,
+            leaf: hashes[0],
+            root: hashes[1],
+        })
+    }
+}
+
+/// A journal of the updates of a tree, stored in its database.
+///
+/// Versions count up from `0` by every update recorded. An entry is recorded first,
+/// then the number of versions, both in the batch of the update.
+pub struct Journal<'a, D, H, C, const N: usize = HASH_LEN> {
+    tree: &'a mut Monotree<D, H, C, N>,
+}
+
+impl<'a, D, H, C, const N: usize> Journal<'a, D, H, C, N>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    pub fn new(tree: &'a mut Monotree<D, H, C, N>) -> Self {
+        Journal { tree }
+    }
+
+    /// Get the number of versions recorded.
+    pub fn len(&mut self) -> Result<u64> {
+        let key = journal_key(None);
+        match self.tree.db.get(&key)? {
+            None => Ok(0),
+            Some(bytes) if bytes.len() == 8 => Ok(bytes_to_int(&bytes)),
+            Some(_) => Err(Errors::Decode(
+                "Journal::len(): invalid journal".to_string(),
+            )),
+        }
+    }
+
+    pub fn is_empty(&mut self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Insert key-leaf entry into the tree, recording it. Returns a new root hash.
+    pub fn insert(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        leaf: &Hash<N>,
+    ) -> Result<Option<Hash<N>>> {
+        self.record(root, key, Some(leaf))
+    }
+
+    /// Remove the entry of a key from the tree, recording it. Returns a new root hash.
+    pub fn remove(&mut self, root: Option<&Hash<N>>, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        self.record(root, key, None)
+    }
+
+    fn record(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        leaf: Option<&Hash<N>>,
+    ) -> Result<Option<Hash<N>>> {
+        let version = self.len()?;
+        let (entry_key, len_key) = (journal_key(Some(version)), journal_key(None));
+        let mut updated = None;
+        self.tree.in_batch(|tree| {
+            updated = match leaf {
+                Some(leaf) => tree.insert(root, key, leaf)?,
+                None => tree.remove(root, key)?,
+            };
+            let entry = JournalEntry {
+                key: *key,
+                leaf: leaf.cloned(),
+                root: updated,
+            };
+            tree.db.put(&entry_key, entry.to_bytes())?;
+            tree.db.put(&len_key, (version + 1).to_be_bytes().to_vec())
+        })?;
+        Ok(updated)
+    }
+
+    /// Get the entry recorded as the given version. Fails if the version is not recorded yet.
+    pub fn entry(&mut self, version: u64) -> Result<JournalEntry<N>> {
+        let key = journal_key(Some(version));
+        if version >= self.len()? {
+            return Err(Errors::NotFound(key));
+        }
+        match self.tree.db.get(&key)? {
+            Some(bytes) => JournalEntry::from_bytes(&bytes),
+            None => Err(Errors::NotFound(key)),
+        }
+    }
+
+    /// Get the root before the update of the given version, which is `None` for version `0`.
+    pub fn root_before(&mut self, version: u64) -> Result<Option<Hash<N>>> {
+        match version {
+            0 => Ok(None),
+            _ => Ok(self.entry(version - 1)?.root),
+        }
+    }
+
+    /// Apply the updates recorded from `from_version` on again to the tree, in a single batch,
+    /// from the root before it. Returns the root of the last version.
+    ///
+    /// This rebuilds the nodes of the tree from a state of `from_version` on, such as
+    /// a database restored from a snapshot. Fails if any root differs from the one recorded.
+    pub fn replay(&mut self, from_version: u64) -> Result<Option<Hash<N>>> {
+        let (root, entries) = self.entries_from(from_version)?;
+        let mut replayed = root;
+        self.tree.in_batch(|tree| {
+            replayed = apply_entries(tree, root, from_version, &entries)?;
+            Ok(())
+        })?;
+        Ok(replayed)
+    }
+
+    /// Apply the updates recorded from `from_version` on to another tree holding the root
+    /// before it, in a single batch, to catch it up. Returns the root of the last version.
+    /// Fails if any root differs from the one recorded.
+    pub fn replay_into<E: Database>(
+        &mut self,
+        from_version: u64,
+        target: &mut Monotree<E, H, C, N>,
+    ) -> Result<Option<Hash<N>>> {
+        let (root, entries) = self.entries_from(from_version)?;
+        let mut replayed = root;
+        target.in_batch(|tree| {
+            replayed = apply_entries(tree, root, from_version, &entries)?;
+            Ok(())
+        })?;
+        Ok(replayed)
+    }
+
+    /// Get the root before `from_version` and the entries recorded from it on.
+    fn entries_from(
+        &mut self,
+        from_version: u64,
+    ) -> Result<(Option<Hash<N>>, Vec<JournalEntry<N>>)> {
+        let len = self.len()?;
+        if from_version > len {
+            return Err(Errors::NotFound(journal_key(Some(from_version))));
+        }
+        let root = self.root_before(from_version)?;
+        let entries = (from_version..len)
+            .map(|version| self.entry(version))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((root, entries))
+    }
+}
+
+/// Apply entries in order from `root`, checking the root of each against the one recorded.
+fn apply_entries<D, H, C, const N: usize>(
+    tree: &mut Monotree<D, H, C, N>,
+    root: Option<Hash<N>>,
+    from_version: u64,
+    entries: &[JournalEntry<N>],
+) -> Result<Option<Hash<N>>>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    let mut root = root;
+    for (i, entry) in entries.iter().enumerate() {
+        root = match entry.leaf {
+            Some(leaf) => tree.insert(root.as_ref(), &entry.key, &leaf)?,
+            None => tree.remove(root.as_ref(), &entry.key)?,
+        };
+        if root != entry.root {
+            return Err(Errors::new(&format!(
+                "replay(): root of version {} differs from the one recorded",
+                from_version + i as u64
+            )));
+        }
+    }
+    Ok(root)
+}
@@ -103,6 +103,24 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! ## Without `std`
+//! With the default feature `std` off, only the core verifying proofs and decoding nodes is built,
+//! with `no_std` + `alloc`: `verify`, `node`, `bits`, `wire` and `Hasher`, along with the hashers
+//! of `hasher-blake2`, `hasher-blake3`, `hasher-sha2` and `hasher-sha3`,
+//! for embedded devices and enclaves verifying proofs of trees served by others.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(not(feature = "std"))]
+pub(crate) use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 
 /// Size of fixed length byte-array from a `Hasher`. Equivalent to `key` length of `monotree`.
 pub const HASH_LEN: usize = 32;
@@ -111,56 +129,262 @@ pub const HASH_LEN: usize = 32;
 pub type BitsLen = u16;
 
 /// A `Result` type redefined for error handling. The same as `std::result::Result<T, Errors>`.
-pub type Result<T> = std::result::Result<T, Errors>;
+pub type Result<T> = core::result::Result<T, Errors>;
 
-/// A type indicating fixed length byte-array. This has the length of `HASH_LEN`.
-pub type Hash = [u8; HASH_LEN];
+/// A type indicating fixed length byte-array. This has the length of `HASH_LEN` by default,
+/// or that of digests of hashers generic over their length, as `Hasher<N>`.
+pub type Hash<const N: usize = HASH_LEN> = [u8; N];
 
 /// A type representing _Merkle proof_.
 pub type Proof = Vec<(bool, Vec<u8>)>;
 
+/// A type representing a proof of _non-inclusion_: serialized nodes walked down along a key.
+pub type NonInclusionProof = Vec<Vec<u8>>;
+
+/// A type representing a _Merkle proof_ of multiple keys: serialized nodes walked down along
+/// the keys in depth-first order, each led by a mask of its cells of which hashes are cut out.
+pub type MultiProof = Vec<Vec<u8>>;
+
+/// A type representing _Merkle proofs_ chained through nested trees, from the outermost tree.
+pub type ChainedProof = Vec<Proof>;
+
+/// A type representing serialized nodes, each with its hash as a key in database.
+pub type NodeSet<const N: usize = HASH_LEN> = Vec<(Hash<N>, Vec<u8>)>;
+
 /// A type indicating database selected by default.
+#[cfg(feature = "std")]
 pub type DefaultDatabase = database::MemoryDB;
 
 /// A type indicating hasher selected by default.
+#[cfg(feature = "hasher-blake3")]
 pub type DefaultHasher = hasher::Blake3;
 
-pub use self::bits::Bits;
+/// A type indicating node codec selected by default.
+pub type DefaultCodec = node::FixedCodec;
+
+pub use self::bits::{BitOrder, Bits, ByteOrder, Traversal};
+#[cfg(feature = "std")]
+pub use self::concurrent::SyncMonotree;
+#[cfg(feature = "std")]
 pub use self::database::Database;
-pub use self::hasher::Hasher;
-pub use self::node::{Cell, Node, Unit};
-pub use self::tree::{verify_proof, Monotree};
+#[cfg(feature = "std")]
+pub use self::filter::BloomFilter;
+pub use self::hasher::{Hasher, LeafPolicy};
+#[cfg(feature = "std")]
+pub use self::hexary::HexaryTree;
+#[cfg(feature = "std")]
+pub use self::journal::Journal;
+#[cfg(feature = "json")]
+pub use self::map::MerkleMap;
+#[cfg(feature = "std")]
+pub use self::metrics::Metrics;
+pub use self::node::{Cell, Node, NodeCodec, Unit};
+#[cfg(feature = "std")]
+pub use self::partial::PartialTree;
+#[cfg(feature = "std")]
+pub use self::policy::{BatchSummary, CommitPolicy, Verdict};
+#[cfg(feature = "std")]
+pub use self::roots::RootStore;
+#[cfg(feature = "signing")]
+pub use self::signing::{RootSigner, SignedProof};
+#[cfg(feature = "std")]
+pub use self::store::MerkleStore;
+#[cfg(feature = "std")]
+pub use self::tree::{
+    agreed_root, verify_consistency, verify_proof_with_quorum, verify_removal, verify_update,
+    BatchBuilder, CommitReport, Consistency, ConsistencyProof, Damage, DamagedNode, Diff, Metadata,
+    Monotree, ProofPage, Quorum, RemovalProof, Replay, RootChange, Transaction, TreeStats,
+    UpdateProof,
+};
+pub use self::verify::{
+    compute_root, verify_chained_proof, verify_leaf_proof, verify_merkle_proofs,
+    verify_non_inclusion_proof, verify_proof, verify_value_proof, LeafProof,
+};
+#[cfg(feature = "json")]
+pub use self::wire::ProofJson;
+pub use self::wire::{ProofBytes, ProofShape};
 
 #[derive(Debug)]
-/// An `Error` type defiend for handling general errors.
-pub struct Errors {
-    details: String,
+/// An `Error` type defiend for handling errors by their kinds.
+pub enum Errors {
+    /// Failures of database backends.
+    Db(String),
+    /// Bytes failing to decode, such as corrupted nodes, metadata or input records.
+    Decode(String),
+    /// An entry referred to by its hash, such as a node or a value, is missing in database.
+    NotFound(Vec<u8>),
+    /// Nodes or proofs received do not hash to what they are meant to.
+    ProofInvalid(String),
+    /// An operation stopped by a cancellation token.
+    Cancelled,
+    /// A batch rejected by the `CommitPolicy` of the tree, with the reason given.
+    Vetoed(String),
+    /// A request to a networked backend not answered within its timeout.
+    Timeout(String),
+    /// Failures of I/O.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+    /// Errors of any other kind.
+    Other(String),
 }
 
 impl Errors {
+    /// Construct an error of `Errors::Other` with the given message.
     pub fn new(msg: &str) -> Errors {
-        Errors {
-            details: msg.to_string(),
-        }
+        Errors::Other(msg.to_string())
     }
 }
 
-impl std::fmt::Display for Errors {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.details)
+impl core::fmt::Display for Errors {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Errors::Db(msg) => write!(f, "database: {}", msg),
+            Errors::Decode(msg) => write!(f, "decode: {}", msg),
+            Errors::NotFound(hash) => {
+                write!(f, "not found: ")?;
+                hash.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+            Errors::ProofInvalid(msg) => write!(f, "invalid proof: {}", msg),
+            Errors::Cancelled => write!(f, "cancelled"),
+            Errors::Vetoed(reason) => write!(f, "vetoed: {}", reason),
+            Errors::Timeout(msg) => write!(f, "timeout: {}", msg),
+            #[cfg(feature = "std")]
+            Errors::Io(err) => write!(f, "io: {}", err),
+            Errors::Other(msg) => write!(f, "{}", msg),
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for Errors {
-    fn description(&self) -> &str {
-        &self.details
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Errors::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Errors {
+    fn from(err: std::io::Error) -> Self {
+        Errors::Io(err)
+    }
+}
+
+#[cfg(feature = "cli")]
+impl From<csv::Error> for Errors {
+    fn from(err: csv::Error) -> Self {
+        Errors::Decode(err.to_string())
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Errors {
+    fn from(err: serde_json::Error) -> Self {
+        Errors::Decode(err.to_string())
+    }
+}
+
+#[cfg(feature = "metrics-prometheus")]
+impl From<prometheus::Error> for Errors {
+    fn from(err: prometheus::Error) -> Self {
+        Errors::new(&err.to_string())
+    }
+}
+
+#[cfg(feature = "db-redis")]
+impl From<redis::RedisError> for Errors {
+    fn from(err: redis::RedisError) -> Self {
+        match (err.is_timeout(), err.is_io_error()) {
+            (true, _) => Errors::Timeout(format!("redis: {}", err)),
+            (false, true) => Errors::Io(std::io::Error::other(err)),
+            (false, false) => Errors::Db(format!("redis: {}", err)),
+        }
+    }
+}
+
+#[cfg(feature = "db-object-store")]
+impl From<object_store::Error> for Errors {
+    fn from(err: object_store::Error) -> Self {
+        Errors::Db(format!("object store: {}", err))
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<tonic::Status> for Errors {
+    fn from(status: tonic::Status) -> Self {
+        match status.code() {
+            tonic::Code::Cancelled => Errors::Cancelled,
+            tonic::Code::DeadlineExceeded => Errors::Timeout(format!("grpc: {}", status.message())),
+            _ => Errors::Db(format!("grpc: {}", status.message())),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl From<tokio::task::JoinError> for Errors {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Errors::new(&err.to_string())
     }
 }
 
 #[macro_use]
 pub mod utils;
+#[cfg(feature = "std")]
+pub mod anchor;
 pub mod bits;
+#[cfg(feature = "std")]
+pub mod concurrent;
+#[cfg(feature = "std")]
 pub mod database;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod hasher;
+#[cfg(feature = "std")]
+pub mod hexary;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "cli")]
+pub mod import;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "json")]
+pub mod map;
+#[cfg(feature = "std")]
+pub mod metrics;
 pub mod node;
+#[cfg(feature = "async")]
+pub mod nonblocking;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "std")]
+pub mod partial;
+#[cfg(feature = "std")]
+pub mod policy;
+#[cfg(feature = "std")]
+pub mod queue;
+#[cfg(feature = "std")]
+pub mod roots;
+#[cfg(feature = "shell")]
+pub mod shell;
+#[cfg(feature = "signing")]
+pub mod signing;
+#[cfg(feature = "std")]
+pub mod smt;
+#[cfg(feature = "std")]
+pub mod store;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "std")]
 pub mod tree;
+#[cfg(feature = "test-vectors")]
+pub mod vectors;
+pub mod verify;
+#[cfg(feature = "std")]
+pub mod view;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod wire;
@@ -0,0 +1,96 @@
+//! A module implementing a typed key-value map on top of `monotree`.
+use crate::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+/// A key-value map backed by `monotree`, taking any `serde` types as keys and values.
+///
+/// Keys are serialized and hashed into the tree keys.
+/// Values are serialized and stored in the database under their hashes,
+/// which are inserted into the tree as leaves.
+/// Thus a Merkle proof given by `MerkleMap::prove()` is verified
+/// against the leaf from `MerkleMap::leaf()`, just like with `verify_proof()`.
+#[derive(Debug)]
+pub struct MerkleMap<K, V, D = DefaultDatabase, H = DefaultHasher, C = DefaultCodec> {
+    pub tree: Monotree<D, H, C>,
+    root: Option<Hash>,
+    marker: PhantomData<(K, V)>,
+}
+
+impl<K, V, D, H, C> MerkleMap<K, V, D, H, C>
+where
+    K: Serialize,
+    V: Serialize + DeserializeOwned,
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    pub fn new(dbpath: &str) -> Self {
+        MerkleMap::with_tree(Monotree::new(dbpath), None)
+    }
+
+    /// The same as `new()`, but returns an error instead of panicking if the tree fails to open.
+    pub fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(MerkleMap::with_tree(Monotree::try_new(dbpath)?, None))
+    }
+
+    /// Construct `MerkleMap` from an existing tree and its root.
+    pub fn with_tree(tree: Monotree<D, H, C>, root: Option<Hash>) -> Self {
+        MerkleMap {
+            tree,
+            root,
+            marker: PhantomData,
+        }
+    }
+
+    /// Get the current root hash of the map.
+    pub fn root(&self) -> Option<Hash> {
+        self.root
+    }
+
+    /// Get the tree key a key is mapped to.
+    pub fn key(&self, key: &K) -> Result<Hash> {
+        Ok(self.tree.hasher.digest(&serde_json::to_vec(key)?))
+    }
+
+    /// Get the leaf a value is mapped to. This is where the Merkle proof starts off.
+    pub fn leaf(&self, value: &V) -> Result<Hash> {
+        Ok(self.tree.hasher.digest_leaf(&serde_json::to_vec(value)?))
+    }
+
+    /// Insert key-value entry into the map. Returns a new root hash.
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<Option<Hash>> {
+        let bytes = serde_json::to_vec(value)?;
+        let leaf = self.tree.hasher.digest_leaf(&bytes);
+        self.tree.db.put(&leaf, bytes)?;
+        let key = self.key(key)?;
+        self.root = self.tree.insert(self.root.as_ref(), &key, &leaf)?;
+        Ok(self.root)
+    }
+
+    /// Get the value matched with a key.
+    pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+        let key = self.key(key)?;
+        match self.tree.get(self.root.as_ref(), &key)? {
+            None => Ok(None),
+            Some(leaf) => match self.tree.db.get(&leaf)? {
+                None => Err(Errors::NotFound(leaf.to_vec())),
+                Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            },
+        }
+    }
+
+    /// Remove the entry of a key from the map. Returns a new root hash.
+    pub fn remove(&mut self, key: &K) -> Result<Option<Hash>> {
+        let key = self.key(key)?;
+        self.root = self.tree.remove(self.root.as_ref(), &key)?;
+        Ok(self.root)
+    }
+
+    /// Generate a Merkle proof for a key against the current root.
+    pub fn prove(&mut self, key: &K) -> Result<Option<Proof>> {
+        let key = self.key(key)?;
+        self.tree.get_merkle_proof(self.root.as_ref(), &key)
+    }
+}
@@ -0,0 +1,232 @@
+//! A module defining metrics of `monotree`, collected from trees and caches as they run.
+//!
+//! Operators set one by `Monotree::set_metrics()` and `CachedDb::set_metrics()`
+//! to see where the time of a node goes, such as to export the counters to a monitoring system,
+//! or to open spans of a tracing framework around batches.
+use crate::tree::CommitReport;
+#[cfg(feature = "metrics-prometheus")]
+use crate::Result;
+#[cfg(feature = "metrics-prometheus")]
+use prometheus::core::Collector;
+#[cfg(feature = "metrics-prometheus")]
+use prometheus::{Gauge, Histogram, HistogramOpts, IntCounter, IntGauge, Opts, Registry};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A trait receiving events of trees and caches, shared by any number of them.
+/// Every event is ignored by default.
+pub trait Metrics: fmt::Debug + Send + Sync {
+    /// Nodes read from database, by a single read or by a read of many at once.
+    fn nodes_read(&self, _count: u64) {}
+    /// A node written to database, in bytes of its hash plus its serialized form.
+    fn node_written(&self, _bytes: u64) {}
+    /// A lookup of `CachedDb`, which hit or missed the cache.
+    fn cache_lookup(&self, _hit: bool) {}
+    /// A Merkle proof generated, in bytes of its encoding by `ProofBytes` for a `Proof`,
+    /// or of its nodes for a `MultiProof`.
+    fn proof_generated(&self, _bytes: u64) {}
+    /// The time taken to generate the proof of `proof_generated()`, reported right after it.
+    fn proof_timed(&self, _duration: Duration) {}
+    /// A batch committed by `inserts()`, `removes()` and the like,
+    /// with its own costs and the time taken from opening it to committing it.
+    fn batch_committed(&self, _report: &CommitReport) {}
+}
+
+/// `Metrics` summing up events in counters, read at any time by any thread.
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub nodes_read: AtomicU64,
+    pub nodes_written: AtomicU64,
+    pub bytes_written: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    pub proofs: AtomicU64,
+    pub proof_bytes: AtomicU64,
+    /// Time taken by the proofs generated, in microseconds.
+    pub proof_micros: AtomicU64,
+    pub batches: AtomicU64,
+    /// Time taken by the batches committed, in microseconds.
+    pub batch_micros: AtomicU64,
+}
+
+impl Counters {
+    /// Get the ratio of lookups hitting the cache, `0.0` if there is none yet.
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.cache_hits.load(Ordering::Relaxed);
+        let lookups = hits + self.cache_misses.load(Ordering::Relaxed);
+        match lookups {
+            0 => 0.0,
+            _ => hits as f64 / lookups as f64,
+        }
+    }
+}
+
+impl Metrics for Counters {
+    fn nodes_read(&self, count: u64) {
+        self.nodes_read.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn node_written(&self, bytes: u64) {
+        self.nodes_written.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn cache_lookup(&self, hit: bool) {
+        match hit {
+            true => self.cache_hits.fetch_add(1, Ordering::Relaxed),
+            false => self.cache_misses.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    fn proof_generated(&self, bytes: u64) {
+        self.proofs.fetch_add(1, Ordering::Relaxed);
+        self.proof_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn proof_timed(&self, duration: Duration) {
+        self.proof_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn batch_committed(&self, report: &CommitReport) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.batch_micros
+            .fetch_add(report.duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Upper bounds of the buckets of the latencies exported by `Prometheus`, in seconds.
+#[cfg(feature = "metrics-prometheus")]
+pub const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.25, 1.0,
+];
+
+/// `Metrics` registered as collectors in a `prometheus::Registry` of the host application,
+/// to be gathered and served at the endpoint it is scraped on along with its own metrics.
+///
+/// Metrics are named `<namespace>_<name>`: the tree size as a gauge set by `set_tree_size()`,
+/// the counters of `Counters`, the cache hit ratio as a gauge, and the latencies of
+/// batch commits and proof generation as histograms over `LATENCY_BUCKETS`.
+#[cfg(feature = "metrics-prometheus")]
+#[derive(Debug)]
+pub struct Prometheus {
+    counters: Counters,
+    tree_size: IntGauge,
+    nodes_read: IntCounter,
+    nodes_written: IntCounter,
+    bytes_written: IntCounter,
+    cache_hits: IntCounter,
+    cache_misses: IntCounter,
+    cache_hit_ratio: Gauge,
+    proofs: IntCounter,
+    proof_bytes: IntCounter,
+    batch_latency: Histogram,
+    proof_latency: Histogram,
+}
+
+#[cfg(feature = "metrics-prometheus")]
+impl Prometheus {
+    /// Create the collectors and register them in `registry`,
+    /// failing if metrics of the same names are registered in it already.
+    pub fn new(namespace: &str, registry: &Registry) -> Result<Self> {
+        let opts = |name: &str, help: &str| Opts::new(name, help).namespace(namespace);
+        let latency = |name: &str, help: &str| {
+            HistogramOpts::new(name, help)
+                .namespace(namespace)
+                .buckets(LATENCY_BUCKETS.to_vec())
+        };
+        let counter =
+            |name: &str, help: &str| register(registry, IntCounter::with_opts(opts(name, help))?);
+        Ok(Prometheus {
+            counters: Counters::default(),
+            tree_size: register(
+                registry,
+                IntGauge::with_opts(opts("tree_size_bytes", "Size of the tree in database."))?,
+            )?,
+            nodes_read: counter("nodes_read_total", "Nodes read from database.")?,
+            nodes_written: counter("nodes_written_total", "Nodes written to database.")?,
+            bytes_written: counter("bytes_written_total", "Bytes of nodes written to database.")?,
+            cache_hits: counter("cache_hits_total", "Lookups hitting the cache.")?,
+            cache_misses: counter("cache_misses_total", "Lookups missing the cache.")?,
+            cache_hit_ratio: register(
+                registry,
+                Gauge::with_opts(opts(
+                    "cache_hit_ratio",
+                    "Ratio of lookups hitting the cache.",
+                ))?,
+            )?,
+            proofs: counter("proofs_total", "Merkle proofs generated.")?,
+            proof_bytes: counter("proof_bytes_total", "Bytes of Merkle proofs generated.")?,
+            batch_latency: register(
+                registry,
+                Histogram::with_opts(latency(
+                    "batch_commit_seconds",
+                    "Time taken by batches committed.",
+                ))?,
+            )?,
+            proof_latency: register(
+                registry,
+                Histogram::with_opts(latency(
+                    "proof_seconds",
+                    "Time taken to generate Merkle proofs.",
+                ))?,
+            )?,
+        })
+    }
+
+    /// Get the counters summed up so far.
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Set the tree size in bytes, such as by `Database::total_size()` after a batch.
+    pub fn set_tree_size(&self, bytes: u64) {
+        self.tree_size.set(bytes as i64);
+    }
+}
+
+#[cfg(feature = "metrics-prometheus")]
+fn register<C: Collector + Clone + 'static>(registry: &Registry, collector: C) -> Result<C> {
+    registry.register(Box::new(collector.clone()))?;
+    Ok(collector)
+}
+
+#[cfg(feature = "metrics-prometheus")]
+impl Metrics for Prometheus {
+    fn nodes_read(&self, count: u64) {
+        self.counters.nodes_read(count);
+        self.nodes_read.inc_by(count);
+    }
+
+    fn node_written(&self, bytes: u64) {
+        self.counters.node_written(bytes);
+        self.nodes_written.inc();
+        self.bytes_written.inc_by(bytes);
+    }
+
+    fn cache_lookup(&self, hit: bool) {
+        self.counters.cache_lookup(hit);
+        match hit {
+            true => self.cache_hits.inc(),
+            false => self.cache_misses.inc(),
+        }
+        self.cache_hit_ratio.set(self.counters.hit_rate());
+    }
+
+    fn proof_generated(&self, bytes: u64) {
+        self.counters.proof_generated(bytes);
+        self.proofs.inc();
+        self.proof_bytes.inc_by(bytes);
+    }
+
+    fn proof_timed(&self, duration: Duration) {
+        self.counters.proof_timed(duration);
+        self.proof_latency.observe(duration.as_secs_f64());
+    }
+
+    fn batch_committed(&self, report: &CommitReport) {
+        self.counters.batch_committed(report);
+        self.batch_latency.observe(report.duration.as_secs_f64());
+    }
+}
@@ -1,6 +1,88 @@
 //! A module for defining `Node` used in `monotree`.
 use crate::utils::*;
 use crate::*;
+use core::convert::TryFrom;
+
+/// Latest version of the format nodes are serialized in, tagged in the indicator of every node.
+///
+/// Version `0` writes ranges of `Bits` with a fixed length, as `FixedCodec` and earlier releases,
+/// and version `1` as varints, as `CompactCodec`. Nodes of any version up to this one are decoded,
+/// and those of later versions fail with `Errors::Decode` rather than being misread.
+pub const NODE_VERSION: u8 = 1;
+
+/// Indicator of a soft node.
+const SOFT: u8 = indicator(0, false);
+/// Indicator of a hard node.
+const HARD: u8 = indicator(0, true);
+/// Indicator of a soft node with ranges as varints.
+const SOFT_COMPACT: u8 = indicator(1, false);
+/// Indicator of a hard node with ranges as varints.
+const HARD_COMPACT: u8 = indicator(1, true);
+
+/// Get the indicator of a node of the given version, the version above a bit of hard or soft.
+const fn indicator(version: u8, hard: bool) -> u8 {
+    (version << 1) | hard as u8
+}
+
+/// A trait defining how `Node`s are serialized into bytes stored in database.
+///
+/// `Merkle proof`s cut the hash of a cell out of serialized nodes, so any codec has to
+/// place the hash of a soft node and of the left cell of a hard node at the very beginning,
+/// and the hash of the right cell of a hard node right before the last byte.
+/// Codecs serve nodes with hashes of any length `N`.
+pub trait NodeCodec {
+    /// Identifier of the codec recorded in `Metadata`.
+    const ID: u8;
+    /// Version of the format of nodes the codec writes, see `NODE_VERSION`.
+    const VERSION: u8 = NODE_VERSION;
+    /// Whether removals merge a child left with a single cell into the cell of its parent,
+    /// so that a tree is shaped by its entries alone, whichever updates built it.
+    /// Off for nodes of version `0`, so that their trees keep the roots earlier releases gave.
+    const MERGE_ON_REMOVE: bool = Self::VERSION > 0;
+    fn encode<const N: usize>(node: &Node<N>) -> Result<Vec<u8>>;
+    fn decode<const N: usize>(bytes: &[u8]) -> Result<Node<'_, N>>;
+}
+
+/// A codec writing ranges of `Bits` with a fixed length of `BitsLen`,
+/// so that every field of a node is found at a position known from the lengths of `Bits`.
+///
+/// This is the default, writing nodes byte for byte as earlier releases did,
+/// so that trees written by them keep their roots.
+#[derive(Debug)]
+pub struct FixedCodec;
+
+impl NodeCodec for FixedCodec {
+    const ID: u8 = 0x00;
+    const VERSION: u8 = 0;
+
+    fn encode<const N: usize>(node: &Node<N>) -> Result<Vec<u8>> {
+        node.to_bytes()
+    }
+
+    fn decode<const N: usize>(bytes: &[u8]) -> Result<Node<'_, N>> {
+        Node::from_bytes(bytes)
+    }
+}
+
+/// A codec writing ranges of `Bits` as varints, which takes 2 bytes less for most cells.
+///
+/// Nodes differ in their bytes from those of `FixedCodec`, so trees of this codec
+/// have other roots than those of the same entries by the default codec.
+/// Removals also merge nodes left with a single cell, see `NodeCodec::MERGE_ON_REMOVE`.
+#[derive(Debug)]
+pub struct CompactCodec;
+
+impl NodeCodec for CompactCodec {
+    const ID: u8 = 0x01;
+
+    fn encode<const N: usize>(node: &Node<N>) -> Result<Vec<u8>> {
+        node.to_compact_bytes()
+    }
+
+    fn decode<const N: usize>(bytes: &[u8]) -> Result<Node<'_, N>> {
+        Node::from_bytes(bytes)
+    }
+}
 
 /// A type for describing components of `Node`: a real element `Unit` or a virtual element `None`.
 pub type Cell<'a> = Option<Unit<'a>>;
@@ -35,24 +117,34 @@ pub struct Unit<'a> {
 /// By default `HashLen = 32`, `BitsLen = 2`.
 ///
 /// _SoftNode_ = `Cell` + `0x00`(1), where    
-/// `Cell` = `hash`(`HASH_LEN`) + `path`(`< HASH_LEN`) + `range_start`(`BitsLen`) + `range_end`(`BitsLen`).   
+/// `Cell` = `hash`(`HASH_LEN`) + `range_start`(`BitsLen`) + `range_end`(`BitsLen`) + `path`(`<= HASH_LEN`).   
 /// `0x00` is an indicator for soft node.  
 ///
 /// _HardNode_ = `Cell_L` + `Cell_R` + `0x01`(1), where    
-/// `Cell_L` = `hash_L`(`HASH_LEN`) + `path_L`(`< HASH_LEN`) + `range_L_start`(`BitsLen`) + `range_L_end`(`BitsLen`)   
-/// `Cell_R` = `path_R`(`< HASH_LEN`) _ `range_R_start`(`BitsLen`) + `range_R_end`(`BitsLen`) + `hash_R`(`HASH_LEN`).   
+/// `Cell_L` = `hash_L`(`HASH_LEN`) + `range_L_start`(`BitsLen`) + `range_L_end`(`BitsLen`) + `path_L`(`<= HASH_LEN`)   
+/// `Cell_R` = `range_R_start`(`BitsLen`) + `range_R_end`(`BitsLen`) + `path_R`(`<= HASH_LEN`) + `hash_R`(`HASH_LEN`).   
 /// `0x01` is an indicator for hard node.
 ///
+/// `path` holds the bytes the range spans as sliced from the key inserted, including
+/// the bits out of the range, so that nodes are serialized byte for byte as earlier releases did.
+///
+/// Nodes written by `CompactCodec` have ranges written as _varints_ (`LEB128`) instead,
+/// which takes 1 or 2 bytes each, indicated by `0x02` (soft) and `0x03` (hard).
+///
+/// The indicator tags the version of the format, `(version << 1) | hard`, see `NODE_VERSION`.
+///
 /// To make ***Merkle proof*** easier, we purposely placed the _hashes_ on outskirts of the serialized form.
 /// With only 1-bit information of left or right, provers can easily guess
 /// which side the hash he holds should be appended for the next step.
 /// Refer to `verify_proof()` implementation regarding on this discussion.
-pub enum Node<'a> {
+///
+/// Hashes of cells are of `HASH_LEN`, or of the length `N` of digests of the hasher of a tree.
+pub enum Node<'a, const N: usize = HASH_LEN> {
     Soft(Cell<'a>),
     Hard(Cell<'a>, Cell<'a>),
 }
 
-impl<'a> Node<'a> {
+impl<'a, const N: usize> Node<'a, N> {
     pub fn new(lc: Cell<'a>, rc: Cell<'a>) -> Self {
         match (&lc, &rc) {
             (&Some(_), &None) => Node::Soft(lc),
@@ -64,77 +156,158 @@ impl<'a> Node<'a> {
 
     /// Construct `Cell`s by deserializing bytes slice.
     pub fn cells_from_bytes(bytes: &'a [u8], right: bool) -> Result<(Cell<'a>, Cell<'a>)> {
-        match Node::from_bytes(&bytes)? {
-            Node::Soft(cell) => Ok((cell, None)),
+        Ok(Self::from_bytes(bytes)?.into_cells(right))
+    }
+
+    /// Get `Cell`s of `Node`, led by the cell of the given side for a hard node.
+    pub fn into_cells(self, right: bool) -> (Cell<'a>, Cell<'a>) {
+        match self {
+            Node::Soft(cell) => (cell, None),
             Node::Hard(lc, rc) => {
                 if right {
-                    Ok((rc, lc))
+                    (rc, lc)
                 } else {
-                    Ok((lc, rc))
+                    (lc, rc)
                 }
             }
         }
     }
 
-    fn parse_bytes(bytes: &'a [u8], right: bool) -> Result<(Cell<'a>, usize)> {
+    fn parse_bytes(bytes: &'a [u8], right: bool, compact: bool) -> Result<(Cell<'a>, usize)> {
         let len_bytes = bytes.len();
-        let len_bits = std::mem::size_of::<BitsLen>();
-        let offset_hash = if right { 0usize } else { HASH_LEN };
+        let malformed = || Errors::Decode("Self::parse_bytes(): malformed node".to_string());
+        let len_bits = core::mem::size_of::<BitsLen>();
+        let len_min = if compact { N } else { N + 2 * len_bits };
+        if len_bytes < len_min {
+            return Err(malformed());
+        }
+        let offset_hash = if right { 0usize } else { N };
         let range_hash = if right {
-            len_bytes - HASH_LEN..len_bytes
+            len_bytes - N..len_bytes
+        } else {
+            0..N
+        };
+        let (start, end, offset_path): (BitsLen, BitsLen, usize) = if compact {
+            let (start, n) = varint_to_int(&bytes[offset_hash..])?;
+            let (end, m) = varint_to_int(&bytes[offset_hash + n..])?;
+            // ranges over `BitsLen` are malformed, rather than narrowed
+            let narrow = |int: u64| BitsLen::try_from(int).map_err(|_| malformed());
+            (narrow(start)?, narrow(end)?, offset_hash + n + m)
         } else {
-            0..HASH_LEN
+            let start = bytes_to_int(&bytes[offset_hash..offset_hash + len_bits]);
+            let end = bytes_to_int(&bytes[offset_hash + len_bits..offset_hash + 2 * len_bits]);
+            (start, end, offset_hash + 2 * len_bits)
         };
-        let start: BitsLen = bytes_to_int(&bytes[offset_hash..offset_hash + len_bits]);
-        let end: BitsLen = bytes_to_int(&bytes[offset_hash + len_bits..offset_hash + 2 * len_bits]);
+        if start > end {
+            return Err(malformed());
+        }
         let offset_bits = nbytes_across(start, end) as usize;
+        if offset_path + offset_bits > len_bytes {
+            return Err(malformed());
+        }
         Ok((
             Some(Unit {
                 hash: &bytes[range_hash],
                 bits: Bits {
-                    path: &bytes
-                        [offset_hash + 2 * len_bits..offset_hash + 2 * len_bits + offset_bits],
+                    path: &bytes[offset_path..offset_path + offset_bits],
                     range: start..end,
                 },
             }),
-            offset_hash + 2 * len_bits + offset_bits,
+            offset_path + offset_bits,
         ))
     }
 
-    /// Construct `Node` by deserializing bytes slice.
-    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+    /// Get the version of the format a serialized node is in, see `NODE_VERSION`.
+    pub fn version(bytes: &[u8]) -> Result<u8> {
         match bytes.last() {
-            Some(&0x00) => {
-                let (cell, _) = Node::parse_bytes(&bytes[..bytes.len() - 1], false)?;
-                Ok(Node::Soft(cell))
+            Some(&indicator) => Ok(indicator >> 1),
+            None => Err(Errors::Decode("Node::version(): empty node".to_string())),
+        }
+    }
+
+    /// Construct `Node` by deserializing bytes slice, of any version up to `NODE_VERSION`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        let compact = match Self::version(bytes)? {
+            0 => false,
+            1 => true,
+            version => {
+                return Err(Errors::Decode(format!(
+                    "Node::from_bytes(): node of version {} later than {}",
+                    version, NODE_VERSION
+                )))
             }
-            Some(&0x01) => {
-                let (lc, size) = Node::parse_bytes(&bytes, false)?;
-                let (rc, _) = Node::parse_bytes(&bytes[size..bytes.len() - 1], true)?;
-                Ok(Node::Hard(lc, rc))
+        };
+        if bytes[bytes.len() - 1] & 1 == 0 {
+            let (cell, _) = Self::parse_bytes(&bytes[..bytes.len() - 1], false, compact)?;
+            Ok(Node::Soft(cell))
+        } else {
+            let (lc, size) = Self::parse_bytes(bytes, false, compact)?;
+            if size >= bytes.len() {
+                let msg = "Node::from_bytes(): truncated hard node";
+                return Err(Errors::Decode(msg.to_string()));
             }
-            _ => unreachable!("Node::from_bytes()"),
+            let (rc, _) = Self::parse_bytes(&bytes[size..bytes.len() - 1], true, compact)?;
+            Ok(Node::Hard(lc, rc))
         }
     }
 
     /// Serialize `Node` into bytes.
     pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.serialize(true)
+    }
+
+    /// Serialize `Node` into bytes with ranges of `Bits` as varints.
+    pub fn to_compact_bytes(&self) -> Result<Vec<u8>> {
+        self.serialize(false)
+    }
+
+    fn serialize(&self, fixed: bool) -> Result<Vec<u8>> {
+        // a single buffer of capacity enough for the ranges of both units as varints
+        let capacity = |unit: &Unit| unit.hash.len() + unit.bits.path.len() + 6;
         match self {
             Node::Soft(Some(unit)) => {
-                Ok([&unit.hash[..], &unit.bits.to_bytes()?, &[0x00]].concat())
+                let mut bytes = Vec::with_capacity(capacity(unit) + 1);
+                bytes.extend_from_slice(unit.hash);
+                unit.bits.write_bytes(&mut bytes, fixed);
+                bytes.push(if fixed { SOFT } else { SOFT_COMPACT });
+                Ok(bytes)
             }
             Node::Hard(Some(lu), Some(ru)) => {
                 let (lu, ru) = if ru.bits.first() { (lu, ru) } else { (ru, lu) };
-                Ok([
-                    &lu.hash[..],
-                    &lu.bits.to_bytes()?,
-                    &ru.bits.to_bytes()?,
-                    &ru.hash[..],
-                    &[0x01],
-                ]
-                .concat())
+                let mut bytes = Vec::with_capacity(capacity(lu) + capacity(ru) + 1);
+                bytes.extend_from_slice(lu.hash);
+                lu.bits.write_bytes(&mut bytes, fixed);
+                ru.bits.write_bytes(&mut bytes, fixed);
+                bytes.extend_from_slice(ru.hash);
+                bytes.push(if fixed { HARD } else { HARD_COMPACT });
+                Ok(bytes)
             }
             _ => unreachable!("node.to_bytes()"),
         }
     }
 }
+
+/// `Node`s are serialized as the bytes of `Node::to_bytes()`.
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for Node<'_, N> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        let bytes = self.to_bytes().map_err(serde::ser::Error::custom)?;
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+/// `Node`s borrow their cells from the bytes deserialized, so they are deserialized
+/// only from formats handing out borrowed bytes, such as CBOR.
+/// Otherwise, deserialize the bytes as `Vec<u8>` then get the node by `Node::from_bytes()`.
+#[cfg(feature = "serde")]
+impl<'de: 'a, 'a, const N: usize> serde::Deserialize<'de> for Node<'a, N> {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> {
+        let bytes = <&'de [u8] as serde::Deserialize>::deserialize(deserializer)?;
+        Node::from_bytes(bytes).map_err(serde::de::Error::custom)
+    }
+}
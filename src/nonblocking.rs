@@ -0,0 +1,238 @@
+//! A module for using `monotree` in async services without blocking the executor.
+use crate::*;
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::task;
+
+/// A type representing a future returned by `AsyncDatabase`.
+pub type DbFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// A trait defining databases accessed asynchronously, such as those over network.
+///
+/// This mirrors `Database`. Wrapped in `BlockingDB`, it serves trees run by `AsyncMonotree`.
+pub trait AsyncDatabase: Send {
+    /// Open the database at the given path.
+    fn try_new(dbpath: &str) -> Result<Self>
+    where
+        Self: Sized;
+    /// The same as `try_new()`, but panics if the database fails to open.
+    fn new(dbpath: &str) -> Self
+    where
+        Self: Sized,
+    {
+        Self::try_new(dbpath).expect("new()")
+    }
+    fn get<'a>(&'a mut self, key: &'a [u8]) -> DbFuture<'a, Option<Vec<u8>>>;
+    fn put<'a>(&'a mut self, key: &'a [u8], value: Vec<u8>) -> DbFuture<'a, ()>;
+    fn delete<'a>(&'a mut self, key: &'a [u8]) -> DbFuture<'a, ()>;
+    fn init_batch(&mut self) -> DbFuture<'_, ()>;
+    fn finish_batch(&mut self) -> DbFuture<'_, ()>;
+    /// Get the total size in bytes the backend currently occupies.
+    /// As with `Database::total_size()`, this is not supported by default.
+    fn total_size(&self) -> DbFuture<'_, u64> {
+        Box::pin(async { Err(Errors::new("total_size(): not supported")) })
+    }
+    /// Persist all data written so far. Backends without durable storage do nothing.
+    fn flush(&mut self) -> DbFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A `Database` bridging an `AsyncDatabase` by blocking on its futures.
+///
+/// Calls block the current thread, so this is only used on threads allowed to block,
+/// such as those `AsyncMonotree` runs trees on.
+pub struct BlockingDB<A> {
+    db: A,
+    handle: Handle,
+}
+
+impl<A: AsyncDatabase> BlockingDB<A> {
+    /// Construct `BlockingDB` from the given database and the runtime its futures are run on.
+    pub fn with_db(db: A, handle: Handle) -> Self {
+        BlockingDB { db, handle }
+    }
+}
+
+impl<A: AsyncDatabase> Database for BlockingDB<A> {
+    /// Open the database on the current runtime. This fails outside of a tokio runtime.
+    fn try_new(dbpath: &str) -> Result<Self> {
+        let handle = Handle::try_current().map_err(|_| Errors::new("new(): no tokio runtime"))?;
+        Ok(BlockingDB {
+            db: A::try_new(dbpath)?,
+            handle,
+        })
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.handle.block_on(self.db.get(key))
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.handle.block_on(self.db.put(key, value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.handle.block_on(self.db.delete(key))
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.handle.block_on(self.db.init_batch())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.handle.block_on(self.db.finish_batch())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        self.handle.block_on(self.db.total_size())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.handle.block_on(self.db.flush())
+    }
+}
+
+/// A wrapper of `Monotree` with an async API.
+///
+/// Every operation runs on a thread dedicated to blocking operations of the tokio runtime,
+/// so neither database I/O nor hashing blocks the executor.
+/// Clones share the same tree, whose operations are run one at a time.
+#[derive(Debug)]
+pub struct AsyncMonotree<D = DefaultDatabase, H = DefaultHasher, C = DefaultCodec> {
+    tree: Arc<Mutex<Monotree<D, H, C>>>,
+}
+
+impl<D, H, C> Clone for AsyncMonotree<D, H, C> {
+    fn clone(&self) -> Self {
+        AsyncMonotree {
+            tree: Arc::clone(&self.tree),
+        }
+    }
+}
+
+impl<D, H, C> AsyncMonotree<D, H, C>
+where
+    D: Database + Send + 'static,
+    H: Hasher + Send + 'static,
+    C: NodeCodec + Send + 'static,
+{
+    pub async fn new(dbpath: &str) -> Result<Self> {
+        let dbpath = dbpath.to_string();
+        let tree = task::spawn_blocking(move || Monotree::try_new(&dbpath)).await??;
+        Ok(AsyncMonotree::with_tree(tree))
+    }
+
+    /// Construct `AsyncMonotree` from an existing tree.
+    pub fn with_tree(tree: Monotree<D, H, C>) -> Self {
+        AsyncMonotree {
+            tree: Arc::new(Mutex::new(tree)),
+        }
+    }
+
+    /// Run the given closure with the tree on a thread where blocking is acceptable.
+    pub async fn run<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Monotree<D, H, C>) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let tree = Arc::clone(&self.tree);
+        task::spawn_blocking(move || {
+            let mut tree = tree
+                .lock()
+                .map_err(|_| Errors::new("AsyncMonotree::run(): poisoned"))?;
+            f(&mut tree)
+        })
+        .await?
+    }
+
+    /// Insert key-leaf entry into the tree. Returns a new root hash.
+    pub async fn insert(&self, root: Option<Hash>, key: Hash, leaf: Hash) -> Result<Option<Hash>> {
+        self.run(move |tree| tree.insert(root.as_ref(), &key, &leaf))
+            .await
+    }
+
+    /// Get the leaf matched with a key.
+    pub async fn get(&self, root: Option<Hash>, key: Hash) -> Result<Option<Hash>> {
+        self.run(move |tree| tree.get(root.as_ref(), &key)).await
+    }
+
+    /// Remove the entry of a key from the tree. Returns a new root hash.
+    pub async fn remove(&self, root: Option<Hash>, key: Hash) -> Result<Option<Hash>> {
+        self.run(move |tree| tree.remove(root.as_ref(), &key)).await
+    }
+
+    /// Insert key-leaf entries in batch. Returns a new root hash.
+    pub async fn inserts(
+        &self,
+        root: Option<Hash>,
+        keys: Vec<Hash>,
+        leaves: Vec<Hash>,
+    ) -> Result<Option<Hash>> {
+        self.run(move |tree| tree.inserts(root.as_ref(), &keys, &leaves))
+            .await
+    }
+
+    /// Get the leaves matched with keys in batch.
+    pub async fn gets(&self, root: Option<Hash>, keys: Vec<Hash>) -> Result<Vec<Option<Hash>>> {
+        self.run(move |tree| tree.gets(root.as_ref(), &keys)).await
+    }
+
+    /// Remove the entries of keys in batch. Returns a new root hash.
+    pub async fn removes(&self, root: Option<Hash>, keys: Vec<Hash>) -> Result<Option<Hash>> {
+        self.run(move |tree| tree.removes(root.as_ref(), &keys))
+            .await
+    }
+
+    /// Generate a Merkle proof for the given root and key.
+    pub async fn get_merkle_proof(&self, root: Option<Hash>, key: Hash) -> Result<Option<Proof>> {
+        self.run(move |tree| tree.get_merkle_proof(root.as_ref(), &key))
+            .await
+    }
+
+    /// Stream `(key, leaf)` entries in the tree of the given root, in the order of `Monotree::iter()`.
+    ///
+    /// Entries are read ahead on a blocking thread by up to `buffer` at a time, then wait
+    /// until the stream is polled for them, so a slow consumer never gets the whole tree in memory.
+    /// The tree is locked only while reading ahead, leaving it to other operations in between.
+    /// The stream ends after the first error, and dropping it stops reading.
+    /// This fails outside of a tokio runtime.
+    pub fn leaves(
+        &self,
+        root: Option<Hash>,
+        buffer: usize,
+    ) -> Result<impl Stream<Item = Result<(Hash, Hash)>>> {
+        let handle =
+            Handle::try_current().map_err(|_| Errors::new("leaves(): no tokio runtime"))?;
+        let buffer = buffer.max(1);
+        let (mut tx, rx) = mpsc::channel(buffer);
+        let tree = Arc::clone(&self.tree);
+        task::spawn_blocking(move || {
+            let mut stack: Vec<_> = root.map(|root| (root, Vec::new())).into_iter().collect();
+            while !stack.is_empty() {
+                let entries: Vec<_> = match tree.lock() {
+                    Ok(mut tree) => {
+                        let mut iter = tree.resume_iter(stack);
+                        let entries = iter.by_ref().take(buffer).collect();
+                        stack = iter.into_stack();
+                        entries
+                    }
+                    Err(_) => {
+                        stack = Vec::new();
+                        vec![Err(Errors::new("AsyncMonotree::leaves(): poisoned"))]
+                    }
+                };
+                for entry in entries {
+                    if handle.block_on(tx.send(entry)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
@@ -0,0 +1,266 @@
+//! A module hashing the nodes of batches on threads of `rayon`, for `Monotree::par_inserts()`.
+//!
+//! Paths of the entries of a batch are sorted and split at the bit they diverge at,
+//! just as a tree of `monotree` branches, so that the subtrees on either side are hashed
+//! independently on threads of their own, then joined by the node above them.
+//! `Database::get()` takes `&mut self`, so the nodes of the tree on the way are read beforehand,
+//! and the nodes hashed are only collected, to be written by the caller in a single batch.
+use crate::utils::*;
+use crate::*;
+use hashbrown::HashMap;
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+/// Subtrees of fewer entries than this are hashed on the thread at hand.
+const PAR_THRESHOLD: usize = 1 << 10;
+
+/// An entry of a batch: the path of a key and its leaf.
+pub(crate) type Entry<'a, const N: usize> = (Cow<'a, [u8]>, Hash<N>);
+
+/// Nodes hashed, each with its serialized form, in no particular order.
+pub(crate) type Nodes<const N: usize> = Vec<(Hash<N>, Vec<u8>)>;
+
+/// Serialized nodes of the tree read beforehand, by their hashes.
+pub(crate) type Read<const N: usize> = HashMap<Hash<N>, Vec<u8>>;
+
+/// A cell hashed, owning the hash it links to.
+type Built<'a, const N: usize> = Option<(Hash<N>, Bits<'a>)>;
+
+fn unit_of<'a, const N: usize>(built: &'a Built<N>) -> Cell<'a> {
+    built.as_ref().map(|(hash, bits)| Unit {
+        hash,
+        bits: bits.clone(),
+    })
+}
+
+/// Get the index of the first of entries with the bit at `at` set.
+fn split<const N: usize>(entries: &[Entry<N>], at: BitsLen) -> usize {
+    entries.partition_point(|(path, _)| !bit(path, at))
+}
+
+/// What putting entries into a unit of the tree at `start` comes down to.
+enum Step<'a> {
+    /// Split the unit at the bit given, past the bits in common with all the entries.
+    Split(BitsLen, Unit<'a>),
+    /// Replace the leaf of the unit, of the very key of the only entry.
+    Replace,
+    /// Put entries into the node the unit links to, at the bit given.
+    Descend(BitsLen),
+}
+
+fn step<'a, const N: usize>(
+    unit: &Unit<'a>,
+    entries: &[Entry<N>],
+    start: BitsLen,
+    depth: BitsLen,
+) -> Step<'a> {
+    // entries are sorted, so the fewest bits in common with any is with the first or the last
+    let n = [entries.first(), entries.last()]
+        .iter()
+        .flatten()
+        .map(|(path, _)| Bits::len_common_bits(&unit.bits, &Bits::new(path).shift(start, false)))
+        .min()
+        .expect("step(): entries");
+    match n {
+        n if n < unit.bits.len() => Step::Split(
+            start + n,
+            Unit {
+                hash: unit.hash,
+                bits: unit.bits.shift(n, false),
+            },
+        ),
+        n if start + n == depth => Step::Replace,
+        n => Step::Descend(start + n),
+    }
+}
+
+/// Get units of a node by the side they branch to.
+fn sides<C: NodeCodec, const N: usize>(bytes: &[u8]) -> Result<(Cell<'_>, Cell<'_>)> {
+    let (lc, rc) = C::decode::<N>(bytes)?.into_cells(false);
+    let (mut lu, mut ru) = (None, None);
+    for unit in lc.into_iter().chain(rc) {
+        match unit.bits.first() {
+            true => ru = Some(unit),
+            false => lu = Some(unit),
+        }
+    }
+    Ok((lu, ru))
+}
+
+/// Put the unit on the side of the bit it leads with.
+fn sided(unit: Unit) -> (Cell, Cell) {
+    match unit.bits.first() {
+        true => (None, Some(unit)),
+        false => (Some(unit), None),
+    }
+}
+
+/// Hashes subtrees of entries sorted by path, with no two of the same path.
+pub(crate) struct Builder<'a, H, C, const N: usize> {
+    hasher: &'a H,
+    depth: BitsLen,
+    read: &'a Read<N>,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<'a, H, C, const N: usize> Builder<'a, H, C, N>
+where
+    H: Hasher<N> + Sync,
+    C: NodeCodec,
+{
+    /// Construct `Builder` over the nodes read by `Builder::read()`,
+    /// with leaves at the depth of `depth` bits.
+    pub(crate) fn new(hasher: &'a H, depth: BitsLen, read: &'a Read<N>) -> Self {
+        Builder {
+            hasher,
+            depth,
+            read,
+            codec: PhantomData,
+        }
+    }
+
+    /// Read the nodes from the given root that `Builder::put()` then walks down along with entries.
+    pub(crate) fn read<D: Database>(
+        db: &mut D,
+        root: &[u8],
+        entries: &[Entry<N>],
+        start: BitsLen,
+        depth: BitsLen,
+        read: &mut Read<N>,
+    ) -> Result<()> {
+        let bytes = match db.get(root)? {
+            Some(bytes) => bytes,
+            None => return Err(Errors::NotFound(root.to_vec())),
+        };
+        let (lu, ru) = sides::<C, N>(&bytes)?;
+        let i = split(entries, start);
+        let mut walking = vec![(lu, &entries[..i], start), (ru, &entries[i..], start)];
+        while let Some((unit, entries, start)) = walking.pop() {
+            let unit = match unit {
+                Some(unit) if !entries.is_empty() => unit,
+                _ => continue,
+            };
+            match step(&unit, entries, start, depth) {
+                Step::Split(at, rest) => {
+                    let i = split(entries, at);
+                    let (lu, ru) = sided(rest);
+                    walking.push((lu, &entries[..i], at));
+                    walking.push((ru, &entries[i..], at));
+                }
+                Step::Replace => {}
+                Step::Descend(at) => Self::read(db, unit.hash, entries, at, depth, read)?,
+            }
+        }
+        read.insert(slice_to_array(root), bytes);
+        Ok(())
+    }
+
+    fn put_node(&self, node: Node<N>, nodes: &mut Nodes<N>) -> Result<Hash<N>> {
+        let bytes = C::encode(&node)?;
+        let hash = self.hasher.digest_node(&bytes);
+        nodes.push((hash, bytes));
+        Ok(hash)
+    }
+
+    /// Run both closures, on threads of their own for `len` entries or more.
+    fn join<A, B, T, U>(&self, len: usize, a: A, b: B, nodes: &mut Nodes<N>) -> Result<(T, U)>
+    where
+        A: FnOnce(&mut Nodes<N>) -> Result<T> + Send,
+        B: FnOnce(&mut Nodes<N>) -> Result<U> + Send,
+        T: Send,
+        U: Send,
+    {
+        if len < PAR_THRESHOLD {
+            return Ok((a(nodes)?, b(nodes)?));
+        }
+        let ((ta, na), (tb, nb)) = rayon::join(
+            || {
+                let mut nodes = Vec::new();
+                (a(&mut nodes), nodes)
+            },
+            || {
+                let mut nodes = Vec::new();
+                (b(&mut nodes), nodes)
+            },
+        );
+        nodes.extend(na);
+        nodes.extend(nb);
+        Ok((ta?, tb?))
+    }
+
+    /// Hash the given units with entries put into them at `start`, split into those on either side.
+    /// Returns the hash of the resulting node.
+    fn put_cells(
+        &self,
+        (lu, ru): (Cell, Cell),
+        entries: &[Entry<N>],
+        start: BitsLen,
+        nodes: &mut Nodes<N>,
+    ) -> Result<Hash<N>> {
+        let i = split(entries, start);
+        let (lc, rc) = self.join(
+            entries.len(),
+            |nodes| self.put_cell(lu, &entries[..i], start, nodes),
+            |nodes| self.put_cell(ru, &entries[i..], start, nodes),
+            nodes,
+        )?;
+        self.put_node(Node::new(unit_of(&lc), unit_of(&rc)), nodes)
+    }
+
+    /// Hash the node of the given hash, or a new one for `None`, with entries put at `start`.
+    /// Returns the hash of the resulting node.
+    pub(crate) fn put(
+        &self,
+        root: Option<&[u8]>,
+        entries: &[Entry<N>],
+        start: BitsLen,
+        nodes: &mut Nodes<N>,
+    ) -> Result<Hash<N>> {
+        let units = match root {
+            Some(root) => match self.read.get(root) {
+                Some(bytes) => sides::<C, N>(bytes)?,
+                None => return Err(Errors::NotFound(root.to_vec())),
+            },
+            None => (None, None),
+        };
+        self.put_cells(units, entries, start, nodes)
+    }
+
+    /// Hash the cell of entries, all of the same bit at `start`, along with the unit of the tree
+    /// in it, if any.
+    fn put_cell<'b>(
+        &self,
+        unit: Cell<'b>,
+        entries: &'b [Entry<N>],
+        start: BitsLen,
+        nodes: &mut Nodes<N>,
+    ) -> Result<Built<'b, N>> {
+        let (first, last) = match (entries.first(), entries.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(unit.map(|unit| (slice_to_array(unit.hash), unit.bits))),
+        };
+        let bits = Bits::new(&first.0).shift(start, false);
+        let unit = match unit {
+            Some(unit) => unit,
+            None if entries.len() == 1 => return Ok(Some((first.1, bits))),
+            None => {
+                // a hard node at the bit the first and the last diverge at
+                let n = Bits::len_common_bits(&bits, &Bits::new(&last.0).shift(start, false));
+                let hash = self.put_cells((None, None), entries, start + n, nodes)?;
+                return Ok(Some((hash, bits.shift(n, true))));
+            }
+        };
+        match step(&unit, entries, start, self.depth) {
+            Step::Split(at, rest) => {
+                let hash = self.put_cells(sided(rest), entries, at, nodes)?;
+                // the bits in common are kept as sliced in the unit split, as `put()` does
+                Ok(Some((hash, unit.bits.shift(at - start, true))))
+            }
+            Step::Replace => Ok(Some((last.1, unit.bits))),
+            Step::Descend(at) => {
+                let hash = self.put(Some(unit.hash), entries, at, nodes)?;
+                Ok(Some((hash, unit.bits)))
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! A module holding part of a tree of `monotree` for stateless clients, built up from proofs.
+//!
+//! A `PartialTree` absorbs Merkle proofs verified against its root, keeping the nodes along
+//! the keys proven, while the rest of the tree appears only as the hashes of the subtrees
+//! those nodes refer to. Keys absorbed are looked up and updated locally, giving the roots
+//! the full tree would have after the same updates. Updates walking into subtrees never absorbed
+//! fail with `Errors::NotFound` of the node missing, which a proof of a nearby key then fills in.
+use crate::database::MemoryDB;
+use crate::*;
+
+/// A part of a tree held in memory, at the root it is given and moved by updates to it.
+pub struct PartialTree<H = DefaultHasher, C = DefaultCodec, const N: usize = HASH_LEN> {
+    pub tree: Monotree<MemoryDB, H, C, N>,
+    root: Option<Hash<N>>,
+}
+
+impl<H, C, const N: usize> PartialTree<H, C, N>
+where
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    /// Construct `PartialTree` of the tree of `root`, holding none of its nodes yet.
+    pub fn new(root: Option<Hash<N>>) -> Self {
+        PartialTree {
+            tree: Monotree::new(""),
+            root,
+        }
+    }
+
+    /// Get the root of the tree with the updates so far.
+    pub fn root(&self) -> Option<&Hash<N>> {
+        self.root.as_ref()
+    }
+
+    /// Absorb a Merkle proof that a key has the given leaf in the tree of `root`,
+    /// keeping the nodes along the key so that it is looked up and updated locally.
+    ///
+    /// Fails with `Errors::ProofInvalid` if `root` is not the root of the tree so far,
+    /// or if the proof is not of the key and the leaf against it.
+    pub fn absorb_proof(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        leaf: &Hash<N>,
+        proof: &Proof,
+    ) -> Result<()> {
+        let invalid = |msg: &str| Errors::ProofInvalid(format!("absorb_proof(): {}", msg));
+        if root.is_none() || root != self.root.as_ref() {
+            return Err(invalid("proof of another root"));
+        }
+        if !verify_proof(&self.tree.hasher, root, leaf, Some(proof)) {
+            return Err(invalid("proof not verified"));
+        }
+        // rebuild the nodes from the leaf up, as `compute_root()` hashes them
+        let mut hash = *leaf;
+        let mut nodes = Vec::with_capacity(proof.len());
+        for (right, cut) in proof.iter().rev() {
+            let bytes = if *right {
+                let l = cut.len();
+                [&cut[..l - 1], &hash[..], &cut[l - 1..]].concat()
+            } else {
+                [&hash[..], &cut[..]].concat()
+            };
+            hash = self.tree.hasher.digest_node(&bytes);
+            nodes.push((hash, bytes));
+        }
+        self.tree.db.init_batch()?;
+        for (hash, bytes) in nodes {
+            self.tree.db.put(&hash, bytes)?;
+        }
+        self.tree.db.finish_batch()?;
+        // the proof binds the leaf to the root alone, so check that its path is of the key
+        match self.tree.get(root, key) {
+            Ok(Some(found)) if found == *leaf => Ok(()),
+            _ => Err(invalid("proof of another key")),
+        }
+    }
+
+    /// Get the leaf of a key absorbed or updated, or `None` if the key is absent.
+    ///
+    /// Keys outside the part held fail with `Errors::NotFound` rather than being taken as absent.
+    pub fn get(&mut self, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        self.tree.get(self.root.as_ref(), key)
+    }
+
+    /// Insert key-leaf entry into the part held. Returns the new root of the tree.
+    pub fn insert(&mut self, key: &Hash<N>, leaf: &Hash<N>) -> Result<Option<Hash<N>>> {
+        self.root = self.tree.insert(self.root.as_ref(), key, leaf)?;
+        Ok(self.root)
+    }
+
+    /// Remove the entry of a key from the part held. Returns the new root of the tree.
+    pub fn remove(&mut self, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        self.root = self.tree.remove(self.root.as_ref(), key)?;
+        Ok(self.root)
+    }
+
+    /// Get a Merkle proof of a key against the root with the updates so far.
+    pub fn get_merkle_proof(&mut self, key: &Hash<N>) -> Result<Option<Proof>> {
+        self.tree.get_merkle_proof(self.root.as_ref(), key)
+    }
+}
@@ -0,0 +1,64 @@
+//! A module defining policies checking batches of `monotree` before they are committed.
+//!
+//! Operators of commitment services set one by `Monotree::set_commit_policy()`
+//! to detect, and halt on, anomalous mass updates of the tree.
+use crate::*;
+use std::fmt;
+
+/// A summary of a batch about to be committed, given to `CommitPolicy::check()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchSummary<const N: usize = HASH_LEN> {
+    /// Number of keys the batch inserts or removes.
+    pub keys: usize,
+    pub nodes_written: u64,
+    /// Bytes of nodes written, each counted as its key (`HASH_LEN`) plus its serialized form.
+    pub bytes_written: u64,
+    /// The root the batch started from.
+    pub before: Option<Hash<N>>,
+    /// The root the batch results in.
+    pub after: Option<Hash<N>>,
+}
+
+/// Decisions of `CommitPolicy::check()` on a batch.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Accept,
+    /// Commit the batch, but record it with the given reason, see `Monotree::take_flags()`.
+    Flag(String),
+    /// Reject the batch with the given reason, which fails with `Errors::Vetoed`.
+    Veto(String),
+}
+
+/// A trait defining policies invoked by batch operations right before committing.
+///
+/// A vetoed batch is discarded before anything of it is written, so that the database
+/// is left as it was and the tree stays at the root it started from.
+pub trait CommitPolicy<const N: usize = HASH_LEN>: fmt::Debug {
+    fn check(&mut self, summary: &BatchSummary<N>) -> Verdict;
+}
+
+/// A `CommitPolicy` flagging or vetoing batches exceeding either of the limits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Thresholds {
+    pub max_keys: usize,
+    pub max_bytes: u64,
+    /// Veto batches exceeding the limits instead of flagging them.
+    pub veto: bool,
+}
+
+impl<const N: usize> CommitPolicy<N> for Thresholds {
+    fn check(&mut self, summary: &BatchSummary<N>) -> Verdict {
+        let reason = if summary.keys > self.max_keys {
+            format!("{} keys over {}", summary.keys, self.max_keys)
+        } else if summary.bytes_written > self.max_bytes {
+            format!("{} bytes over {}", summary.bytes_written, self.max_bytes)
+        } else {
+            return Verdict::Accept;
+        };
+        if self.veto {
+            Verdict::Veto(reason)
+        } else {
+            Verdict::Flag(reason)
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! A module implementing a durable queue of batches of `monotree`, each committed exactly once.
+//!
+//! Batches are journaled in the database of the tree by `submit()` before being applied.
+//! `apply_next()` applies the oldest one on the root recorded by `Monotree::swap_root()`,
+//! then records the new root and drops the batch from the journal in a single batch of writes.
+//! A crash before that leaves the root as it was, so applying the batch again after restart,
+//! as `recover()` does, gives the same root, while a batch applied is never applied again.
+//! The queue assumes a single process applying batches and recording roots.
+use crate::tree::ROOT_KEY;
+use crate::utils::*;
+use crate::*;
+
+/// Tag prepended to derive the keys the queue and its batches are stored under.
+const QUEUE_TAG: u8 = 0xfb;
+
+/// An update of a batch: a key with its new leaf, or `None` to remove it.
+pub type Update = (Hash, Option<Hash>);
+
+/// Get the key the sequence numbers of the queue, or the batch numbered `seq`, are stored under.
+fn queue_key<D, H: Hasher, C>(tree: &Monotree<D, H, C>, seq: Option<u64>) -> Hash {
+    match seq {
+        None => tree.hasher.digest(&[QUEUE_TAG]),
+        Some(seq) => tree
+            .hasher
+            .digest(&[&[QUEUE_TAG], &seq.to_be_bytes()[..]].concat()),
+    }
+}
+
+/// Get the sequence numbers of the oldest batch pending and of the next one to submit.
+pub fn bounds<D, H, C>(tree: &mut Monotree<D, H, C>) -> Result<(u64, u64)>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let key = queue_key(tree, None);
+    match tree.db.get(&key)? {
+        None => Ok((0, 0)),
+        Some(bytes) if bytes.len() == 16 => {
+            Ok((bytes_to_int(&bytes[..8]), bytes_to_int(&bytes[8..])))
+        }
+        Some(_) => Err(Errors::Decode("bounds(): invalid queue".to_string())),
+    }
+}
+
+/// Get the number of batches pending.
+pub fn pending<D, H, C>(tree: &mut Monotree<D, H, C>) -> Result<u64>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let (head, tail) = bounds(tree)?;
+    Ok(tail - head)
+}
+
+/// Journal a batch of updates, applied in order. Returns its sequence number.
+pub fn submit<D, H, C>(tree: &mut Monotree<D, H, C>, updates: &[Update]) -> Result<u64>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let (head, tail) = bounds(tree)?;
+    let mut bytes = Vec::with_capacity(updates.len() * (HASH_LEN * 2 + 1));
+    for (key, leaf) in updates.iter() {
+        bytes.extend_from_slice(key);
+        match leaf {
+            Some(leaf) => {
+                bytes.push(0x01);
+                bytes.extend_from_slice(leaf);
+            }
+            None => bytes.push(0x00),
+        }
+    }
+    let (entry, key) = (queue_key(tree, Some(tail)), queue_key(tree, None));
+    tree.in_batch(|tree| {
+        tree.db.put(&entry, bytes)?;
+        tree.db.put(
+            &key,
+            [head.to_be_bytes(), (tail + 1).to_be_bytes()].concat(),
+        )
+    })?;
+    Ok(tail)
+}
+
+/// Get the updates of the batch numbered `seq`, or `None` if it is not pending.
+pub fn batch<D, H, C>(tree: &mut Monotree<D, H, C>, seq: u64) -> Result<Option<Vec<Update>>>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let entry = queue_key(tree, Some(seq));
+    let bytes = match tree.db.get(&entry)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let mut updates = Vec::new();
+    let mut rest = &bytes[..];
+    while !rest.is_empty() {
+        let invalid = || Errors::Decode("batch(): truncated".to_string());
+        let (key, flag) = match (rest.get(..HASH_LEN), rest.get(HASH_LEN)) {
+            (Some(key), Some(flag)) => (slice_to_hash(key), *flag),
+            _ => return Err(invalid()),
+        };
+        rest = &rest[HASH_LEN + 1..];
+        let leaf = match flag {
+            0x00 => None,
+            0x01 => {
+                let leaf = rest.get(..HASH_LEN).ok_or_else(invalid)?;
+                rest = &rest[HASH_LEN..];
+                Some(slice_to_hash(leaf))
+            }
+            _ => return Err(Errors::Decode("batch(): unknown update".to_string())),
+        };
+        updates.push((key, leaf));
+    }
+    Ok(Some(updates))
+}
+
+/// Apply the oldest batch pending on the recorded root, then record the new root and drop
+/// the batch at once. Returns the new root, or `None` if no batch is pending.
+pub fn apply_next<D, H, C>(tree: &mut Monotree<D, H, C>) -> Result<Option<Option<Hash>>>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let (head, tail) = bounds(tree)?;
+    if head == tail {
+        return Ok(None);
+    }
+    let updates = match batch(tree, head)? {
+        Some(updates) => updates,
+        None => return Err(Errors::new("apply_next(): batch missing")),
+    };
+    let mut root = tree.load_root()?;
+    tree.in_batch(|tree| {
+        for (key, leaf) in updates.iter() {
+            root = match leaf {
+                Some(leaf) => tree.insert(root.as_ref(), key, leaf)?,
+                None => tree.remove(root.as_ref(), key)?,
+            };
+        }
+        Ok(())
+    })?;
+
+    // nodes written above are harmless if this never commits, as they are only ever added
+    let (entry, key) = (queue_key(tree, Some(head)), queue_key(tree, None));
+    tree.in_batch(|tree| {
+        match root {
+            Some(root) => tree.db.put(&ROOT_KEY, root.to_vec())?,
+            None => tree.db.delete(&ROOT_KEY)?,
+        }
+        tree.db.delete(&entry)?;
+        tree.db.put(
+            &key,
+            [(head + 1).to_be_bytes(), tail.to_be_bytes()].concat(),
+        )
+    })?;
+    Ok(Some(root))
+}
+
+/// Apply every batch pending, such as those left by a crash. Returns the number applied.
+pub fn recover<D, H, C>(tree: &mut Monotree<D, H, C>) -> Result<u64>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let mut applied = 0;
+    while apply_next(tree)?.is_some() {
+        applied += 1;
+    }
+    Ok(applied)
+}
@@ -0,0 +1,93 @@
+//! A module recording the history of roots of `monotree`, each under a version.
+//!
+//! Versions count up from `0` by every root committed, as heights of a chain do,
+//! and are stored in the database of the tree along with its nodes,
+//! so that any root committed is found again by its version after restart.
+use crate::utils::*;
+use crate::*;
+
+/// Tag prepended to derive the keys the history and the roots in it are stored under.
+const ROOTS_TAG: u8 = 0xf9;
+
+/// A history of roots of a tree, stored in its database.
+///
+/// A root is recorded first, then the number of versions, so that a crash in between
+/// leaves the history as it was, with the root recorded again by the next commit.
+pub struct RootStore<'a, D, H, C> {
+    tree: &'a mut Monotree<D, H, C>,
+}
+
+impl<'a, D, H, C> RootStore<'a, D, H, C>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    pub fn new(tree: &'a mut Monotree<D, H, C>) -> Self {
+        RootStore { tree }
+    }
+
+    /// Get the key the number of versions, or the root of `version`, is stored under.
+    fn key(&self, version: Option<u64>) -> Hash {
+        match version {
+            None => self.tree.hasher.digest(&[ROOTS_TAG]),
+            Some(version) => self
+                .tree
+                .hasher
+                .digest(&[&[ROOTS_TAG], &version.to_be_bytes()[..]].concat()),
+        }
+    }
+
+    /// Get the number of versions committed.
+    pub fn len(&mut self) -> Result<u64> {
+        let key = self.key(None);
+        match self.tree.db.get(&key)? {
+            None => Ok(0),
+            Some(bytes) if bytes.len() == 8 => Ok(bytes_to_int(&bytes)),
+            Some(_) => Err(Errors::Decode(
+                "RootStore::len(): invalid history".to_string(),
+            )),
+        }
+    }
+
+    pub fn is_empty(&mut self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Record a root, or `None` for an empty tree, as the next version. Returns the version.
+    pub fn commit(&mut self, root: Option<&Hash>) -> Result<u64> {
+        let version = self.len()?;
+        let key = self.key(Some(version));
+        let bytes = root.map(|root| root.to_vec()).unwrap_or_default();
+        self.tree.db.put(&key, bytes)?;
+        let key = self.key(None);
+        self.tree
+            .db
+            .put(&key, (version + 1).to_be_bytes().to_vec())?;
+        Ok(version)
+    }
+
+    /// Get the root committed as the given version. Fails if the version is not committed yet.
+    pub fn root_at(&mut self, version: u64) -> Result<Option<Hash>> {
+        let key = self.key(Some(version));
+        if version >= self.len()? {
+            return Err(Errors::NotFound(key.to_vec()));
+        }
+        match self.tree.db.get(&key)? {
+            Some(bytes) if bytes.is_empty() => Ok(None),
+            Some(bytes) if bytes.len() == HASH_LEN => Ok(Some(slice_to_hash(&bytes))),
+            Some(_) => Err(Errors::Decode(
+                "RootStore::root_at(): invalid root".to_string(),
+            )),
+            None => Err(Errors::NotFound(key.to_vec())),
+        }
+    }
+
+    /// Get the last version committed with its root, or `None` if none is committed yet.
+    pub fn latest(&mut self) -> Result<Option<(u64, Option<Hash>)>> {
+        match self.len()? {
+            0 => Ok(None),
+            len => Ok(Some((len - 1, self.root_at(len - 1)?))),
+        }
+    }
+}
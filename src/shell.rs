@@ -0,0 +1,260 @@
+//! A module implementing an interactive shell to inspect trees of `monotree`.
+//!
+//! The shell keeps a current node, walked down from a root by `cd`, for example:
+//!
+//! ```text
+//! monotree> root 5f2e...          set the root, or print it without an argument
+//! monotree> ls                    list the cells of the current node
+//! monotree> cd 0110               walk down along bits, `cd x7f` along a prefix in hex
+//! monotree> cd ..                 walk back up, `cd /` to the root
+//! monotree> proof 9a3c...         print the Merkle proof of a key from the root
+//! monotree> stats                 count nodes and leaves under the current node
+//! ```
+use crate::utils::*;
+use crate::*;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+root [<hash>|-]    print or set the root, `-` for an empty tree
+pwd                print the bits walked down to the current node
+ls                 list the cells of the current node
+cd <bits>|x<hex>   walk down along bits, or along a prefix in hex
+cd ..|/            walk back up a node, or back to the root
+get <key>          print the leaf of a key
+proof <key>        print the Merkle proof of a key and verify it
+stats              count nodes, leaves and bytes under the current node
+quit               leave the shell
+";
+
+/// An interactive shell walking the trees of a `Monotree`.
+pub struct Shell<'a, D, H, C> {
+    tree: &'a mut Monotree<D, H, C>,
+    root: Option<Hash>,
+    /// Nodes walked down from the root, each with the bits leading to it.
+    stack: Vec<(Hash, Vec<bool>)>,
+}
+
+impl<'a, D, H, C> Shell<'a, D, H, C>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    /// Construct `Shell` starting at the root recorded by `Monotree::swap_root()`, if any.
+    pub fn new(tree: &'a mut Monotree<D, H, C>) -> Result<Self> {
+        let root = tree.load_root()?;
+        let mut shell = Shell {
+            tree,
+            root: None,
+            stack: Vec::new(),
+        };
+        shell.set_root(root);
+        Ok(shell)
+    }
+
+    /// Read commands from `input` until `quit` or the end of it, writing results to `output`.
+    ///
+    /// A failing command only prints its error, so that the session goes on.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, output: &mut W) -> Result<()> {
+        write!(output, "monotree> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), "quit" | "exit") {
+                break;
+            }
+            match self.execute(&line) {
+                Ok(out) => write!(output, "{}", out)?,
+                Err(err) => writeln!(output, "error: {}", err)?,
+            }
+            write!(output, "monotree> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Execute a single command line, returning what it prints.
+    pub fn execute(&mut self, line: &str) -> Result<String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.as_slice() {
+            [] => Ok(String::new()),
+            ["help"] => Ok(HELP.to_string()),
+            ["root"] => Ok(format!("{}\n", hex_or_dash(self.root.as_ref()))),
+            ["root", "-"] => {
+                self.set_root(None);
+                Ok(String::new())
+            }
+            ["root", hash] => {
+                self.set_root(Some(parse_hash(hash)?));
+                Ok(String::new())
+            }
+            ["pwd"] => Ok(match self.stack.last() {
+                None => "-\n".to_string(),
+                Some((hash, bits)) => format!("/{} {}\n", bits_to_str(bits), hex::encode(hash)),
+            }),
+            ["ls"] => self.ls(),
+            ["cd", "/"] => {
+                self.stack.truncate(1);
+                Ok(String::new())
+            }
+            ["cd", ".."] => {
+                if self.stack.len() > 1 {
+                    self.stack.pop();
+                }
+                Ok(String::new())
+            }
+            ["cd", bits] => {
+                self.cd(&parse_bits(bits)?)?;
+                Ok(String::new())
+            }
+            ["get", key] => {
+                let leaf = self.tree.get(self.root.as_ref(), &parse_hash(key)?)?;
+                Ok(format!("{}\n", hex_or_dash(leaf.as_ref())))
+            }
+            ["proof", key] => self.proof(&parse_hash(key)?),
+            ["stats"] => self.stats(),
+            _ => Err(Errors::new(&format!("unknown command: {}", line.trim()))),
+        }
+    }
+
+    fn set_root(&mut self, root: Option<Hash>) {
+        self.root = root;
+        self.stack = root.map(|root| (root, Vec::new())).into_iter().collect();
+    }
+
+    /// Get the cells of a node, each with the bits leading to it and its hash.
+    fn children(&mut self, hash: &Hash, prefix: &[bool]) -> Result<Vec<(Vec<bool>, Hash)>> {
+        let bytes = match self.tree.db.get(hash)? {
+            Some(bytes) => bytes,
+            None => return Err(Errors::NotFound(hash.to_vec())),
+        };
+        let (lc, rc) = C::decode::<HASH_LEN>(&bytes)?.into_cells(false);
+        Ok([lc, rc]
+            .iter()
+            .flatten()
+            .map(|unit| {
+                let mut bits = prefix.to_vec();
+                bits.extend(bytes_to_slicebit(unit.bits.path, &unit.bits.range));
+                (bits, slice_to_hash(unit.hash))
+            })
+            .collect())
+    }
+
+    fn current(&self) -> Result<(Hash, Vec<bool>)> {
+        match self.stack.last() {
+            Some(current) => Ok(current.to_owned()),
+            None => Err(Errors::new("empty tree")),
+        }
+    }
+
+    fn ls(&mut self) -> Result<String> {
+        let (hash, prefix) = self.current()?;
+        let mut out = String::new();
+        for (bits, child) in self.children(&hash, &prefix)? {
+            let kind = if bits.len() == self.tree.key_len() * 8 {
+                "leaf"
+            } else {
+                "node"
+            };
+            let cell = bits_to_str(&bits[prefix.len()..]);
+            writeln!(out, "{} {} {}", kind, hex::encode(child), cell).ok();
+        }
+        Ok(out)
+    }
+
+    /// Walk down from the current node along the given bits, which may end within a cell.
+    fn cd(&mut self, bits: &[bool]) -> Result<()> {
+        let (mut hash, mut prefix) = self.current()?;
+        let mut walked = Vec::new();
+        let mut rest = bits;
+        while !rest.is_empty() {
+            let next = self
+                .children(&hash, &prefix)?
+                .into_iter()
+                .find(|(child, _)| child[prefix.len()] == rest[0]);
+            let (child, child_hash) = match next {
+                Some(next) => next,
+                None => return Err(Errors::new("cd(): no such path")),
+            };
+            let cell = &child[prefix.len()..];
+            let n = cell.len().min(rest.len());
+            if cell[..n] != rest[..n] {
+                return Err(Errors::new("cd(): no such path"));
+            }
+            if child.len() == self.tree.key_len() * 8 {
+                return Err(Errors::new("cd(): a leaf is not a node"));
+            }
+            walked.push((child_hash, child.clone()));
+            hash = child_hash;
+            prefix = child;
+            rest = &rest[n..];
+        }
+        self.stack.extend(walked);
+        Ok(())
+    }
+
+    fn proof(&mut self, key: &Hash) -> Result<String> {
+        let root = self.root;
+        let proof = match self.tree.get_merkle_proof(root.as_ref(), key)? {
+            Some(proof) => proof,
+            None => return Ok("-\n".to_string()),
+        };
+        let mut out = String::new();
+        for (right, cut) in proof.iter() {
+            let side = if *right { "right" } else { "left" };
+            writeln!(out, "{} {}", side, hex::encode(cut)).ok();
+        }
+        let verified = match self.tree.get(root.as_ref(), key)? {
+            Some(leaf) => verify_proof(&self.tree.hasher, root.as_ref(), &leaf, Some(&proof)),
+            None => false,
+        };
+        writeln!(out, "verified: {}", verified).ok();
+        Ok(out)
+    }
+
+    fn stats(&mut self) -> Result<String> {
+        let (hash, prefix) = self.current()?;
+        let stats = self.tree.stats_under(&hash, prefix.len() as BitsLen)?;
+        Ok(format!(
+            "nodes: {}\nleaves: {}\nbytes: {}\ndepth: {}\n",
+            stats.nodes,
+            stats.leaves,
+            stats.bytes,
+            stats.depth()
+        ))
+    }
+}
+
+fn hex_or_dash(hash: Option<&Hash>) -> String {
+    hash.map_or("-".to_string(), hex::encode)
+}
+
+fn bits_to_str(bits: &[bool]) -> String {
+    bits.iter()
+        .map(|&bit| if bit { '1' } else { '0' })
+        .collect()
+}
+
+fn parse_hash(arg: &str) -> Result<Hash> {
+    match hex::decode(arg) {
+        Ok(bytes) if bytes.len() == HASH_LEN => Ok(slice_to_hash(&bytes)),
+        _ => Err(Errors::new(&format!("invalid hash: {}", arg))),
+    }
+}
+
+/// Parse bits given as `0`s and `1`s, or as a prefix in hex led by `x`.
+fn parse_bits(arg: &str) -> Result<Vec<bool>> {
+    let invalid = || Errors::new(&format!("invalid bits: {}", arg));
+    if let Some(prefix) = arg.strip_prefix('x') {
+        let bytes = hex::decode(prefix).map_err(|_| invalid())?;
+        return Ok(bytes_to_slicebit(&bytes, &(0..bytes.len() as BitsLen * 8)));
+    }
+    arg.chars()
+        .map(|c| match c {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(invalid()),
+        })
+        .collect()
+}
@@ -0,0 +1,143 @@
+//! A module signing roots of `monotree`, so that operators attest the roots they commit
+//! to their clients, and packaging proofs along with signed roots into `SignedProof`s,
+//! verified in one call by `verify_signed_proof()`.
+//!
+//! Roots are signed by a `RootSigner`, such as `Ed25519Signer` signing by Ed25519 of RFC 8032,
+//! over `ROOT_DOMAIN` followed by the root, so that signatures of roots are never mistaken
+//! for those of other messages signed by the same key.
+use crate::*;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use std::convert::TryFrom;
+
+/// Bytes prepended to a root to make the message signed by `RootSigner::sign_root()`.
+pub const ROOT_DOMAIN: &[u8] = b"monotree/root";
+
+/// Get the message signed for a root, where the root of an empty tree, `None`, is no bytes.
+pub fn root_message(root: Option<&Hash>) -> Vec<u8> {
+    [ROOT_DOMAIN, root.map_or(&[][..], |root| &root[..])].concat()
+}
+
+/// A trait signing roots committed by a tree, and verifying the signatures by public keys.
+pub trait RootSigner {
+    /// Get the public key the signatures are verified by.
+    fn public_key(&self) -> Vec<u8>;
+
+    /// Sign a message by the secret key.
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+
+    /// Verify a signature of a message by a public key.
+    fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool
+    where
+        Self: Sized;
+
+    /// Sign a root, `None` for an empty tree.
+    fn sign_root(&self, root: Option<&Hash>) -> Vec<u8> {
+        self.sign(&root_message(root))
+    }
+
+    /// Verify a signature of a root by a public key.
+    fn verify_root(public_key: &[u8], root: Option<&Hash>, signature: &[u8]) -> bool
+    where
+        Self: Sized,
+    {
+        Self::verify(public_key, &root_message(root), signature)
+    }
+}
+
+/// A Merkle proof packaged along with the root it is against and a signature of the root.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignedProof {
+    pub root: Hash,
+    /// Signature of `root` by `RootSigner::sign_root()`.
+    pub signature: Vec<u8>,
+    pub proof: Proof,
+}
+
+impl SignedProof {
+    /// Package a proof against a root, signing the root.
+    pub fn new<S: RootSigner>(signer: &S, root: &Hash, proof: Proof) -> Self {
+        SignedProof {
+            root: *root,
+            signature: signer.sign_root(Some(root)),
+            proof,
+        }
+    }
+}
+
+/// Get a proof of a key against `root` packaged as a `SignedProof`, signing the root,
+/// or `None` if the key is not in the tree.
+pub fn signed_proof<D, H, C, S>(
+    tree: &mut Monotree<D, H, C>,
+    signer: &S,
+    root: Option<&Hash>,
+    key: &Hash,
+) -> Result<Option<SignedProof>>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+    S: RootSigner,
+{
+    match (root, tree.get_merkle_proof(root, key)?) {
+        (Some(root), Some(proof)) => Ok(Some(SignedProof::new(signer, root, proof))),
+        _ => Ok(None),
+    }
+}
+
+/// Verify a `SignedProof` in one call: that its root is signed by the holder of `public_key`,
+/// and that the leaf is in the tree of the root by its proof.
+pub fn verify_signed_proof<S: RootSigner, H: Hasher>(
+    hasher: &H,
+    public_key: &[u8],
+    leaf: &Hash,
+    proof: &SignedProof,
+) -> bool {
+    S::verify_root(public_key, Some(&proof.root), &proof.signature)
+        && verify_proof(hasher, Some(&proof.root), leaf, Some(&proof.proof))
+}
+
+/// A `RootSigner` signing by Ed25519 of RFC 8032 with `ed25519-dalek`, with a secret key
+/// of 32 bytes, public keys of 32 bytes and signatures of 64 bytes.
+///
+/// The secret key is wiped from memory when the signer is dropped.
+pub struct Ed25519Signer {
+    key: SigningKey,
+}
+
+impl Ed25519Signer {
+    /// Construct `Ed25519Signer` from a secret key, the seed of RFC 8032.
+    pub fn from_secret(secret: &[u8; 32]) -> Self {
+        Ed25519Signer {
+            key: SigningKey::from_bytes(secret),
+        }
+    }
+
+    /// Construct `Ed25519Signer` from a random secret key drawn from the operating system.
+    pub fn generate() -> Self {
+        Ed25519Signer {
+            key: SigningKey::generate(&mut OsRng),
+        }
+    }
+}
+
+impl RootSigner for Ed25519Signer {
+    fn public_key(&self) -> Vec<u8> {
+        self.key.verifying_key().to_bytes().to_vec()
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.key.sign(message).to_bytes().to_vec()
+    }
+
+    fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        match (
+            VerifyingKey::try_from(public_key),
+            Signature::from_slice(signature),
+        ) {
+            (Ok(key), Ok(signature)) => key.verify_strict(message, &signature).is_ok(),
+            _ => false,
+        }
+    }
+}
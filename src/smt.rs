@@ -0,0 +1,249 @@
+//! A module implementing a sparse Merkle tree of the layout common to other ecosystems,
+//! so that its roots and proofs are checked by verifiers already deployed, such as on-chain.
+//!
+//! Unlike `Monotree`, every key is walked down all of its bits, the higher of each byte first,
+//! to a leaf at depth `HASH_LEN * 8`, the left child taken by `0` and the right by `1`.
+//! A node hashes `left || right` by `Hasher::digest_node()`, and the leaf of a key is its value.
+//! Empty subtrees are all of zero hash, `H(0..0) = 0`, and so is a node of two of them,
+//! as in the sparse Merkle trees of Plasma and Ethereum libraries.
+//! The root of an empty tree is then zero, taken as `None` as in `Monotree`.
+use crate::utils::*;
+use crate::*;
+
+/// Number of bits of keys, which is the depth of leaves.
+const DEPTH: usize = HASH_LEN * 8;
+
+/// The hash of empty subtrees.
+const ZERO: Hash = [0u8; HASH_LEN];
+
+/// A type representing a proof of `SparseMerkleTree`: the siblings along a key,
+/// from the leaf up to the root, with empty subtrees as zero.
+///
+/// The same proof shows either the leaf of a key or that the key is absent,
+/// see `verify_smt_proof()`.
+pub type SmtProof = Vec<Hash>;
+
+/// Get the bit of a key at the given depth, the higher of each byte first.
+fn bit(key: &Hash, depth: usize) -> bool {
+    key[depth / 8] & (0x80 >> (depth % 8)) != 0
+}
+
+/// Hash a node of two children, or zero if both are empty.
+fn hash_node<H: Hasher>(hasher: &H, left: &Hash, right: &Hash) -> Hash {
+    if *left == ZERO && *right == ZERO {
+        return ZERO;
+    }
+    hasher.digest_node(&[&left[..], &right[..]].concat())
+}
+
+fn to_root(hash: Hash) -> Option<Hash> {
+    if hash == ZERO {
+        None
+    } else {
+        Some(hash)
+    }
+}
+
+/// A sparse Merkle tree of `(key, leaf)` entries over a database, the same as `Monotree` in use.
+///
+/// Roots differ from those of `Monotree` with the same entries, and its proofs are verified
+/// by `verify_smt_proof()` instead. A zero leaf is no leaf, so inserting one removes the key.
+#[derive(Debug)]
+pub struct SparseMerkleTree<D = DefaultDatabase, H = DefaultHasher> {
+    pub db: D,
+    pub hasher: H,
+}
+
+impl<D, H> SparseMerkleTree<D, H>
+where
+    D: Database,
+    H: Hasher,
+{
+    pub fn new(dbpath: &str) -> Self {
+        Self::try_new(dbpath).expect("new()")
+    }
+
+    /// The same as `new()`, but returns an error instead of panicking if the database fails to open.
+    pub fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(Self::with_db(Database::try_new(dbpath)?))
+    }
+
+    /// Construct `SparseMerkleTree` over a database already opened.
+    pub fn with_db(db: D) -> Self {
+        SparseMerkleTree {
+            db,
+            hasher: Hasher::new(),
+        }
+    }
+
+    /// Read the children of a node, both empty for an empty subtree.
+    fn read_node(&mut self, hash: &Hash) -> Result<(Hash, Hash)> {
+        if *hash == ZERO {
+            return Ok((ZERO, ZERO));
+        }
+        match self.db.get(hash)? {
+            Some(bytes) if bytes.len() == HASH_LEN * 2 => Ok((
+                slice_to_hash(&bytes[..HASH_LEN]),
+                slice_to_hash(&bytes[HASH_LEN..]),
+            )),
+            Some(_) => Err(Errors::Decode(
+                "SparseMerkleTree::read_node(): malformed node".to_string(),
+            )),
+            None => Err(Errors::NotFound(hash.to_vec())),
+        }
+    }
+
+    fn put_node(&mut self, left: &Hash, right: &Hash) -> Result<Hash> {
+        let hash = hash_node(&self.hasher, left, right);
+        if hash != ZERO {
+            self.db.put(&hash, [&left[..], &right[..]].concat())?;
+        }
+        Ok(hash)
+    }
+
+    /// Insert key-leaf entry into the tree. Returns a new root hash.
+    pub fn insert(&mut self, root: Option<&Hash>, key: &Hash, leaf: &Hash) -> Result<Option<Hash>> {
+        let root = root.cloned().unwrap_or(ZERO);
+        self.put(&root, key, 0, leaf).map(to_root)
+    }
+
+    /// Put a leaf into the subtree of `hash` at `depth` in bits, returning the hash of it updated.
+    fn put(&mut self, hash: &Hash, key: &Hash, depth: usize, leaf: &Hash) -> Result<Hash> {
+        if depth == DEPTH {
+            return Ok(*leaf);
+        }
+        let (mut left, mut right) = self.read_node(hash)?;
+        if bit(key, depth) {
+            right = self.put(&right, key, depth + 1, leaf)?;
+        } else {
+            left = self.put(&left, key, depth + 1, leaf)?;
+        }
+        self.put_node(&left, &right)
+    }
+
+    /// Insert key-leaf entries into the tree in a single batch. Returns a new root hash.
+    pub fn inserts(
+        &mut self,
+        root: Option<&Hash>,
+        keys: &[Hash],
+        leaves: &[Hash],
+    ) -> Result<Option<Hash>> {
+        let mut root = root.cloned();
+        self.db.init_batch()?;
+        for (key, leaf) in keys.iter().zip(leaves.iter()) {
+            root = self.insert(root.as_ref(), key, leaf)?;
+        }
+        self.db.finish_batch()?;
+        Ok(root)
+    }
+
+    /// Get the leaf matched with a key, or `None` if the key is absent.
+    pub fn get(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        let mut hash = root.cloned().unwrap_or(ZERO);
+        for depth in 0..DEPTH {
+            if hash == ZERO {
+                return Ok(None);
+            }
+            let (left, right) = self.read_node(&hash)?;
+            hash = if bit(key, depth) { right } else { left };
+        }
+        Ok(to_root(hash))
+    }
+
+    /// Remove the entry of a key from the tree. Returns a new root hash.
+    pub fn remove(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        self.insert(root, key, &ZERO)
+    }
+
+    /// Remove the entries of keys from the tree in a single batch. Returns a new root hash.
+    pub fn removes(&mut self, root: Option<&Hash>, keys: &[Hash]) -> Result<Option<Hash>> {
+        let mut root = root.cloned();
+        self.db.init_batch()?;
+        for key in keys.iter() {
+            root = self.remove(root.as_ref(), key)?;
+        }
+        self.db.finish_batch()?;
+        Ok(root)
+    }
+
+    /// Get the siblings along a key, proving the leaf of the key or that it is absent.
+    pub fn get_proof(&mut self, root: Option<&Hash>, key: &Hash) -> Result<SmtProof> {
+        let mut hash = root.cloned().unwrap_or(ZERO);
+        let mut proof = Vec::with_capacity(DEPTH);
+        for depth in 0..DEPTH {
+            let (left, right) = self.read_node(&hash)?;
+            let (next, sibling) = if bit(key, depth) {
+                (right, left)
+            } else {
+                (left, right)
+            };
+            proof.push(sibling);
+            hash = next;
+        }
+        proof.reverse();
+        Ok(proof)
+    }
+}
+
+/// Verify an `SmtProof` that a key has the given leaf in the tree of `root`,
+/// or is absent from it if `leaf` is `None`.
+pub fn verify_smt_proof<H: Hasher>(
+    hasher: &H,
+    root: Option<&Hash>,
+    key: &Hash,
+    leaf: Option<&Hash>,
+    proof: &SmtProof,
+) -> bool {
+    if proof.len() != DEPTH {
+        return false;
+    }
+    let mut hash = leaf.cloned().unwrap_or(ZERO);
+    for (i, sibling) in proof.iter().enumerate() {
+        hash = if bit(key, DEPTH - 1 - i) {
+            hash_node(hasher, sibling, &hash)
+        } else {
+            hash_node(hasher, &hash, sibling)
+        };
+    }
+    to_root(hash).as_ref() == root
+}
+
+/// Compress an `SmtProof` by leaving out its empty siblings, which most of them are
+/// but those near the leaf: a bitmap of `HASH_LEN` bytes, where bit `i % 8`
+/// (the most significant first) of byte `i / 8` is set if the `i`-th sibling is not empty,
+/// followed by those siblings in order.
+pub fn compress_smt_proof(proof: &SmtProof) -> Vec<u8> {
+    let mut bitmap = vec![0u8; DEPTH / 8];
+    let mut siblings = Vec::new();
+    for (i, sibling) in proof.iter().take(DEPTH).enumerate() {
+        if *sibling != ZERO {
+            bitmap[i / 8] |= 0x80 >> (i % 8);
+            siblings.extend_from_slice(sibling);
+        }
+    }
+    [bitmap, siblings].concat()
+}
+
+/// Decompress an `SmtProof` compressed by `compress_smt_proof()`.
+pub fn decompress_smt_proof(bytes: &[u8]) -> Result<SmtProof> {
+    let malformed = || Errors::Decode("decompress_smt_proof(): malformed".to_string());
+    let bitmap = slice_to_hash(bytes.get(..DEPTH / 8).ok_or_else(malformed)?);
+    let mut siblings = bytes[DEPTH / 8..].chunks(HASH_LEN);
+    let mut proof = Vec::with_capacity(DEPTH);
+    for i in 0..DEPTH {
+        if bit(&bitmap, i) {
+            match siblings.next() {
+                Some(sibling) if sibling.len() == HASH_LEN && sibling != ZERO => {
+                    proof.push(slice_to_hash(sibling))
+                }
+                _ => return Err(malformed()),
+            }
+        } else {
+            proof.push(ZERO);
+        }
+    }
+    match siblings.next() {
+        Some(_) => Err(malformed()),
+        None => Ok(proof),
+    }
+}
@@ -0,0 +1,76 @@
+//! A module defining the interface of authenticated stores such as `monotree`.
+//!
+//! Applications coding against `MerkleStore` rather than `Monotree` keep their call sites
+//! when the commitment behind them is swapped for another implementing the trait.
+use crate::*;
+
+/// A trait defining stores committing to their entries by roots, proving entries against them.
+///
+/// Every operation is given the root it applies to, as a store keeps every version
+/// of its entries, and updates return the new root, `None` for an empty store.
+pub trait MerkleStore {
+    type Key;
+    type Value;
+    type Root;
+    type Proof;
+
+    fn insert(
+        &mut self,
+        root: Option<&Self::Root>,
+        key: &Self::Key,
+        value: &Self::Value,
+    ) -> Result<Option<Self::Root>>;
+    fn get(&mut self, root: Option<&Self::Root>, key: &Self::Key) -> Result<Option<Self::Value>>;
+    fn remove(&mut self, root: Option<&Self::Root>, key: &Self::Key) -> Result<Option<Self::Root>>;
+    /// Get the proof of the entry of a key, or `None` if the key is absent.
+    fn prove(&mut self, root: Option<&Self::Root>, key: &Self::Key) -> Result<Option<Self::Proof>>;
+    /// Verify that the proof binds the entry of a key to the root.
+    fn verify(
+        &self,
+        root: Option<&Self::Root>,
+        key: &Self::Key,
+        value: &Self::Value,
+        proof: Option<&Self::Proof>,
+    ) -> bool;
+}
+
+/// `Monotree` takes keys and leaves of `HASH_LEN` and proves them by `Proof`,
+/// the same as `Monotree::get_merkle_proof()` and `verify_proof()`.
+impl<D, H, C> MerkleStore for Monotree<D, H, C>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    type Key = Hash;
+    type Value = Hash;
+    type Root = Hash;
+    type Proof = Proof;
+
+    fn insert(&mut self, root: Option<&Hash>, key: &Hash, value: &Hash) -> Result<Option<Hash>> {
+        Monotree::insert(self, root, key, value)
+    }
+
+    fn get(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        Monotree::get(self, root, key)
+    }
+
+    fn remove(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+        Monotree::remove(self, root, key)
+    }
+
+    fn prove(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Proof>> {
+        self.get_merkle_proof(root, key)
+    }
+
+    /// The proof walks down along the key, so that the key itself is not needed to verify it.
+    fn verify(
+        &self,
+        root: Option<&Hash>,
+        _key: &Hash,
+        value: &Hash,
+        proof: Option<&Proof>,
+    ) -> bool {
+        verify_proof(&self.hasher, root, value, proof)
+    }
+}
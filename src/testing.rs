@@ -0,0 +1,171 @@
+//! A module of harnesses checking invariants of `monotree` over any `Database` and `Hasher`,
+//! for authors of backends to validate their implementations of `Database`.
+//!
+//! `check_invariants()` runs rounds of randomized inserts, removes and proofs on a tree over
+//! the backend, and fails with `Errors::Other` telling the first invariant broken and the seed
+//! to repeat it.
+use crate::*;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+/// Parameters of the randomized sequences `check_invariants()` runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checks {
+    /// Seed of the generator of keys, leaves and orders, so that a failing run is repeated.
+    pub seed: u64,
+    /// Number of rounds, each of a fresh set of entries inserted into an empty tree.
+    pub rounds: usize,
+    /// Number of entries of each round.
+    pub entries: usize,
+}
+
+impl Default for Checks {
+    fn default() -> Self {
+        Checks {
+            seed: 0,
+            rounds: 8,
+            entries: 64,
+        }
+    }
+}
+
+/// Check invariants of a tree over its database by the randomized sequences of `checks`:
+///
+/// - Determinism: inserting the same entries one by one, in a batch or in any order
+///   gives the same tree by `Monotree::same_tree()`.
+/// - Lookups: every key inserted gives its leaf, and keys never inserted give none.
+/// - Proofs: every key inserted has a proof verified against the root by `verify_proof()`,
+///   and keys never inserted have none.
+/// - Removal symmetry: removing some of the entries gives the tree of inserting the rest alone,
+///   inserting them back gives the tree before, and removing all gives no root.
+/// - Read-your-writes: within a batch of `Monotree::in_batch()` or a `Monotree::transaction()`,
+///   lookups and proofs against the roots of the updates made so far observe them, and once
+///   the batch fails, nothing of it is observed.
+pub fn check_invariants<D, H, C, const N: usize>(
+    tree: &mut Monotree<D, H, C, N>,
+    checks: &Checks,
+) -> Result<()>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    let mut rng = StdRng::seed_from_u64(checks.seed);
+    for round in 0..checks.rounds {
+        let broken = |invariant: &str| {
+            Errors::new(&format!(
+                "check_invariants(): {} broken in round {} of seed {}",
+                invariant, round, checks.seed
+            ))
+        };
+        let keys: Vec<Hash<N>> = (0..checks.entries).map(|_| random(&mut rng)).collect();
+        let leaves: Vec<Hash<N>> = (0..checks.entries).map(|_| random(&mut rng)).collect();
+
+        // determinism
+        let mut root = None;
+        for (key, leaf) in keys.iter().zip(&leaves) {
+            root = tree.insert(root.as_ref(), key, leaf)?;
+        }
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.shuffle(&mut rng);
+        let (shuffled_keys, shuffled_leaves) = pick(&keys, &leaves, &order);
+        let shuffled = tree.inserts(None, &shuffled_keys, &shuffled_leaves)?;
+        if !tree.same_tree(shuffled.as_ref(), root.as_ref())? {
+            return Err(broken("determinism"));
+        }
+
+        // lookups and proofs
+        for (key, leaf) in keys.iter().zip(&leaves) {
+            if tree.get(root.as_ref(), key)? != Some(*leaf) {
+                return Err(broken("lookup"));
+            }
+            let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+            if !verify_proof(&tree.hasher, root.as_ref(), leaf, proof.as_ref()) {
+                return Err(broken("proof"));
+            }
+        }
+        let absent: Hash<N> = random(&mut rng);
+        if tree.get(root.as_ref(), &absent)?.is_some()
+            || tree.get_merkle_proof(root.as_ref(), &absent)?.is_some()
+        {
+            return Err(broken("lookup of a key never inserted"));
+        }
+
+        // read-your-writes
+        let (key, leaf): (Hash<N>, Hash<N>) = (random(&mut rng), random(&mut rng));
+        let observed =
+            |tree: &mut Monotree<D, H, C, N>, staged: Option<&Hash<N>>| -> Result<bool> {
+                let proof = tree.get_merkle_proof(staged, &key)?;
+                Ok(tree.get(staged, &key)? == Some(leaf)
+                    && verify_proof(&tree.hasher, staged, &leaf, proof.as_ref()))
+            };
+        let mut staged = None;
+        let mut seen = false;
+        tree.in_batch(|tree| {
+            staged = tree.insert(root.as_ref(), &key, &leaf)?;
+            seen = observed(tree, staged.as_ref())?;
+            Ok(())
+        })?;
+        if !seen || !observed(tree, staged.as_ref())? {
+            return Err(broken("read-your-writes in a batch"));
+        }
+        let mut discarded = None;
+        let failed = tree.in_batch(|tree| {
+            discarded = tree.insert(root.as_ref(), &absent, &leaf)?;
+            Err(Errors::new("discarded"))
+        });
+        let discarded = discarded.ok_or_else(|| broken("read-your-writes in a batch discarded"))?;
+        if failed.is_ok() || tree.db.get(&discarded)?.is_some() {
+            return Err(broken("read-your-writes in a batch discarded"));
+        }
+        let (hasher, mut seen) = (tree.hasher.clone(), false);
+        let committed = tree.transaction(root.as_ref(), |txn| {
+            txn.insert(&key, &leaf)?;
+            let proof = txn.get_merkle_proof(&key)?;
+            seen = txn.get(&key)? == Some(leaf)
+                && verify_proof(&hasher, txn.root(), &leaf, proof.as_ref());
+            Ok(())
+        })?;
+        if !seen || committed != staged {
+            return Err(broken("read-your-writes in a transaction"));
+        }
+
+        // removal symmetry
+        order.shuffle(&mut rng);
+        let (removed, kept) = order.split_at(rng.gen_range(0, order.len() + 1));
+        let (removed_keys, removed_leaves) = pick(&keys, &leaves, removed);
+        let (kept_keys, kept_leaves) = pick(&keys, &leaves, kept);
+        let pruned = tree.removes(root.as_ref(), &removed_keys)?;
+        let rest = tree.inserts(None, &kept_keys, &kept_leaves)?;
+        if !tree.same_tree(pruned.as_ref(), rest.as_ref())? {
+            return Err(broken("removal symmetry"));
+        }
+        for key in removed_keys.iter() {
+            if tree.get(pruned.as_ref(), key)?.is_some() {
+                return Err(broken("lookup of a key removed"));
+            }
+        }
+        let reinserted = tree.inserts(pruned.as_ref(), &removed_keys, &removed_leaves)?;
+        if !tree.same_tree(reinserted.as_ref(), root.as_ref())? {
+            return Err(broken("reinsertion"));
+        }
+        if tree.removes(root.as_ref(), &keys)?.is_some() {
+            return Err(broken("removal of all"));
+        }
+    }
+    Ok(())
+}
+
+fn random<const N: usize>(rng: &mut StdRng) -> Hash<N> {
+    let mut hash = [0u8; N];
+    rng.fill(&mut hash[..]);
+    hash
+}
+
+/// Pick entries at the given indices, in the order of `indices`.
+fn pick<const N: usize>(
+    keys: &[Hash<N>],
+    leaves: &[Hash<N>],
+    indices: &[usize],
+) -> (Vec<Hash<N>>, Vec<Hash<N>>) {
+    indices.iter().map(|&i| (keys[i], leaves[i])).unzip()
+}
@@ -1,12 +1,492 @@
 //! A module implementing `monotree`.
+use crate::database::{NamespacedDB, OverlayDB, SnapshotDB, SnapshotId};
+#[cfg(feature = "parallel")]
+use crate::parallel::{Builder, Entry};
 use crate::utils::*;
 use crate::*;
+use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// Verifiers moved to `verify`, which is built without `std`, still found here.
+pub use crate::verify::{
+    compute_root, verify_chained_proof, verify_merkle_proofs, verify_non_inclusion_proof,
+    verify_proof, verify_value_proof,
+};
+
+/// Key under which `Metadata` of a tree is stored in database.
+/// Keys extending it are reserved for other records of the tree, such as those of `Journal`.
+pub const METADATA_KEY: Hash = [0xff; HASH_LEN];
+
+/// Key under which the root recorded by `Monotree::swap_root()` is stored in database.
+pub const ROOT_KEY: Hash = [0xfe; HASH_LEN];
+
+/// Key under which the `BloomFilter` enabled by `Monotree::enable_filter()` is stored in database.
+pub const FILTER_KEY: Hash = [0xfd; HASH_LEN];
+
+/// Key under which the leaf set by `Monotree::set_default_leaf()` is stored in database.
+pub const DEFAULT_LEAF_KEY: Hash = [0xfc; HASH_LEN];
+
+/// Tag prepended to a key and the leaf it replaces to derive a tombstone of `Monotree::remove()`.
+const TOMBSTONE_TAG: &[u8] = b"monotree-tombstone";
+
+/// Tag prepended to a key to derive the key the application key it was hashed from is stored under.
+const RAW_KEY_TAG: u8 = 0xfa;
+
+/// A subtree built by `Monotree::build_from_sorted()`: the bit its node branches at,
+/// or the depth of leaves for a leaf, its hash and the path of an entry in it.
+type Built<'a, const N: usize> = (BitsLen, Hash<N>, Cow<'a, [u8]>);
+
+/// Version of snapshots written by `Monotree::export()`.
+pub const SNAPSHOT_VERSION: u8 = 0x01;
+
+/// Number of nodes `Monotree::import()` writes in a batch.
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+/// Read exactly the bytes of `buf` from a snapshot.
+fn read_frame_bytes<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            Errors::Decode("import(): truncated snapshot".to_string())
+        }
+        _ => Errors::Io(err),
+    })
+}
+
+/// Read a frame of a snapshot: a varint of its length, then as many bytes.
+fn read_frame<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut varint = Vec::new();
+    loop {
+        let mut byte = [0u8];
+        read_frame_bytes(reader, &mut byte)?;
+        varint.push(byte[0]);
+        if byte[0] & 0x80 == 0 || varint.len() == 10 {
+            break;
+        }
+    }
+    let (len, _) = varint_to_int(&varint)?;
+    // read as the bytes arrive rather than allocated at once for whatever length is given
+    let mut bytes = Vec::new();
+    reader.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(Errors::Decode("import(): truncated snapshot".to_string()));
+    }
+    Ok(bytes)
+}
+
+/// Parameters of a tree recorded in database along with its nodes.
+///
+/// Serialized as pairs of a tag and a value, one byte each.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Metadata {
+    pub traversal: Traversal,
+    /// `NodeCodec::ID` of the codec nodes are serialized with.
+    pub codec: u8,
+    pub leaf: LeafPolicy,
+    /// Number of bytes of keys traversed, the length of hashes of the tree by default.
+    pub key_len: usize,
+    /// Whether removed keys are replaced with tombstones, see `Monotree::set_soft_delete()`.
+    pub soft_delete: bool,
+    /// Whether application keys are stored, see `Monotree::set_raw_keys()`.
+    pub raw_keys: bool,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata {
+            traversal: Traversal::default(),
+            codec: 0,
+            leaf: LeafPolicy::default(),
+            key_len: HASH_LEN,
+            soft_delete: false,
+            raw_keys: false,
+        }
+    }
+}
+
+impl Metadata {
+    /// Serialize `Metadata` into bytes.
+    /// Fails if `key_len` is over 255 bytes, beyond the single byte it is recorded in.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let bits = match self.traversal.bits {
+            BitOrder::MsbFirst => 0x00,
+            BitOrder::LsbFirst => 0x01,
+        };
+        let bytes = match self.traversal.bytes {
+            ByteOrder::BigEndian => 0x00,
+            ByteOrder::LittleEndian => 0x01,
+        };
+        let leaf = match self.leaf {
+            LeafPolicy::Raw => 0x00,
+            LeafPolicy::Hash => 0x01,
+            LeafPolicy::KeyedHash => 0x02,
+        };
+        let mut metadata = vec![0x01, bits, 0x02, bytes, 0x03, self.codec, 0x04, leaf];
+        // written only for keys of other than `HASH_LEN`, so that metadata of other trees stays the same
+        if self.key_len != HASH_LEN {
+            let len = u8::try_from(self.key_len)
+                .map_err(|_| Errors::new("Metadata::to_bytes(): key_len over 255"))?;
+            metadata.extend_from_slice(&[0x05, len]);
+        }
+        if self.soft_delete {
+            metadata.extend_from_slice(&[0x06, 0x01]);
+        }
+        if self.raw_keys {
+            metadata.extend_from_slice(&[0x07, 0x01]);
+        }
+        Ok(metadata)
+    }
+
+    /// Construct `Metadata` by deserializing bytes slice.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut metadata = Metadata::default();
+        for pair in bytes.chunks(2) {
+            match pair {
+                [0x01, 0x00] => metadata.traversal.bits = BitOrder::MsbFirst,
+                [0x01, 0x01] => metadata.traversal.bits = BitOrder::LsbFirst,
+                [0x02, 0x00] => metadata.traversal.bytes = ByteOrder::BigEndian,
+                [0x02, 0x01] => metadata.traversal.bytes = ByteOrder::LittleEndian,
+                [0x03, codec] => metadata.codec = *codec,
+                [0x04, 0x00] => metadata.leaf = LeafPolicy::Raw,
+                [0x04, 0x01] => metadata.leaf = LeafPolicy::Hash,
+                [0x04, 0x02] => metadata.leaf = LeafPolicy::KeyedHash,
+                [0x05, len] if *len > 0 => metadata.key_len = *len as usize,
+                [0x06, 0x00] => metadata.soft_delete = false,
+                [0x06, 0x01] => metadata.soft_delete = true,
+                [0x07, 0x00] => metadata.raw_keys = false,
+                [0x07, 0x01] => metadata.raw_keys = true,
+                _ => {
+                    let msg = "Metadata::from_bytes(): unknown entry";
+                    return Err(Errors::Decode(msg.to_string()));
+                }
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// Semantics of updates a `ConsistencyProof` is allowed to carry.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Consistency {
+    /// Only keys absent from the old tree are inserted.
+    AppendOnly,
+    /// Keys are inserted or their leaves are changed, but no key is removed.
+    UpdateOnly,
+    /// Keys are inserted, changed or removed.
+    Any,
+}
+
+/// A proof that a tree was derived from an older one by a set of key updates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsistencyProof<const N: usize = HASH_LEN> {
+    /// Updated keys in order, each with its new leaf or `None` if removed.
+    pub updates: Vec<(Hash<N>, Option<Hash<N>>)>,
+    /// Nodes of the old tree along the paths of the updated keys,
+    /// and nodes of either tree not found at the same position in the other.
+    pub nodes: NodeSet<N>,
+    /// The order both trees traverse keys in.
+    pub traversal: Traversal,
+    /// Number of bytes of keys both trees traverse.
+    pub key_len: usize,
+    /// `NodeCodec::ID` of the codec both trees are serialized with.
+    pub codec: u8,
+}
+
+/// A proof of a key removed from a tree by `Monotree::remove_with_proof()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemovalProof<const N: usize = HASH_LEN> {
+    /// The leaf the key had before.
+    pub leaf: Hash<N>,
+    /// Merkle proof of `leaf` against the root before, verified by `verify_proof()`.
+    pub proof: Proof,
+    /// Proof that the root after was derived by removing the key alone,
+    /// of nodes along the key in the tree before.
+    pub consistency: ConsistencyProof<N>,
+}
+
+/// A proof of the leaf of a key updated by `Monotree::prove_update()`, pairing the proofs
+/// before and after along with both roots, such as for rollup circuits and fraud proofs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpdateProof<const N: usize = HASH_LEN> {
+    pub old_root: Hash<N>,
+    pub new_root: Hash<N>,
+    pub old_leaf: Hash<N>,
+    pub new_leaf: Hash<N>,
+    /// Merkle proof of `old_leaf` against `old_root`.
+    pub old_proof: Proof,
+    /// Merkle proof of `new_leaf` against `new_root`, of the same siblings as `old_proof`.
+    pub new_proof: Proof,
+}
+
+/// A page of a `MultiProof` generated by `Monotree::get_merkle_proof_page()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofPage<const N: usize = HASH_LEN> {
+    /// Keys proven by this page.
+    pub keys: Vec<Hash<N>>,
+    pub proof: MultiProof,
+    /// Key the next page starts from, or `None` if this is the last page.
+    pub next: Option<Hash<N>>,
+}
+
+/// Entries changed between two trees, returned by `Monotree::diff()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff<const N: usize = HASH_LEN> {
+    /// Entries only in the newer tree, as `(key, leaf)`.
+    pub inserted: Vec<(Hash<N>, Hash<N>)>,
+    /// Entries only in the older tree, as `(key, leaf)`.
+    pub removed: Vec<(Hash<N>, Hash<N>)>,
+    /// Keys in both trees with different leaves, as `(key, old leaf, new leaf)`.
+    pub modified: Vec<(Hash<N>, Hash<N>, Hash<N>)>,
+}
+
+/// A new root produced by a batch committed, sent to the receivers of `Monotree::watch_roots()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RootChange<const N: usize = HASH_LEN> {
+    /// The root the batch started from.
+    pub before: Option<Hash<N>>,
+    /// The root the batch results in.
+    pub after: Option<Hash<N>>,
+}
+
+/// Outcome of replaying updates by `Monotree::replay_and_verify()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Replay<const N: usize = HASH_LEN> {
+    /// The root the updates result in.
+    pub root: Option<Hash<N>>,
+    /// The root the updates were expected to result in.
+    pub expected: Option<Hash<N>>,
+    /// Indices of updates changing nothing, i.e. removals of absent keys
+    /// and insertions of the leaves already there.
+    pub noops: Vec<usize>,
+    /// Entries of the expected tree against those of the resulting one, if the roots differ
+    /// and the nodes of the expected tree are in database, or `None` otherwise.
+    pub diff: Option<Diff<N>>,
+}
+
+impl<const N: usize> Replay<N> {
+    /// Check if the updates result in the expected root.
+    pub fn is_match(&self) -> bool {
+        self.root == self.expected
+    }
+}
+
+/// Statistics of a tree, returned by `Monotree::stats()`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TreeStats {
+    pub nodes: u64,
+    pub leaves: u64,
+    /// Bytes of nodes, each counted as its key (`HASH_LEN`) plus its serialized form,
+    /// as by `Monotree::estimated_size()`.
+    pub bytes: u64,
+    /// Numbers of leaves by their depth, the number of nodes walked down to each of them.
+    pub depths: Vec<u64>,
+}
+
+impl TreeStats {
+    /// Get the depth of the deepest leaf, or `0` for an empty tree.
+    pub fn depth(&self) -> usize {
+        self.depths.len().saturating_sub(1)
+    }
+}
+
+/// Kinds of damage to nodes found by `Monotree::verify_integrity()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Damage {
+    /// The node is not in database.
+    Missing,
+    /// The node fails to decode, or links past the depth of leaves.
+    Malformed,
+    /// The node decodes, but hashes to other than the hash it is stored under.
+    Mismatch,
+}
+
+/// A node found damaged by `Monotree::verify_integrity()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DamagedNode<const N: usize = HASH_LEN> {
+    /// Bits walked down from the root to the node.
+    pub path: Vec<bool>,
+    pub hash: Hash<N>,
+    pub damage: Damage,
+}
+
+/// The form `ConsistencyProof`s are (de)serialized in, with hashes as bytes of any length.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ConsistencyProofRepr {
+    updates: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    nodes: Vec<(Vec<u8>, Vec<u8>)>,
+    traversal: Traversal,
+    key_len: usize,
+    codec: u8,
+}
+
+#[cfg(feature = "serde")]
+impl<const N: usize> Serialize for ConsistencyProof<N> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        ConsistencyProofRepr {
+            updates: self
+                .updates
+                .iter()
+                .map(|(key, leaf)| (key.to_vec(), leaf.map(|leaf| leaf.to_vec())))
+                .collect(),
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(hash, bytes)| (hash.to_vec(), bytes.clone()))
+                .collect(),
+            traversal: self.traversal,
+            key_len: self.key_len,
+            codec: self.codec,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> Deserialize<'de> for ConsistencyProof<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let repr = ConsistencyProofRepr::deserialize(deserializer)?;
+        let mut updates = Vec::with_capacity(repr.updates.len());
+        for (key, leaf) in repr.updates {
+            let leaf = match leaf {
+                Some(leaf) => Some(vec_to_array(leaf)?),
+                None => None,
+            };
+            updates.push((vec_to_array(key)?, leaf));
+        }
+        let mut nodes = Vec::with_capacity(repr.nodes.len());
+        for (hash, bytes) in repr.nodes {
+            nodes.push((vec_to_array(hash)?, bytes));
+        }
+        Ok(ConsistencyProof {
+            updates,
+            nodes,
+            traversal: repr.traversal,
+            key_len: repr.key_len,
+            codec: repr.codec,
+        })
+    }
+}
+
+/// The form `ProofPage`s are (de)serialized in, with hashes as bytes of any length.
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct ProofPageRepr {
+    keys: Vec<Vec<u8>>,
+    proof: MultiProof,
+    next: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "serde")]
+impl<const N: usize> Serialize for ProofPage<N> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        ProofPageRepr {
+            keys: self.keys.iter().map(|key| key.to_vec()).collect(),
+            proof: self.proof.clone(),
+            next: self.next.map(|key| key.to_vec()),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> Deserialize<'de> for ProofPage<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let repr = ProofPageRepr::deserialize(deserializer)?;
+        let mut keys = Vec::with_capacity(repr.keys.len());
+        for key in repr.keys {
+            keys.push(vec_to_array(key)?);
+        }
+        let next = match repr.next {
+            Some(key) => Some(vec_to_array(key)?),
+            None => None,
+        };
+        Ok(ProofPage {
+            keys,
+            proof: repr.proof,
+            next,
+        })
+    }
+}
+
+/// Costs of a batch operation, returned by `Monotree::inserts_with_report()` and the like.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommitReport {
+    pub nodes_read: u64,
+    pub nodes_written: u64,
+    /// Bytes of nodes written, each counted as its key (`HASH_LEN`) plus its serialized form.
+    pub bytes_written: u64,
+    pub hashes: u64,
+    /// Time taken by the whole operation, including committing the batch.
+    pub duration: Duration,
+}
+
+/// State of a migration by `Monotree::migrate()`: the new hashes of nodes migrated so far,
+/// and the number of nodes rewritten.
+struct Migration<'a, const N: usize> {
+    from_version: u8,
+    migrated: HashMap<Hash<N>, Hash<N>>,
+    written: usize,
+    progress: &'a mut dyn FnMut(usize),
+}
+
+/// The leaf `Monotree::put()` expects a key to have for the key to be put, if any,
+/// and the leaf it found the key to have.
+#[derive(Default)]
+struct Swap<const N: usize> {
+    /// `Some(None)` for the key to be absent, or `Some(Some(leaf))` for it to have the leaf.
+    expected: Option<Option<Hash<N>>>,
+    replaced: Option<Hash<N>>,
+}
+
+/// A unit of a tree, given as the bits walked down to it and its hash.
+type PathUnit<const N: usize = HASH_LEN> = (Vec<bool>, Hash<N>);
 
 /// A structure for `monotree`.
 #[derive(Debug)]
-pub struct Monotree<D = DefaultDatabase, H = DefaultHasher> {
+pub struct Monotree<
+    D = DefaultDatabase,
+    H = DefaultHasher,
+    C = DefaultCodec,
+    const N: usize = HASH_LEN,
+> {
     pub db: D,
     pub hasher: H,
+    cancel: Option<Arc<AtomicBool>>,
+    traversal: Traversal,
+    leaf: LeafPolicy,
+    key_len: usize,
+    soft_delete: bool,
+    raw_keys: bool,
+    default_leaf: Option<Hash<N>>,
+    filter: Option<BloomFilter>,
+    report: CommitReport,
+    policy: Option<Box<dyn CommitPolicy<N> + Send>>,
+    flags: Vec<(BatchSummary<N>, String)>,
+    metrics: Option<Arc<dyn Metrics>>,
+    watchers: Vec<mpsc::Sender<RootChange<N>>>,
+    prefetch: usize,
+    /// Nodes read ahead by `prefetch()` for the batch running, served by `read_node()`.
+    prefetched: HashMap<Hash<N>, Vec<u8>>,
+    codec: PhantomData<C>,
 }
 
 impl Default for Monotree<DefaultDatabase, DefaultHasher> {
@@ -15,31 +495,571 @@ impl Default for Monotree<DefaultDatabase, DefaultHasher> {
     }
 }
 
-impl<D, H> Monotree<D, H>
+impl<D, H, C, const N: usize> Monotree<D, H, C, N>
 where
     D: Database,
-    H: Hasher,
+    H: Hasher<N>,
+    C: NodeCodec,
 {
     pub fn new(dbpath: &str) -> Self {
-        let db = Database::new(dbpath);
+        Self::try_new(dbpath).expect("new()")
+    }
+
+    /// The same as `new()`, but returns an error instead of panicking
+    /// if the database fails to open or was written with another codec.
+    pub fn try_new(dbpath: &str) -> Result<Self> {
+        Self::with_db(Database::try_new(dbpath)?)
+    }
+
+    /// Construct `Monotree` over a database already opened, such as by `RocksDB::with_cf()`.
+    pub fn with_db(mut db: D) -> Result<Self> {
         let hasher = Hasher::new();
-        Monotree { db, hasher }
+        let metadata = match db.get(&METADATA_KEY)? {
+            Some(bytes) => Metadata::from_bytes(&bytes)?,
+            None => {
+                // no metadata means the default codec, so record any other
+                let metadata = Metadata {
+                    codec: C::ID,
+                    key_len: N,
+                    ..Default::default()
+                };
+                if metadata != Metadata::default() {
+                    db.put(&METADATA_KEY, metadata.to_bytes()?)?;
+                }
+                metadata
+            }
+        };
+        if metadata.codec != C::ID {
+            return Err(Errors::new("new(): codec mismatch"));
+        }
+        if metadata.key_len > N {
+            return Err(Errors::new("new(): key length over that of hashes"));
+        }
+        let filter = match db.get(&FILTER_KEY)? {
+            Some(bytes) => Some(BloomFilter::from_bytes(&bytes)?),
+            None => None,
+        };
+        let default_leaf = match db.get(&DEFAULT_LEAF_KEY)? {
+            Some(bytes) if bytes.len() == N => Some(slice_to_array(&bytes)),
+            Some(_) => return Err(Errors::Decode("new(): invalid default leaf".to_string())),
+            None => None,
+        };
+        Ok(Monotree {
+            db,
+            hasher,
+            cancel: None,
+            traversal: metadata.traversal,
+            leaf: metadata.leaf,
+            key_len: metadata.key_len,
+            soft_delete: metadata.soft_delete,
+            raw_keys: metadata.raw_keys,
+            default_leaf,
+            filter,
+            report: CommitReport::default(),
+            policy: None,
+            flags: Vec::new(),
+            metrics: None,
+            watchers: Vec::new(),
+            prefetch: 0,
+            prefetched: HashMap::new(),
+            codec: PhantomData,
+        })
+    }
+
+    /// Get the order in which bits of keys are traversed.
+    pub fn traversal(&self) -> Traversal {
+        self.traversal
+    }
+
+    /// Set the order in which bits of keys are traversed, and record it in database.
+    ///
+    /// Trees reopened from the database keep traversing keys in this order.
+    /// This must be set before any entry is inserted, as existing nodes are not rearranged.
+    pub fn set_traversal(&mut self, traversal: Traversal) -> Result<()> {
+        self.traversal = traversal;
+        self.save_metadata()
+    }
+
+    /// Get the policy of how values are folded into leaves.
+    pub fn leaf_policy(&self) -> LeafPolicy {
+        self.leaf
+    }
+
+    /// Set the policy of how values are folded into leaves, and record it in database.
+    ///
+    /// Like the order of traversal, this must be set before any entry is inserted.
+    pub fn set_leaf_policy(&mut self, leaf: LeafPolicy) -> Result<()> {
+        self.leaf = leaf;
+        self.save_metadata()
+    }
+
+    /// Get the number of bytes of keys traversed, which is the depth of leaves in bytes.
+    pub fn key_len(&self) -> usize {
+        self.key_len
+    }
+
+    /// Set the number of bytes of keys traversed, from 1 to the length of keys, and record it in database.
+    ///
+    /// Only the leading `key_len` bytes of keys, in the order of traversal, are walked down,
+    /// so leaves are found at that depth and proofs and hashing stop there.
+    /// Keys sharing those bytes are the same key, and keys given back, as by `iter()`,
+    /// have the rest of their bytes zeroed. Like the order of traversal,
+    /// this must be set before any entry is inserted.
+    pub fn set_key_len(&mut self, key_len: usize) -> Result<()> {
+        if key_len == 0 || key_len > N {
+            return Err(Errors::new("set_key_len(): out of range"));
+        }
+        self.key_len = key_len;
+        self.save_metadata()
+    }
+
+    /// Check if `remove()` replaces leaves with tombstones instead of removing keys.
+    pub fn soft_delete(&self) -> bool {
+        self.soft_delete
+    }
+
+    /// Make `remove()` and `removes()` replace leaves with tombstones, and record it in database.
+    ///
+    /// A tombstone is the digest of the key and the leaf it replaces, so the root changes
+    /// deterministically, and that preimage is stored under it, so that `restore()` brings
+    /// the leaf back. `get()` answers `None` for tombstoned keys, while `iter()` and proofs
+    /// see tombstones as the leaves they are. Tombstones stay in the tree until overwritten.
+    pub fn set_soft_delete(&mut self, soft_delete: bool) -> Result<()> {
+        self.soft_delete = soft_delete;
+        self.save_metadata()
+    }
+
+    /// Check if `insert_raw_key()` stores application keys for `original_key()`.
+    pub fn raw_keys(&self) -> bool {
+        self.raw_keys
+    }
+
+    /// Make `insert_raw_key()` store application keys along with their leaves, so that
+    /// `original_key()` gives them back, and record it in database.
+    ///
+    /// Keys are stored apart from the tree, so this changes no root. Stored keys are kept
+    /// after `remove_raw_key()`, as older roots still hold them.
+    pub fn set_raw_keys(&mut self, raw_keys: bool) -> Result<()> {
+        self.raw_keys = raw_keys;
+        self.save_metadata()
+    }
+
+    /// Get the leaf absent keys are taken to have, if any.
+    pub fn default_leaf(&self) -> Option<&Hash<N>> {
+        self.default_leaf.as_ref()
+    }
+
+    /// Set the leaf absent keys are taken to have, or `None` for them to be absent only,
+    /// which is the default, and record it in database.
+    ///
+    /// With a default leaf, as in sparse Merkle trees of zero leaves, `get()` gives it for
+    /// keys absent, and `get_leaf_proof()` proves it by their absence. Keys never hold it
+    /// in the tree: inserting it by `insert()` or `inserts()` removes the key instead,
+    /// so that a key of the default leaf and an absent key give the same root.
+    /// Like the order of traversal, this must be set before any entry is inserted.
+    pub fn set_default_leaf(&mut self, leaf: Option<&Hash<N>>) -> Result<()> {
+        self.default_leaf = leaf.cloned();
+        match leaf {
+            Some(leaf) => self.db.put(&DEFAULT_LEAF_KEY, leaf.to_vec()),
+            None => self.db.delete(&DEFAULT_LEAF_KEY),
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            traversal: self.traversal,
+            codec: C::ID,
+            leaf: self.leaf,
+            key_len: self.key_len,
+            soft_delete: self.soft_delete,
+            raw_keys: self.raw_keys,
+        }
+    }
+
+    fn save_metadata(&mut self) -> Result<()> {
+        let metadata = self.metadata();
+        self.db.put(&METADATA_KEY, metadata.to_bytes()?)
+    }
+
+    /// Get the path a key is walked down along, i.e. the key arranged in the order of traversal
+    /// and cut to `key_len`.
+    fn path<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.traversal.arrange(key) {
+            Cow::Borrowed(key) => Cow::Borrowed(&key[..self.key_len.min(key.len())]),
+            Cow::Owned(mut key) => {
+                key.truncate(self.key_len);
+                Cow::Owned(key)
+            }
+        }
+    }
+
+    /// Get the key walked down along the given bits, the inverse of `path()`.
+    fn key_of(&self, bits: &[bool]) -> Hash<N> {
+        let mut path = bits_to_bytes(bits);
+        path.resize(N, 0);
+        slice_to_array(&self.traversal.arrange(&path))
+    }
+
+    /// Get the depth of leaves in bits.
+    fn leaf_depth(&self) -> BitsLen {
+        self.key_len as BitsLen * 8
+    }
+
+    /// Get the leaf a value of a key is folded into by the policy of the tree.
+    pub fn leaf(&self, key: &Hash<N>, value: &[u8]) -> Result<Hash<N>> {
+        self.leaf.leaf(&self.hasher, key, value)
+    }
+
+    /// Get the root recorded in database by `swap_root()`.
+    pub fn load_root(&mut self) -> Result<Option<Hash<N>>> {
+        Ok(self.db.get(&ROOT_KEY)?.map(|bytes| slice_to_array(&bytes)))
+    }
+
+    /// Record a new root in database only if the recorded one still is `expected`.
+    ///
+    /// Returns `false` without recording if another writer has swapped the root since,
+    /// in which case the caller reloads the root with `load_root()` and redoes its updates.
+    pub fn swap_root(&mut self, expected: Option<&Hash<N>>, new: Option<&Hash<N>>) -> Result<bool> {
+        let expected = expected.map(|hash| &hash[..]);
+        self.db
+            .cas(&ROOT_KEY, expected, new.map(|hash| hash.to_vec()))
+    }
+
+    /// Keep a `BloomFilter` over inserted keys, so that `get()` and `remove()` of keys never
+    /// inserted return without walking down the tree. Any filter enabled before is replaced.
+    ///
+    /// The filter starts with the keys in the trees of `roots`, sized for `capacity` keys
+    /// with a false positive rate of `rate`. It is stored in database along with every insertion
+    /// and loaded when the tree is reopened. Keys of trees not given here, or of nodes written
+    /// by `apply_nodes()`, are not in the filter and thus looked up as if absent.
+    pub fn enable_filter(&mut self, roots: &[Hash<N>], capacity: usize, rate: f64) -> Result<()> {
+        let mut filter = BloomFilter::new(capacity, rate);
+        for root in roots.iter() {
+            for entry in self.iter(Some(root)) {
+                filter.insert(&entry?.0);
+            }
+        }
+        self.filter = Some(filter);
+        self.save_filter()
+    }
+
+    /// Stop using the filter enabled by `enable_filter()`, and delete it from database.
+    pub fn disable_filter(&mut self) -> Result<()> {
+        self.filter = None;
+        self.db.delete(&FILTER_KEY)
+    }
+
+    fn save_filter(&mut self) -> Result<()> {
+        match &self.filter {
+            Some(filter) => self.db.put(&FILTER_KEY, filter.to_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    /// Check if a key is definitely absent by the filter, if enabled.
+    fn filtered_out(&self, key: &[u8]) -> bool {
+        match &self.filter {
+            Some(filter) => !filter.contains(&self.filter_key(key)),
+            None => false,
+        }
+    }
+
+    /// Get the key a filter holds for a key, which is the key given back by `iter()`.
+    pub(crate) fn filter_key<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.key_len == N {
+            return Cow::Borrowed(key);
+        }
+        let mut path = self.path(key).into_owned();
+        path.resize(N, 0);
+        Cow::Owned(self.traversal.arrange(&path).into_owned())
+    }
+
+    /// Set a policy checking every batch of `inserts()` and `removes()` right before committing.
+    pub fn set_commit_policy(&mut self, policy: Box<dyn CommitPolicy<N> + Send>) {
+        self.policy = Some(policy);
+    }
+
+    /// Take the batches flagged by the commit policy so far, each with the reason given.
+    pub fn take_flags(&mut self) -> Vec<(BatchSummary<N>, String)> {
+        std::mem::take(&mut self.flags)
+    }
+
+    /// Set metrics receiving the nodes read and written, the proofs generated and the batches
+    /// committed by the tree, which may be shared with other trees and with `CachedDb`.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Set the number of levels of nodes `inserts()` and `removes()` read ahead, before walking
+    /// down keys one by one. Nodes of a level are read by a single `Database::get_many()`
+    /// for all the keys of the batch, so that backends over a network take a round trip
+    /// per level rather than one per node. `0`, the default, reads no node ahead.
+    ///
+    /// A single key walks down a chain of nodes each found only in the one above it,
+    /// so `get()` and `insert()` have nothing to read ahead. Use `gets()` and `inserts()`.
+    pub fn set_prefetch(&mut self, levels: usize) {
+        self.prefetch = levels;
+    }
+
+    /// Get a receiver of every new root produced by a batch of `inserts()`, `removes()` and the like,
+    /// or by `transaction()`, once committed, such as to anchor roots externally without polling.
+    /// Batches vetoed by the commit policy, or leaving the root as it was, send nothing.
+    /// Receivers dropped are forgotten with the next root sent.
+    pub fn watch_roots(&mut self) -> mpsc::Receiver<RootChange<N>> {
+        let (sender, receiver) = mpsc::channel();
+        self.watchers.push(sender);
+        receiver
+    }
+
+    /// Send a new root to the receivers of `watch_roots()`, if it is new.
+    fn notify_root(&mut self, before: Option<&Hash<N>>, after: Option<&Hash<N>>) {
+        if before == after {
+            return;
+        }
+        let change = RootChange {
+            before: before.cloned(),
+            after: after.cloned(),
+        };
+        self.watchers
+            .retain(|watcher| watcher.send(change.clone()).is_ok());
+    }
+
+    /// Count nodes read in the report and by the metrics, if set.
+    fn count_read(&mut self, count: u64) {
+        self.report.nodes_read += count;
+        if let Some(metrics) = &self.metrics {
+            metrics.nodes_read(count);
+        }
+    }
+
+    /// Count a node written, hashed to a key in database, in the report and by the metrics, if set.
+    fn count_written(&mut self, bytes: usize) {
+        self.report.hashes += 1;
+        self.report.nodes_written += 1;
+        self.report.bytes_written += (N + bytes) as u64;
+        if let Some(metrics) = &self.metrics {
+            metrics.node_written((N + bytes) as u64);
+        }
+    }
+
+    /// Commit the batch of a batch operation opened at `start`, with the commit policy run over it.
+    fn commit_batch(
+        &mut self,
+        before: Option<&Hash<N>>,
+        after: Option<&Hash<N>>,
+        keys: usize,
+        counted: &CommitReport,
+        start: Instant,
+    ) -> Result<()> {
+        if let Err(err) = self.check_commit(before, after, keys, counted) {
+            // opening the batch again drops its writes, then finishing it commits nothing
+            let _ = self.db.init_batch().and_then(|_| self.db.finish_batch());
+            return Err(err);
+        }
+        self.db.finish_batch()?;
+        if let Some(metrics) = &self.metrics {
+            metrics.batch_committed(&CommitReport {
+                nodes_read: self.report.nodes_read - counted.nodes_read,
+                nodes_written: self.report.nodes_written - counted.nodes_written,
+                bytes_written: self.report.bytes_written - counted.bytes_written,
+                hashes: self.report.hashes - counted.hashes,
+                duration: start.elapsed(),
+            });
+        }
+        self.notify_root(before, after);
+        Ok(())
+    }
+
+    /// Run the commit policy, if set, over a batch about to be committed.
+    ///
+    /// `counted` is the `report` when the batch started, so that only its own costs are summed.
+    fn check_commit(
+        &mut self,
+        before: Option<&Hash<N>>,
+        after: Option<&Hash<N>>,
+        keys: usize,
+        counted: &CommitReport,
+    ) -> Result<()> {
+        let policy = match &mut self.policy {
+            Some(policy) => policy,
+            None => return Ok(()),
+        };
+        let summary = BatchSummary {
+            keys,
+            nodes_written: self.report.nodes_written - counted.nodes_written,
+            bytes_written: self.report.bytes_written - counted.bytes_written,
+            before: before.cloned(),
+            after: after.cloned(),
+        };
+        match policy.check(&summary) {
+            Verdict::Accept => Ok(()),
+            Verdict::Flag(reason) => {
+                self.flags.push((summary, reason));
+                Ok(())
+            }
+            Verdict::Veto(reason) => Err(Errors::Vetoed(reason)),
+        }
+    }
+
+    /// Set a cancellation token checked by batch operations between entries.
+    ///
+    /// Once the token is set to `true`, a running batch operation stops,
+    /// commits the nodes staged so far and returns an error.
+    /// Since nodes are only ever added, the root given to the operation stays valid.
+    pub fn set_cancel_token(&mut self, token: Arc<AtomicBool>) {
+        self.cancel = Some(token);
+    }
+
+    /// Read the serialized node of a hash, which has to be in database.
+    fn read_node(&mut self, hash: &[u8]) -> Result<Vec<u8>> {
+        if let Some(bytes) = self.prefetched.get(hash) {
+            return Ok(bytes.clone());
+        }
+        self.count_read(1);
+        match self.db.get(hash)? {
+            Some(bytes) => Ok(bytes),
+            None => Err(Errors::NotFound(hash.to_vec())),
+        }
+    }
+
+    fn cells_from_bytes(bytes: &[u8], right: bool) -> Result<(Cell<'_>, Cell<'_>)> {
+        Ok(C::decode::<N>(bytes)?.into_cells(right))
+    }
+
+    fn check_cancelled(&mut self) -> Result<()> {
+        match &self.cancel {
+            Some(token) if token.load(Ordering::SeqCst) => {
+                self.save_filter()?;
+                self.db.finish_batch()?;
+                Err(Errors::Cancelled)
+            }
+            _ => Ok(()),
+        }
     }
 
     /// Insert key-leaf entry into the `monotree`. Returns a new root hash.
-    pub fn insert(&mut self, root: Option<&Hash>, key: &Hash, leaf: &Hash) -> Result<Option<Hash>> {
+    ///
+    /// With a filter enabled, this stores the filter as well. Use `inserts()` for many keys,
+    /// which stores it once.
+    pub fn insert(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        leaf: &Hash<N>,
+    ) -> Result<Option<Hash<N>>> {
+        let root = self.insert_key(root, key, leaf, &mut Swap::default())?;
+        self.save_filter()?;
+        Ok(root)
+    }
+
+    /// The same as `insert()`, but also returns the leaf the key had before, if any,
+    /// found along the same walk down the tree rather than by another `get()`.
+    /// Returns the new root hash and the previous leaf.
+    pub fn insert_replace(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        leaf: &Hash<N>,
+    ) -> Result<(Option<Hash<N>>, Option<Hash<N>>)> {
+        let mut swap = Swap::default();
+        let root = self.insert_key(root, key, leaf, &mut swap)?;
+        self.save_filter()?;
+        Ok((root, self.found(swap.replaced)?))
+    }
+
+    /// Insert the entry only if the key is not in the tree, such as to record a nullifier
+    /// spent or a claim made at most once. Returns the new root hash, or the given one
+    /// if the key is already in it, and whether the entry was inserted.
+    ///
+    /// The key is looked up along the same walk down the tree that inserts it.
+    /// Keys removed with soft-delete set are not in the tree.
+    pub fn insert_if_absent(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        leaf: &Hash<N>,
+    ) -> Result<(Option<Hash<N>>, bool)> {
+        let mut swap = Swap {
+            expected: Some(None),
+            ..Default::default()
+        };
+        let root = self.insert_key(root, key, leaf, &mut swap)?;
+        self.save_filter()?;
+        Ok((root, self.found(swap.replaced)?.is_none()))
+    }
+
+    /// Replace the leaf of a key with `new` only if it is `expected`. Returns the new root hash,
+    /// or the given one if the key has another leaf or none, and whether the leaf was replaced.
+    ///
+    /// The leaf is compared along the same walk down the tree that replaces it.
+    pub fn compare_and_swap(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        expected: &Hash<N>,
+        new: &Hash<N>,
+    ) -> Result<(Option<Hash<N>>, bool)> {
+        let mut swap = Swap {
+            expected: Some(Some(*expected)),
+            ..Default::default()
+        };
+        let root = self.insert_key(root, key, new, &mut swap)?;
+        self.save_filter()?;
+        Ok((root, self.found(swap.replaced)?.as_ref() == Some(expected)))
+    }
+
+    /// Get the leaf found as it is to applications, with tombstones taken as no leaf.
+    fn found(&mut self, leaf: Option<Hash<N>>) -> Result<Option<Hash<N>>> {
+        match leaf {
+            Some(leaf) if self.soft_delete && self.tombstoned(&leaf)?.is_some() => Ok(None),
+            leaf => Ok(leaf),
+        }
+    }
+
+    /// Insert an entry if the key has the leaf `swap` expects, if any,
+    /// setting the leaf it had before to `swap`.
+    fn insert_key(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        leaf: &Hash<N>,
+        swap: &mut Swap<N>,
+    ) -> Result<Option<Hash<N>>> {
+        if self.default_leaf.as_ref() == Some(leaf) {
+            // a key of the default leaf is a key absent, so it is removed instead
+            swap.replaced = self.get_present(root, key)?;
+            if !self.expects(swap, swap.replaced)? {
+                return Ok(root.cloned());
+            }
+            return self.remove(root, key);
+        }
+        let filter_key = self.filter_key(key);
+        if let Some(filter) = &mut self.filter {
+            filter.insert(&filter_key);
+        }
+        let key = self.path(key);
         match root {
+            None if !self.expects(swap, None)? => Ok(None),
             None => {
-                let (hash, bits) = (leaf, Bits::new(key));
+                let (hash, bits) = (leaf, Bits::new(&key));
                 self.put_node(Node::new(Some(Unit { hash, bits }), None))
             }
-            Some(root) => self.put(root, Bits::new(key), leaf),
+            Some(root) => self.put(root, Bits::new(&key), leaf, swap),
+        }
+    }
+
+    /// Whether the leaf found for a key is the one `swap` expects, if any.
+    fn expects(&mut self, swap: &Swap<N>, found: Option<Hash<N>>) -> Result<bool> {
+        match swap.expected {
+            None => Ok(true),
+            Some(expected) => Ok(self.found(found)? == expected),
         }
     }
 
-    fn put_node(&mut self, node: Node) -> Result<Option<Hash>> {
-        let bytes = node.to_bytes()?;
-        let hash = self.hasher.digest(&bytes);
+    fn put_node(&mut self, node: Node) -> Result<Option<Hash<N>>> {
+        let bytes = C::encode(&node)?;
+        let hash = self.hasher.digest_node(&bytes);
+        self.count_written(bytes.len());
         self.db.put(&hash, bytes)?;
         Ok(Some(hash))
     }
@@ -69,18 +1089,41 @@ where
     /// * split-node (2)
     ///     immediately split node into two with the longest common prefix,
     ///     then wind the recursive stack from there returning resulting hashes.
-    fn put(&mut self, root: &[u8], bits: Bits, leaf: &[u8]) -> Result<Option<Hash>> {
-        let bytes = self.db.get(root)?.expect("bytes");
-        let (lc, rc) = Node::cells_from_bytes(&bytes, bits.first())?;
+    ///
+    /// The leaf replaced, if any, is set to `swap`. If the key does not have the leaf
+    /// `swap` expects, nothing is put and the root is returned as it is,
+    /// as is a node of which the child is returned as it was.
+    fn put(
+        &mut self,
+        root: &[u8],
+        bits: Bits,
+        leaf: &[u8],
+        swap: &mut Swap<N>,
+    ) -> Result<Option<Hash<N>>> {
+        let bytes = self.read_node(root)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, bits.first())?;
         let unit = lc.as_ref().expect("put(): left-unit");
         let n = Bits::len_common_bits(&unit.bits, &bits);
+        let absent = n != bits.len() && n != unit.bits.len();
+        if absent && !self.expects(swap, None)? {
+            return Ok(Some(slice_to_array(root)));
+        }
         match n {
             n if n == 0 => self.put_node(Node::new(lc, Some(Unit { hash: leaf, bits }))),
-            n if n == bits.len() => self.put_node(Node::new(Some(Unit { hash: leaf, bits }), rc)),
+            n if n == bits.len() => {
+                swap.replaced = Some(slice_to_array(unit.hash));
+                if !self.expects(swap, swap.replaced)? {
+                    return Ok(Some(slice_to_array(root)));
+                }
+                self.put_node(Node::new(Some(Unit { hash: leaf, bits }), rc))
+            }
             n if n == unit.bits.len() => {
                 let hash = &self
-                    .put(unit.hash, bits.shift(n, false), leaf)?
+                    .put(unit.hash, bits.shift(n, false), leaf, swap)?
                     .expect("put(): hash");
+                if hash[..] == unit.hash[..] {
+                    return Ok(Some(slice_to_array(root)));
+                }
                 let unit = unit.to_owned();
                 self.put_node(Node::new(Some(Unit { hash, ..unit }), rc))
             }
@@ -102,36 +1145,115 @@ where
     }
 
     /// Get a leaf hash for the given root and key.
-    pub fn get(&mut self, root: Option<&Hash>, key: &Hash) -> Result<Option<Hash>> {
+    ///
+    /// Keys absent have the leaf set by `set_default_leaf()`, if any.
+    pub fn get(&mut self, root: Option<&Hash<N>>, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        Ok(self.get_present(root, key)?.or(self.default_leaf))
+    }
+
+    /// Get the leaf of a key in the tree, or `None` if it is absent, whatever the default leaf.
+    fn get_present(&mut self, root: Option<&Hash<N>>, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        if self.filtered_out(key) {
+            return Ok(None);
+        }
+        let key = self.path(key);
         match root {
             None => Ok(None),
-            Some(root) => self.find_key(root, Bits::new(key)),
+            Some(root) => match self.find_key(root, Bits::new(&key))? {
+                Some(leaf) if self.soft_delete && self.tombstoned(&leaf)?.is_some() => Ok(None),
+                leaf => Ok(leaf),
+            },
         }
     }
 
-    fn find_key(&mut self, root: &[u8], bits: Bits) -> Result<Option<Hash>> {
-        let bytes = self.db.get(root)?.expect("bytes");
-        let (cell, _) = Node::cells_from_bytes(&bytes, bits.first())?;
+    fn find_key(&mut self, root: &[u8], bits: Bits) -> Result<Option<Hash<N>>> {
+        let bytes = self.read_node(root)?;
+        let (cell, _) = Self::cells_from_bytes(&bytes, bits.first())?;
         let unit = cell.as_ref().expect("find_key(): left-unit");
         let n = Bits::len_common_bits(&unit.bits, &bits);
         match n {
-            n if n == bits.len() => Ok(Some(slice_to_hash(unit.hash))),
+            n if n == bits.len() => Ok(Some(slice_to_array(unit.hash))),
             n if n == unit.bits.len() => self.find_key(&unit.hash, bits.shift(n, false)),
             _ => Ok(None),
         }
     }
 
     /// Remove the given key and its corresponding leaf from the tree. Returns a new root hash.
-    pub fn remove(&mut self, root: Option<&Hash>, key: &[u8]) -> Result<Option<Hash>> {
+    ///
+    /// The root stays unchanged if the key is not in the tree.
+    /// With soft-delete set by `set_soft_delete()`, the leaf is replaced with a tombstone instead,
+    /// and the root stays unchanged if the key is tombstoned already.
+    pub fn remove(&mut self, root: Option<&Hash<N>>, key: &[u8]) -> Result<Option<Hash<N>>> {
+        if self.filtered_out(key) {
+            return Ok(root.cloned());
+        }
+        let path = self.path(key);
         match root {
             None => Ok(None),
-            Some(root) => self.delete_key(root, Bits::new(key)),
+            Some(root) => match self.find_key(root, Bits::new(&path))? {
+                None => Ok(Some(*root)),
+                Some(leaf) if self.soft_delete => match self.tombstoned(&leaf)? {
+                    Some(_) => Ok(Some(*root)),
+                    None => {
+                        let tombstone = self.put_tombstone(key, &leaf)?;
+                        self.put(root, Bits::new(&path), &tombstone, &mut Swap::default())
+                    }
+                },
+                Some(_) => self.delete_key(root, Bits::new(&path)),
+            },
+        }
+    }
+
+    /// Bring back the leaf a key had before `remove()` replaced it with a tombstone.
+    /// Returns a new root hash.
+    ///
+    /// The root stays unchanged if the key is not tombstoned. Only the latest removal is undone,
+    /// as a key inserted and removed again is tombstoned over its latest leaf.
+    pub fn restore(&mut self, root: Option<&Hash<N>>, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        let root = match root {
+            Some(root) => root,
+            None => return Ok(None),
+        };
+        let path = self.path(key);
+        let leaf = match self.find_key(root, Bits::new(&path))? {
+            Some(leaf) => leaf,
+            None => return Ok(Some(*root)),
+        };
+        match self.tombstoned(&leaf)? {
+            Some(previous) => self.put(root, Bits::new(&path), &previous, &mut Swap::default()),
+            None => Ok(Some(*root)),
+        }
+    }
+
+    /// Get the leaf a tombstone replaced, or `None` if the given leaf is not a tombstone.
+    pub fn tombstoned(&mut self, leaf: &Hash<N>) -> Result<Option<Hash<N>>> {
+        let bytes = match self.db.get(leaf)? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let tag = TOMBSTONE_TAG.len();
+        if bytes.len() != tag + N * 2
+            || !bytes.starts_with(TOMBSTONE_TAG)
+            || self.hasher.digest(&bytes) != *leaf
+        {
+            return Ok(None);
         }
+        Ok(Some(slice_to_array(&bytes[tag + N..])))
+    }
+
+    /// Store the preimage of a tombstone replacing the leaf of a key, under the tombstone.
+    fn put_tombstone(&mut self, key: &[u8], leaf: &Hash<N>) -> Result<Hash<N>> {
+        let bytes = [TOMBSTONE_TAG, &self.filter_key(key), &leaf[..]].concat();
+        let tombstone = self.hasher.digest(&bytes);
+        self.report.hashes += 1;
+        self.report.bytes_written += (N + bytes.len()) as u64;
+        self.db.put(&tombstone, bytes)?;
+        Ok(tombstone)
     }
 
-    fn delete_key(&mut self, root: &[u8], bits: Bits) -> Result<Option<Hash>> {
-        let bytes = self.db.get(root)?.expect("bytes");
-        let (lc, rc) = Node::cells_from_bytes(&bytes, bits.first())?;
+    fn delete_key(&mut self, root: &[u8], bits: Bits) -> Result<Option<Hash<N>>> {
+        let bytes = self.read_node(root)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, bits.first())?;
         let unit = lc.as_ref().expect("delete_key(): left-unit");
         let n = Bits::len_common_bits(&unit.bits, &bits);
         match n {
@@ -145,9 +1267,27 @@ where
                     (None, None) => Ok(None),
                     (None, Some(_)) => self.put_node(Node::new(None, rc)),
                     (Some(ref hash), _) => {
-                        let unit = unit.to_owned();
-                        let lc = Some(Unit { hash, ..unit });
-                        self.put_node(Node::new(lc, rc))
+                        // a child left with a single cell is merged into this cell, if opted in
+                        let child = match C::MERGE_ON_REMOVE {
+                            true => Some(self.read_node(hash)?),
+                            false => None,
+                        };
+                        match child.as_deref().map(C::decode::<N>).transpose()? {
+                            Some(Node::Soft(Some(cu))) => {
+                                let (path, range) = Bits::concat(&unit.bits, &cu.bits);
+                                let bits = Bits { path: &path, range };
+                                let lc = Some(Unit {
+                                    hash: cu.hash,
+                                    bits,
+                                });
+                                self.put_node(Node::new(lc, rc))
+                            }
+                            _ => {
+                                let unit = unit.to_owned();
+                                let lc = Some(Unit { hash, ..unit });
+                                self.put_node(Node::new(lc, rc))
+                            }
+                        }
                     }
                 }
             }
@@ -155,113 +1295,2685 @@ where
         }
     }
 
+    /// Remove the given key, the same as `remove()`, along with a `RemovalProof` that the key
+    /// was in the tree of `root` and that the new root was derived by removing it alone,
+    /// such as to prove a slot retired. Returns the new root hash and the proof,
+    /// or `None` for a key not in the tree, with the root unchanged.
+    ///
+    /// Fails with soft-delete set, of which removals keep keys under tombstones.
+    pub fn remove_with_proof(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+    ) -> Result<(Option<Hash<N>>, Option<RemovalProof<N>>)> {
+        if self.soft_delete {
+            return Err(Errors::new("remove_with_proof(): soft-delete set"));
+        }
+        let (old_root, leaf) = match (root, self.get(root, key)?) {
+            (Some(old_root), Some(leaf)) => (old_root, leaf),
+            _ => return Ok((root.cloned(), None)),
+        };
+        let proof = self
+            .get_merkle_proof(root, key)?
+            .ok_or_else(|| Errors::new("remove_with_proof(): missing proof"))?;
+        let mut nodes = HashMap::new();
+        let path = self.path(key).to_vec();
+        self.path_nodes(old_root, Bits::new(&path), &mut nodes)?;
+        let consistency = ConsistencyProof {
+            updates: vec![(*key, None)],
+            nodes: nodes.into_iter().collect(),
+            traversal: self.traversal,
+            key_len: self.key_len,
+            codec: C::ID,
+        };
+        let root = self.remove(root, key)?;
+        Ok((
+            root,
+            Some(RemovalProof {
+                leaf,
+                proof,
+                consistency,
+            }),
+        ))
+    }
+
+    /// Replace the leaf of a key with `new_leaf` only if it is `old_leaf`, the same as
+    /// `compare_and_swap()`, along with an `UpdateProof` of the leaves before and after.
+    /// Returns the new root hash and the proof, or `None` for a key of another leaf or none,
+    /// with the root unchanged.
+    pub fn prove_update(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        old_leaf: &Hash<N>,
+        new_leaf: &Hash<N>,
+    ) -> Result<(Option<Hash<N>>, Option<UpdateProof<N>>)> {
+        let old_root = match (root, self.get(root, key)?) {
+            (Some(old_root), Some(leaf)) if leaf == *old_leaf => *old_root,
+            _ => return Ok((root.cloned(), None)),
+        };
+        let old_proof = self
+            .get_merkle_proof(root, key)?
+            .ok_or_else(|| Errors::new("prove_update(): missing proof"))?;
+        let root = self.insert(root, key, new_leaf)?;
+        let new_proof = self
+            .get_merkle_proof(root.as_ref(), key)?
+            .ok_or_else(|| Errors::new("prove_update(): missing proof"))?;
+        let new_root = root.ok_or_else(|| Errors::new("prove_update(): missing root"))?;
+        Ok((
+            root,
+            Some(UpdateProof {
+                old_root,
+                new_root,
+                old_leaf: *old_leaf,
+                new_leaf: *new_leaf,
+                old_proof,
+                new_proof,
+            }),
+        ))
+    }
+
+    /// Get the key of the tree an application key of any length is hashed to.
+    ///
+    /// This is the key `insert_raw_key()` and the like use, and the one to prove entries by.
+    pub fn raw_key(&self, key: &[u8]) -> Hash<N> {
+        self.hasher.digest(key)
+    }
+
+    fn raw_key_key(&self, key: &Hash<N>) -> Hash<N> {
+        self.hasher.digest(&[&[RAW_KEY_TAG], &key[..]].concat())
+    }
+
+    /// Insert an entry of an application key of any length, hashed into the key of the tree
+    /// by `raw_key()`. Returns a new root hash.
+    ///
+    /// The application key is stored as well if set by `set_raw_keys()`.
+    pub fn insert_raw_key(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &[u8],
+        leaf: &Hash<N>,
+    ) -> Result<Option<Hash<N>>> {
+        let hashed = self.raw_key(key);
+        if self.raw_keys {
+            let raw_key_key = self.raw_key_key(&hashed);
+            self.db.put(&raw_key_key, key.to_vec())?;
+        }
+        self.insert(root, &hashed, leaf)
+    }
+
+    /// Get the leaf of an application key inserted by `insert_raw_key()`.
+    pub fn get_raw_key(&mut self, root: Option<&Hash<N>>, key: &[u8]) -> Result<Option<Hash<N>>> {
+        let hashed = self.raw_key(key);
+        self.get(root, &hashed)
+    }
+
+    /// Remove the entry of an application key inserted by `insert_raw_key()`.
+    /// Returns a new root hash.
+    pub fn remove_raw_key(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &[u8],
+    ) -> Result<Option<Hash<N>>> {
+        let hashed = self.raw_key(key);
+        self.remove(root, &hashed)
+    }
+
+    /// Get the application key a key of the tree was hashed from, such as one given by `iter()`,
+    /// or `None` if it was not stored by `insert_raw_key()`.
+    pub fn original_key(&mut self, key: &Hash<N>) -> Result<Option<Vec<u8>>> {
+        let raw_key_key = self.raw_key_key(key);
+        match self.db.get(&raw_key_key)? {
+            Some(raw) if self.raw_key(&raw) == *key => Ok(Some(raw)),
+            Some(_) => Err(Errors::Decode("original_key(): key mismatch".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Insert a key-value entry, folding the value into a leaf by the policy of the tree.
+    /// Returns a new root hash.
+    ///
+    /// Unless the policy is `LeafPolicy::Raw`, where the value is the leaf itself, the value is
+    /// stored in database under its leaf as well, so that `get_value()` gives it back.
+    /// Set `LeafPolicy::Hash` to store values of any length.
+    pub fn insert_value(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &Hash<N>,
+        value: &[u8],
+    ) -> Result<Option<Hash<N>>> {
+        let leaf = self.put_value(key, value)?;
+        self.insert(root, key, &leaf)
+    }
+
+    /// This method is intended to use the `insert_value()` method in batch mode.
+    pub fn inserts_values(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+        values: &[Vec<u8>],
+    ) -> Result<Option<Hash<N>>> {
+        let leaves = keys
+            .iter()
+            .zip(values)
+            .map(|(key, value)| self.put_value(key, value))
+            .collect::<Result<Vec<_>>>()?;
+        self.inserts(root, keys, &leaves)
+    }
+
+    /// Fold a value into its leaf, storing the value under it unless it is the leaf itself.
+    fn put_value(&mut self, key: &Hash<N>, value: &[u8]) -> Result<Hash<N>> {
+        let leaf = self.leaf(key, value)?;
+        if self.leaf != LeafPolicy::Raw {
+            self.db.put(&leaf, value.to_vec())?;
+        }
+        Ok(leaf)
+    }
+
+    /// Get the value of a key inserted by `insert_value()`, or `None` if the key is absent.
+    ///
+    /// The value is checked against the leaf it is stored under, by the policy of the tree.
+    pub fn get_value(&mut self, root: Option<&Hash<N>>, key: &Hash<N>) -> Result<Option<Vec<u8>>> {
+        let leaf = match self.get(root, key)? {
+            Some(leaf) => leaf,
+            None => return Ok(None),
+        };
+        if self.leaf == LeafPolicy::Raw {
+            return Ok(Some(leaf.to_vec()));
+        }
+        match self.db.get(&leaf)? {
+            Some(value) if self.leaf(key, &value)? == leaf => Ok(Some(value)),
+            Some(_) => Err(Errors::Decode("get_value(): value mismatch".to_string())),
+            None => Err(Errors::NotFound(leaf.to_vec())),
+        }
+    }
+
     /// This method is intended to use the `insert()` method in batch mode.
     pub fn inserts(
         &mut self,
-        root: Option<&Hash>,
-        keys: &[Hash],
-        leaves: &[Hash],
-    ) -> Result<Option<Hash>> {
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+        leaves: &[Hash<N>],
+    ) -> Result<Option<Hash<N>>> {
+        self.inserts_with_progress(root, keys, leaves, &mut |_, _| {})
+    }
+
+    /// The same as `inserts()`, but reports `(processed, total)` entries to `progress`
+    /// each time an entry is inserted.
+    pub fn inserts_with_progress(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+        leaves: &[Hash<N>],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Option<Hash<N>>> {
         let indices = get_sorted_indices(keys, false);
+        let (counted, start) = (self.report.clone(), Instant::now());
         self.db.init_batch()?;
-        let mut root = root.cloned();
-        for i in indices.iter() {
-            root = self.insert(root.as_ref(), &keys[*i], &leaves[*i])?;
+        self.prefetch(root, keys)?;
+        let (before, mut root) = (root, root.cloned());
+        for (n, i) in indices.iter().enumerate() {
+            self.check_cancelled()?;
+            root = self.insert_key(root.as_ref(), &keys[*i], &leaves[*i], &mut Swap::default())?;
+            progress(n + 1, indices.len());
         }
-        self.db.finish_batch()?;
+        self.prefetched.clear();
+        self.save_filter()?;
+        self.commit_batch(before, root.as_ref(), indices.len(), &counted, start)?;
+        Ok(root)
+    }
+
+    /// Build a tree of entries of keys and leaves, sorted by path with no two of the same key.
+    /// Returns the root hash, the very one `inserts()` gives for the same entries.
+    ///
+    /// The tree is built bottom-up in a single pass, writing every node exactly once,
+    /// rather than rewriting the path of every key inserted. Paths are keys themselves
+    /// unless set otherwise by `set_traversal()` or `set_key_len()`.
+    /// Returns an error on the first entry out of order, with the nodes so far committed.
+    pub fn build_from_sorted(&mut self, leaves: &[(Hash<N>, Hash<N>)]) -> Result<Option<Hash<N>>> {
+        let depth = self.leaf_depth();
+        let (counted, start) = (self.report.clone(), Instant::now());
+        self.db.init_batch()?;
+        // subtrees left of the last entry, each with the bit it branches off the one on its right
+        let mut stack: Vec<(BitsLen, Built<N>)> = Vec::new();
+        let mut last: Option<Built<N>> = None;
+        for (key, leaf) in leaves {
+            self.check_cancelled()?;
+            let filter_key = self.filter_key(key);
+            if let Some(filter) = &mut self.filter {
+                filter.insert(&filter_key);
+            }
+            let built = (depth, *leaf, self.path(key));
+            let mut right = match last.replace(built) {
+                Some(right) => right,
+                None => continue,
+            };
+            let next = &last.as_ref().expect("build_from_sorted(): last").2;
+            let at = Bits::len_common_bits(&Bits::new(&right.2), &Bits::new(next));
+            if at == depth || right.2.as_ref() > next.as_ref() {
+                self.db.finish_batch()?;
+                return Err(Errors::new("build_from_sorted(): leaves not sorted"));
+            }
+            // in a binary tree, no two subtrees beside each other branch off at the same bit
+            while let Some((n, left)) = stack.pop() {
+                if n < at {
+                    stack.push((n, left));
+                    break;
+                }
+                right = self.join_built(left, right, n)?;
+            }
+            stack.push((at, right));
+        }
+        let root = match last {
+            Some(mut right) => {
+                while let Some((n, left)) = stack.pop() {
+                    right = self.join_built(left, right, n)?;
+                }
+                match right.0 {
+                    0 => Some(right.1),
+                    n => {
+                        let bits = Bits::new(&right.2).shift(n, true);
+                        let unit = Unit {
+                            hash: &right.1,
+                            bits,
+                        };
+                        self.put_node(Node::new(Some(unit), None))?
+                    }
+                }
+            }
+            None => None,
+        };
+        self.save_filter()?;
+        self.commit_batch(None, root.as_ref(), leaves.len(), &counted, start)?;
         Ok(root)
     }
 
+    /// Join two subtrees built by `build_from_sorted()` by a hard node branching at `at`.
+    fn join_built<'a>(
+        &mut self,
+        left: Built<'a, N>,
+        right: Built<'a, N>,
+        at: BitsLen,
+    ) -> Result<Built<'a, N>> {
+        let lu = Unit {
+            hash: &left.1,
+            bits: Bits::new(&left.2).shift(at, false).shift(left.0 - at, true),
+        };
+        let ru = Unit {
+            hash: &right.1,
+            bits: Bits::new(&right.2)
+                .shift(at, false)
+                .shift(right.0 - at, true),
+        };
+        let hash = self.put_node(Node::new(Some(lu), Some(ru)))?;
+        Ok((at, hash.expect("join_built(): hash"), left.2))
+    }
+
+    /// The same as `inserts()`, but hashes the nodes on threads of `rayon`.
+    /// Returns the very root `inserts()` does.
+    ///
+    /// Entries are split by the leading bits of their paths into subtrees hashed in parallel,
+    /// then joined at the top, after the nodes of the tree given on the way are read.
+    /// The nodes are written in a single batch at the end, so the batch is best kept to
+    /// what fits in memory. Of entries of the same key, the last one given is inserted.
+    #[cfg(feature = "parallel")]
+    pub fn par_inserts(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+        leaves: &[Hash<N>],
+    ) -> Result<Option<Hash<N>>>
+    where
+        H: Sync,
+    {
+        self.check_cancelled()?;
+        let mut entries: Vec<Entry<N>> = keys
+            .iter()
+            .zip(leaves)
+            .map(|(key, leaf)| (self.path(key), *leaf))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut unique: Vec<Entry<N>> = Vec::with_capacity(entries.len());
+        for (path, leaf) in entries {
+            match unique.last_mut() {
+                Some(last) if last.0 == path => last.1 = leaf,
+                _ => unique.push((path, leaf)),
+            }
+        }
+        if unique.is_empty() {
+            return Ok(root.cloned());
+        }
+        for key in keys {
+            let filter_key = self.filter_key(key);
+            if let Some(filter) = &mut self.filter {
+                filter.insert(&filter_key);
+            }
+        }
+
+        let depth = self.leaf_depth();
+        let mut read = HashMap::new();
+        if let Some(root) = root {
+            Builder::<H, C, N>::read(&mut self.db, root, &unique, 0, depth, &mut read)?;
+            self.count_read(read.len() as u64);
+        }
+        let builder = Builder::<H, C, N>::new(&self.hasher, depth, &read);
+        let mut nodes = Vec::new();
+        let after = builder.put(root.map(|root| &root[..]), &unique, 0, &mut nodes)?;
+
+        let (counted, start) = (self.report.clone(), Instant::now());
+        self.db.init_batch()?;
+        for (hash, bytes) in nodes {
+            self.count_written(bytes.len());
+            self.db.put(&hash, bytes)?;
+        }
+        self.save_filter()?;
+        self.commit_batch(root, Some(&after), unique.len(), &counted, start)?;
+        Ok(Some(after))
+    }
+
+    /// The same as `inserts()`, but also returns a `CommitReport` of the batch.
+    pub fn inserts_with_report(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+        leaves: &[Hash<N>],
+    ) -> Result<(Option<Hash<N>>, CommitReport)> {
+        self.with_report(|tree| tree.inserts(root, keys, leaves))
+    }
+
+    /// The same as `removes()`, but also returns a `CommitReport` of the batch.
+    pub fn removes_with_report(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+    ) -> Result<(Option<Hash<N>>, CommitReport)> {
+        self.with_report(|tree| tree.removes(root, keys))
+    }
+
+    fn with_report<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<R>,
+    ) -> Result<(R, CommitReport)> {
+        self.report = CommitReport::default();
+        let start = Instant::now();
+        let result = f(self)?;
+        let report = CommitReport {
+            duration: start.elapsed(),
+            ..std::mem::take(&mut self.report)
+        };
+        Ok((result, report))
+    }
+
     /// This method is intended to use the `get()` method in batch mode.
-    pub fn gets(&mut self, root: Option<&Hash>, keys: &[Hash]) -> Result<Vec<Option<Hash>>> {
-        let mut leaves: Vec<Option<Hash>> = Vec::new();
-        for key in keys.iter() {
-            leaves.push(self.get(root, key)?);
+    ///
+    /// Keys are walked down together a level at a time, reading the nodes of a level
+    /// by a single `Database::get_many()`, so that backends over a network take a round trip
+    /// per level rather than per node.
+    pub fn gets(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+    ) -> Result<Vec<Option<Hash<N>>>> {
+        let mut leaves: Vec<Option<Hash<N>>> = vec![None; keys.len()];
+        let root = match root {
+            Some(root) => root,
+            None => return Ok(leaves),
+        };
+        let paths: Vec<Cow<[u8]>> = keys.iter().map(|key| self.path(key)).collect();
+        // keys yet to walk, each with its index and the node to read with the bits left
+        let mut walking: Vec<(usize, Hash<N>, Bits)> = paths
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.filtered_out(&keys[*i]))
+            .map(|(i, path)| (i, *root, Bits::new(path)))
+            .collect();
+        while !walking.is_empty() {
+            let mut hashes: Vec<Hash<N>> = walking.iter().map(|(_, hash, _)| *hash).collect();
+            hashes.sort_unstable();
+            hashes.dedup();
+            let refs: Vec<&[u8]> = hashes.iter().map(|hash| &hash[..]).collect();
+            let nodes = self.db.get_many(&refs)?;
+            self.count_read(nodes.len() as u64);
+            let mut next = Vec::new();
+            for (i, hash, bits) in walking.into_iter() {
+                let found = hashes
+                    .binary_search(&hash)
+                    .ok()
+                    .and_then(|j| nodes[j].as_ref());
+                let bytes = found.ok_or_else(|| Errors::NotFound(hash.to_vec()))?;
+                let (cell, _) = Self::cells_from_bytes(bytes, bits.first())?;
+                let unit = cell.as_ref().expect("gets(): left-unit");
+                let n = Bits::len_common_bits(&unit.bits, &bits);
+                if n == bits.len() {
+                    leaves[i] = Some(slice_to_array(unit.hash));
+                } else if n == unit.bits.len() {
+                    next.push((i, slice_to_array(unit.hash), bits.shift(n, false)));
+                }
+            }
+            walking = next;
+        }
+        if self.soft_delete {
+            for leaf in leaves.iter_mut() {
+                if let Some(hash) = leaf {
+                    if self.tombstoned(hash)?.is_some() {
+                        *leaf = None;
+                    }
+                }
+            }
         }
         Ok(leaves)
     }
 
+    /// Get the leaves of the given keys along with their Merkle proofs, as `gets()` does,
+    /// or `None` for keys not in the tree.
+    ///
+    /// Keys are walked down together a level at a time, so that a node shared by adjacent keys
+    /// is read once by a single `Database::get_many()` per level, rather than once per key
+    /// by `get()` and `get_merkle_proof()` each.
+    pub fn gets_with_proofs(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+    ) -> Result<Vec<Option<(Hash<N>, Proof)>>> {
+        let mut found: Vec<Option<(Hash<N>, Proof)>> = vec![None; keys.len()];
+        let root = match root {
+            Some(root) => root,
+            None => return Ok(found),
+        };
+        let paths: Vec<Cow<[u8]>> = keys.iter().map(|key| self.path(key)).collect();
+        // keys yet to walk, each with its index, the node to read, the bits left and its proof
+        let mut walking: Vec<(usize, Hash<N>, Bits, Proof)> = paths
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.filtered_out(&keys[*i]))
+            .map(|(i, path)| (i, *root, Bits::new(path), Vec::new()))
+            .collect();
+        while !walking.is_empty() {
+            let mut hashes: Vec<Hash<N>> = walking.iter().map(|(_, hash, _, _)| *hash).collect();
+            hashes.sort_unstable();
+            hashes.dedup();
+            let refs: Vec<&[u8]> = hashes.iter().map(|hash| &hash[..]).collect();
+            let nodes = self.db.get_many(&refs)?;
+            self.count_read(nodes.len() as u64);
+            let mut next = Vec::new();
+            for (i, hash, bits, mut proof) in walking.into_iter() {
+                let node = hashes
+                    .binary_search(&hash)
+                    .ok()
+                    .and_then(|j| nodes[j].as_ref());
+                let bytes = node.ok_or_else(|| Errors::NotFound(hash.to_vec()))?;
+                let (cell, _) = Self::cells_from_bytes(bytes, bits.first())?;
+                let unit = cell.as_ref().expect("gets_with_proofs(): left-unit");
+                let n = Bits::len_common_bits(&unit.bits, &bits);
+                if n == bits.len() {
+                    proof.push(self.encode_proof(bytes, bits.first())?);
+                    found[i] = Some((slice_to_array(unit.hash), proof));
+                } else if n == unit.bits.len() {
+                    proof.push(self.encode_proof(bytes, bits.first())?);
+                    next.push((i, slice_to_array(unit.hash), bits.shift(n, false), proof));
+                }
+            }
+            walking = next;
+        }
+        for entry in found.iter_mut() {
+            match entry {
+                Some((leaf, _)) if self.soft_delete && self.tombstoned(leaf)?.is_some() => {
+                    *entry = None
+                }
+                Some((_, proof)) => {
+                    if let Some(metrics) = &self.metrics {
+                        metrics.proof_generated(proof.to_bytes().len() as u64);
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(found)
+    }
+
+    /// Read the nodes the given keys walk down to from the root, as many levels as set by
+    /// `set_prefetch()`, a level at a time as `gets()` does, and keep them for `read_node()`
+    /// until the batch is done. Nodes are immutable under their hashes, so they never go stale.
+    fn prefetch(&mut self, root: Option<&Hash<N>>, keys: &[Hash<N>]) -> Result<()> {
+        self.prefetched.clear();
+        let root = match root {
+            Some(root) if self.prefetch > 0 => *root,
+            _ => return Ok(()),
+        };
+        let paths: Vec<Cow<[u8]>> = keys.iter().map(|key| self.path(key)).collect();
+        let mut walking: Vec<(Hash<N>, Bits)> =
+            paths.iter().map(|path| (root, Bits::new(path))).collect();
+        for _ in 0..self.prefetch {
+            let mut hashes: Vec<Hash<N>> = walking.iter().map(|(hash, _)| *hash).collect();
+            hashes.sort_unstable();
+            hashes.dedup();
+            if hashes.is_empty() {
+                break;
+            }
+            let refs: Vec<&[u8]> = hashes.iter().map(|hash| &hash[..]).collect();
+            let nodes = self.db.get_many(&refs)?;
+            self.count_read(nodes.len() as u64);
+            for (hash, node) in hashes.into_iter().zip(nodes) {
+                if let Some(bytes) = node {
+                    self.prefetched.insert(hash, bytes);
+                }
+            }
+            // nodes missing are left to `read_node()` to fail on
+            let mut next = Vec::new();
+            for (hash, bits) in walking.into_iter() {
+                let bytes = match self.prefetched.get(&hash) {
+                    Some(bytes) => bytes,
+                    None => continue,
+                };
+                let (cell, _) = Self::cells_from_bytes(bytes, bits.first())?;
+                let unit = cell.as_ref().expect("prefetch(): left-unit");
+                let n = Bits::len_common_bits(&unit.bits, &bits);
+                if n == unit.bits.len() && n < bits.len() {
+                    next.push((slice_to_array(unit.hash), bits.shift(n, false)));
+                }
+            }
+            walking = next;
+        }
+        Ok(())
+    }
+
     /// This method is intended to use the `remove()` method in batch mode.
-    pub fn removes(&mut self, root: Option<&Hash>, keys: &[Hash]) -> Result<Option<Hash>> {
+    pub fn removes(&mut self, root: Option<&Hash<N>>, keys: &[Hash<N>]) -> Result<Option<Hash<N>>> {
+        self.removes_with_progress(root, keys, &mut |_, _| {})
+    }
+
+    /// The same as `removes()`, but reports `(processed, total)` keys to `progress`
+    /// each time a key is removed.
+    pub fn removes_with_progress(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Option<Hash<N>>> {
         let indices = get_sorted_indices(keys, false);
-        let mut root = root.cloned();
+        let (counted, start) = (self.report.clone(), Instant::now());
+        let (before, mut root) = (root, root.cloned());
         self.db.init_batch()?;
-        for i in indices.iter() {
+        self.prefetch(root.as_ref(), keys)?;
+        for (n, i) in indices.iter().enumerate() {
+            self.check_cancelled()?;
             root = self.remove(root.as_ref(), &keys[*i])?;
+            progress(n + 1, indices.len());
         }
-        self.db.finish_batch()?;
+        self.prefetched.clear();
+        self.commit_batch(before, root.as_ref(), indices.len(), &counted, start)?;
         Ok(root)
     }
 
-    /// Generate a Merkle proof for the given root and key.
-    pub fn get_merkle_proof(&mut self, root: Option<&Hash>, key: &[u8]) -> Result<Option<Proof>> {
-        let mut proof: Proof = Vec::new();
+    /// Get the numbers of nodes and leaves, the bytes stored and the depths of leaves
+    /// in the tree of the given root, walking the whole tree.
+    pub fn stats(&mut self, root: Option<&Hash<N>>) -> Result<TreeStats> {
         match root {
-            None => Ok(None),
-            Some(root) => self.gen_proof(root, Bits::new(key), &mut proof),
+            None => Ok(TreeStats::default()),
+            Some(root) => self.stats_under(root, 0),
         }
     }
 
-    fn gen_proof(&mut self, root: &[u8], bits: Bits, proof: &mut Proof) -> Result<Option<Proof>> {
-        let bytes = self.db.get(root)?.expect("bytes");
-        let (cell, _) = Node::cells_from_bytes(&bytes, bits.first())?;
-        let unit = cell.as_ref().expect("gen_proof(): left-unit");
-        let n = Bits::len_common_bits(&unit.bits, &bits);
-        match n {
-            n if n == bits.len() => {
-                proof.push(self.encode_proof(&bytes, bits.first())?);
-                Ok(Some(proof.to_owned()))
+    /// Get the statistics of the subtree of the node at the given depth in bits.
+    pub(crate) fn stats_under(&mut self, root: &Hash<N>, depth: BitsLen) -> Result<TreeStats> {
+        let mut stats = TreeStats::default();
+        let mut stack = vec![(*root, depth, 1)];
+        while let Some((hash, depth, level)) = stack.pop() {
+            let bytes = self.read_node(&hash)?;
+            stats.nodes += 1;
+            stats.bytes += (N + bytes.len()) as u64;
+            let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+            for unit in [lc, rc].iter().flatten() {
+                let depth = depth + unit.bits.len();
+                if depth < self.leaf_depth() {
+                    stack.push((slice_to_array(unit.hash), depth, level + 1));
+                    continue;
+                }
+                if stats.depths.len() <= level {
+                    stats.depths.resize(level + 1, 0);
+                }
+                stats.depths[level] += 1;
+                stats.leaves += 1;
             }
-            n if n == unit.bits.len() => {
-                proof.push(self.encode_proof(&bytes, bits.first())?);
-                self.gen_proof(unit.hash, bits.shift(n, false), proof)
+        }
+        Ok(stats)
+    }
+
+    /// Walk the whole tree of the given root, re-hashing every node, to find those missing
+    /// or corrupted in database, such as by silent corruption of the disk.
+    /// Returns the nodes found damaged, each with the path to it, or none if the tree is intact.
+    ///
+    /// Nodes under a damaged one are still walked as long as it decodes.
+    pub fn verify_integrity(&mut self, root: Option<&Hash<N>>) -> Result<Vec<DamagedNode<N>>> {
+        let (mut damaged, leaf_depth) = (Vec::new(), self.leaf_depth() as usize);
+        let mut stack: Vec<PathUnit<N>> =
+            root.map(|root| (Vec::new(), *root)).into_iter().collect();
+        while let Some((path, hash)) = stack.pop() {
+            let mut damage = |damage| {
+                damaged.push(DamagedNode {
+                    path: path.to_owned(),
+                    hash,
+                    damage,
+                })
+            };
+            let bytes = match self.db.get(&hash)? {
+                Some(bytes) => bytes,
+                None => {
+                    damage(Damage::Missing);
+                    continue;
+                }
+            };
+            let (lc, rc) = match Self::cells_from_bytes(&bytes, false) {
+                Ok(cells) => cells,
+                Err(_) => {
+                    damage(Damage::Malformed);
+                    continue;
+                }
+            };
+            let units: Vec<_> = [rc, lc]
+                .iter()
+                .flatten()
+                .map(|unit| {
+                    let mut path = path.to_owned();
+                    path.extend(bytes_to_slicebit(unit.bits.path, &unit.bits.range));
+                    (path, slice_to_array::<N>(unit.hash))
+                })
+                .collect();
+            if units.iter().any(|(path, _)| path.len() > leaf_depth) {
+                damage(Damage::Malformed);
+                continue;
             }
-            _ => Ok(None),
+            if self.hasher.digest_node(&bytes) != hash {
+                damage(Damage::Mismatch);
+            }
+            stack.extend(
+                units
+                    .into_iter()
+                    .filter(|(path, _)| path.len() < leaf_depth),
+            );
         }
+        Ok(damaged)
     }
 
-    fn encode_proof(&self, bytes: &[u8], right: bool) -> Result<(bool, Vec<u8>)> {
-        match Node::from_bytes(bytes)? {
-            Node::Soft(_) => Ok((false, bytes[HASH_LEN..].to_vec())),
-            Node::Hard(_, _) => {
-                if right {
-                    Ok((
-                        true,
-                        [&bytes[..bytes.len() - HASH_LEN - 1], &[0x01]].concat(),
+    /// Estimate the size in bytes of all nodes reachable from the given root.
+    ///
+    /// Each node is counted as its key (`HASH_LEN` by default) plus its serialized form,
+    /// which is what a backend has to store at least for the tree.
+    pub fn estimated_size(&mut self, root: Option<&Hash<N>>) -> Result<u64> {
+        match root {
+            None => Ok(0),
+            Some(root) => self.size_of_node(root, 0),
+        }
+    }
+
+    fn size_of_node(&mut self, root: &[u8], depth: BitsLen) -> Result<u64> {
+        let bytes = self.read_node(root)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+        let mut size = (N + bytes.len()) as u64;
+        for unit in [lc, rc].iter().flatten() {
+            let depth = depth + unit.bits.len();
+            if depth < self.leaf_depth() {
+                size += self.size_of_node(unit.hash, depth)?;
+            }
+        }
+        Ok(size)
+    }
+
+    /// Apply updates, each a key with its new leaf or `None` to remove it, to the tree of
+    /// `old_root` in order, and check the resulting root against `expected`.
+    ///
+    /// Nodes are written to an `OverlayDB` over the database, which is only ever read,
+    /// so that a block produced by others is validated without storing anything of it.
+    /// A mismatch is reported by `Replay` rather than failing, e.g. along with the entries
+    /// the expected tree differs in, if its nodes are at hand.
+    pub fn replay_and_verify(
+        &mut self,
+        old_root: Option<&Hash<N>>,
+        updates: &[(Hash<N>, Option<Hash<N>>)],
+        expected: Option<&Hash<N>>,
+    ) -> Result<Replay<N>> {
+        let mut tree = self.overlay(None);
+        let mut root = old_root.cloned();
+        let mut noops = Vec::new();
+        for (i, (key, leaf)) in updates.iter().enumerate() {
+            let updated = match leaf {
+                Some(leaf) => tree.insert_key(root.as_ref(), key, leaf, &mut Swap::default())?,
+                None => tree.remove(root.as_ref(), key)?,
+            };
+            if updated == root {
+                noops.push(i);
+            }
+            root = updated;
+        }
+        let diff = match expected {
+            _ if root.as_ref() != expected => tree.diff(expected, root.as_ref()).ok(),
+            _ => None,
+        };
+        Ok(Replay {
+            root,
+            expected: expected.cloned(),
+            noops,
+            diff,
+        })
+    }
+
+    /// Collect the witness of a batch on the given keys: the nodes of the tree of `root`
+    /// along their paths, which are all `apply_with_witness()` reads to execute the batch,
+    /// e.g. shipped along with a block to validators holding no database.
+    ///
+    /// Nodes shared by the paths of several keys appear only once.
+    pub fn collect_witness(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+    ) -> Result<NodeSet<N>> {
+        let mut nodes = HashMap::new();
+        if let Some(root) = root {
+            for key in keys.iter() {
+                let path = self.path(key);
+                self.path_nodes(root, Bits::new(&path), &mut nodes)?;
+            }
+        }
+        Ok(nodes.into_iter().collect())
+    }
+
+    /// Apply updates, each a key with its new leaf or `None` to remove it, to the tree of `root`
+    /// in order, given only the witness of `collect_witness()` for their keys.
+    /// Returns the resulting root.
+    ///
+    /// Nothing is read from the database nor written to it: the nodes of the witness are checked
+    /// to hash to their keys, then kept in memory along with those the updates write,
+    /// in a tree of the same settings as this one, such as the order of traversing keys.
+    /// Fails with `Errors::ProofInvalid` if the witness lacks a node the updates read.
+    pub fn apply_with_witness(
+        &self,
+        root: Option<&Hash<N>>,
+        witness: &NodeSet<N>,
+        updates: &[(Hash<N>, Option<Hash<N>>)],
+    ) -> Result<Option<Hash<N>>> {
+        let mut tree = Monotree::<database::MemoryDB, H, C, N>::new("");
+        tree.hasher = self.hasher.clone();
+        tree.traversal = self.traversal;
+        tree.leaf = self.leaf;
+        tree.key_len = self.key_len;
+        tree.soft_delete = self.soft_delete;
+        tree.raw_keys = self.raw_keys;
+        tree.default_leaf = self.default_leaf;
+        for (hash, bytes) in witness.iter() {
+            if tree.hasher.digest_node(bytes) != *hash {
+                return Err(Errors::ProofInvalid(
+                    "apply_with_witness(): node hash mismatch".to_string(),
+                ));
+            }
+            tree.db.put(hash, bytes.to_vec())?;
+        }
+        let mut root = root.cloned();
+        for (key, leaf) in updates.iter() {
+            let updated = match leaf {
+                Some(leaf) => tree.insert(root.as_ref(), key, leaf),
+                None => tree.remove(root.as_ref(), key),
+            };
+            root = match updated {
+                Err(Errors::NotFound(_)) => {
+                    return Err(Errors::ProofInvalid(
+                        "apply_with_witness(): node missing from witness".to_string(),
                     ))
-                } else {
-                    Ok((false, bytes[HASH_LEN..].to_vec()))
                 }
+                updated => updated?,
+            };
+        }
+        Ok(root)
+    }
+
+    /// Get the same tree over an `OverlayDB` over the database, with the given filter.
+    fn overlay(&mut self, filter: Option<BloomFilter>) -> Monotree<OverlayDB<'_, D>, H, C, N> {
+        Monotree {
+            db: OverlayDB::with_db(&mut self.db),
+            hasher: self.hasher.clone(),
+            cancel: None,
+            traversal: self.traversal,
+            leaf: self.leaf,
+            key_len: self.key_len,
+            soft_delete: self.soft_delete,
+            raw_keys: self.raw_keys,
+            default_leaf: self.default_leaf,
+            filter,
+            report: CommitReport::default(),
+            policy: None,
+            flags: Vec::new(),
+            metrics: self.metrics.clone(),
+            watchers: Vec::new(),
+            prefetch: self.prefetch,
+            prefetched: HashMap::new(),
+            codec: PhantomData,
+        }
+    }
+
+    /// Apply the updates made by `f` to the tree of the given root as a whole.
+    /// Returns the resulting root.
+    ///
+    /// Nodes are staged in an `OverlayDB` over the database until `f` returns,
+    /// then written in a single batch only if it succeeds. Otherwise, the error of `f` is returned
+    /// with nothing written, nor cached by a `CachedDb`, and the filter, if enabled, left as it was.
+    /// Lookups and proofs by `Transaction` observe the updates staged so far, whatever the database.
+    pub fn transaction<F>(&mut self, root: Option<&Hash<N>>, f: F) -> Result<Option<Hash<N>>>
+    where
+        F: FnOnce(&mut Transaction<D, H, C, N>) -> Result<()>,
+    {
+        let filter = self.filter.clone();
+        let mut txn = Transaction {
+            tree: self.overlay(filter),
+            root: root.cloned(),
+        };
+        f(&mut txn)?;
+        let Transaction { tree, root: after } = txn;
+        let (filter, report) = (tree.filter, tree.report);
+        tree.db.commit()?;
+        self.filter = filter;
+        self.report = report;
+        self.notify_root(root, after.as_ref());
+        Ok(after)
+    }
+
+    /// Run the updates made by `f` in a single batch of the database, committed only if it succeeds.
+    /// If it fails, the batch is discarded rather than left open, so that no later read observes it.
+    ///
+    /// Within the batch, `get()` and proofs of the tree against the roots `f` gets observe
+    /// the updates made so far, as if already committed, on every backend alike,
+    /// while other handles of the database observe none of them until `f` returns.
+    /// Unlike `transaction()`, nodes are staged by the backend rather than in memory,
+    /// and operations opening batches of their own, such as `inserts()`, are not to be called by `f`.
+    pub fn in_batch<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        self.db.init_batch()?;
+        match f(self) {
+            Ok(()) => self.db.finish_batch(),
+            Err(err) => {
+                // opening the batch again drops its writes, then finishing it commits nothing
+                let _ = self.db.init_batch().and_then(|_| self.db.finish_batch());
+                Err(err)
             }
         }
     }
-}
 
-/// Verify a Merkle proof with the given root, leaf and hasher if the proof is valid or not.
-///
-/// Be aware of that it fails if not provided a suitable hasher used in the tree
-/// This generic fn must be independantly called upon request, not a member of Monotree.
-pub fn verify_proof<H: Hasher>(
-    hasher: &H,
-    root: Option<&Hash>,
-    leaf: &Hash,
-    proof: Option<&Proof>,
-) -> bool {
-    match proof {
-        None => false,
-        Some(proof) => {
-            let mut hash = leaf.to_owned();
-            proof.iter().rev().for_each(|(right, cut)| {
-                if *right {
-                    let l = cut.len();
-                    let o = [&cut[..l - 1], &hash[..], &cut[l - 1..]].concat();
-                    hash = hasher.digest(&o);
-                } else {
-                    let o = [&hash[..], &cut[..]].concat();
-                    hash = hasher.digest(&o);
+    /// Start a batch of inserts and removes, mixed, on the tree of the given root,
+    /// applied by `BatchBuilder::commit()` in a single batch of the database.
+    pub fn batch(&mut self, root: Option<&Hash<N>>) -> BatchBuilder<'_, D, H, C, N> {
+        BatchBuilder {
+            tree: self,
+            root: root.cloned(),
+            updates: Vec::new(),
+        }
+    }
+
+    /// Get an iterator over `(key, leaf)` entries in the tree of the given root,
+    /// walking the tree in the order its keys are traversed.
+    pub fn iter(&mut self, root: Option<&Hash<N>>) -> Iter<'_, D, H, C, N> {
+        let stack = root.map(|root| (*root, Vec::new())).into_iter().collect();
+        self.resume_iter(stack)
+    }
+
+    /// Resume iterating from the hashes left to visit by `Iter::into_stack()`.
+    pub(crate) fn resume_iter(&mut self, stack: Vec<(Hash<N>, Vec<bool>)>) -> Iter<'_, D, H, C, N> {
+        Iter { tree: self, stack }
+    }
+
+    /// Get the entries inserted, removed or modified from the tree of `root_a` to that of `root_b`.
+    ///
+    /// Both trees are walked down together and every subtree they share is skipped as a whole,
+    /// so the cost is bound to the changes rather than the size of the trees.
+    /// Entries are listed in the order their keys are traversed.
+    pub fn diff(&mut self, root_a: Option<&Hash<N>>, root_b: Option<&Hash<N>>) -> Result<Diff<N>> {
+        let mut diff = Diff::default();
+        let a = root_a.map(|root| (Vec::new(), *root));
+        let b = root_b.map(|root| (Vec::new(), *root));
+        self.diff_units(a, b, &mut diff)?;
+        Ok(diff)
+    }
+
+    fn diff_units(
+        &mut self,
+        a: Option<PathUnit<N>>,
+        b: Option<PathUnit<N>>,
+        diff: &mut Diff<N>,
+    ) -> Result<()> {
+        let ((path_a, hash_a), (path_b, hash_b)) = match (a, b) {
+            (None, None) => return Ok(()),
+            (Some(a), None) => return self.unit_entries(a, &mut diff.removed),
+            (None, Some(b)) => return self.unit_entries(b, &mut diff.inserted),
+            (Some(a), Some(b)) => (a, b),
+        };
+        let n = path_a
+            .iter()
+            .zip(&path_b)
+            .take_while(|(x, y)| x == y)
+            .count();
+        if n < path_a.len() && n < path_b.len() {
+            // Paths diverge, so no key is under both units.
+            self.unit_entries((path_a, hash_a), &mut diff.removed)?;
+            return self.unit_entries((path_b, hash_b), &mut diff.inserted);
+        }
+        if path_a.len() == path_b.len() {
+            if hash_a == hash_b {
+                return Ok(());
+            }
+            if path_a.len() == self.leaf_depth() as usize {
+                let key = self.key_of(&path_a);
+                diff.modified.push((key, hash_a, hash_b));
+                return Ok(());
+            }
+            let [left_a, right_a] = self.children((path_a, hash_a))?;
+            let [left_b, right_b] = self.children((path_b, hash_b))?;
+            self.diff_units(left_a, left_b, diff)?;
+            return self.diff_units(right_a, right_b, diff);
+        }
+        // One path is a prefix of the other: walk down the shorter one to meet the longer.
+        if path_a.len() < path_b.len() {
+            let [left_a, right_a] = self.children((path_a, hash_a))?;
+            let right = path_b[n];
+            let b = Some((path_b, hash_b));
+            let (left_b, right_b) = if right { (None, b) } else { (b, None) };
+            self.diff_units(left_a, left_b, diff)?;
+            self.diff_units(right_a, right_b, diff)
+        } else {
+            let [left_b, right_b] = self.children((path_b, hash_b))?;
+            let right = path_a[n];
+            let a = Some((path_a, hash_a));
+            let (left_a, right_a) = if right { (None, a) } else { (a, None) };
+            self.diff_units(left_a, left_b, diff)?;
+            self.diff_units(right_a, right_b, diff)
+        }
+    }
+
+    /// Get the left and right children of a unit, i.e. the units of the node it points to.
+    fn children(&mut self, (path, hash): PathUnit<N>) -> Result<[Option<PathUnit<N>>; 2]> {
+        let bytes = self.read_node(&hash)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+        let mut children = [None, None];
+        for unit in [lc, rc].iter().flatten() {
+            let mut child = path.clone();
+            child.extend(bytes_to_slicebit(unit.bits.path, &unit.bits.range));
+            let right = child[path.len()];
+            children[right as usize] = Some((child, slice_to_array(unit.hash)));
+        }
+        Ok(children)
+    }
+
+    /// Collect all `(key, leaf)` entries under a unit, in the order their keys are traversed.
+    fn unit_entries(
+        &mut self,
+        (path, hash): PathUnit<N>,
+        entries: &mut Vec<(Hash<N>, Hash<N>)>,
+    ) -> Result<()> {
+        let iter = Iter {
+            tree: self,
+            stack: vec![(hash, path)],
+        };
+        for entry in iter {
+            entries.push(entry?);
+        }
+        Ok(())
+    }
+
+    /// Get the nodes needed to reach the tree of `to` from the tree of `from`.
+    ///
+    /// This is what a replica holding `from` lacks to sync up with a leader at `to`.
+    /// As nodes are content-addressed, every subtree shared by both trees is skipped as a whole.
+    /// Both roots must be available in the database.
+    pub fn diff_nodes(
+        &mut self,
+        from: Option<&Hash<N>>,
+        to: Option<&Hash<N>>,
+    ) -> Result<NodeSet<N>> {
+        let mut known = HashSet::new();
+        if let Some(from) = from {
+            self.collect_nodes(from, 0, &mut known, &mut None)?;
+        }
+        let mut nodes = Vec::new();
+        if let Some(to) = to {
+            self.collect_nodes(to, 0, &mut known, &mut Some(&mut nodes))?;
+        }
+        Ok(nodes)
+    }
+
+    fn collect_nodes(
+        &mut self,
+        root: &[u8],
+        depth: BitsLen,
+        known: &mut HashSet<Hash<N>>,
+        nodes: &mut Option<&mut NodeSet<N>>,
+    ) -> Result<()> {
+        if !known.insert(slice_to_array(root)) {
+            return Ok(());
+        }
+        let bytes = self.read_node(root)?;
+        {
+            let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+            for unit in [lc, rc].iter().flatten() {
+                let depth = depth + unit.bits.len();
+                if depth < self.leaf_depth() {
+                    self.collect_nodes(unit.hash, depth, known, nodes)?;
                 }
-            });
-            root.expect("verify_proof(): root") == &hash
+            }
         }
+        if let Some(nodes) = nodes {
+            nodes.push((slice_to_array(root), bytes));
+        }
+        Ok(())
+    }
+
+    /// Apply the nodes given by `diff_nodes()` so that `root` becomes available.
+    ///
+    /// Every node is checked to hash to its key, and the tree of `root` is walked down
+    /// until reaching nodes already held, failing if any node is missing.
+    /// Only the nodes reachable from `root` are written, all in a single batch.
+    pub fn apply_nodes(
+        &mut self,
+        root: Option<&Hash<N>>,
+        nodes: &[(Hash<N>, Vec<u8>)],
+    ) -> Result<()> {
+        let mut received = HashMap::with_capacity(nodes.len());
+        for (hash, bytes) in nodes.iter() {
+            if self.hasher.digest_node(bytes) != *hash {
+                return Err(Errors::ProofInvalid(
+                    "apply_nodes(): node hash mismatch".to_string(),
+                ));
+            }
+            received.insert(*hash, bytes);
+        }
+        let mut needed = HashSet::new();
+        if let Some(root) = root {
+            self.walk_received(root, 0, &received, &mut needed)?;
+        }
+        self.db.init_batch()?;
+        for hash in needed.iter() {
+            self.db.put(hash, received[hash].to_vec())?;
+        }
+        self.db.finish_batch()
+    }
+
+    fn walk_received(
+        &mut self,
+        root: &Hash<N>,
+        depth: BitsLen,
+        received: &HashMap<Hash<N>, &Vec<u8>>,
+        needed: &mut HashSet<Hash<N>>,
+    ) -> Result<()> {
+        if needed.contains(root) || self.db.get(root)?.is_some() {
+            return Ok(());
+        }
+        let bytes = match received.get(root) {
+            Some(bytes) => bytes,
+            None => {
+                let msg = "apply_nodes(): missing node";
+                return Err(Errors::ProofInvalid(msg.to_string()));
+            }
+        };
+        needed.insert(*root);
+        let (lc, rc) = Self::cells_from_bytes(bytes, false)?;
+        for unit in [lc, rc].iter().flatten() {
+            let depth = depth + unit.bits.len();
+            if depth < self.leaf_depth() {
+                self.walk_received(&slice_to_array(unit.hash), depth, received, needed)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the root of the subtree covering the keys whose paths start with `bit_prefix`,
+    /// the bits walked down from the root as arranged by the traversal of the tree,
+    /// or `None` if no key is under it.
+    ///
+    /// Returns the bits leading to the subtree root along with its hash. As paths shared by keys
+    /// are compressed, those bits may run past `bit_prefix`, and the root is a leaf when
+    /// a single key is under it.
+    pub fn subtree_root(
+        &mut self,
+        root: Option<&Hash<N>>,
+        bit_prefix: &[bool],
+    ) -> Result<Option<(Vec<bool>, Hash<N>)>> {
+        self.walk_prefix(root, bit_prefix, &mut None)
+    }
+
+    /// Get the nodes of the subtree covering the keys whose paths start with `bit_prefix`,
+    /// along with those on the way down to it from `root`, as for `subtree_root()`.
+    ///
+    /// The nodes serve lookups and proofs of the keys under the prefix against `root`,
+    /// e.g. by a shard holding a range of keys. As nodes are content-addressed,
+    /// the nodes exported for prefixes covering all keys make up the whole tree again,
+    /// to be recombined by `apply_nodes()`. If no key is under the prefix,
+    /// the nodes on the way down show that.
+    pub fn export_subtree(
+        &mut self,
+        root: Option<&Hash<N>>,
+        bit_prefix: &[bool],
+    ) -> Result<NodeSet<N>> {
+        let mut nodes = Vec::new();
+        if let Some((path, hash)) = self.walk_prefix(root, bit_prefix, &mut Some(&mut nodes))? {
+            let depth = path.len() as BitsLen;
+            if depth < self.leaf_depth() {
+                self.collect_nodes(&hash, depth, &mut HashSet::new(), &mut Some(&mut nodes))?;
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Walk down the tree along `bit_prefix`, collecting the nodes passed if `nodes` is given.
+    fn walk_prefix(
+        &mut self,
+        root: Option<&Hash<N>>,
+        bit_prefix: &[bool],
+        nodes: &mut Option<&mut NodeSet<N>>,
+    ) -> Result<Option<(Vec<bool>, Hash<N>)>> {
+        if bit_prefix.len() > self.leaf_depth() as usize {
+            return Err(Errors::new("walk_prefix(): prefix longer than keys"));
+        }
+        let mut hash = match root {
+            Some(root) => *root,
+            None => return Ok(None),
+        };
+        let mut path = Vec::with_capacity(bit_prefix.len());
+        while path.len() < bit_prefix.len() {
+            let bytes = self.read_node(&hash)?;
+            let (bits, next) = {
+                let (cell, _) = Self::cells_from_bytes(&bytes, bit_prefix[path.len()])?;
+                let unit = cell.as_ref().expect("walk_prefix(): left-unit");
+                (
+                    bytes_to_slicebit(unit.bits.path, &unit.bits.range),
+                    slice_to_array(unit.hash),
+                )
+            };
+            if let Some(nodes) = nodes {
+                nodes.push((hash, bytes));
+            }
+            let rest = &bit_prefix[path.len()..];
+            let n = bits.len().min(rest.len());
+            if bits[..n] != rest[..n] {
+                return Ok(None);
+            }
+            path.extend(bits);
+            hash = next;
+        }
+        Ok(Some((path, hash)))
+    }
+
+    /// Merge the trees of `root_a` and `root_b`, both in the database, into a single tree
+    /// of the entries of both. Returns the root of it, of the same tree inserting all of them
+    /// gives by `same_tree()`, as the nodes merged are written anew.
+    ///
+    /// The trees are joined where their paths part, so only the nodes above the subtrees
+    /// they do not share are read and written, rather than every leaf being inserted again,
+    /// e.g. for shards of key prefixes apart, such as those of `export_subtree()`.
+    /// Fails if a key is in both trees with different leaves. The nodes are written
+    /// in a single batch. A filter enabled by `enable_filter()` is not updated with
+    /// the keys merged in, so it is to be enabled again over the root merged.
+    pub fn merge(
+        &mut self,
+        root_a: Option<&Hash<N>>,
+        root_b: Option<&Hash<N>>,
+    ) -> Result<Option<Hash<N>>> {
+        let (root_a, root_b) = match (root_a, root_b) {
+            (Some(a), Some(b)) if a != b => (a, b),
+            (None, root) | (root, _) => return Ok(root.cloned()),
+        };
+        self.db.init_batch()?;
+        let merged = self.merge_node(&[root_a, root_b], Vec::new(), &mut Vec::new());
+        self.db.finish_batch()?;
+        merged.map(Some)
+    }
+
+    /// Write the node at the position of `prefix` holding the units of the nodes of `hashes`
+    /// and `units`, each of the bits from there and the hash they lead to,
+    /// merging those on the same side. Returns the hash of the node.
+    fn merge_node(
+        &mut self,
+        hashes: &[&Hash<N>],
+        mut units: Vec<PathUnit<N>>,
+        prefix: &mut Vec<bool>,
+    ) -> Result<Hash<N>> {
+        for hash in hashes.iter() {
+            let bytes = self.read_node(*hash)?;
+            let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+            for unit in [lc, rc].iter().flatten() {
+                let bits = bytes_to_slicebit(unit.bits.path, &unit.bits.range);
+                units.push((bits, slice_to_array(unit.hash)));
+            }
+        }
+        let mut cells = [None, None];
+        for unit in units {
+            let side = unit.0[0] as usize;
+            cells[side] = match cells[side].take() {
+                None => Some(unit),
+                Some(other) => Some(self.merge_units(other, unit, prefix)?),
+            };
+        }
+        let paths: Vec<_> = cells
+            .iter()
+            .flatten()
+            .map(|(bits, _)| {
+                let mut path = [&prefix[..], &bits[..]].concat();
+                path.resize(nbytes_across(0, path.len()) * 8, false);
+                bits_to_bytes(&path)
+            })
+            .collect();
+        let mut built = cells
+            .iter()
+            .flatten()
+            .zip(paths.iter())
+            .map(|((bits, hash), path)| {
+                Some(Unit {
+                    hash,
+                    bits: Bits::new(path)
+                        .shift(prefix.len() as BitsLen, false)
+                        .shift(bits.len() as BitsLen, true),
+                })
+            });
+        let (lc, rc) = (built.next().flatten(), built.next().flatten());
+        Ok(self
+            .put_node(Node::new(lc, rc))?
+            .expect("merge_node(): hash"))
+    }
+
+    /// Merge two units on the same side of the node at the position of `prefix`.
+    fn merge_units(
+        &mut self,
+        a: PathUnit<N>,
+        b: PathUnit<N>,
+        prefix: &mut Vec<bool>,
+    ) -> Result<PathUnit<N>> {
+        if a == b {
+            return Ok(a);
+        }
+        let n =
+            a.0.iter()
+                .zip(b.0.iter())
+                .take_while(|(x, y)| x == y)
+                .count();
+        let depth = prefix.len();
+        let hash = if n == a.0.len() && n == b.0.len() {
+            if (depth + n) as BitsLen == self.leaf_depth() {
+                return Err(Errors::new("merge(): key in both trees"));
+            }
+            prefix.extend(&a.0);
+            self.merge_node(&[&a.1, &b.1], Vec::new(), prefix)
+        } else if n == a.0.len() || n == b.0.len() {
+            // the shorter leads to a node, which the other joins below
+            let (short, long) = if n == a.0.len() { (&a, &b) } else { (&b, &a) };
+            prefix.extend(&short.0);
+            self.merge_node(&[&short.1], vec![(long.0[n..].to_vec(), long.1)], prefix)
+        } else {
+            prefix.extend(&a.0[..n]);
+            let units = vec![(a.0[n..].to_vec(), a.1), (b.0[n..].to_vec(), b.1)];
+            self.merge_node(&[], units, prefix)
+        };
+        prefix.truncate(depth);
+        Ok((a.0[..n].to_vec(), hash?))
+    }
+
+    /// Write a snapshot of the tree of `root` to `writer`. Returns the number of nodes written.
+    ///
+    /// Every node reachable from `root` is written once, each after a node linking to it,
+    /// with `Metadata` of the tree, so that `import()` restores the tree into another database,
+    /// of any backend. Values and application keys stored along with leaves are not written.
+    ///
+    /// # Byte-Serialized View
+    /// _Snapshot_ = `version`(1) + `Frame`(metadata) + `Frame`(root) + `Frame`(node) * n + `0x00`(1),
+    /// where `version` = `0x01`, `Frame` = `len`(varint) + `bytes`(`len`),
+    /// the root is empty for an empty tree, and `0x00` marks the end, as no node is empty.
+    pub fn export<W: Write>(&mut self, root: Option<&Hash<N>>, mut writer: W) -> Result<u64> {
+        let frame = |writer: &mut W, bytes: &[u8]| -> Result<()> {
+            writer.write_all(&int_to_varint(bytes.len() as u64))?;
+            writer.write_all(bytes)?;
+            Ok(())
+        };
+        writer.write_all(&[SNAPSHOT_VERSION])?;
+        frame(&mut writer, &self.metadata().to_bytes()?)?;
+        frame(&mut writer, root.map(|root| &root[..]).unwrap_or_default())?;
+        let (mut known, mut count) = (HashSet::new(), 0);
+        let mut stack: Vec<(Hash<N>, BitsLen)> = root.map(|root| (*root, 0)).into_iter().collect();
+        while let Some((hash, depth)) = stack.pop() {
+            if !known.insert(hash) {
+                continue;
+            }
+            let bytes = self.read_node(&hash)?;
+            let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+            for unit in [rc, lc].iter().flatten() {
+                let depth = depth + unit.bits.len();
+                if depth < self.leaf_depth() {
+                    stack.push((slice_to_array(unit.hash), depth));
+                }
+            }
+            frame(&mut writer, &bytes)?;
+            count += 1;
+        }
+        writer.write_all(&[0x00])?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Restore a tree from a snapshot written by `export()`. Returns the root of the tree.
+    ///
+    /// The snapshot has to be of a tree of the same `Metadata`, i.e. of the same settings.
+    /// Every node is checked to hash to where it is linked from, and the snapshot to hold
+    /// every node of its root, failing otherwise. Nodes are written in batches as they are read,
+    /// so a snapshot failing midway leaves only nodes unreachable from any root.
+    pub fn import<R: Read>(&mut self, mut reader: R) -> Result<Option<Hash<N>>> {
+        let mut version = [0u8];
+        read_frame_bytes(&mut reader, &mut version)?;
+        if version[0] != SNAPSHOT_VERSION {
+            return Err(Errors::Decode("import(): unknown version".to_string()));
+        }
+        if Metadata::from_bytes(&read_frame(&mut reader)?)? != self.metadata() {
+            return Err(Errors::new("import(): metadata mismatch"));
+        }
+        let root = match read_frame(&mut reader)? {
+            root if root.is_empty() => None,
+            root if root.len() == N => Some(slice_to_array(&root)),
+            _ => return Err(Errors::Decode("import(): malformed root".to_string())),
+        };
+        // nodes linked to but not read yet, each with its depth
+        let mut expected: HashMap<Hash<N>, BitsLen> =
+            root.map(|root| (root, 0)).into_iter().collect();
+        let (mut imported, mut staged) = (HashSet::new(), 0);
+        self.db.init_batch()?;
+        loop {
+            let bytes = read_frame(&mut reader)?;
+            if bytes.is_empty() {
+                break;
+            }
+            let hash = self.hasher.digest_node(&bytes);
+            let depth = match expected.remove(&hash) {
+                Some(depth) => depth,
+                None => {
+                    self.db.finish_batch()?;
+                    let msg = "import(): unexpected node";
+                    return Err(Errors::ProofInvalid(msg.to_string()));
+                }
+            };
+            {
+                let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+                for unit in [lc, rc].iter().flatten() {
+                    let (child, depth) = (slice_to_array(unit.hash), depth + unit.bits.len());
+                    if depth < self.leaf_depth() && !imported.contains(&child) {
+                        expected.insert(child, depth);
+                    }
+                }
+            }
+            imported.insert(hash);
+            self.db.put(&hash, bytes)?;
+            staged += 1;
+            if staged % IMPORT_BATCH_SIZE == 0 {
+                self.db.finish_batch()?;
+                self.db.init_batch()?;
+            }
+        }
+        self.db.finish_batch()?;
+        if !expected.is_empty() {
+            return Err(Errors::ProofInvalid("import(): missing node".to_string()));
+        }
+        Ok(root)
+    }
+
+    /// Copy the tree of `root` into another tree, over a database of any backend.
+    /// Returns the root, the same in `target`.
+    ///
+    /// Every node reachable from `root` is written into `target`, in batches, skipping
+    /// the subtrees it holds already. Nodes are written after the nodes they link to,
+    /// so a copy failing midway leaves only whole subtrees, skipped when copied again.
+    /// As for `export()`, `target` has to be of the same `Metadata`,
+    /// and values and application keys stored along with leaves are not copied.
+    pub fn copy_to<D2: Database>(
+        &mut self,
+        root: Option<&Hash<N>>,
+        target: &mut Monotree<D2, H, C, N>,
+    ) -> Result<Option<Hash<N>>> {
+        if target.metadata() != self.metadata() {
+            return Err(Errors::new("copy_to(): metadata mismatch"));
+        }
+        // nodes to copy, each with its depth, along with its bytes once its children are pushed
+        let mut stack: Vec<(Hash<N>, BitsLen, Option<Vec<u8>>)> =
+            root.map(|root| (*root, 0, None)).into_iter().collect();
+        let (mut copied, mut staged) = (HashSet::new(), 0);
+        target.db.init_batch()?;
+        while let Some((hash, depth, bytes)) = stack.pop() {
+            if let Some(bytes) = bytes {
+                copied.insert(hash);
+                target.db.put(&hash, bytes)?;
+                staged += 1;
+                if staged % IMPORT_BATCH_SIZE == 0 {
+                    target.db.finish_batch()?;
+                    target.db.init_batch()?;
+                }
+                continue;
+            }
+            if copied.contains(&hash) || target.db.get(&hash)?.is_some() {
+                continue;
+            }
+            let bytes = self.read_node(&hash)?;
+            let mut children = Vec::new();
+            {
+                let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+                for unit in [lc, rc].iter().flatten() {
+                    let depth = depth + unit.bits.len();
+                    if depth < self.leaf_depth() {
+                        children.push((slice_to_array(unit.hash), depth, None));
+                    }
+                }
+            }
+            stack.push((hash, depth, Some(bytes)));
+            stack.extend(children);
+        }
+        target.db.finish_batch()?;
+        Ok(root.cloned())
+    }
+
+    /// Rewrite every node reachable from `root` of version `from_version` into the version
+    /// `to_version` the codec of the tree writes, see `NODE_VERSION`. Returns the new root.
+    ///
+    /// Nodes of `to_version` already are kept as they are, unless nodes below them are rewritten.
+    /// Nodes are written in batches after the nodes they link to, so a migration failing midway
+    /// leaves only whole subtrees, skipped when migrated again. Nodes of other versions fail it.
+    /// Roots change along with the encoding, and the old nodes are left for `prune()`.
+    pub fn migrate(
+        &mut self,
+        root: Option<&Hash<N>>,
+        from_version: u8,
+        to_version: u8,
+    ) -> Result<Option<Hash<N>>> {
+        self.migrate_with_progress(root, from_version, to_version, &mut |_| {})
+    }
+
+    /// The same as `migrate()`, but reports the number of nodes rewritten to `progress`
+    /// after every batch committed.
+    pub fn migrate_with_progress(
+        &mut self,
+        root: Option<&Hash<N>>,
+        from_version: u8,
+        to_version: u8,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<Option<Hash<N>>> {
+        if to_version != C::VERSION {
+            return Err(Errors::new("migrate(): codec writes another version"));
+        }
+        let root = match root {
+            None => return Ok(None),
+            Some(root) => root,
+        };
+        let mut migration = Migration {
+            from_version,
+            migrated: HashMap::new(),
+            written: 0,
+            progress,
+        };
+        self.db.init_batch()?;
+        let root = self.migrate_node(root, 0, &mut migration)?;
+        self.db.finish_batch()?;
+        (migration.progress)(migration.written);
+        Ok(Some(root))
+    }
+
+    /// Migrate the node of `hash` at `depth` and the nodes below it, returning its new hash.
+    fn migrate_node(
+        &mut self,
+        hash: &Hash<N>,
+        depth: BitsLen,
+        migration: &mut Migration<N>,
+    ) -> Result<Hash<N>> {
+        if let Some(migrated) = migration.migrated.get(hash) {
+            return Ok(*migrated);
+        }
+        let bytes = self.read_node(hash)?;
+        let version = Node::<N>::version(&bytes)?;
+        if version != migration.from_version && version != C::VERSION {
+            return Err(Errors::Decode(format!(
+                "migrate(): node of version {}",
+                version
+            )));
+        }
+        let node = C::decode::<N>(&bytes)?;
+        let units: Vec<Unit> = match &node {
+            Node::Soft(cell) => cell.iter().cloned().collect(),
+            Node::Hard(lc, rc) => lc.iter().chain(rc.iter()).cloned().collect(),
+        };
+        let mut hashes = Vec::with_capacity(units.len());
+        for unit in units.iter() {
+            let child: Hash<N> = slice_to_array(unit.hash);
+            if depth + unit.bits.len() < self.leaf_depth() {
+                hashes.push(self.migrate_node(&child, depth + unit.bits.len(), migration)?);
+            } else {
+                hashes.push(child);
+            }
+        }
+        let cells: Vec<Cell> = units
+            .iter()
+            .zip(hashes.iter())
+            .map(|(unit, hash)| {
+                Some(Unit {
+                    hash: &hash[..],
+                    bits: unit.bits.clone(),
+                })
+            })
+            .collect();
+        let node: Node<N> = match cells.as_slice() {
+            [cell] => Node::Soft(cell.clone()),
+            [lc, rc] => Node::Hard(lc.clone(), rc.clone()),
+            _ => unreachable!("migrate_node(): cells"),
+        };
+        let encoded = C::encode(&node)?;
+        let migrated = if encoded == bytes {
+            *hash
+        } else {
+            let migrated = self.hasher.digest_node(&encoded);
+            self.count_written(encoded.len());
+            self.db.put(&migrated, encoded)?;
+            migration.written += 1;
+            if migration.written.is_multiple_of(IMPORT_BATCH_SIZE) {
+                self.db.finish_batch()?;
+                (migration.progress)(migration.written);
+                self.db.init_batch()?;
+            }
+            migrated
+        };
+        migration.migrated.insert(*hash, migrated);
+        Ok(migrated)
+    }
+
+    /// Delete every node unreachable from the given roots from the database.
+    /// Returns the number of entries deleted.
+    ///
+    /// Nodes and leaves reachable from `retain_roots` and the root stored by `swap_root()`
+    /// are marked first. Then every other entry stored under the hash of its value is deleted
+    /// in a single batch, i.e. stale nodes as well as values of stale leaves such as those of
+    /// `MerkleMap`. Entries under other keys, such as `Metadata`, are kept.
+    /// Roots of nested trees are leaves of their parents, so give them too to retain the trees.
+    /// With soft-delete set, the leaves tombstones in those trees replaced are retained as well.
+    /// The database must support scanning its entries by `Database::iter_prefix()`.
+    pub fn prune(&mut self, retain_roots: &[Hash<N>]) -> Result<usize> {
+        self.prefetched.clear();
+        let (mut nodes, mut leaves) = (HashSet::new(), HashSet::new());
+        for root in retain_roots.iter().chain(self.load_root()?.as_ref()) {
+            self.mark_reachable(root, 0, &mut nodes, &mut leaves)?;
+        }
+        let mut stale = Vec::new();
+        let hasher = &self.hasher;
+        for entry in self.db.iter_prefix(&[])? {
+            let (key, value) = entry?;
+            let reserved = key.starts_with(&METADATA_KEY) || key == ROOT_KEY;
+            if reserved || nodes.contains(&key[..]) || leaves.contains(&key[..]) {
+                continue;
+            }
+            let hashes = [hasher.digest_node(&value), hasher.digest_leaf(&value)];
+            if hashes.iter().any(|hash| hash[..] == key[..]) || hasher.digest(&value)[..] == key[..]
+            {
+                stale.push(key);
+            }
+        }
+        self.db.init_batch()?;
+        for key in stale.iter() {
+            self.db.delete(key)?;
+        }
+        self.db.finish_batch()?;
+        Ok(stale.len())
+    }
+
+    /// The same as `prune()`, but compacts the database after, by `Database::compact()`,
+    /// so that the space of the entries deleted is returned to the disk on `RocksDB` and `Sled`.
+    pub fn prune_and_compact(&mut self, retain_roots: &[Hash<N>]) -> Result<usize> {
+        let pruned = self.prune(retain_roots)?;
+        self.db.compact()?;
+        Ok(pruned)
+    }
+
+    fn mark_reachable(
+        &mut self,
+        root: &[u8],
+        depth: BitsLen,
+        nodes: &mut HashSet<Hash<N>>,
+        leaves: &mut HashSet<Hash<N>>,
+    ) -> Result<()> {
+        if !nodes.insert(slice_to_array(root)) {
+            return Ok(());
+        }
+        let bytes = self.read_node(root)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+        for unit in [lc, rc].iter().flatten() {
+            let depth = depth + unit.bits.len();
+            if depth < self.leaf_depth() {
+                self.mark_reachable(unit.hash, depth, nodes, leaves)?;
+            } else {
+                let leaf = slice_to_array(unit.hash);
+                leaves.insert(leaf);
+                // the leaf a tombstone replaced is kept for `restore()`
+                if self.soft_delete {
+                    if let Some(previous) = self.tombstoned(&leaf)? {
+                        leaves.insert(previous);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Insert key-leaf entry into a nested tree. Returns a new root hash of the outermost tree.
+    ///
+    /// Roots of child trees are stored as leaves of their parent tree.
+    /// `path` is a sequence of keys walking down from the outermost tree to the tree to update.
+    /// The update and propagating the resulting roots up to the outermost tree are done in a single batch.
+    pub fn insert_nested(
+        &mut self,
+        root: Option<&Hash<N>>,
+        path: &[Hash<N>],
+        key: &Hash<N>,
+        leaf: &Hash<N>,
+    ) -> Result<Option<Hash<N>>> {
+        self.db.init_batch()?;
+        let root =
+            self.update_nested(root, path, &mut |tree, root| tree.insert(root, key, leaf))?;
+        self.db.finish_batch()?;
+        Ok(root)
+    }
+
+    /// Remove the given key from a nested tree. Returns a new root hash of the outermost tree.
+    ///
+    /// A child tree becoming empty is removed from its parent tree as well.
+    pub fn remove_nested(
+        &mut self,
+        root: Option<&Hash<N>>,
+        path: &[Hash<N>],
+        key: &Hash<N>,
+    ) -> Result<Option<Hash<N>>> {
+        self.db.init_batch()?;
+        let root = self.update_nested(root, path, &mut |tree, root| tree.remove(root, key))?;
+        self.db.finish_batch()?;
+        Ok(root)
+    }
+
+    /// Get a leaf hash for the given key from a nested tree.
+    pub fn get_nested(
+        &mut self,
+        root: Option<&Hash<N>>,
+        path: &[Hash<N>],
+        key: &Hash<N>,
+    ) -> Result<Option<Hash<N>>> {
+        let mut root = root.cloned();
+        for id in path.iter() {
+            root = self.get(root.as_ref(), id)?;
+        }
+        self.get(root.as_ref(), key)
+    }
+
+    fn update_nested<F>(
+        &mut self,
+        root: Option<&Hash<N>>,
+        path: &[Hash<N>],
+        update: &mut F,
+    ) -> Result<Option<Hash<N>>>
+    where
+        F: FnMut(&mut Self, Option<&Hash<N>>) -> Result<Option<Hash<N>>>,
+    {
+        match path.split_first() {
+            None => update(self, root),
+            Some((id, path)) => {
+                let child = self.get(root, id)?;
+                match self.update_nested(child.as_ref(), path, update)? {
+                    Some(child) => self.insert(root, id, &child),
+                    None => self.remove(root, id),
+                }
+            }
+        }
+    }
+
+    /// Generate a Merkle proof for the given root and key.
+    pub fn get_merkle_proof(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &[u8],
+    ) -> Result<Option<Proof>> {
+        let start = Instant::now();
+        let mut proof: Proof = Vec::new();
+        let key = self.path(key);
+        let proof = match root {
+            None => None,
+            Some(root) => self.gen_proof(root, Bits::new(&key), &mut proof)?,
+        };
+        if let (Some(metrics), Some(proof)) = (&self.metrics, &proof) {
+            metrics.proof_generated(proof.to_bytes().len() as u64);
+            metrics.proof_timed(start.elapsed());
+        }
+        Ok(proof)
+    }
+
+    fn gen_proof(&mut self, root: &[u8], bits: Bits, proof: &mut Proof) -> Result<Option<Proof>> {
+        let bytes = self.read_node(root)?;
+        let (cell, _) = Self::cells_from_bytes(&bytes, bits.first())?;
+        let unit = cell.as_ref().expect("gen_proof(): left-unit");
+        let n = Bits::len_common_bits(&unit.bits, &bits);
+        match n {
+            n if n == bits.len() => {
+                proof.push(self.encode_proof(&bytes, bits.first())?);
+                Ok(Some(proof.to_owned()))
+            }
+            n if n == unit.bits.len() => {
+                proof.push(self.encode_proof(&bytes, bits.first())?);
+                self.gen_proof(unit.hash, bits.shift(n, false), proof)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Generate Merkle proofs for the same key against each of the given roots,
+    /// such as the roots of versions retained over the history of the tree.
+    ///
+    /// Nodes on the path of the key shared by adjacent roots are read from database only once.
+    /// A proof is `None` for the roots of which the key is not in the tree.
+    pub fn get_merkle_proofs_across(
+        &mut self,
+        roots: &[Option<Hash<N>>],
+        key: &[u8],
+    ) -> Result<Vec<Option<Proof>>> {
+        let key = self.path(key);
+        let mut proofs = Vec::with_capacity(roots.len());
+        let mut shared: HashMap<Hash<N>, Vec<u8>> = HashMap::new();
+        for root in roots.iter() {
+            let mut walked: HashMap<Hash<N>, Vec<u8>> = HashMap::new();
+            let mut proof: Proof = Vec::new();
+            let mut next = *root;
+            let mut bits = Bits::new(&key);
+            let found = loop {
+                let hash = match next {
+                    None => break false,
+                    Some(hash) => hash,
+                };
+                let bytes = match shared.remove(&hash) {
+                    Some(bytes) => bytes,
+                    None => self.read_node(&hash)?,
+                };
+                let (cell, _) = Self::cells_from_bytes(&bytes, bits.first())?;
+                let unit = cell
+                    .as_ref()
+                    .expect("get_merkle_proofs_across(): left-unit");
+                let n = Bits::len_common_bits(&unit.bits, &bits);
+                let child = slice_to_array(unit.hash);
+                let done = n == bits.len();
+                if !done && n != unit.bits.len() {
+                    break false;
+                }
+                proof.push(self.encode_proof(&bytes, bits.first())?);
+                walked.insert(hash, bytes);
+                if done {
+                    break true;
+                }
+                bits = bits.shift(n, false);
+                next = Some(child);
+            };
+            proofs.push(if found { Some(proof) } else { None });
+            shared = walked;
+        }
+        Ok(proofs)
+    }
+
+    /// Generate a single `MultiProof` for the given keys, where nodes shared by their paths
+    /// appear only once and hashes computable from the leaves or descendant nodes are cut out.
+    ///
+    /// Returns `None` if any of the keys is not in the tree.
+    pub fn get_merkle_proofs(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+    ) -> Result<Option<MultiProof>> {
+        let start = Instant::now();
+        let mut keys: Vec<_> = keys.iter().map(|key| self.path(key)).collect();
+        keys.sort();
+        keys.dedup();
+        let bits: Vec<Bits> = keys.iter().map(|key| Bits::new(key)).collect();
+        let mut proof: MultiProof = Vec::new();
+        let proof = match root {
+            None => None,
+            Some(_) if bits.is_empty() => Some(proof),
+            Some(root) => match self.gen_multiproof(root, &bits, &mut proof)? {
+                true => Some(proof),
+                false => None,
+            },
+        };
+        if let (Some(metrics), Some(proof)) = (&self.metrics, &proof) {
+            metrics.proof_generated(proof.iter().map(|node| node.len()).sum::<usize>() as u64);
+            metrics.proof_timed(start.elapsed());
+        }
+        Ok(proof)
+    }
+
+    /// Generate a page of a `MultiProof` for the given keys, fitting in `budget` bytes.
+    ///
+    /// Keys are paged in the order they are traversed, starting from the key `from` or the
+    /// first one. Each page is verified on its own with `verify_merkle_proofs()` and tells
+    /// the key the next page starts from. Returns `None` if a key of the page is not in the tree.
+    pub fn get_merkle_proof_page(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+        budget: usize,
+        from: Option<&Hash<N>>,
+    ) -> Result<Option<ProofPage<N>>> {
+        let traversal = self.traversal;
+        let arrange = |key: &Hash<N>| slice_to_array(&traversal.arrange(key));
+        let mut keys: Vec<(Hash<N>, Hash<N>)> =
+            keys.iter().map(|key| (arrange(key), *key)).collect();
+        keys.sort();
+        keys.dedup();
+        let start = match from {
+            None => 0,
+            Some(from) => keys.partition_point(|(key, _)| key < &arrange(from)),
+        };
+        let rest: Vec<Hash<N>> = keys[start..].iter().map(|(_, key)| *key).collect();
+        let size = |proof: &MultiProof| proof.iter().map(|node| node.len()).sum::<usize>();
+
+        // find the most keys whose proof fits, as it never shrinks with more keys
+        let (mut lo, mut hi) = (0, rest.len());
+        let mut proof = Vec::new();
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            match self.get_merkle_proofs(root, &rest[..mid])? {
+                None => return Ok(None),
+                Some(page) if size(&page) <= budget => {
+                    lo = mid;
+                    proof = page;
+                }
+                Some(_) => hi = mid - 1,
+            }
+        }
+        if lo == 0 && !rest.is_empty() {
+            return Err(Errors::new("get_merkle_proof_page(): budget too small"));
+        }
+        Ok(Some(ProofPage {
+            keys: rest[..lo].to_vec(),
+            proof,
+            next: rest.get(lo).cloned(),
+        }))
+    }
+
+    fn gen_multiproof(
+        &mut self,
+        root: &[u8],
+        bits: &[Bits],
+        proof: &mut MultiProof,
+    ) -> Result<bool> {
+        let bytes = self.read_node(root)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+        let hard = rc.is_some();
+        let mut mask = 0u8;
+        let mut children = Vec::new();
+        for (i, cell) in [lc, rc].iter().enumerate() {
+            let part: Vec<&Bits> = bits
+                .iter()
+                .filter(|b| if hard { b.first() == (i == 1) } else { i == 0 })
+                .collect();
+            if part.is_empty() {
+                continue;
+            }
+            let unit = cell.as_ref().expect("gen_multiproof(): unit");
+            let n = unit.bits.len();
+            if part
+                .iter()
+                .any(|b| Bits::len_common_bits(&unit.bits, b) != n)
+            {
+                return Ok(false);
+            }
+            mask |= 1 << i;
+            if part.iter().all(|b| b.len() > n) {
+                let part: Vec<Bits> = part.iter().map(|b| b.shift(n, false)).collect();
+                children.push((unit.hash, part));
+            }
+        }
+        let len = bytes.len();
+        let start = if mask & 0x01 != 0 { N } else { 0 };
+        let end = if mask & 0x02 != 0 {
+            len - N - 1
+        } else {
+            len - 1
+        };
+        proof.push([&[mask], &bytes[start..end], &bytes[len - 1..]].concat());
+        for (hash, part) in children.iter() {
+            if !self.gen_multiproof(hash, part, proof)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Generate a proof that the given key is not in the tree of the root.
+    ///
+    /// The proof consists of the nodes walked down along the key until it diverges.
+    /// Returns `None` if the key is in the tree.
+    pub fn get_non_inclusion_proof(
+        &mut self,
+        root: Option<&Hash<N>>,
+        key: &[u8],
+    ) -> Result<Option<NonInclusionProof>> {
+        let key = self.path(key);
+        let mut proof: NonInclusionProof = Vec::new();
+        match root {
+            None => Ok(Some(proof)),
+            Some(root) => self.gen_non_inclusion_proof(root, Bits::new(&key), &mut proof),
+        }
+    }
+
+    fn gen_non_inclusion_proof(
+        &mut self,
+        root: &[u8],
+        bits: Bits,
+        proof: &mut NonInclusionProof,
+    ) -> Result<Option<NonInclusionProof>> {
+        let bytes = self.read_node(root)?;
+        let (cell, _) = Self::cells_from_bytes(&bytes, bits.first())?;
+        let unit = cell.as_ref().expect("gen_non_inclusion_proof(): left-unit");
+        let n = Bits::len_common_bits(&unit.bits, &bits);
+        match n {
+            n if n == bits.len() => Ok(None),
+            n if n == unit.bits.len() => {
+                proof.push(bytes.to_owned());
+                self.gen_non_inclusion_proof(unit.hash, bits.shift(n, false), proof)
+            }
+            _ => {
+                proof.push(bytes.to_owned());
+                Ok(Some(proof.to_owned()))
+            }
+        }
+    }
+
+    /// Generate a proof of the leaf `get()` gives for the given key: a Merkle proof if the key
+    /// is in the tree, or else a proof of its absence, by which it has the default leaf, if any.
+    ///
+    /// Verified by `verify_leaf_proof()` along with the default leaf of the tree.
+    pub fn get_leaf_proof(&mut self, root: Option<&Hash<N>>, key: &Hash<N>) -> Result<LeafProof> {
+        match self.get_merkle_proof(root, key)? {
+            Some(proof) => Ok(LeafProof::Inclusion(proof)),
+            None => match self.get_non_inclusion_proof(root, key)? {
+                Some(proof) => Ok(LeafProof::Absence(proof)),
+                None => Err(Errors::new("get_leaf_proof(): key neither in nor out")),
+            },
+        }
+    }
+
+    /// Generate a `ChainedProof` for nested trees, where roots of child trees are
+    /// inserted as leaves of their parent tree and share the same database.
+    ///
+    /// `keys` are used to walk down from the outermost tree to the tree holding the leaf.
+    pub fn get_chained_proof(
+        &mut self,
+        root: Option<&Hash<N>>,
+        keys: &[Hash<N>],
+    ) -> Result<Option<ChainedProof>> {
+        let mut root = root.cloned();
+        let mut chain: ChainedProof = Vec::new();
+        for key in keys.iter() {
+            match self.get_merkle_proof(root.as_ref(), key)? {
+                None => return Ok(None),
+                Some(proof) => chain.push(proof),
+            }
+            root = self.get(root.as_ref(), key)?;
+        }
+        Ok(Some(chain))
+    }
+
+    /// Generate a `ConsistencyProof` that the tree of `new_root` was derived from
+    /// the tree of `old_root` by updating a set of keys.
+    ///
+    /// Keys are found by comparing both trees, skipping subtrees shared by them.
+    /// The proof is checked by replaying the updates on the old tree, then comparing the result
+    /// with the new tree by `same_tree()`, as the order of the updates replayed may differ
+    /// from the one the new tree was built in. The nodes both trees differ in are carried along.
+    pub fn consistency_proof(
+        &mut self,
+        old_root: Option<&Hash<N>>,
+        new_root: Option<&Hash<N>>,
+    ) -> Result<ConsistencyProof<N>> {
+        let (mut old_nodes, mut new_nodes) = (HashSet::new(), HashSet::new());
+        if let Some(root) = old_root {
+            self.node_positions(root, &mut Vec::new(), &mut old_nodes)?;
+        }
+        if let Some(root) = new_root {
+            self.node_positions(root, &mut Vec::new(), &mut new_nodes)?;
+        }
+        let (mut old, mut new) = (HashMap::new(), HashMap::new());
+        let mut nodes = HashMap::new();
+        if let Some(root) = old_root {
+            self.changed_entries(root, &mut Vec::new(), &new_nodes, &mut old, &mut nodes)?;
+        }
+        if let Some(root) = new_root {
+            self.changed_entries(root, &mut Vec::new(), &old_nodes, &mut new, &mut nodes)?;
+        }
+
+        let mut updates: Vec<(Hash<N>, Option<Hash<N>>)> = new
+            .iter()
+            .filter(|(path, leaf)| old.get(*path) != Some(leaf))
+            .map(|(path, leaf)| (*path, Some(*leaf)))
+            .chain(
+                old.keys()
+                    .filter(|path| !new.contains_key(*path))
+                    .map(|path| (*path, None)),
+            )
+            .collect();
+        updates.sort_unstable();
+
+        if let Some(root) = old_root {
+            for (path, _) in updates.iter() {
+                self.path_nodes(root, Bits::new(&path[..self.key_len]), &mut nodes)?;
+            }
+        }
+        let traversal = self.traversal;
+        Ok(ConsistencyProof {
+            updates: updates
+                .into_iter()
+                .map(|(path, leaf)| (slice_to_array(&traversal.arrange(&path)), leaf))
+                .collect(),
+            nodes: nodes.into_iter().collect(),
+            traversal,
+            key_len: self.key_len,
+            codec: C::ID,
+        })
+    }
+
+    /// Check if the trees of two roots have the same entries, comparing their cells
+    /// by the bits of their ranges.
+    ///
+    /// Bytes of paths out of the ranges of cells are serialized as sliced from the keys
+    /// inserted, so trees of the same entries may differ in their roots by the order
+    /// the entries were inserted in, or by the operations building them, such as `merge()`.
+    /// Trees of codecs not merging nodes on removal, see `NodeCodec::MERGE_ON_REMOVE`,
+    /// also differ in their shapes by the removals building them, so their entries are
+    /// compared instead, those under nodes missing in database by the hashes of the nodes.
+    pub fn same_tree(&mut self, a: Option<&Hash<N>>, b: Option<&Hash<N>>) -> Result<bool> {
+        match (a, b) {
+            (Some(a), Some(b)) if a == b => Ok(true),
+            (Some(a), Some(b)) if C::MERGE_ON_REMOVE => self.same_cells(a, b, 0),
+            (Some(a), Some(b)) => {
+                let (mut of_a, mut of_b) = (HashMap::new(), HashMap::new());
+                self.cell_entries(a, &mut Vec::new(), &mut of_a)?;
+                self.cell_entries(b, &mut Vec::new(), &mut of_b)?;
+                Ok(of_a == of_b)
+            }
+            (a, b) => Ok(a == b),
+        }
+    }
+
+    /// Collect the leaves of a tree by the bits leading to them, along with the hashes
+    /// of nodes missing in database, such as those left out of proofs, by the bits leading to them.
+    fn cell_entries(
+        &mut self,
+        root: &[u8],
+        prefix: &mut Vec<bool>,
+        entries: &mut HashMap<Vec<bool>, Hash<N>>,
+    ) -> Result<()> {
+        let bytes = match self.read_node(root) {
+            Ok(bytes) => bytes,
+            Err(Errors::NotFound(_)) => {
+                entries.insert(prefix.clone(), slice_to_array(root));
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+        for unit in [lc, rc].iter().flatten() {
+            let depth = prefix.len();
+            prefix.extend(bytes_to_slicebit(unit.bits.path, &unit.bits.range));
+            if prefix.len() < self.leaf_depth() as usize {
+                self.cell_entries(unit.hash, prefix, entries)?;
+            } else {
+                entries.insert(prefix.clone(), slice_to_array(unit.hash));
+            }
+            prefix.truncate(depth);
+        }
+        Ok(())
+    }
+
+    /// Collect all nodes of a tree as pairs of their position, i.e. bits leading to them, and hash.
+    fn node_positions(
+        &mut self,
+        root: &[u8],
+        prefix: &mut Vec<bool>,
+        positions: &mut HashSet<(Vec<bool>, Hash<N>)>,
+    ) -> Result<()> {
+        positions.insert((prefix.clone(), slice_to_array(root)));
+        let bytes = self.read_node(root)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+        for unit in [lc, rc].iter().flatten() {
+            let depth = prefix.len();
+            prefix.extend(bytes_to_slicebit(unit.bits.path, &unit.bits.range));
+            if prefix.len() < self.leaf_depth() as usize {
+                self.node_positions(unit.hash, prefix, positions)?;
+            }
+            prefix.truncate(depth);
+        }
+        Ok(())
+    }
+
+    /// Collect entries of a tree as pairs of their path and leaf, along with its nodes,
+    /// skipping nodes in `shared`.
+    fn changed_entries(
+        &mut self,
+        root: &[u8],
+        prefix: &mut Vec<bool>,
+        shared: &HashSet<(Vec<bool>, Hash<N>)>,
+        entries: &mut HashMap<Hash<N>, Hash<N>>,
+        nodes: &mut HashMap<Hash<N>, Vec<u8>>,
+    ) -> Result<()> {
+        if shared.contains(&(prefix.clone(), slice_to_array(root))) {
+            return Ok(());
+        }
+        let bytes = self.read_node(root)?;
+        let (lc, rc) = Self::cells_from_bytes(&bytes, false)?;
+        for unit in [lc, rc].iter().flatten() {
+            let depth = prefix.len();
+            prefix.extend(bytes_to_slicebit(unit.bits.path, &unit.bits.range));
+            if prefix.len() < self.leaf_depth() as usize {
+                self.changed_entries(unit.hash, prefix, shared, entries, nodes)?;
+            } else {
+                let mut path = bits_to_bytes(prefix);
+                path.resize(N, 0);
+                entries.insert(slice_to_array(&path), slice_to_array(unit.hash));
+            }
+            prefix.truncate(depth);
+        }
+        nodes.insert(slice_to_array(root), bytes);
+        Ok(())
+    }
+
+    /// Check if the subtrees of `a` and `b` at `depth` have the same cells, see `same_tree()`.
+    fn same_cells(&mut self, a: &[u8], b: &[u8], depth: usize) -> Result<bool> {
+        if a == b {
+            return Ok(true);
+        }
+        let (a, b) = (self.read_node(a)?, self.read_node(b)?);
+        let (al, ar) = Self::cells_from_bytes(&a, false)?;
+        let (bl, br) = Self::cells_from_bytes(&b, false)?;
+        for pair in [(al, bl), (ar, br)].iter() {
+            let (a, b) = match pair {
+                (None, None) => continue,
+                (Some(a), Some(b)) => (a, b),
+                _ => return Ok(false),
+            };
+            let bits = bytes_to_slicebit(a.bits.path, &a.bits.range);
+            if bits != bytes_to_slicebit(b.bits.path, &b.bits.range) {
+                return Ok(false);
+            }
+            let depth = depth + bits.len();
+            let same = if depth < self.leaf_depth() as usize {
+                self.same_cells(a.hash, b.hash, depth)?
+            } else {
+                a.hash == b.hash
+            };
+            if !same {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Collect nodes walking down the tree along `bits`. Fails if any node is missing.
+    fn path_nodes(
+        &mut self,
+        root: &[u8],
+        bits: Bits,
+        nodes: &mut HashMap<Hash<N>, Vec<u8>>,
+    ) -> Result<()> {
+        let bytes = self.read_node(root)?;
+        {
+            let (cell, _) = Self::cells_from_bytes(&bytes, bits.first())?;
+            let unit = cell.as_ref().expect("path_nodes(): left-unit");
+            let n = Bits::len_common_bits(&unit.bits, &bits);
+            if n == unit.bits.len() && n < bits.len() {
+                self.path_nodes(unit.hash, bits.shift(n, false), nodes)?;
+            }
+        }
+        nodes.insert(slice_to_array(root), bytes);
+        Ok(())
+    }
+
+    fn encode_proof(&self, bytes: &[u8], right: bool) -> Result<(bool, Vec<u8>)> {
+        match C::decode::<N>(bytes)? {
+            Node::Soft(_) => Ok((false, bytes[N..].to_vec())),
+            Node::Hard(_, _) => {
+                if right {
+                    Ok((
+                        true,
+                        [&bytes[..bytes.len() - N - 1], &bytes[bytes.len() - 1..]].concat(),
+                    ))
+                } else {
+                    Ok((false, bytes[N..].to_vec()))
+                }
+            }
+        }
+    }
+}
+
+impl<D, H, C, const N: usize> Monotree<NamespacedDB<D>, H, C, N>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    /// Construct `Monotree` over a database shared with other trees, under the given namespace.
+    ///
+    /// Trees of other namespaces are constructed over `tree.db.namespace()` by `with_db()`.
+    /// Each of them has nodes, roots and settings of its own, so pruning one never touches others.
+    pub fn with_namespace(db: D, namespace: &[u8]) -> Result<Self> {
+        Self::with_db(NamespacedDB::with_db(db, namespace))
+    }
+}
+
+impl<D, H, C, const N: usize> Monotree<SnapshotDB<D>, H, C, N>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    /// Take a snapshot of the tree, after which updates are staged in memory.
+    ///
+    /// Speculative updates, for example, validating a block before committing it,
+    /// can be applied and proven, then discarded by `rollback()` or kept by `release()`.
+    pub fn snapshot(&mut self) -> SnapshotId {
+        self.db.snapshot()
+    }
+
+    /// Discard all updates since the given snapshot, with nothing written to the database.
+    /// Settings recorded in database since, such as the order of traversal, are restored as well.
+    pub fn rollback(&mut self, id: SnapshotId) -> Result<()> {
+        self.db.rollback(id)?;
+        let metadata = match self.db.get(&METADATA_KEY)? {
+            Some(bytes) => Metadata::from_bytes(&bytes)?,
+            None => Metadata::default(),
+        };
+        self.traversal = metadata.traversal;
+        self.leaf = metadata.leaf;
+        self.key_len = metadata.key_len;
+        self.soft_delete = metadata.soft_delete;
+        self.raw_keys = metadata.raw_keys;
+        self.filter = match self.db.get(&FILTER_KEY)? {
+            Some(bytes) => Some(BloomFilter::from_bytes(&bytes)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Keep all updates since the given snapshot. See `SnapshotDB::release()`.
+    pub fn release(&mut self, id: SnapshotId) -> Result<()> {
+        self.db.release(id)
+    }
+}
+
+/// An iterator over `(key, leaf)` entries of a tree, returned by `Monotree::iter()`.
+///
+/// Keys are rebuilt from the bits walked down to their leaves.
+/// Iteration stops after the first error, such as a node missing in database.
+pub struct Iter<'a, D, H, C, const N: usize = HASH_LEN> {
+    tree: &'a mut Monotree<D, H, C, N>,
+    /// Hashes yet to visit, each with the bits walked down to it. Full-length ones are leaves.
+    stack: Vec<(Hash<N>, Vec<bool>)>,
+}
+
+impl<D, H, C, const N: usize> Iter<'_, D, H, C, N> {
+    /// Release the tree, keeping the hashes left to visit for `Monotree::resume_iter()`.
+    #[cfg(feature = "async")]
+    pub(crate) fn into_stack(self) -> Vec<(Hash<N>, Vec<bool>)> {
+        self.stack
+    }
+}
+
+impl<D, H, C, const N: usize> Iterator for Iter<'_, D, H, C, N>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    type Item = Result<(Hash<N>, Hash<N>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (hash, prefix) = self.stack.pop()?;
+            if prefix.len() == self.tree.leaf_depth() as usize {
+                return Some(Ok((self.tree.key_of(&prefix), hash)));
+            }
+            let children = self.tree.read_node(&hash).and_then(|bytes| {
+                let (lc, rc) = Monotree::<D, H, C, N>::cells_from_bytes(&bytes, false)?;
+                Ok([rc, lc]
+                    .iter()
+                    .flatten()
+                    .map(|unit| {
+                        let mut path = prefix.to_owned();
+                        path.extend(bytes_to_slicebit(unit.bits.path, &unit.bits.range));
+                        (slice_to_array(unit.hash), path)
+                    })
+                    .collect::<Vec<_>>())
+            });
+            match children {
+                Ok(children) => self.stack.extend(children),
+                Err(err) => {
+                    self.stack.clear();
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// Updates staged by `Monotree::transaction()` on the tree of the root it is given.
+pub struct Transaction<'a, D, H, C, const N: usize = HASH_LEN> {
+    tree: Monotree<OverlayDB<'a, D>, H, C, N>,
+    root: Option<Hash<N>>,
+}
+
+impl<D, H, C, const N: usize> Transaction<'_, D, H, C, N>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    /// Get the root of the tree with the updates so far.
+    pub fn root(&self) -> Option<&Hash<N>> {
+        self.root.as_ref()
+    }
+
+    pub fn get(&mut self, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        self.tree.get(self.root.as_ref(), key)
+    }
+
+    /// Generate a Merkle proof for the given key against `root()`, with the updates so far.
+    pub fn get_merkle_proof(&mut self, key: &Hash<N>) -> Result<Option<Proof>> {
+        self.tree.get_merkle_proof(self.root.as_ref(), key)
+    }
+
+    /// Generate a proof of the leaf `get()` gives for the given key against `root()`.
+    pub fn get_leaf_proof(&mut self, key: &Hash<N>) -> Result<LeafProof> {
+        self.tree.get_leaf_proof(self.root.as_ref(), key)
+    }
+
+    pub fn insert(&mut self, key: &Hash<N>, leaf: &Hash<N>) -> Result<()> {
+        self.root = self.tree.insert(self.root.as_ref(), key, leaf)?;
+        Ok(())
+    }
+
+    pub fn inserts(&mut self, keys: &[Hash<N>], leaves: &[Hash<N>]) -> Result<()> {
+        self.root = self.tree.inserts(self.root.as_ref(), keys, leaves)?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Hash<N>) -> Result<()> {
+        self.root = self.tree.remove(self.root.as_ref(), key)?;
+        Ok(())
+    }
+
+    pub fn removes(&mut self, keys: &[Hash<N>]) -> Result<()> {
+        self.root = self.tree.removes(self.root.as_ref(), keys)?;
+        Ok(())
+    }
+}
+
+/// Inserts and removes on the tree of a root, gathered by `Monotree::batch()`.
+///
+/// Nothing is applied until `commit()`, which sorts the updates by key, keeping those of
+/// the same key in the order given so that the last one wins, and applies them all
+/// in a single batch of the database, as `inserts()` does, producing a single new root.
+pub struct BatchBuilder<'a, D, H, C, const N: usize = HASH_LEN> {
+    tree: &'a mut Monotree<D, H, C, N>,
+    root: Option<Hash<N>>,
+    updates: Vec<(Hash<N>, Option<Hash<N>>)>,
+}
+
+impl<D, H, C, const N: usize> BatchBuilder<'_, D, H, C, N>
+where
+    D: Database,
+    H: Hasher<N>,
+    C: NodeCodec,
+{
+    pub fn insert(mut self, key: &Hash<N>, leaf: &Hash<N>) -> Self {
+        self.updates.push((*key, Some(*leaf)));
+        self
+    }
+
+    pub fn remove(mut self, key: &Hash<N>) -> Self {
+        self.updates.push((*key, None));
+        self
+    }
+
+    /// Get the leaf of a key with the updates gathered so far, the last one of the key winning,
+    /// or else the one in the tree of the root.
+    pub fn get(&mut self, key: &Hash<N>) -> Result<Option<Hash<N>>> {
+        match self.updates.iter().rev().find(|(k, _)| k == key) {
+            Some((_, Some(leaf))) => Ok(Some(*leaf)),
+            Some((_, None)) => Ok(self.tree.default_leaf),
+            None => self.tree.get(self.root.as_ref(), key),
+        }
+    }
+
+    /// Get the number of updates gathered.
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    /// Apply the updates gathered in a single batch. Returns the new root.
+    pub fn commit(self) -> Result<Option<Hash<N>>> {
+        let BatchBuilder {
+            tree,
+            root,
+            mut updates,
+        } = self;
+        updates.sort_by_key(|update| update.0);
+        let keys: Vec<Hash<N>> = updates.iter().map(|(key, _)| *key).collect();
+        let (counted, start) = (tree.report.clone(), Instant::now());
+        tree.db.init_batch()?;
+        tree.prefetch(root.as_ref(), &keys)?;
+        let mut after = root;
+        for (key, leaf) in updates.iter() {
+            tree.check_cancelled()?;
+            after = match leaf {
+                Some(leaf) => tree.insert_key(after.as_ref(), key, leaf, &mut Swap::default())?,
+                None => tree.remove(after.as_ref(), key)?,
+            };
+        }
+        tree.prefetched.clear();
+        tree.save_filter()?;
+        tree.commit_batch(
+            root.as_ref(),
+            after.as_ref(),
+            updates.len(),
+            &counted,
+            start,
+        )?;
+        Ok(after)
+    }
+}
+
+/// Rules on how many of the roots reported by independent providers have to agree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quorum {
+    /// At least the given number of providers.
+    AtLeast(usize),
+    /// More than half of the providers.
+    Majority,
+    /// Every provider.
+    All,
+}
+
+impl Quorum {
+    /// Check if the given number of agreeing providers out of all reaches the quorum.
+    pub fn is_reached(&self, agreeing: usize, total: usize) -> bool {
+        match self {
+            Quorum::AtLeast(n) => agreeing >= *n,
+            Quorum::Majority => agreeing * 2 > total,
+            Quorum::All => agreeing == total,
+        }
+    }
+}
+
+/// Get the root agreed on by the quorum of providers.
+///
+/// `None` stands for a provider reporting no root, which agrees with no other.
+/// Returns `None` if no root reaches the quorum, or if conflicting roots do.
+pub fn agreed_root<const N: usize>(roots: &[Option<Hash<N>>], quorum: Quorum) -> Option<Hash<N>> {
+    let mut counts: HashMap<Hash<N>, usize> = HashMap::new();
+    roots
+        .iter()
+        .flatten()
+        .for_each(|root| *counts.entry(*root).or_default() += 1);
+    let mut reached = counts
+        .into_iter()
+        .filter(|(_, n)| quorum.is_reached(*n, roots.len()));
+    match (reached.next(), reached.next()) {
+        (Some((root, _)), None) => Some(root),
+        _ => None,
+    }
+}
+
+/// Verify a Merkle proof against the roots reported by multiple independent providers.
+///
+/// This only accepts if the proof leads to the root agreed on by the quorum,
+/// so that no single provider of proofs or roots has to be trusted.
+pub fn verify_proof_with_quorum<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    roots: &[Option<Hash<N>>],
+    leaf: &Hash<N>,
+    proof: Option<&Proof>,
+    quorum: Quorum,
+) -> bool {
+    match agreed_root(roots, quorum) {
+        None => false,
+        Some(root) => verify_proof(hasher, Some(&root), leaf, proof),
+    }
+}
+
+/// Verify a `ConsistencyProof` that the tree of `new_root` was derived from the tree of `old_root`.
+///
+/// Only `old_root` needs to be trusted: the nodes given are checked against it,
+/// then the updates are replayed on them and have to result in `new_root`.
+/// Proofs from trees with other than the built-in `NodeCodec`s do not verify.
+pub fn verify_consistency<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    old_root: Option<&Hash<N>>,
+    new_root: Option<&Hash<N>>,
+    proof: &ConsistencyProof<N>,
+    consistency: Consistency,
+) -> bool {
+    let replayed = match proof.codec {
+        node::CompactCodec::ID => replay_consistency::<H, node::CompactCodec, N>(
+            hasher,
+            old_root,
+            new_root,
+            proof,
+            consistency,
+        ),
+        node::FixedCodec::ID => replay_consistency::<H, node::FixedCodec, N>(
+            hasher,
+            old_root,
+            new_root,
+            proof,
+            consistency,
+        ),
+        _ => return false,
+    };
+    matches!(replayed, Ok(true))
+}
+
+/// Verify a `RemovalProof` that the key had the leaf of the proof in the tree of `old_root`,
+/// and that the tree of `new_root` was derived from it by removing the key alone.
+pub fn verify_removal<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    old_root: Option<&Hash<N>>,
+    new_root: Option<&Hash<N>>,
+    key: &Hash<N>,
+    proof: &RemovalProof<N>,
+) -> bool {
+    // inserting the leaf again leaves the old tree as it is only if the key had the leaf
+    let kept = ConsistencyProof {
+        updates: vec![(*key, Some(proof.leaf))],
+        ..proof.consistency.clone()
+    };
+    proof.consistency.updates == [(*key, None)]
+        && verify_proof(hasher, old_root, &proof.leaf, Some(&proof.proof))
+        && verify_consistency(hasher, old_root, old_root, &kept, Consistency::Any)
+        && verify_consistency(
+            hasher,
+            old_root,
+            new_root,
+            &proof.consistency,
+            Consistency::Any,
+        )
+}
+
+/// Verify an `UpdateProof` that the key it proves had `old_leaf` in the tree of `old_root`
+/// and has `new_leaf` in the tree of `new_root`, with every other entry unchanged.
+///
+/// Both proofs have to verify against their roots and be of the same siblings,
+/// which only the leaf of the one key differs in.
+pub fn verify_update<H: Hasher<N>, const N: usize>(hasher: &H, proof: &UpdateProof<N>) -> bool {
+    proof.old_proof == proof.new_proof
+        && verify_proof(
+            hasher,
+            Some(&proof.old_root),
+            &proof.old_leaf,
+            Some(&proof.old_proof),
+        )
+        && verify_proof(
+            hasher,
+            Some(&proof.new_root),
+            &proof.new_leaf,
+            Some(&proof.new_proof),
+        )
+}
+
+/// Replay the updates of a `ConsistencyProof` on the old tree rebuilt from its nodes,
+/// then compare the result with the new tree. Returns `false` if the updates are not allowed.
+fn replay_consistency<H: Hasher<N>, C: NodeCodec, const N: usize>(
+    hasher: &H,
+    old_root: Option<&Hash<N>>,
+    new_root: Option<&Hash<N>>,
+    proof: &ConsistencyProof<N>,
+    consistency: Consistency,
+) -> Result<bool> {
+    let mut tree = Monotree::<database::MemoryDB, H, C, N>::new("");
+    tree.hasher = hasher.clone();
+    if proof.key_len == 0 || proof.key_len > N {
+        return Err(Errors::ProofInvalid(
+            "replay_consistency(): key length out of range".to_string(),
+        ));
+    }
+    tree.traversal = proof.traversal;
+    tree.key_len = proof.key_len;
+    for (hash, bytes) in proof.nodes.iter() {
+        if hasher.digest_node(bytes) != *hash {
+            return Err(Errors::ProofInvalid(
+                "replay_consistency(): node hash mismatch".to_string(),
+            ));
+        }
+        tree.db.put(hash, bytes.to_vec())?;
+    }
+    if let Some(root) = old_root {
+        for (key, _) in proof.updates.iter() {
+            let path = tree.path(key).to_vec();
+            tree.path_nodes(root, Bits::new(&path), &mut HashMap::new())?;
+        }
+    }
+    for (key, leaf) in proof.updates.iter() {
+        let allowed = match (consistency, leaf) {
+            (Consistency::Any, _) => true,
+            (Consistency::UpdateOnly, leaf) => leaf.is_some(),
+            (Consistency::AppendOnly, leaf) => leaf.is_some() && tree.get(old_root, key)?.is_none(),
+        };
+        if !allowed {
+            return Ok(false);
+        }
+    }
+    let mut root = old_root.cloned();
+    for (key, leaf) in proof.updates.iter() {
+        root = match leaf {
+            Some(leaf) => tree.insert(root.as_ref(), key, leaf)?,
+            None => tree.remove(root.as_ref(), key)?,
+        };
     }
+    tree.same_tree(root.as_ref(), new_root)
 }
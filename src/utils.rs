@@ -1,9 +1,10 @@
 //! A module for implementing some helpful functions for `monotree`.
 use crate::*;
+use core::cmp;
+use core::ops::Range;
 use num::{NumCast, PrimInt};
+#[cfg(feature = "std")]
 use rand::Rng;
-use std::cmp;
-use std::ops::Range;
 
 #[macro_export]
 /// std::cmp::max() extension for use with multiple arguments.
@@ -78,21 +79,25 @@ pub fn cast<T: NumCast, U: NumCast>(n: T) -> U {
     NumCast::from(n).expect("cast(): Numcast")
 }
 
+#[cfg(feature = "std")]
 /// Generate a random byte based on `rand::random`.
 pub fn random_byte() -> u8 {
     rand::random::<u8>()
 }
 
+#[cfg(feature = "std")]
 /// Generate random bytes of the given length.
 pub fn random_bytes(n: usize) -> Vec<u8> {
     (0..n).map(|_| random_byte()).collect()
 }
 
+#[cfg(feature = "std")]
 /// Generate a random `Hash`, byte-array of `HASH_LEN` length.
 pub fn random_hash() -> Hash {
     slice_to_hash(&random_bytes(HASH_LEN))
 }
 
+#[cfg(feature = "std")]
 /// Generate a vector of random `Hash` with the given length.
 pub fn random_hashes(n: usize) -> Vec<Hash> {
     (0..n).map(|_| random_hash()).collect()
@@ -100,11 +105,65 @@ pub fn random_hashes(n: usize) -> Vec<Hash> {
 
 /// Get a fixed lenght byte-array or `Hash` from slice.
 pub fn slice_to_hash(slice: &[u8]) -> Hash {
-    let mut hash = [0x00; HASH_LEN];
+    slice_to_array(slice)
+}
+
+/// Get a byte-array of any length `N`, or `Hash<N>`, from slice.
+pub fn slice_to_array<const N: usize>(slice: &[u8]) -> Hash<N> {
+    let mut hash = [0x00; N];
     hash.copy_from_slice(slice);
     hash
 }
 
+#[cfg(feature = "serde")]
+/// Get a `Hash<N>` from bytes deserialized, failing with the error of the deserializer
+/// unless they are of length `N`.
+pub fn vec_to_array<E: serde::de::Error, const N: usize>(
+    bytes: Vec<u8>,
+) -> core::result::Result<Hash<N>, E> {
+    match bytes.len() == N {
+        true => Ok(slice_to_array(&bytes)),
+        false => Err(E::custom(format_args!(
+            "invalid length {}, expected {} bytes",
+            bytes.len(),
+            N
+        ))),
+    }
+}
+
+#[cfg(feature = "std")]
+/// Decode a `Hash<N>` from hex, with or without a leading `0x`,
+/// failing with `Errors::Decode` unless it is of `N` bytes.
+pub fn hex_to_hash<const N: usize>(hex: &str) -> Result<Hash<N>> {
+    let digits = hex.strip_prefix("0x").unwrap_or(hex);
+    match hex::decode(digits) {
+        Ok(bytes) if bytes.len() == N => Ok(slice_to_array(&bytes)),
+        Ok(bytes) => Err(Errors::Decode(format!(
+            "hex_to_hash(): {} bytes, expected {}",
+            bytes.len(),
+            N
+        ))),
+        Err(err) => Err(Errors::Decode(format!("hex_to_hash(): {}", err))),
+    }
+}
+
+#[cfg(feature = "std")]
+/// Encode a root in hex, or into an empty string for the root of an empty tree, `None`.
+pub fn root_to_hex<const N: usize>(root: Option<&Hash<N>>) -> String {
+    root.map(hex::encode).unwrap_or_default()
+}
+
+#[cfg(feature = "std")]
+/// Decode a root encoded by `root_to_hex()`, an empty string being `None`,
+/// failing with `Errors::Decode` unless it is of `N` bytes otherwise.
+pub fn hex_to_root<const N: usize>(hex: &str) -> Result<Option<Hash<N>>> {
+    match hex {
+        "" => Ok(None),
+        hex => hex_to_hash(hex).map(Some),
+    }
+}
+
+#[cfg(feature = "std")]
 /// Shuffle a slice using _Fisher-Yates_ algorithm.
 pub fn shuffle<T: Clone>(slice: &mut [T]) {
     let mut rng = rand::thread_rng();
@@ -143,8 +202,8 @@ where
 
 /// Get `i`-th bit from bytes slice. Index `i` starts from 0.
 pub fn bit<T: PrimInt + NumCast>(bytes: &[u8], i: T) -> bool {
-    let q = i.to_usize().expect("bit(): usize") / 8;
-    let r = i.to_u8().expect("bit(): u8") % 8;
+    let i = i.to_usize().expect("bit(): usize");
+    let (q, r) = (i / 8, i % 8);
     (bytes[q] >> (7 - r)) & 0x01 == 0x01
 }
 
@@ -192,6 +251,42 @@ pub fn int_to_bytes(number: u64) -> Vec<u8> {
     }
 }
 
+/// Encode a `u64` into variable-length bytes (unsigned `LEB128`, the least significant group first).
+pub fn int_to_varint(number: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_varint(&mut bytes, number);
+    bytes
+}
+
+/// Append a number encoded by `int_to_varint()` to the given bytes, with no allocation of its own.
+pub fn push_varint(bytes: &mut Vec<u8>, number: u64) {
+    let mut n = number;
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Decode variable-length bytes encoded by `int_to_varint()` at the beginning of the given slice.
+/// Returns the number and the length of bytes it occupied.
+pub fn varint_to_int(bytes: &[u8]) -> Result<(u64, usize)> {
+    let mut number = 0u64;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        number |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((number, i + 1));
+        }
+    }
+    Err(Errors::Decode(
+        "varint_to_int(): malformed varint".to_string(),
+    ))
+}
+
 /// Convert a Vec slice of bit or `bool` into a number as `usize`.
 pub fn bits_to_usize(bits: &[bool]) -> usize {
     let l = bits.len();
@@ -264,6 +359,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_varint() {
+        assert_eq!(int_to_varint(0), [0x00]);
+        assert_eq!(int_to_varint(127), [0x7f]);
+        assert_eq!(int_to_varint(256), [0x80, 0x02]);
+        assert_eq!(
+            int_to_varint(495790221665u64),
+            [0xe1, 0xd2, 0x99, 0xfb, 0xb6, 0x0e]
+        );
+        assert_eq!(varint_to_int(&[0x80, 0x02, 0xff]).unwrap(), (256, 2));
+        assert_eq!(
+            varint_to_int(&int_to_varint(u64::MAX)).unwrap(),
+            (u64::MAX, 10)
+        );
+        assert!(varint_to_int(&[0x80, 0x80]).is_err());
+    }
+
     #[test]
     fn test_bytes_to_bits() {
         assert_eq!(
@@ -0,0 +1,295 @@
+//! A module generating test vectors of `monotree` for implementations in other languages.
+//!
+//! Every vector is derived from a counter only, so the same vectors are generated on every run.
+//! Serialized into JSON by `TestVector::to_json()`, hashes and cut-out bytes are given in hex.
+//!
+//! Fixtures in the same schema, such as those produced by implementations in other languages,
+//! are loaded by `load()` and checked against this implementation by `check()` byte for byte.
+use crate::database::MemoryDB;
+use crate::hasher::*;
+use crate::utils::*;
+use crate::*;
+use serde_json::{json, Value};
+
+/// Tags prepended to a counter to derive keys and leaves of a vector.
+const KEY_TAG: &[u8] = b"monotree-vector-key";
+const LEAF_TAG: &[u8] = b"monotree-vector-leaf";
+
+/// Orders of traversal every vector is generated for.
+pub const TRAVERSALS: [Traversal; 4] = [
+    Traversal {
+        bits: BitOrder::MsbFirst,
+        bytes: ByteOrder::BigEndian,
+    },
+    Traversal {
+        bits: BitOrder::LsbFirst,
+        bytes: ByteOrder::BigEndian,
+    },
+    Traversal {
+        bits: BitOrder::MsbFirst,
+        bytes: ByteOrder::LittleEndian,
+    },
+    Traversal {
+        bits: BitOrder::LsbFirst,
+        bytes: ByteOrder::LittleEndian,
+    },
+];
+
+/// A test vector: entries inserted into an empty tree, the expected root and a proof of each key,
+/// then keys removed one by one from that root, with the expected root after each removal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestVector {
+    /// Name of the hasher the tree is built with, e.g. `blake3`.
+    pub hasher: String,
+    pub traversal: Traversal,
+    pub keys: Vec<Hash>,
+    pub leaves: Vec<Hash>,
+    pub root: Option<Hash>,
+    /// Merkle proofs of `keys` in order, verified against `root` with `verify_proof()`.
+    pub proofs: Vec<Proof>,
+    /// Keys removed in order from the tree of `root`, every third one of `keys`.
+    pub removes: Vec<Hash>,
+    /// Roots after each of `removes` in order.
+    pub removed_roots: Vec<Option<Hash>>,
+}
+
+impl TestVector {
+    /// Serialize `TestVector` into JSON.
+    ///
+    /// A proof is an array of `[right, cut]`, where `cut` is the node bytes
+    /// with the hash on the path cut out, which goes right before its last byte if `right`.
+    pub fn to_json(&self) -> Value {
+        let hashes = |hashes: &[Hash]| hashes.iter().map(hex::encode).collect::<Vec<_>>();
+        json!({
+            "hasher": self.hasher,
+            "traversal": {
+                "bits": format!("{:?}", self.traversal.bits),
+                "bytes": format!("{:?}", self.traversal.bytes),
+            },
+            "keys": hashes(&self.keys),
+            "leaves": hashes(&self.leaves),
+            "root": self.root.map(hex::encode),
+            "proofs": self.proofs.iter().map(Proof::to_json).collect::<Vec<_>>(),
+            "removes": hashes(&self.removes),
+            "removed_roots": self
+                .removed_roots
+                .iter()
+                .map(|root| root.map(hex::encode))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Construct `TestVector` by deserializing JSON in the schema of `to_json()`.
+    /// Fixtures without `removes` and `removed_roots` are read as removing no key.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let invalid =
+            |field: &str| Errors::Decode(format!("TestVector::from_json(): invalid {}", field));
+        let hash = |value: &Value, field: &str| match value.as_str().map(hex::decode) {
+            Some(Ok(bytes)) if bytes.len() == HASH_LEN => Ok(slice_to_hash(&bytes)),
+            _ => Err(invalid(field)),
+        };
+        let hashes = |field: &str| match json[field].as_array() {
+            Some(values) => values.iter().map(|value| hash(value, field)).collect(),
+            None => Err(invalid(field)),
+        };
+        let optional = |value: &Value, field: &str| match value {
+            Value::Null => Ok(None),
+            value => hash(value, field).map(Some),
+        };
+        let bits = match json["traversal"]["bits"].as_str() {
+            Some("MsbFirst") => BitOrder::MsbFirst,
+            Some("LsbFirst") => BitOrder::LsbFirst,
+            _ => return Err(invalid("traversal")),
+        };
+        let bytes = match json["traversal"]["bytes"].as_str() {
+            Some("BigEndian") => ByteOrder::BigEndian,
+            Some("LittleEndian") => ByteOrder::LittleEndian,
+            _ => return Err(invalid("traversal")),
+        };
+        let root = optional(&json["root"], "root")?;
+        let proof = |value: &Value| Proof::from_json(value).map_err(|_| invalid("proofs"));
+        let proofs = match json["proofs"].as_array() {
+            Some(values) => values.iter().map(proof).collect::<Result<_>>()?,
+            None => return Err(invalid("proofs")),
+        };
+        let (removes, removed_roots) = match (&json["removes"], &json["removed_roots"]) {
+            (Value::Null, Value::Null) => (vec![], vec![]),
+            (_, Value::Array(roots)) => (
+                hashes("removes")?,
+                roots
+                    .iter()
+                    .map(|root| optional(root, "removed_roots"))
+                    .collect::<Result<_>>()?,
+            ),
+            _ => return Err(invalid("removed_roots")),
+        };
+        Ok(TestVector {
+            hasher: json["hasher"]
+                .as_str()
+                .ok_or_else(|| invalid("hasher"))?
+                .to_string(),
+            traversal: Traversal { bits, bytes },
+            keys: hashes("keys")?,
+            leaves: hashes("leaves")?,
+            root,
+            proofs,
+            removes,
+            removed_roots,
+        })
+    }
+}
+
+/// Generate a vector of `n` entries for the given hasher and order of traversal.
+pub fn generate<H: Hasher>(name: &str, traversal: Traversal, n: usize) -> Result<TestVector> {
+    let hasher = H::new();
+    let derive = |tag: &[u8], i: usize| hasher.digest(&[tag, &(i as u64).to_be_bytes()].concat());
+    let keys: Vec<Hash> = (0..n).map(|i| derive(KEY_TAG, i)).collect();
+    let leaves: Vec<Hash> = (0..n).map(|i| derive(LEAF_TAG, i)).collect();
+
+    let mut tree = Monotree::<MemoryDB, H>::try_new("")?;
+    tree.set_traversal(traversal)?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let proofs = keys
+        .iter()
+        .map(|key| {
+            let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+            proof.ok_or_else(|| Errors::new("generate(): missing proof"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let removes: Vec<Hash> = keys.iter().step_by(3).cloned().collect();
+    let removed_roots = removals(&mut tree, root, &removes)?;
+    Ok(TestVector {
+        hasher: name.to_string(),
+        traversal,
+        keys,
+        leaves,
+        root,
+        proofs,
+        removes,
+        removed_roots,
+    })
+}
+
+/// Remove keys one by one from the tree of `root`, giving the root after each removal.
+fn removals<H: Hasher>(
+    tree: &mut Monotree<MemoryDB, H>,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+) -> Result<Vec<Option<Hash>>> {
+    keys.iter()
+        .map(|key| {
+            root = tree.remove(root.as_ref(), key)?;
+            Ok(root)
+        })
+        .collect()
+}
+
+/// Generate vectors of `n` entries for every hasher enabled and every order in `TRAVERSALS`.
+pub fn generate_all(n: usize) -> Result<Vec<TestVector>> {
+    let mut vectors = Vec::new();
+    for traversal in TRAVERSALS.iter().cloned() {
+        #[cfg(feature = "hasher-blake3")]
+        vectors.push(generate::<Blake3>("blake3", traversal, n)?);
+        #[cfg(feature = "hasher-blake2")]
+        vectors.push(generate::<Blake2s>("blake2s", traversal, n)?);
+        #[cfg(feature = "hasher-blake2")]
+        vectors.push(generate::<Blake2b>("blake2b", traversal, n)?);
+        #[cfg(feature = "hasher-sha2")]
+        vectors.push(generate::<Sha2>("sha2", traversal, n)?);
+        #[cfg(feature = "hasher-sha2")]
+        vectors.push(generate::<Sha256d>("sha256d", traversal, n)?);
+        #[cfg(feature = "hasher-sha3")]
+        vectors.push(generate::<Sha3>("sha3", traversal, n)?);
+        #[cfg(feature = "hasher-poseidon")]
+        vectors.push(generate::<PoseidonBn254>("poseidon-bn254", traversal, n)?);
+        #[cfg(feature = "hasher-poseidon")]
+        vectors.push(generate::<PoseidonBls12381>(
+            "poseidon-bls12-381",
+            traversal,
+            n,
+        )?);
+    }
+    Ok(vectors)
+}
+
+/// Serialize vectors into a JSON array of `TestVector::to_json()`, the schema `load()` reads.
+pub fn export(vectors: &[TestVector]) -> Value {
+    Value::Array(vectors.iter().map(TestVector::to_json).collect())
+}
+
+/// Load vectors from JSON, either an array of them or a single one.
+pub fn load(json: &str) -> Result<Vec<TestVector>> {
+    let json: Value = serde_json::from_str(json)?;
+    match json.as_array() {
+        Some(values) => values.iter().map(TestVector::from_json).collect(),
+        None => Ok(vec![TestVector::from_json(&json)?]),
+    }
+}
+
+/// Check a vector against this implementation, by the hasher it is named with.
+///
+/// The root and every proof have to be the same bytes as those this implementation generates
+/// from the entries, every proof has to verify against the root, and so do the roots after
+/// removals have to be the same as this implementation gives. Fails with
+/// `Errors::ProofInvalid` telling the first mismatch, or with `Errors::Other` for hashers
+/// not enabled.
+pub fn check(vector: &TestVector) -> Result<()> {
+    match vector.hasher.as_str() {
+        #[cfg(feature = "hasher-blake3")]
+        "blake3" => check_with::<Blake3>(vector),
+        #[cfg(feature = "hasher-blake2")]
+        "blake2s" => check_with::<Blake2s>(vector),
+        #[cfg(feature = "hasher-blake2")]
+        "blake2b" => check_with::<Blake2b>(vector),
+        #[cfg(feature = "hasher-sha2")]
+        "sha2" => check_with::<Sha2>(vector),
+        #[cfg(feature = "hasher-sha2")]
+        "sha256d" => check_with::<Sha256d>(vector),
+        #[cfg(feature = "hasher-sha3")]
+        "sha3" => check_with::<Sha3>(vector),
+        #[cfg(feature = "hasher-poseidon")]
+        "poseidon-bn254" => check_with::<PoseidonBn254>(vector),
+        #[cfg(feature = "hasher-poseidon")]
+        "poseidon-bls12-381" => check_with::<PoseidonBls12381>(vector),
+        name => Err(Errors::new(&format!(
+            "check(): hasher {} not enabled",
+            name
+        ))),
+    }
+}
+
+/// Check a vector against this implementation with the given hasher, see `check()`.
+pub fn check_with<H: Hasher>(vector: &TestVector) -> Result<()> {
+    let mismatch = |what: &str| Err(Errors::ProofInvalid(format!("check(): {}", what)));
+    let n = vector.keys.len();
+    if vector.leaves.len() != n || vector.proofs.len() != n {
+        return mismatch("numbers of keys, leaves and proofs differ");
+    }
+    if vector.removes.len() != vector.removed_roots.len() {
+        return mismatch("numbers of removes and removed roots differ");
+    }
+    let mut tree = Monotree::<MemoryDB, H>::try_new("")?;
+    tree.set_traversal(vector.traversal)?;
+    let root = tree.inserts(None, &vector.keys, &vector.leaves)?;
+    if root != vector.root {
+        return mismatch("root mismatch");
+    }
+    for (i, key) in vector.keys.iter().enumerate() {
+        let proof = &vector.proofs[i];
+        if tree.get_merkle_proof(root.as_ref(), key)?.as_ref() != Some(proof) {
+            return mismatch(&format!("proof mismatch of key {}", hex::encode(key)));
+        }
+        if !verify_proof(&tree.hasher, root.as_ref(), &vector.leaves[i], Some(proof)) {
+            return mismatch(&format!("proof of key {} not verified", hex::encode(key)));
+        }
+    }
+    let removed_roots = removals(&mut tree, root, &vector.removes)?;
+    match removed_roots
+        .iter()
+        .zip(&vector.removed_roots)
+        .position(|(a, b)| a != b)
+    {
+        Some(i) => mismatch(&format!("root mismatch after removes[{}]", i)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,260 @@
+//! A module verifying proofs of `monotree` with a hasher alone, with neither a tree nor a database.
+//!
+//! This is the core built with `no_std` + `alloc` when the feature `std` is off,
+//! along with the decoding of nodes, for light clients on embedded devices or in enclaves.
+use crate::*;
+
+/// Verify a Merkle proof with the given root, leaf and hasher if the proof is valid or not.
+///
+/// Be aware of that it fails if not provided a suitable hasher used in the tree
+/// This generic fn must be independantly called upon request, not a member of Monotree.
+/// It needs neither a tree nor a database, so light clients verify proofs by a hasher alone,
+/// such as `Blake3::new()`. Proofs against no root, as well as malformed ones, fail to verify.
+pub fn verify_proof<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    root: Option<&Hash<N>>,
+    leaf: &Hash<N>,
+    proof: Option<&Proof>,
+) -> bool {
+    match (root, compute_root(hasher, leaf, proof)) {
+        (Some(root), Some(hash)) => root == &hash,
+        _ => false,
+    }
+}
+
+/// Verify a Merkle proof that a key has the given value in the tree of the root,
+/// folding the value into the leaf by the policy the tree was built with.
+pub fn verify_value_proof<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    leaf_policy: LeafPolicy,
+    root: Option<&Hash<N>>,
+    key: &Hash<N>,
+    value: &[u8],
+    proof: Option<&Proof>,
+) -> bool {
+    match leaf_policy.leaf(hasher, key, value) {
+        Ok(leaf) => root.is_some() && verify_proof(hasher, root, &leaf, proof),
+        Err(_) => false,
+    }
+}
+
+/// Verify a `MultiProof` that the given keys are in the tree of the root with the given leaves.
+///
+/// The root is recomputed by walking down the nodes in the proof along the keys,
+/// filling the hashes cut out with the leaves or the hashes of descendant nodes.
+/// For a tree traversing keys in other than the default order, give the keys rearranged
+/// by `Traversal::arrange()`, and cut to `Monotree::key_len()` for a tree of shorter keys.
+pub fn verify_merkle_proofs<H: Hasher<N>, K: AsRef<[u8]>, const N: usize>(
+    hasher: &H,
+    root: Option<&Hash<N>>,
+    keys: &[K],
+    leaves: &[Hash<N>],
+    proof: Option<&MultiProof>,
+) -> bool {
+    let (root, proof) = match (root, proof) {
+        (Some(root), Some(proof)) if keys.len() == leaves.len() => (root, proof),
+        _ => return false,
+    };
+    let mut entries: Vec<(&[u8], &Hash<N>)> = keys
+        .iter()
+        .map(|key| key.as_ref())
+        .zip(leaves.iter())
+        .collect();
+    entries.sort();
+    entries.dedup();
+    if entries.windows(2).any(|pair| pair[0].0 == pair[1].0)
+        || entries.iter().any(|(key, _)| key.is_empty())
+    {
+        return false;
+    }
+    if entries.is_empty() {
+        return proof.is_empty();
+    }
+    let entries: Vec<(Bits, &Hash<N>)> = entries
+        .iter()
+        .map(|(key, leaf)| (Bits::new(key), *leaf))
+        .collect();
+    let mut nodes = proof.iter();
+    match replay_multiproof(hasher, &mut nodes, &entries) {
+        Some(hash) => &hash == root && nodes.next().is_none(),
+        None => false,
+    }
+}
+
+fn replay_multiproof<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    nodes: &mut core::slice::Iter<Vec<u8>>,
+    entries: &[(Bits, &Hash<N>)],
+) -> Option<Hash<N>> {
+    let (mask, cut) = nodes.next()?.split_first()?;
+    if mask & !0x03 != 0 || cut.is_empty() {
+        return None;
+    }
+    let (body, indicator) = cut.split_at(cut.len() - 1);
+    let placeholder = [0u8; N];
+    let lh: &[u8] = if mask & 0x01 != 0 { &placeholder } else { &[] };
+    let rh: &[u8] = if mask & 0x02 != 0 { &placeholder } else { &[] };
+    let bytes = [lh, body, rh, indicator].concat();
+    let (lc, rc) = Node::<N>::cells_from_bytes(&bytes, false).ok()?;
+    let hard = rc.is_some();
+    let mut hashes: [Option<Hash<N>>; 2] = [None, None];
+    for (i, cell) in [lc, rc].iter().enumerate() {
+        let part: Vec<&(Bits, &Hash<N>)> = entries
+            .iter()
+            .filter(|(b, _)| if hard { b.first() == (i == 1) } else { i == 0 })
+            .collect();
+        if part.is_empty() != (mask & (1 << i) == 0) {
+            return None;
+        }
+        if part.is_empty() {
+            continue;
+        }
+        let unit = cell.as_ref()?;
+        // cells of no bits never lead anywhere, and would only deepen the recursion
+        let n = unit.bits.len();
+        if n == 0 {
+            return None;
+        }
+        if part
+            .iter()
+            .any(|(b, _)| Bits::len_common_bits(&unit.bits, b) != n)
+        {
+            return None;
+        }
+        hashes[i] = match part.as_slice() {
+            [(b, leaf)] if b.len() == n => Some(**leaf),
+            _ if part.iter().any(|(b, _)| b.len() == n) => return None,
+            _ => {
+                let part: Vec<(Bits, &Hash<N>)> = part
+                    .iter()
+                    .map(|(b, leaf)| (b.shift(n, false), *leaf))
+                    .collect();
+                Some(replay_multiproof(hasher, nodes, &part)?)
+            }
+        };
+    }
+    let hash = |hash: &Option<Hash<N>>| hash.map(|h| h.to_vec()).unwrap_or_default();
+    let o = [&hash(&hashes[0])[..], body, &hash(&hashes[1]), indicator].concat();
+    Some(hasher.digest_node(&o))
+}
+
+/// Verify a proof that the given key is not in the tree of the root.
+///
+/// Every node in the proof has to hash to the one the previous node points to along the key,
+/// and the key has to diverge in the last node.
+/// For a tree traversing keys in other than the default order, give the key rearranged
+/// by `Traversal::arrange()`, and cut to `Monotree::key_len()` for a tree of shorter keys.
+pub fn verify_non_inclusion_proof<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    root: Option<&Hash<N>>,
+    key: &[u8],
+    proof: Option<&NonInclusionProof>,
+) -> bool {
+    let (root, proof) = match (root, proof) {
+        (None, Some(proof)) => return proof.is_empty(),
+        (Some(root), Some(proof)) if !key.is_empty() => (root, proof),
+        _ => return false,
+    };
+    let mut hash = root.to_vec();
+    let mut bits = Bits::new(key);
+    for (i, bytes) in proof.iter().enumerate() {
+        if hasher.digest_node(bytes)[..] != hash[..] {
+            return false;
+        }
+        let unit = match Node::<N>::cells_from_bytes(bytes, bits.first()) {
+            Ok((Some(unit), _)) => unit,
+            _ => return false,
+        };
+        let n = Bits::len_common_bits(&unit.bits, &bits);
+        match n {
+            n if n == bits.len() => return false,
+            n if n == unit.bits.len() => {
+                hash = unit.hash.to_vec();
+                bits = bits.shift(n, false);
+            }
+            _ => return i == proof.len() - 1,
+        }
+    }
+    false
+}
+
+/// A proof of the leaf a key has, given by `Monotree::get_leaf_proof()`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LeafProof {
+    /// A Merkle proof of the key in the tree with its leaf.
+    Inclusion(Proof),
+    /// A proof of the key absent from the tree, having the default leaf, if any.
+    Absence(NonInclusionProof),
+}
+
+/// Verify a `LeafProof` that a key has the given leaf in the tree of the root, where keys absent
+/// have `default_leaf`, or `None` for them to be absent only, as set by `Monotree::set_default_leaf()`.
+///
+/// Keys absent are proven to have the default leaf, and `None` for no default leaf,
+/// while keys in the tree never have it. As for `verify_non_inclusion_proof()`,
+/// give the key rearranged for a tree traversing keys in other than the default order.
+pub fn verify_leaf_proof<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    root: Option<&Hash<N>>,
+    key: &[u8],
+    leaf: Option<&Hash<N>>,
+    default_leaf: Option<&Hash<N>>,
+    proof: &LeafProof,
+) -> bool {
+    match (proof, leaf) {
+        (LeafProof::Inclusion(proof), Some(leaf)) if Some(leaf) != default_leaf => {
+            verify_proof(hasher, root, leaf, Some(proof))
+        }
+        (LeafProof::Absence(proof), leaf) if leaf == default_leaf => {
+            verify_non_inclusion_proof(hasher, root, key, Some(proof))
+        }
+        _ => false,
+    }
+}
+
+/// Reconstruct the root a Merkle proof binds the given leaf to.
+///
+/// Unlike `verify_proof()`, this does not need a root, so that the result can be
+/// compared against several candidate roots. Returns `None` if no proof is given,
+/// or if a step on the right has nothing to fold the hash into.
+pub fn compute_root<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    leaf: &Hash<N>,
+    proof: Option<&Proof>,
+) -> Option<Hash<N>> {
+    let proof = proof?;
+    if proof.iter().any(|(right, cut)| *right && cut.is_empty()) {
+        return None;
+    }
+    let mut hash = leaf.to_owned();
+    proof.iter().rev().for_each(|(right, cut)| {
+        if *right {
+            let l = cut.len();
+            let o = [&cut[..l - 1], &hash[..], &cut[l - 1..]].concat();
+            hash = hasher.digest_node(&o);
+        } else {
+            let o = [&hash[..], &cut[..]].concat();
+            hash = hasher.digest_node(&o);
+        }
+    });
+    Some(hash)
+}
+
+/// Verify a `ChainedProof` binding the leaf of the innermost tree to the root of the outermost tree.
+pub fn verify_chained_proof<H: Hasher<N>, const N: usize>(
+    hasher: &H,
+    root: Option<&Hash<N>>,
+    leaf: &Hash<N>,
+    proof: Option<&ChainedProof>,
+) -> bool {
+    match proof {
+        None => false,
+        Some(chain) => {
+            let hash = chain.iter().rev().try_fold(*leaf, |hash, proof| {
+                compute_root(hasher, &hash, Some(proof))
+            });
+            hash.as_ref() == root
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! A module serving partial views of trees of `monotree` by labels of leaves.
+//!
+//! Keys are tagged with visibility labels by `set_labels()`, recorded in database apart from
+//! the tree, so that labels change no root. A `View` holds only the entries carrying any of
+//! the labels of a client, with a `MultiProof` binding them to the full root,
+//! where the rest of the tree appears only as hashes.
+use crate::*;
+
+/// Tag prepended to a key to derive the key its labels are stored under.
+const LABEL_TAG: u8 = 0xfc;
+
+/// Entries of a tree visible to a set of labels, returned by `view()`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct View {
+    /// The root of the full tree.
+    pub root: Option<Hash>,
+    /// Visible entries as `(key, leaf)`, in the order of `Monotree::iter()`.
+    pub entries: Vec<(Hash, Hash)>,
+    /// A `MultiProof` of `entries`, or `None` for an empty tree.
+    pub proof: Option<MultiProof>,
+    /// The order the tree traverses keys in.
+    pub traversal: Traversal,
+    /// Number of bytes of keys the tree traverses.
+    pub key_len: usize,
+}
+
+/// Get the key the labels of a key are stored under.
+pub fn label_key<D, H, C>(tree: &Monotree<D, H, C>, key: &Hash) -> Hash
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    tree.hasher
+        .digest(&[&[LABEL_TAG], &tree.filter_key(key)[..]].concat())
+}
+
+/// Tag a key with the given labels, replacing those set before. No labels untag the key.
+///
+/// Keys sharing the leading `Monotree::key_len()` bytes share their labels as well.
+pub fn set_labels<D, H, C>(tree: &mut Monotree<D, H, C>, key: &Hash, labels: &[&str]) -> Result<()>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let mut labels = labels.to_vec();
+    labels.sort_unstable();
+    labels.dedup();
+    let mut bytes = Vec::new();
+    for label in labels.iter() {
+        if label.is_empty() || label.len() > u8::MAX as usize {
+            return Err(Errors::new("set_labels(): label of 1 to 255 bytes"));
+        }
+        bytes.push(label.len() as u8);
+        bytes.extend_from_slice(label.as_bytes());
+    }
+    let label_key = label_key(tree, key);
+    if bytes.is_empty() {
+        tree.db.delete(&label_key)
+    } else {
+        tree.db.put(&label_key, bytes)
+    }
+}
+
+/// Get the labels a key is tagged with, in order.
+pub fn labels<D, H, C>(tree: &mut Monotree<D, H, C>, key: &Hash) -> Result<Vec<String>>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let label_key = label_key(tree, key);
+    let bytes = match tree.db.get(&label_key)? {
+        Some(bytes) => bytes,
+        None => return Ok(Vec::new()),
+    };
+    let mut labels = Vec::new();
+    let mut rest = &bytes[..];
+    while let Some((&len, tail)) = rest.split_first() {
+        let (label, tail) = match tail.get(..len as usize) {
+            Some(label) => (label, &tail[len as usize..]),
+            None => return Err(Errors::Decode("labels(): truncated".to_string())),
+        };
+        match std::str::from_utf8(label) {
+            Ok(label) => labels.push(label.to_string()),
+            Err(_) => return Err(Errors::Decode("labels(): not utf-8".to_string())),
+        }
+        rest = tail;
+    }
+    Ok(labels)
+}
+
+/// Get the view of the tree of the given root to a client of the given labels.
+///
+/// Entries whose keys carry any of the labels are visible, while untagged keys are visible
+/// to no one. Walks every entry of the tree to find those visible.
+pub fn view<D, H, C>(
+    tree: &mut Monotree<D, H, C>,
+    root: Option<&Hash>,
+    labels: &[&str],
+) -> Result<View>
+where
+    D: Database,
+    H: Hasher,
+    C: NodeCodec,
+{
+    let entries = tree.iter(root).collect::<Result<Vec<_>>>()?;
+    let mut visible = Vec::new();
+    for (key, leaf) in entries {
+        let tags = self::labels(tree, &key)?;
+        if tags.iter().any(|tag| labels.contains(&tag.as_str())) {
+            visible.push((key, leaf));
+        }
+    }
+    let keys: Vec<Hash> = visible.iter().map(|(key, _)| *key).collect();
+    let proof = tree.get_merkle_proofs(root, &keys)?;
+    Ok(View {
+        root: root.cloned(),
+        entries: visible,
+        proof,
+        traversal: tree.traversal(),
+        key_len: tree.key_len(),
+    })
+}
+
+/// Verify that the entries of a `View` are in the tree of its root.
+///
+/// Only the entries given are verified: a view cannot prove no other entry is visible.
+pub fn verify_view<H: Hasher>(hasher: &H, view: &View) -> bool {
+    if view.key_len == 0 || view.key_len > HASH_LEN {
+        return false;
+    }
+    let keys: Vec<Vec<u8>> = view
+        .entries
+        .iter()
+        .map(|(key, _)| view.traversal.arrange(key)[..view.key_len].to_vec())
+        .collect();
+    let leaves: Vec<Hash> = view.entries.iter().map(|(_, leaf)| *leaf).collect();
+    verify_merkle_proofs(
+        hasher,
+        view.root.as_ref(),
+        &keys,
+        &leaves,
+        view.proof.as_ref(),
+    )
+}
@@ -0,0 +1,346 @@
+//! A module exporting proof verification and in-memory trees of `monotree` to JavaScript.
+//!
+//! Built for `wasm32-unknown-unknown` by `wasm-pack` with the feature `wasm` alone,
+//! as in `wasm-pack build -- --no-default-features --features wasm`, so that web light clients
+//! verify the proofs served by `Monotree` with this very code rather than a port of it.
+//! Hashes cross as `Uint8Array`s of `HASH_LEN`, hashed by `DefaultHasher`,
+//! and proofs as those of the bytes of `ProofBytes::to_bytes()`.
+//!
+//! Trees are kept in memory, or persisted across sessions in `IndexedDB` of the browser
+//! by `Monotree.withIndexedDb(await openIndexedDb(name))`, so that light clients keep
+//! their own keys and the nodes along them instead of refetching proofs on every load.
+use crate::database::{Entries, MemoryDB};
+use crate::utils::*;
+use crate::*;
+use wasm_bindgen::prelude::*;
+
+/// Get a `Hash` from bytes given by JavaScript, or an empty array for `None`.
+fn hash_from_js(bytes: &[u8]) -> std::result::Result<Option<Hash>, JsValue> {
+    match bytes.len() {
+        0 => Ok(None),
+        HASH_LEN => Ok(Some(slice_to_hash(bytes))),
+        n => Err(JsValue::from_str(&format!(
+            "expected {} bytes of a hash, got {}",
+            HASH_LEN, n
+        ))),
+    }
+}
+
+fn to_js(err: Errors) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
+/// Verify a proof encoded by `ProofBytes::to_bytes()` that the leaf is in the tree of the root.
+/// Malformed input fails to verify rather than throwing.
+#[wasm_bindgen(js_name = verifyProof)]
+pub fn verify_proof(root: &[u8], leaf: &[u8], proof: &[u8]) -> bool {
+    let (root, leaf) = match (hash_from_js(root), hash_from_js(leaf)) {
+        (Ok(Some(root)), Ok(Some(leaf))) => (root, leaf),
+        _ => return false,
+    };
+    match Proof::from_bytes(proof) {
+        Ok(proof) => verify::verify_proof(&DefaultHasher::new(), Some(&root), &leaf, Some(&proof)),
+        Err(_) => false,
+    }
+}
+
+#[wasm_bindgen(inline_js = r#"
+export function idbOpen(name) {
+  return new Promise((resolve, reject) => {
+    const request = indexedDB.open(name, 1);
+    request.onupgradeneeded = () => request.result.createObjectStore("entries");
+    request.onerror = () => reject(request.error);
+    request.onsuccess = () => {
+      const handle = { db: request.result, entries: [], pending: [], error: undefined };
+      const cursor = handle.db.transaction("entries").objectStore("entries").openCursor();
+      cursor.onerror = () => reject(cursor.error);
+      cursor.onsuccess = () => {
+        const entry = cursor.result;
+        if (!entry) return resolve(handle);
+        handle.entries.push([new Uint8Array(entry.key), new Uint8Array(entry.value)]);
+        entry.continue();
+      };
+    };
+  });
+}
+export function idbEntries(handle) {
+  const size = handle.entries.reduce((n, [key, value]) => n + 8 + key.length + value.length, 0);
+  const bytes = new Uint8Array(size);
+  const view = new DataView(bytes.buffer);
+  let i = 0;
+  for (const entry of handle.entries) {
+    for (const part of entry) {
+      view.setUint32(i, part.length);
+      bytes.set(part, i + 4);
+      i += 4 + part.length;
+    }
+  }
+  handle.entries = [];
+  return bytes;
+}
+export function idbPut(handle, key, value) { handle.pending.push([key.slice().buffer, value.slice()]); }
+export function idbDelete(handle, key) { handle.pending.push([key.slice().buffer, undefined]); }
+export function idbDiscard(handle) { handle.pending = []; }
+export function idbCommit(handle) {
+  if (handle.pending.length === 0) return;
+  const tx = handle.db.transaction("entries", "readwrite");
+  const store = tx.objectStore("entries");
+  for (const [key, value] of handle.pending) {
+    if (value === undefined) store.delete(key); else store.put(value, key);
+  }
+  handle.pending = [];
+  tx.onabort = () => { handle.error = String(tx.error); };
+}
+export function idbTakeError(handle) {
+  const error = handle.error;
+  handle.error = undefined;
+  return error;
+}
+"#)]
+extern "C" {
+    /// A database of `IndexedDB` opened by `openIndexedDb()`, with the entries read from it.
+    pub type IdbHandle;
+    #[wasm_bindgen(js_name = idbOpen)]
+    fn idb_open(name: &str) -> JsValue;
+    /// Take the entries read as `len`(4, big-endian) + `key`(`len`) + `len` + `value`(`len`) each.
+    #[wasm_bindgen(js_name = idbEntries)]
+    fn idb_entries(handle: &IdbHandle) -> Vec<u8>;
+    #[wasm_bindgen(js_name = idbPut)]
+    fn idb_put(handle: &IdbHandle, key: &[u8], value: &[u8]);
+    #[wasm_bindgen(js_name = idbDelete)]
+    fn idb_delete(handle: &IdbHandle, key: &[u8]);
+    #[wasm_bindgen(js_name = idbDiscard)]
+    fn idb_discard(handle: &IdbHandle);
+    /// Write the pending writes in a single transaction, completing in the background.
+    #[wasm_bindgen(js_name = idbCommit)]
+    fn idb_commit(handle: &IdbHandle);
+    /// Take the failure of a transaction since the last call, if any.
+    #[wasm_bindgen(js_name = idbTakeError)]
+    fn idb_take_error(handle: &IdbHandle) -> Option<String>;
+}
+
+/// Open a database of `IndexedDB` by name, creating it if missing, and read all of its entries.
+/// Returns a `Promise` of the handle taken by `Monotree.withIndexedDb()`.
+#[wasm_bindgen(js_name = openIndexedDb)]
+pub fn open_indexed_db(name: &str) -> JsValue {
+    idb_open(name)
+}
+
+/// A database persisting entries in `IndexedDB` of the browser, across sessions of a light client.
+///
+/// As `IndexedDB` is read only asynchronously, every entry is read into memory on opening
+/// by `openIndexedDb()`, and served from there. Writes are applied in memory, then each batch,
+/// or each write outside a batch, is written to `IndexedDB` in a single transaction completing
+/// in the background, whose failure is returned by the next `finish_batch()` or `flush()`.
+///
+/// Constructed by `try_new()`, with no handle, entries are kept in memory alone.
+pub struct IndexedDb {
+    memory: MemoryDB,
+    handle: Option<IdbHandle>,
+    batch_on: bool,
+}
+
+impl IndexedDb {
+    /// Construct `IndexedDb` over a handle of `openIndexedDb()`, starting with the entries read.
+    pub fn with_handle(handle: IdbHandle) -> Result<Self> {
+        let bytes = idb_entries(&handle);
+        let mut memory = MemoryDB::new("");
+        let mut i = 0;
+        while i < bytes.len() {
+            let key = read_part(&bytes, &mut i)?;
+            let value = read_part(&bytes, &mut i)?;
+            memory.put(&key, value)?;
+        }
+        Ok(IndexedDb {
+            memory,
+            handle: Some(handle),
+            batch_on: false,
+        })
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        match self.handle.as_ref().and_then(idb_take_error) {
+            Some(error) => Err(Errors::Db(format!("IndexedDb: {}", error))),
+            None => {
+                self.handle.iter().for_each(idb_commit);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Read a part of the entries of `idb_entries()` at `i`, moving `i` past it.
+fn read_part(bytes: &[u8], i: &mut usize) -> Result<Vec<u8>> {
+    let truncated = || Errors::Decode("IndexedDb: truncated entry".to_string());
+    let len: usize = bytes_to_int(bytes.get(*i..*i + 4).ok_or_else(truncated)?);
+    let part = bytes.get(*i + 4..*i + 4 + len).ok_or_else(truncated)?;
+    *i += 4 + len;
+    Ok(part.to_vec())
+}
+
+impl Database for IndexedDb {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(IndexedDb {
+            memory: MemoryDB::try_new(dbpath)?,
+            handle: None,
+            batch_on: false,
+        })
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.memory.get(key)
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        if let Some(handle) = self.handle.as_ref() {
+            idb_put(handle, key, &value);
+        }
+        self.memory.put(key, value)?;
+        if self.batch_on {
+            return Ok(());
+        }
+        self.commit()
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        if let Some(handle) = self.handle.as_ref() {
+            idb_delete(handle, key);
+        }
+        self.memory.delete(key)?;
+        if self.batch_on {
+            return Ok(());
+        }
+        self.commit()
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.handle.iter().for_each(idb_discard);
+        self.batch_on = true;
+        self.memory.init_batch()
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        self.batch_on = false;
+        self.memory.finish_batch()?;
+        self.commit()
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        self.memory.total_size()
+    }
+
+    /// Return the failure of a transaction written in the background, if any.
+    fn flush(&mut self) -> Result<()> {
+        match self.handle.as_ref().and_then(idb_take_error) {
+            Some(error) => Err(Errors::Db(format!("IndexedDb: {}", error))),
+            None => Ok(()),
+        }
+    }
+
+    fn keys(&mut self) -> Result<Vec<Vec<u8>>> {
+        self.memory.keys()
+    }
+
+    fn iter_prefix<'a>(&'a mut self, prefix: &'a [u8]) -> Result<Entries<'a>> {
+        self.memory.iter_prefix(prefix)
+    }
+}
+
+/// A tree kept in memory, or in `IndexedDB` if constructed by `withIndexedDb()`,
+/// with the root it is at.
+#[wasm_bindgen(js_name = Monotree)]
+pub struct WasmTree {
+    tree: Monotree<IndexedDb, DefaultHasher>,
+    root: Option<Hash>,
+}
+
+#[wasm_bindgen(js_class = Monotree)]
+impl WasmTree {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmTree {
+        WasmTree {
+            tree: Monotree::new(""),
+            root: None,
+        }
+    }
+
+    /// Construct a tree persisted in `IndexedDB` by a handle of `openIndexedDb()`,
+    /// at the root it was at when last updated.
+    #[wasm_bindgen(js_name = withIndexedDb)]
+    pub fn with_indexed_db(handle: IdbHandle) -> std::result::Result<WasmTree, JsValue> {
+        let db = IndexedDb::with_handle(handle).map_err(to_js)?;
+        let mut tree = Monotree::with_db(db).map_err(to_js)?;
+        let root = tree.load_root().map_err(to_js)?;
+        Ok(WasmTree { tree, root })
+    }
+
+    /// Move to a new root, recording it for the next session.
+    fn set_root(&mut self, root: Option<Hash>) -> std::result::Result<(), JsValue> {
+        if !self
+            .tree
+            .swap_root(self.root.as_ref(), root.as_ref())
+            .map_err(to_js)?
+        {
+            return Err(JsValue::from_str("root changed by another instance"));
+        }
+        self.root = root;
+        Ok(())
+    }
+
+    /// Get the root of the tree, or an empty array for an empty tree.
+    pub fn root(&self) -> Vec<u8> {
+        self.root.map(|root| root.to_vec()).unwrap_or_default()
+    }
+
+    pub fn insert(&mut self, key: &[u8], leaf: &[u8]) -> std::result::Result<(), JsValue> {
+        let (key, leaf) = match (hash_from_js(key)?, hash_from_js(leaf)?) {
+            (Some(key), Some(leaf)) => (key, leaf),
+            _ => return Err(JsValue::from_str("insert(): empty key or leaf")),
+        };
+        let root = self
+            .tree
+            .insert(self.root.as_ref(), &key, &leaf)
+            .map_err(to_js)?;
+        self.set_root(root)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> std::result::Result<(), JsValue> {
+        if let Some(key) = hash_from_js(key)? {
+            let root = self.tree.remove(self.root.as_ref(), &key).map_err(to_js)?;
+            self.set_root(root)?;
+        }
+        Ok(())
+    }
+
+    /// Get the leaf of a key, or `undefined` if the key is absent.
+    pub fn get(&mut self, key: &[u8]) -> std::result::Result<Option<Vec<u8>>, JsValue> {
+        match hash_from_js(key)? {
+            Some(key) => {
+                let leaf = self.tree.get(self.root.as_ref(), &key).map_err(to_js)?;
+                Ok(leaf.map(|leaf| leaf.to_vec()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Get the proof of a key encoded by `ProofBytes::to_bytes()`, as taken by `verifyProof()`,
+    /// or `undefined` if the key is absent.
+    pub fn prove(&mut self, key: &[u8]) -> std::result::Result<Option<Vec<u8>>, JsValue> {
+        match hash_from_js(key)? {
+            Some(key) => {
+                let proof = self
+                    .tree
+                    .get_merkle_proof(self.root.as_ref(), &key)
+                    .map_err(to_js)?;
+                Ok(proof.map(|proof| proof.to_bytes()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Default for WasmTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,208 @@
+//! A module defining the canonical binary encoding of `Proof`s, for verifiers in other languages.
+//!
+//! # Byte-Serialized View
+//! Numbers in parentheses refer to byte length.
+//! Counts and lengths are written as _varints_ (unsigned `LEB128`, the least significant group
+//! first) in their shortest form, so that every proof has exactly one encoding.
+//!
+//! _Proof_ = `version`(1) + `count`(varint) + `Step` * `count`, where
+//! `version` = `0x01`, the plain encoding.
+//! `Step` = `side`(1) + `len`(varint) + `cut`(`len`), in the order of `Proof`, from the root down.
+//! `side` = `0x00` if the hash folded in goes first, i.e. `false` in `Proof`,
+//! or `0x01` if it goes right before the last byte of `cut`, i.e. `true`.
+//! `cut` is the serialized node with the hash folded in cut out, which is never empty for `0x01`.
+//!
+//! Verifying a proof then follows `compute_root()`: starting from the leaf,
+//! for every step from the last one, the hash is `digest_node(hash + cut)` for `0x00`,
+//! or `digest_node(cut[..len - 1] + hash + cut[len - 1..])` for `0x01`,
+//! and the proof verifies if the last hash is the root.
+//!
+//! # Compressed Form
+//! _Compressed proof_ = `version`(1) + `count`(varint) + `sides`((`count` + 7) / 8)
+//! \+ `Step'` * `count`, where `version` = `0x02`, the side of the `i`-th step is bit `i % 8`
+//! (the least significant first) of byte `i / 8` of `sides`, the unused bits being `0`,
+//! and `Step'` = `len`(varint) + `cut`(`len`). Only the sides are packed into a bitmap,
+//! so that a proof of `count` steps is `count - (count + 7) / 8` bytes shorter.
+use crate::utils::*;
+use crate::*;
+use core::convert::TryFrom;
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+/// Version of the encoding written by `ProofBytes::to_bytes()`.
+pub const WIRE_VERSION: u8 = 0x01;
+
+/// Version of the compressed encoding written by `ProofShape::to_compressed_bytes()`.
+pub const WIRE_VERSION_COMPRESSED: u8 = 0x02;
+
+/// A trait encoding `Proof`s into bytes, and decoding them back, by the encoding of this module.
+///
+/// `from_bytes()` decodes the compressed form as well.
+pub trait ProofBytes: Sized {
+    fn to_bytes(&self) -> Vec<u8>;
+    fn from_bytes(bytes: &[u8]) -> Result<Self>;
+}
+
+#[cfg(feature = "json")]
+/// A trait converting `Proof`s to JSON and back, as arrays of steps `[right, cut]`
+/// with `cut` in hex, the same as `http` serves and `vectors` fixtures hold.
+pub trait ProofJson: Sized {
+    fn to_json(&self) -> Value;
+    fn from_json(json: &Value) -> Result<Self>;
+}
+
+/// A trait telling the size and the shape of `Proof`s, and encoding them in the compressed form.
+pub trait ProofShape {
+    /// Number of steps, i.e. of nodes from the root down to the leaf.
+    fn depth(&self) -> usize;
+    /// Length of the encoding by `ProofBytes::to_bytes()`, without encoding it.
+    fn encoded_len(&self) -> usize;
+    /// Length of the encoding by `to_compressed_bytes()`, without encoding it.
+    fn compressed_len(&self) -> usize;
+    fn to_compressed_bytes(&self) -> Vec<u8>;
+}
+
+/// Decode a varint at the beginning of `bytes`, rejecting any but the shortest form.
+fn read_varint(bytes: &[u8]) -> Result<(usize, usize)> {
+    let (number, n) = varint_to_int(bytes)?;
+    if int_to_varint(number).len() != n {
+        return Err(Errors::Decode(
+            "Proof::from_bytes(): non-canonical varint".to_string(),
+        ));
+    }
+    let number = usize::try_from(number)
+        .map_err(|_| Errors::Decode("Proof::from_bytes(): varint over usize".to_string()))?;
+    Ok((number, n))
+}
+
+impl ProofBytes for Proof {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![WIRE_VERSION];
+        bytes.extend(int_to_varint(self.len() as u64));
+        for (right, cut) in self.iter() {
+            bytes.push(*right as u8);
+            bytes.extend(int_to_varint(cut.len() as u64));
+            bytes.extend_from_slice(cut);
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let truncated = || Errors::Decode("Proof::from_bytes(): truncated".to_string());
+        let compressed = match bytes.first() {
+            Some(&WIRE_VERSION) => false,
+            Some(&WIRE_VERSION_COMPRESSED) => true,
+            Some(_) => {
+                let msg = "Proof::from_bytes(): unknown version";
+                return Err(Errors::Decode(msg.to_string()));
+            }
+            None => return Err(truncated()),
+        };
+        let (count, n) = read_varint(&bytes[1..])?;
+        let mut rest = &bytes[1 + n..];
+        let mut sides = None;
+        if compressed {
+            let len = count.checked_add(7).ok_or_else(truncated)? / 8;
+            let bitmap = rest.get(..len).ok_or_else(truncated)?;
+            if count % 8 != 0 && bitmap[len - 1] >> (count % 8) != 0 {
+                let msg = "Proof::from_bytes(): unused sides set";
+                return Err(Errors::Decode(msg.to_string()));
+            }
+            sides = Some(bitmap);
+            rest = &rest[len..];
+        }
+        // a step takes 1 byte at least, so a count beyond that is never allocated for
+        let mut proof = Vec::with_capacity(count.min(rest.len()));
+        for i in 0..count {
+            let right = match sides {
+                Some(bitmap) => bitmap[i / 8] & (1 << (i % 8)) != 0,
+                None => {
+                    let right = match rest.first() {
+                        Some(0x00) => false,
+                        Some(0x01) => true,
+                        Some(_) => {
+                            let msg = "Proof::from_bytes(): unknown side";
+                            return Err(Errors::Decode(msg.to_string()));
+                        }
+                        None => return Err(truncated()),
+                    };
+                    rest = &rest[1..];
+                    right
+                }
+            };
+            let (len, n) = read_varint(rest)?;
+            let cut = rest[n..].get(..len).ok_or_else(truncated)?;
+            if right && cut.is_empty() {
+                let msg = "Proof::from_bytes(): empty cut on the right";
+                return Err(Errors::Decode(msg.to_string()));
+            }
+            proof.push((right, cut.to_vec()));
+            rest = &rest[n + len..];
+        }
+        if !rest.is_empty() {
+            let msg = "Proof::from_bytes(): trailing bytes";
+            return Err(Errors::Decode(msg.to_string()));
+        }
+        Ok(proof)
+    }
+}
+
+impl ProofShape for Proof {
+    fn depth(&self) -> usize {
+        self.len()
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.compressed_len() - self.len().div_ceil(8) + self.len()
+    }
+
+    fn compressed_len(&self) -> usize {
+        let steps: usize = self
+            .iter()
+            .map(|(_, cut)| int_to_varint(cut.len() as u64).len() + cut.len())
+            .sum();
+        1 + int_to_varint(self.len() as u64).len() + self.len().div_ceil(8) + steps
+    }
+
+    fn to_compressed_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![WIRE_VERSION_COMPRESSED];
+        bytes.extend(int_to_varint(self.len() as u64));
+        let mut sides = vec![0u8; self.len().div_ceil(8)];
+        for (i, (right, _)) in self.iter().enumerate() {
+            sides[i / 8] |= (*right as u8) << (i % 8);
+        }
+        bytes.extend(sides);
+        for (_, cut) in self.iter() {
+            bytes.extend(int_to_varint(cut.len() as u64));
+            bytes.extend_from_slice(cut);
+        }
+        bytes
+    }
+}
+
+#[cfg(feature = "json")]
+impl ProofJson for Proof {
+    fn to_json(&self) -> Value {
+        self.iter()
+            .map(|(right, cut)| json!([right, hex::encode(cut)]))
+            .collect()
+    }
+
+    fn from_json(json: &Value) -> Result<Self> {
+        let invalid = |msg: &str| Errors::Decode(format!("Proof::from_json(): {}", msg));
+        let steps = json.as_array().ok_or_else(|| invalid("not an array"))?;
+        steps
+            .iter()
+            .map(|step| {
+                let cut = step[1].as_str().map(hex::decode);
+                match (step.as_array().map(Vec::len), step[0].as_bool(), cut) {
+                    (Some(2), Some(true), Some(Ok(cut))) if cut.is_empty() => {
+                        Err(invalid("empty cut on the right"))
+                    }
+                    (Some(2), Some(right), Some(Ok(cut))) => Ok((right, cut)),
+                    _ => Err(invalid("malformed step")),
+                }
+            })
+            .collect()
+    }
+}
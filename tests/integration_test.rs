@@ -1,8 +1,23 @@
+use monotree::anchor::*;
 use monotree::database::*;
 use monotree::hasher::*;
+use monotree::hexary::*;
+use monotree::http::*;
+use monotree::import::*;
+use monotree::metrics::*;
+use monotree::node::*;
+use monotree::nonblocking::*;
+use monotree::policy::*;
+use monotree::shell::*;
+use monotree::signing::*;
+use monotree::smt::*;
+use monotree::testing::*;
 use monotree::utils::*;
+use monotree::vectors::*;
 use monotree::*;
 use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 extern crate paste;
 extern crate scopeguard;
@@ -175,6 +190,2116 @@ fn insert_keys_then_delete_keys_immediately<D: Database, H: Hasher>(
     Ok(())
 }
 
+fn insert_keys_then_estimate_size<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    assert_eq!(tree.estimated_size(root.as_ref())?, 0);
+
+    // a single soft node: key + hash + (range_start + range_end + path) + indicator
+    root = tree.insert(root.as_ref(), &keys[0], &leaves[0])?;
+    let size = tree.estimated_size(root.as_ref())?;
+    assert_eq!(size, (2 * HASH_LEN + 4 + HASH_LEN + 1) as u64);
+
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    assert!(tree.estimated_size(root.as_ref())? > size);
+    assert!(tree.db.total_size()? > 0);
+
+    root = tree.removes(root.as_ref(), keys)?;
+    assert_eq!(tree.estimated_size(root.as_ref())?, 0);
+    Ok(())
+}
+
+fn insert_keys_then_get_stats<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    assert_eq!(tree.stats(root.as_ref())?, TreeStats::default());
+    root = tree.insert(root.as_ref(), &keys[0], &leaves[0])?;
+    let stats = tree.stats(root.as_ref())?;
+    assert_eq!(
+        (stats.nodes, stats.leaves, stats.depths.clone()),
+        (1, 1, vec![0, 1])
+    );
+
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let stats = tree.stats(root.as_ref())?;
+    assert_eq!(stats.leaves, keys.len() as u64);
+    assert_eq!(stats.depths.iter().sum::<u64>(), stats.leaves);
+    assert_eq!(
+        stats.nodes as usize,
+        tree.diff_nodes(None, root.as_ref())?.len()
+    );
+    assert_eq!(stats.bytes, tree.estimated_size(root.as_ref())?);
+    assert!(stats.depth() > 1 && stats.depths[stats.depth()] > 0);
+    Ok(())
+}
+
+fn insert_keys_then_remove_keys_with_progress<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let mut reported = Vec::new();
+    root = tree.inserts_with_progress(root.as_ref(), keys, leaves, &mut |n, total| {
+        reported.push((n, total))
+    })?;
+    assert_eq!(reported.len(), keys.len());
+    assert_eq!(reported.last(), Some(&(keys.len(), keys.len())));
+
+    let mut count = 0;
+    root = tree.removes_with_progress(root.as_ref(), keys, &mut |n, total| {
+        assert_eq!(total, keys.len());
+        count = n;
+    })?;
+    assert_eq!(count, keys.len());
+    assert_eq!(root, None);
+    Ok(())
+}
+
+fn insert_keys_then_cancel_batch<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+
+    // cancel the batch in the middle of the way
+    let token = Arc::new(AtomicBool::new(false));
+    tree.set_cancel_token(token.clone());
+    let cancelled = tree.inserts_with_progress(
+        root.as_ref(),
+        &keys[half..],
+        &leaves[half..],
+        &mut |n, _| {
+            if n == 10 {
+                token.store(true, Ordering::SeqCst)
+            }
+        },
+    );
+    assert!(matches!(cancelled, Err(Errors::Cancelled)));
+
+    // the root before cancellation must be still valid
+    for (k, v) in keys.iter().zip(leaves.iter()).take(half) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+    }
+    assert!(tree.removes(root.as_ref(), keys).is_err());
+
+    token.store(false, Ordering::SeqCst);
+    root = tree.inserts(root.as_ref(), &keys[half..], &leaves[half..])?;
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+    }
+    Ok(())
+}
+
+fn insert_keys_then_compute_root_from_proof<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let old = root;
+    root = tree.remove(root.as_ref(), &keys[0])?;
+    for (k, v) in keys.iter().zip(leaves.iter()).skip(1) {
+        let proof = tree.get_merkle_proof(root.as_ref(), k)?;
+        let computed = tree::compute_root(hasher, v, proof.as_ref());
+        assert_eq!(computed, root);
+        assert_ne!(computed, old);
+    }
+    assert_eq!(tree::compute_root(hasher, &leaves[0], None), None);
+    Ok(())
+}
+
+fn read_legacy_nodes_then_insert_keys<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    _root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    // a soft node as written by earlier versions: fixed-length ranges, indicator 0x00
+    let bytes = [
+        &leaves[0][..],
+        &(0 as BitsLen).to_be_bytes(),
+        &((HASH_LEN * 8) as BitsLen).to_be_bytes(),
+        &keys[0][..],
+        &[0x00],
+    ]
+    .concat();
+    let legacy = hasher.digest(&bytes);
+    tree.db.put(&legacy, bytes)?;
+    let mut root = Some(legacy);
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[0])?;
+    assert!(tree::verify_proof(
+        hasher,
+        root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+
+    // new nodes are written on top of the legacy one
+    root = tree.inserts(root.as_ref(), &keys[1..], &leaves[1..])?;
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+        let proof = tree.get_merkle_proof(root.as_ref(), k)?;
+        assert!(tree::verify_proof(hasher, root.as_ref(), v, proof.as_ref()));
+    }
+    Ok(())
+}
+
+fn insert_keys_then_sync_replica_by_diff<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let mut replica = Monotree::<MemoryDB, H>::new("");
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    let old = root;
+    let nodes = tree.diff_nodes(None, old.as_ref())?;
+    replica.apply_nodes(old.as_ref(), &nodes)?;
+    for (k, v) in keys.iter().zip(leaves.iter()).take(half) {
+        assert_eq!(replica.get(old.as_ref(), k)?, Some(*v));
+    }
+
+    // the leader moves on: only the nodes the replica lacks are sent
+    root = tree.inserts(root.as_ref(), &keys[half..], &leaves[half..])?;
+    root = tree.remove(root.as_ref(), &keys[0])?;
+    let nodes = tree.diff_nodes(old.as_ref(), root.as_ref())?;
+    assert!(nodes.len() < tree.diff_nodes(None, root.as_ref())?.len());
+    assert!(!nodes.is_empty());
+
+    // tampered or incomplete node sets are rejected
+    let mut tampered = nodes.clone();
+    let last = tampered[0].1.len() - 1;
+    tampered[0].1[last - 1] ^= 0x01;
+    assert!(matches!(
+        replica.apply_nodes(root.as_ref(), &tampered),
+        Err(Errors::ProofInvalid(_))
+    ));
+    let root_only: Vec<_> = nodes
+        .iter()
+        .filter(|(h, _)| Some(*h) == root)
+        .cloned()
+        .collect();
+    if nodes.len() > 1 {
+        assert!(matches!(
+            replica.apply_nodes(root.as_ref(), &root_only),
+            Err(Errors::ProofInvalid(_))
+        ));
+    }
+
+    replica.apply_nodes(root.as_ref(), &nodes)?;
+    assert_eq!(replica.get(root.as_ref(), &keys[0])?, None);
+    for (k, v) in keys.iter().zip(leaves.iter()).skip(1) {
+        assert_eq!(replica.get(root.as_ref(), k)?, Some(*v));
+    }
+    assert_eq!(tree.diff_nodes(root.as_ref(), root.as_ref())?.len(), 0);
+    Ok(())
+}
+
+fn insert_keys_then_traverse_in_reverse_order<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let traversal = Traversal {
+        bits: BitOrder::LsbFirst,
+        bytes: ByteOrder::LittleEndian,
+    };
+    tree.set_traversal(traversal)?;
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+        let proof = tree.get_merkle_proof(root.as_ref(), k)?;
+        assert!(tree::verify_proof(hasher, root.as_ref(), v, proof.as_ref()));
+    }
+    let metadata = tree.db.get(&tree::METADATA_KEY)?.expect("metadata");
+    assert_eq!(Metadata::from_bytes(&metadata)?.traversal, traversal);
+
+    // the same tree as with the rearranged keys traversed in the default order,
+    // inserted in the same order as `inserts()` does
+    let arranged: Vec<Hash> = keys
+        .iter()
+        .map(|k| {
+            let mut k = *k;
+            k.reverse();
+            k.iter_mut().for_each(|b| *b = b.reverse_bits());
+            k
+        })
+        .collect();
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    let mut other_root = None;
+    for i in get_sorted_indices(keys, false) {
+        other_root = other.insert(other_root.as_ref(), &arranged[i], &leaves[i])?;
+    }
+    assert_eq!(other_root, root);
+    Ok(())
+}
+
+fn insert_keys_in_any_order_then_compare_roots<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+
+    // a tree is determined by its entries, not by the history of operations,
+    // though its root depends on the order they were inserted in
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    let mut indices: Vec<usize> = (0..keys.len()).collect();
+    shuffle(&mut indices);
+    let extra = random_hashes(keys.len() / 2);
+    let mut other_root = other.inserts(None, &extra, &extra)?;
+    for i in indices.into_iter() {
+        other_root = other.insert(other_root.as_ref(), &keys[i], &leaves[i])?;
+    }
+    other_root = other.removes(other_root.as_ref(), &extra)?;
+    let inserted = other.inserts(None, keys, leaves)?;
+    assert!(other.same_tree(other_root.as_ref(), inserted.as_ref())?);
+
+    // removing a key not in the tree leaves it unchanged
+    assert_eq!(tree.remove(root.as_ref(), &random_hash())?, root);
+    Ok(())
+}
+
+fn insert_keys_then_remove_with_proofs<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    for (i, key) in keys[..half].iter().enumerate() {
+        let (removed, proof) = tree.remove_with_proof(root.as_ref(), key)?;
+        let proof = proof.expect("proof");
+        assert_eq!(removed, tree.remove(root.as_ref(), key)?);
+        assert_eq!(proof.leaf, leaves[i]);
+        assert!(verify_removal(
+            hasher,
+            root.as_ref(),
+            removed.as_ref(),
+            key,
+            &proof
+        ));
+
+        // neither another key, nor another root, nor another leaf verifies
+        let other = &keys[(i + 1) % half];
+        assert!(!verify_removal(
+            hasher,
+            root.as_ref(),
+            removed.as_ref(),
+            other,
+            &proof
+        ));
+        assert!(!verify_removal(
+            hasher,
+            root.as_ref(),
+            root.as_ref(),
+            key,
+            &proof
+        ));
+        let mut forged = proof.clone();
+        forged.leaf = leaves[(i + 1) % half];
+        assert!(!verify_removal(
+            hasher,
+            root.as_ref(),
+            removed.as_ref(),
+            key,
+            &forged
+        ));
+        root = removed;
+    }
+    assert_eq!(root, None);
+
+    // keys not in the tree give no proof
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    assert_eq!(
+        tree.remove_with_proof(root.as_ref(), &keys[half])?,
+        (root, None)
+    );
+    tree.set_soft_delete(true)?;
+    assert!(tree.remove_with_proof(root.as_ref(), &keys[0]).is_err());
+    Ok(())
+}
+
+fn insert_keys_then_prove_updates<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    for (i, key) in keys[..half].iter().enumerate() {
+        let new_leaf = leaves[half + i];
+        let (updated, proof) = tree.prove_update(root.as_ref(), key, &leaves[i], &new_leaf)?;
+        let proof = proof.expect("proof");
+        assert_eq!(updated, tree.insert(root.as_ref(), key, &new_leaf)?);
+        assert_eq!(
+            (Some(proof.old_root), Some(proof.new_root)),
+            (root, updated)
+        );
+        assert!(verify_update(hasher, &proof));
+
+        // neither another leaf, nor another root, nor the proof of another key verifies
+        let mut forged = proof.clone();
+        forged.new_leaf = leaves[i];
+        assert!(!verify_update(hasher, &forged));
+        let mut forged = proof.clone();
+        forged.new_root = proof.old_root;
+        assert!(!verify_update(hasher, &forged));
+        if half > 1 {
+            let other = &keys[(i + 1) % half];
+            let mut forged = proof.clone();
+            forged.new_proof = tree.get_merkle_proof(updated.as_ref(), other)?.unwrap();
+            assert!(!verify_update(hasher, &forged));
+        }
+        root = updated;
+    }
+
+    // keys of another leaf or none give no proof
+    assert_eq!(
+        tree.prove_update(root.as_ref(), &keys[0], &leaves[0], &leaves[1])?,
+        (root, None)
+    );
+    assert_eq!(
+        tree.prove_update(root.as_ref(), &keys[half], &leaves[half], &leaves[0])?,
+        (root, None)
+    );
+    Ok(())
+}
+
+fn insert_keys_then_verify_consistency<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let (half, more) = (keys.len() / 2, keys.len() * 3 / 4);
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    let old = root;
+
+    // append some keys only
+    root = tree.inserts(root.as_ref(), &keys[half..more], &leaves[half..more])?;
+    let appended = root;
+    let proof = tree.consistency_proof(old.as_ref(), appended.as_ref())?;
+    assert_eq!(proof.updates.len(), more - half);
+    for mode in [
+        Consistency::AppendOnly,
+        Consistency::UpdateOnly,
+        Consistency::Any,
+    ]
+    .iter()
+    {
+        assert!(verify_consistency(
+            hasher,
+            old.as_ref(),
+            appended.as_ref(),
+            &proof,
+            *mode
+        ));
+    }
+
+    // the same keys appended in another order than the updates of the proof are replayed in
+    let mut reversed = old;
+    for (key, leaf) in keys[half..more].iter().zip(leaves[half..more].iter()).rev() {
+        reversed = tree.insert(reversed.as_ref(), key, leaf)?;
+    }
+    let proof = tree.consistency_proof(old.as_ref(), reversed.as_ref())?;
+    assert!(verify_consistency(
+        hasher,
+        old.as_ref(),
+        reversed.as_ref(),
+        &proof,
+        Consistency::AppendOnly
+    ));
+
+    // then change and remove some keys as well
+    let changed = random_hashes(5);
+    root = tree.inserts(root.as_ref(), &keys[..5], &changed)?;
+    root = tree.inserts(root.as_ref(), &keys[more..], &leaves[more..])?;
+    root = tree.removes(root.as_ref(), &keys[5..10])?;
+    let proof = tree.consistency_proof(old.as_ref(), root.as_ref())?;
+    assert_eq!(proof.updates.len(), keys.len() - half + 10);
+    assert!(verify_consistency(
+        hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &proof,
+        Consistency::Any
+    ));
+    assert!(!verify_consistency(
+        hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &proof,
+        Consistency::UpdateOnly
+    ));
+    assert!(!verify_consistency(
+        hasher,
+        old.as_ref(),
+        appended.as_ref(),
+        &proof,
+        Consistency::Any
+    ));
+
+    // tampered proofs do not verify
+    let mut tampered = proof.clone();
+    tampered.updates[0].1 = Some(random_hash());
+    assert!(!verify_consistency(
+        hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &tampered,
+        Consistency::Any
+    ));
+    let mut tampered = proof.clone();
+    tampered.updates.pop();
+    assert!(!verify_consistency(
+        hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &tampered,
+        Consistency::Any
+    ));
+    let mut tampered = proof;
+    tampered
+        .nodes
+        .retain(|(hash, _)| Some(hash) != old.as_ref());
+    assert!(!verify_consistency(
+        hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &tampered,
+        Consistency::Any
+    ));
+    Ok(())
+}
+
+fn insert_keys_then_verify_non_inclusion_proof<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let absent = random_hash();
+    let proof = tree.get_non_inclusion_proof(root.as_ref(), &absent)?;
+    assert!(verify_non_inclusion_proof(
+        hasher,
+        root.as_ref(),
+        &absent,
+        proof.as_ref()
+    ));
+
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    for key in keys.iter() {
+        assert_eq!(tree.get_non_inclusion_proof(root.as_ref(), key)?, None);
+    }
+    for key in random_hashes(keys.len()).iter() {
+        let proof = tree.get_non_inclusion_proof(root.as_ref(), key)?;
+        assert!(verify_non_inclusion_proof(
+            hasher,
+            root.as_ref(),
+            key,
+            proof.as_ref()
+        ));
+        assert!(!verify_non_inclusion_proof(
+            hasher,
+            root.as_ref(),
+            &keys[0],
+            proof.as_ref()
+        ));
+    }
+
+    // neither a proof against another root nor a truncated one verifies
+    let proof = tree.get_non_inclusion_proof(root.as_ref(), &absent)?;
+    let other = tree.insert(root.as_ref(), &absent, &leaves[0])?;
+    assert!(!verify_non_inclusion_proof(
+        hasher,
+        other.as_ref(),
+        &absent,
+        proof.as_ref()
+    ));
+    let mut truncated = proof.expect("proof");
+    truncated.pop();
+    assert!(!verify_non_inclusion_proof(
+        hasher,
+        root.as_ref(),
+        &absent,
+        Some(&truncated)
+    ));
+    Ok(())
+}
+
+fn update_key_then_gen_proofs_across_versions<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    // retain a root per version, updating the leaf of the key of interest every other one
+    let key = keys[0];
+    let mut roots = vec![root];
+    let mut history = vec![None];
+    for (i, (k, v)) in keys.iter().zip(leaves.iter()).enumerate() {
+        root = tree.insert(root.as_ref(), k, v)?;
+        if i % 2 == 1 {
+            let leaf = random_hash();
+            root = tree.insert(root.as_ref(), &key, &leaf)?;
+            history.push(Some(leaf));
+        } else {
+            history.push(tree.get(root.as_ref(), &key)?);
+        }
+        roots.push(root);
+    }
+    root = tree.remove(root.as_ref(), &key)?;
+    roots.push(root);
+    history.push(None);
+
+    let proofs = tree.get_merkle_proofs_across(&roots, &key)?;
+    assert_eq!(proofs.len(), roots.len());
+    for ((root, leaf), proof) in roots.iter().zip(history.iter()).zip(proofs.iter()) {
+        assert_eq!(proof, &tree.get_merkle_proof(root.as_ref(), &key)?);
+        if let Some(leaf) = leaf {
+            assert!(verify_proof(hasher, root.as_ref(), leaf, proof.as_ref()));
+        }
+    }
+    Ok(())
+}
+
+fn insert_keys_then_gets_with_proofs<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    let found = tree.gets_with_proofs(root.as_ref(), keys)?;
+    assert_eq!(found.len(), keys.len());
+    for (i, key) in keys.iter().enumerate() {
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+        match &found[i] {
+            Some((leaf, found)) => {
+                assert_eq!(
+                    (Some(*leaf), Some(found)),
+                    (Some(leaves[i]), proof.as_ref())
+                );
+                assert!(verify_proof(hasher, root.as_ref(), leaf, Some(found)));
+            }
+            None => assert!(i >= half && proof.is_none()),
+        }
+    }
+
+    // nodes shared by keys are read once
+    let counters = Arc::new(Counters::default());
+    tree.set_metrics(counters.clone());
+    tree.gets_with_proofs(root.as_ref(), &keys[..half])?;
+    let walked = counters.nodes_read.swap(0, Ordering::Relaxed);
+    for key in keys[..half].iter() {
+        tree.get_merkle_proof(root.as_ref(), key)?;
+    }
+    assert!(walked < counters.nodes_read.load(Ordering::Relaxed));
+    assert_eq!(counters.proofs.load(Ordering::Relaxed), 2 * half as u64);
+    assert_eq!(tree.gets_with_proofs(None, keys)?, vec![None; keys.len()]);
+    Ok(())
+}
+
+fn insert_keys_then_replace_leaves<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        let (inserted, replaced) = tree.insert_replace(root.as_ref(), key, leaf)?;
+        assert_eq!(inserted, tree.insert(root.as_ref(), key, leaf)?);
+        assert_eq!(replaced, None);
+        root = inserted;
+    }
+    let updated = random_hashes(keys.len());
+    for (i, key) in keys.iter().enumerate() {
+        let (inserted, replaced) = tree.insert_replace(root.as_ref(), key, &updated[i])?;
+        assert_eq!(replaced, Some(leaves[i]));
+        assert_eq!(tree.get(inserted.as_ref(), key)?, Some(updated[i]));
+        root = inserted;
+    }
+    // the same leaf again leaves the root as it is
+    let (unchanged, replaced) = tree.insert_replace(root.as_ref(), &keys[0], &updated[0])?;
+    assert_eq!((unchanged, replaced), (root, Some(updated[0])));
+
+    // keys tombstoned had no leaf
+    tree.set_soft_delete(true)?;
+    root = tree.remove(root.as_ref(), &keys[0])?;
+    let (_, replaced) = tree.insert_replace(root.as_ref(), &keys[0], &leaves[0])?;
+    assert_eq!(replaced, None);
+    Ok(())
+}
+
+fn insert_keys_if_absent_then_compare_and_swap<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    for (key, leaf) in keys[..half].iter().zip(leaves.iter()) {
+        let (inserted, applied) = tree.insert_if_absent(root.as_ref(), key, leaf)?;
+        assert!(applied);
+        root = inserted;
+    }
+    let inserted = tree.inserts(None, &keys[..half], &leaves[..half])?;
+    assert!(tree.same_tree(root.as_ref(), inserted.as_ref())?);
+
+    // keys claimed once are never claimed again, and the root stays as it is
+    for key in keys[..half].iter() {
+        assert_eq!(
+            tree.insert_if_absent(root.as_ref(), key, &random_hash())?,
+            (root, false)
+        );
+    }
+    let updated = random_hashes(keys.len());
+    for i in 0..half {
+        let stale = tree.compare_and_swap(root.as_ref(), &keys[i], &updated[i], &leaves[i])?;
+        assert_eq!(stale, (root, false));
+        let (swapped, applied) =
+            tree.compare_and_swap(root.as_ref(), &keys[i], &leaves[i], &updated[i])?;
+        assert!(applied);
+        assert_eq!(tree.get(swapped.as_ref(), &keys[i])?, Some(updated[i]));
+        root = swapped;
+    }
+    for i in half..keys.len() {
+        let absent = tree.compare_and_swap(root.as_ref(), &keys[i], &leaves[i], &updated[i])?;
+        assert_eq!(absent, (root, false));
+    }
+    assert_eq!(
+        tree.compare_and_swap(None, &keys[0], &leaves[0], &updated[0])?,
+        (None, false)
+    );
+
+    // keys removed with soft-delete set are absent again
+    tree.set_soft_delete(true)?;
+    root = tree.remove(root.as_ref(), &keys[0])?;
+    let (inserted, applied) = tree.insert_if_absent(root.as_ref(), &keys[0], &leaves[0])?;
+    assert!(applied);
+    assert_eq!(tree.get(inserted.as_ref(), &keys[0])?, Some(leaves[0]));
+    Ok(())
+}
+
+fn insert_keys_then_gen_and_verify_multiproof<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let proof = tree.get_merkle_proofs(root.as_ref(), keys)?;
+    assert!(verify_merkle_proofs(
+        hasher,
+        root.as_ref(),
+        keys,
+        leaves,
+        proof.as_ref()
+    ));
+
+    // shared nodes appear only once
+    let size = |proof: &MultiProof| proof.iter().map(|node| node.len()).sum::<usize>();
+    let mut total = 0;
+    for key in keys.iter() {
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?.expect("proof");
+        total += proof.iter().map(|(_, cut)| cut.len()).sum::<usize>();
+    }
+    assert!(size(proof.as_ref().expect("proof")) < total);
+
+    // any subset of keys in any order is proven as well
+    let half = keys.len() / 2;
+    let (subset, sub_leaves) = (&keys[half..], &leaves[half..]);
+    let sub = tree.get_merkle_proofs(root.as_ref(), subset)?;
+    let (mut rev_keys, mut rev_leaves) = (subset.to_vec(), sub_leaves.to_vec());
+    rev_keys.reverse();
+    rev_leaves.reverse();
+    assert!(verify_merkle_proofs(
+        hasher,
+        root.as_ref(),
+        &rev_keys,
+        &rev_leaves,
+        sub.as_ref()
+    ));
+
+    // wrong leaves, other keys and other roots do not verify
+    let mut wrong = sub_leaves.to_vec();
+    wrong[0] = random_hash();
+    assert!(!verify_merkle_proofs(
+        hasher,
+        root.as_ref(),
+        subset,
+        &wrong,
+        sub.as_ref()
+    ));
+    assert!(!verify_merkle_proofs(
+        hasher,
+        root.as_ref(),
+        &subset[1..],
+        &sub_leaves[1..],
+        sub.as_ref()
+    ));
+    assert!(!verify_merkle_proofs(
+        hasher,
+        root.as_ref(),
+        keys,
+        leaves,
+        sub.as_ref()
+    ));
+    let other = tree.insert(root.as_ref(), &random_hash(), &random_hash())?;
+    assert!(!verify_merkle_proofs(
+        hasher,
+        other.as_ref(),
+        subset,
+        sub_leaves,
+        sub.as_ref()
+    ));
+
+    // keys absent from the tree yield no proof
+    let absent = [keys[0], random_hash()];
+    assert_eq!(tree.get_merkle_proofs(root.as_ref(), &absent)?, None);
+    Ok(())
+}
+
+fn insert_keys_then_swap_root<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    assert_eq!(tree.load_root()?, None);
+    let half = keys.len() / 2;
+    let first = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    assert!(tree.swap_root(None, first.as_ref())?);
+
+    // a writer holding the root loaded before the swap above cannot clobber it
+    let stale = tree.inserts(None, &keys[half..], &leaves[half..])?;
+    assert!(!tree.swap_root(None, stale.as_ref())?);
+    assert_eq!(tree.load_root()?, first);
+
+    // so it reloads the root and redoes its updates instead
+    let loaded = tree.load_root()?;
+    let second = tree.inserts(loaded.as_ref(), &keys[half..], &leaves[half..])?;
+    assert!(tree.swap_root(loaded.as_ref(), second.as_ref())?);
+    assert_eq!(tree.load_root()?, second);
+    assert!(!tree.swap_root(first.as_ref(), None)?);
+    assert!(tree.swap_root(second.as_ref(), None)?);
+    assert_eq!(tree.load_root()?, None);
+    Ok(())
+}
+
+fn insert_keys_then_iterate_entries<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    assert_eq!(tree.iter(root.as_ref()).count(), 0);
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let mut entries: Vec<_> = keys.iter().cloned().zip(leaves.iter().cloned()).collect();
+    entries.sort();
+    let iterated = tree.iter(root.as_ref()).collect::<Result<Vec<_>>>()?;
+    assert_eq!(iterated, entries);
+
+    let half = keys.len() / 2;
+    root = tree.removes(root.as_ref(), &keys[..half])?;
+    let mut rest: Vec<_> = keys[half..]
+        .iter()
+        .cloned()
+        .zip(leaves[half..].iter().cloned())
+        .collect();
+    rest.sort();
+    assert_eq!(tree.iter(root.as_ref()).collect::<Result<Vec<_>>>()?, rest);
+
+    // keys are restored as they were for trees traversing in other orders
+    let mut reversed = Monotree::<MemoryDB, H>::new("");
+    reversed.set_traversal(Traversal {
+        bits: BitOrder::LsbFirst,
+        bytes: ByteOrder::LittleEndian,
+    })?;
+    let root = reversed.inserts(None, keys, leaves)?;
+    let mut iterated = reversed.iter(root.as_ref()).collect::<Result<Vec<_>>>()?;
+    iterated.sort();
+    assert_eq!(iterated, entries);
+
+    // a missing node ends the iteration with an error
+    let unknown = random_hash();
+    let mut iter = tree.iter(Some(&unknown));
+    assert!(matches!(iter.next(), Some(Err(Errors::NotFound(_)))));
+    assert!(iter.next().is_none());
+    Ok(())
+}
+
+fn insert_keys_then_diff_roots<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let old = root;
+    let n = keys.len() / 4;
+    let (removed, rest) = keys.split_at(n);
+    let modified = &rest[..n];
+    let new_leaves = random_hashes(n);
+    let new_keys = random_hashes(n);
+    let new_entries = random_hashes(n);
+    root = tree.removes(root.as_ref(), removed)?;
+    root = tree.inserts(root.as_ref(), modified, &new_leaves)?;
+    root = tree.inserts(root.as_ref(), &new_keys, &new_entries)?;
+
+    let mut expected = Diff {
+        inserted: new_keys.iter().cloned().zip(new_entries).collect(),
+        removed: removed
+            .iter()
+            .cloned()
+            .zip(leaves[..n].iter().cloned())
+            .collect(),
+        modified: (0..n)
+            .map(|i| (modified[i], leaves[n + i], new_leaves[i]))
+            .collect(),
+    };
+    expected.inserted.sort();
+    expected.removed.sort();
+    expected.modified.sort();
+    assert_eq!(tree.diff(old.as_ref(), root.as_ref())?, expected);
+
+    // diffing backwards swaps insertions and removals
+    let backwards = tree.diff(root.as_ref(), old.as_ref())?;
+    assert_eq!(backwards.inserted, expected.removed);
+    assert_eq!(backwards.removed, expected.inserted);
+    let swapped: Vec<_> = expected
+        .modified
+        .iter()
+        .map(|(k, a, b)| (*k, *b, *a))
+        .collect();
+    assert_eq!(backwards.modified, swapped);
+
+    // an identical tree has no changes, and an empty one has every entry changed
+    assert_eq!(tree.diff(root.as_ref(), root.as_ref())?, Diff::default());
+    let entries = tree.iter(root.as_ref()).collect::<Result<Vec<_>>>()?;
+    assert_eq!(tree.diff(None, root.as_ref())?.inserted, entries);
+    assert_eq!(tree.diff(root.as_ref(), None)?.removed, entries);
+    Ok(())
+}
+
+fn insert_keys_then_verify_integrity<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    assert!(tree.verify_integrity(root.as_ref())?.is_empty());
+    assert!(tree.verify_integrity(None)?.is_empty());
+    let nodes = tree.diff_nodes(None, root.as_ref())?;
+    let (hash, bytes) = nodes
+        .into_iter()
+        .find(|(hash, _)| Some(hash) != root.as_ref())
+        .expect("node");
+
+    // a node missing is reported along with the path to it, and nothing under it
+    tree.db.delete(&hash)?;
+    let damaged = tree.verify_integrity(root.as_ref())?;
+    assert_eq!(damaged.len(), 1);
+    assert_eq!(
+        (damaged[0].hash, damaged[0].damage),
+        (hash, Damage::Missing)
+    );
+    assert!(!damaged[0].path.is_empty() && damaged[0].path.len() < HASH_LEN * 8);
+
+    // so is a node corrupted, however it is
+    let mut corrupted = bytes.to_owned();
+    *corrupted.last_mut().expect("byte") ^= 0x01;
+    tree.db.put(&hash, corrupted)?;
+    let damaged = tree.verify_integrity(root.as_ref())?;
+    assert!(damaged
+        .iter()
+        .any(|node| node.hash == hash && node.damage != Damage::Missing));
+    tree.db.put(&hash, bytes)?;
+    assert!(tree.verify_integrity(root.as_ref())?.is_empty());
+    let root = root.expect("root");
+    tree.db.put(&root, vec![0xff; 3])?;
+    let damaged = tree.verify_integrity(Some(&root))?;
+    assert_eq!(
+        damaged,
+        vec![DamagedNode {
+            path: Vec::new(),
+            hash: root,
+            damage: Damage::Malformed
+        }]
+    );
+    Ok(())
+}
+
+fn insert_keys_then_scan_entries_by_prefix<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let root = tree.inserts(root.as_ref(), keys, leaves)?.expect("root");
+    let nodes = tree.diff_nodes(None, Some(&root))?;
+    let mut scanned = std::collections::HashMap::new();
+    for entry in tree.db.iter_prefix(&[])? {
+        let (key, value) = entry?;
+        scanned.insert(key, value);
+    }
+    for (hash, bytes) in nodes.iter() {
+        assert_eq!(scanned.get(&hash[..]), Some(bytes));
+    }
+
+    // only keys under the prefix are scanned, all of them
+    let prefix = &root[..1];
+    let mut under = 0;
+    let mut found = false;
+    for entry in tree.db.iter_prefix(prefix)? {
+        let (key, _) = entry?;
+        assert!(key.starts_with(prefix));
+        found |= key == root;
+        under += 1;
+    }
+    assert!(found);
+    assert_eq!(
+        under,
+        scanned.keys().filter(|key| key.starts_with(prefix)).count()
+    );
+    Ok(())
+}
+
+fn insert_keys_then_prune_stale_nodes<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    let first = tree.inserts(root.as_ref(), keys, leaves)?.expect("root");
+    let second = tree.removes(Some(&first), &keys[..half])?.expect("root");
+    let third = tree.inserts(Some(&second), &keys[..half], &random_hashes(half))?;
+    let third = third.expect("root");
+
+    // retained trees stay intact while nodes only the others have are gone
+    assert!(tree.prune(&[first, third])? > 0);
+    assert_eq!(
+        tree.gets(Some(&first), keys)?,
+        leaves.iter().cloned().map(Some).collect::<Vec<_>>()
+    );
+    assert!(matches!(
+        tree.get(Some(&second), &keys[half]),
+        Err(Errors::NotFound(_))
+    ));
+
+    // compacting the database after keeps the retained tree as it is
+    assert!(tree.prune_and_compact(&[third])? > 0);
+    assert_eq!(tree.prune(&[third])?, 0);
+    assert!(tree.get(Some(&first), &keys[0]).is_err());
+    for (key, leaf) in keys[half..].iter().zip(&leaves[half..]) {
+        assert_eq!(tree.get(Some(&third), key)?, Some(*leaf));
+    }
+    let nodes = tree.diff_nodes(None, Some(&third))?;
+    assert!(nodes
+        .iter()
+        .all(|(hash, _)| matches!(tree.db.get(hash), Ok(Some(_)))));
+
+    // the root stored by swap_root() is always retained
+    let fourth = tree.removes(Some(&third), &keys[half..])?.expect("root");
+    assert!(tree.swap_root(None, Some(&fourth))?);
+    tree.prune(&[])?;
+    assert!(tree.get(Some(&fourth), &keys[0])?.is_some());
+    assert!(tree.get(Some(&third), &keys[half]).is_err());
+    Ok(())
+}
+
+fn insert_keys_with_filter_then_reject_absent_keys<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    tree.enable_filter(root.as_slice(), keys.len(), 0.01)?;
+    root = tree.inserts(root.as_ref(), &keys[half..], &leaves[half..])?;
+    let extra = random_hash();
+    root = tree.insert(root.as_ref(), &extra, &extra)?;
+
+    // keys present are never rejected, whether inserted before or after enabling
+    let found = tree.gets(root.as_ref(), keys)?;
+    assert_eq!(found, leaves.iter().cloned().map(Some).collect::<Vec<_>>());
+    assert_eq!(tree.get(root.as_ref(), &extra)?, Some(extra));
+
+    // absent keys are rejected before reading any node, even of a root missing in database
+    let unknown = random_hash();
+    let rejected = random_hashes(100)
+        .iter()
+        .filter(|key| matches!(tree.get(Some(&unknown), key), Ok(None)))
+        .count();
+    assert!(rejected > 90);
+    let absent = random_hash();
+    let removed = tree.remove(root.as_ref(), &absent)?;
+    assert_eq!(removed, root);
+
+    tree.disable_filter()?;
+    assert!(tree.get(Some(&unknown), &absent).is_err());
+    assert_eq!(tree.gets(root.as_ref(), keys)?, found);
+    Ok(())
+}
+
+fn insert_keys_then_report_costs<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let (root, report) = tree.inserts_with_report(root.as_ref(), keys, leaves)?;
+    // every node of the tree was written in the batch, along with those superseded within it
+    let nodes = tree.diff_nodes(None, root.as_ref())?;
+    assert!(report.nodes_written >= nodes.len() as u64);
+    assert_eq!(report.hashes, report.nodes_written);
+    let size = tree.estimated_size(root.as_ref())?;
+    assert!(report.bytes_written >= size);
+    assert!(report.nodes_read > 0);
+
+    // reports cover a single batch each
+    let (_, removal) = tree.removes_with_report(root.as_ref(), &keys[..1])?;
+    assert!(removal.nodes_written < report.nodes_written);
+    assert!(removal.nodes_read > 0);
+    let (same, idle) = tree.removes_with_report(root.as_ref(), &[])?;
+    assert_eq!(same, root);
+    assert_eq!(
+        idle,
+        CommitReport {
+            duration: idle.duration,
+            ..Default::default()
+        }
+    );
+    Ok(())
+}
+
+fn insert_values_then_verify_by_leaf_policy<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    _leaves: &[Hash],
+) -> Result<()> {
+    let values: Vec<Vec<u8>> = keys.iter().map(|_| random_bytes(40)).collect();
+    tree.set_leaf_policy(LeafPolicy::KeyedHash)?;
+    root = tree.inserts_values(root.as_ref(), keys, &values)?;
+    let metadata = tree.db.get(&tree::METADATA_KEY)?.expect("metadata");
+    assert_eq!(Metadata::from_bytes(&metadata)?.leaf, LeafPolicy::KeyedHash);
+
+    for (i, (key, value)) in keys.iter().zip(&values).enumerate() {
+        let leaf = hasher.digest(&[&key[..], value].concat());
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(leaf));
+        assert_eq!(tree.get_value(root.as_ref(), key)?, Some(value.to_vec()));
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+        let policy = tree.leaf_policy();
+        assert!(verify_value_proof(
+            hasher,
+            policy,
+            root.as_ref(),
+            key,
+            value,
+            proof.as_ref()
+        ));
+        // the value is bound to its key, and taken by no other policy
+        let other = &keys[(i + 1) % keys.len()];
+        assert!(!verify_value_proof(
+            hasher,
+            policy,
+            root.as_ref(),
+            other,
+            value,
+            proof.as_ref()
+        ));
+        let hashed = LeafPolicy::Hash;
+        assert!(!verify_value_proof(
+            hasher,
+            hashed,
+            root.as_ref(),
+            key,
+            value,
+            proof.as_ref()
+        ));
+    }
+
+    // raw values have to be of the length of leaves
+    let mut raw = Monotree::<MemoryDB, H>::new("");
+    assert_eq!(raw.leaf_policy(), LeafPolicy::Raw);
+    assert!(raw.insert_value(None, &keys[0], &values[0]).is_err());
+    let root = raw.insert_value(None, &keys[0], &keys[1])?;
+    assert_eq!(raw.get(root.as_ref(), &keys[0])?, Some(keys[1]));
+    assert_eq!(
+        raw.get_value(root.as_ref(), &keys[0])?,
+        Some(keys[1].to_vec())
+    );
+    assert_eq!(raw.get_value(root.as_ref(), &keys[1])?, None);
+
+    // values of any length are stored under their hashes
+    let mut hashed = Monotree::<MemoryDB, H>::new("");
+    hashed.set_leaf_policy(LeafPolicy::Hash)?;
+    let blobs: Vec<Vec<u8>> = (0..10).map(|i| random_bytes(i * 100)).collect();
+    let root = hashed.inserts_values(None, &keys[..10], &blobs)?;
+    for (key, blob) in keys.iter().zip(blobs.iter()) {
+        assert_eq!(hashed.get_value(root.as_ref(), key)?, Some(blob.to_vec()));
+    }
+    let leaf = hashed.get(root.as_ref(), &keys[1])?.expect("leaf");
+    hashed.db.put(&leaf, b"tampered".to_vec())?;
+    assert!(matches!(
+        hashed.get_value(root.as_ref(), &keys[1]),
+        Err(Errors::Decode(_))
+    ));
+    Ok(())
+}
+
+fn insert_keys_then_check_commits_by_policy<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    tree.set_commit_policy(Box::new(Thresholds {
+        max_keys: half,
+        max_bytes: u64::MAX,
+        veto: false,
+    }));
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let flags = tree.take_flags();
+    assert_eq!(flags.len(), 1);
+    let (summary, reason) = &flags[0];
+    assert_eq!(
+        (summary.keys, summary.before, summary.after),
+        (keys.len(), None, root)
+    );
+    assert!(summary.nodes_written > 0 && summary.bytes_written > 0);
+    assert_eq!(reason, &format!("{} keys over {}", keys.len(), half));
+    tree.removes(root.as_ref(), &keys[..half])?;
+    assert!(tree.take_flags().is_empty());
+
+    // a vetoed batch leaves the tree at the root it started from, with nothing written
+    tree.set_commit_policy(Box::new(Thresholds {
+        max_keys: usize::MAX,
+        max_bytes: 1,
+        veto: true,
+    }));
+    let mut stored = tree.db.keys()?;
+    stored.sort();
+    let vetoed = tree.removes(root.as_ref(), &keys[..1]);
+    assert!(matches!(vetoed, Err(Errors::Vetoed(_))));
+    assert!(tree
+        .inserts(root.as_ref(), &random_hashes(1), &random_hashes(1))
+        .is_err());
+    let mut after = tree.db.keys()?;
+    after.sort();
+    assert_eq!(after, stored);
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert!(tree.take_flags().is_empty());
+    Ok(())
+}
+
+fn insert_keys_then_gen_and_verify_proof_pages<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let leaf_of: std::collections::HashMap<_, _> = keys.iter().zip(leaves.iter()).collect();
+    let whole = tree.get_merkle_proofs(root.as_ref(), keys)?.expect("proof");
+    let budget = whole.iter().map(|node| node.len()).sum::<usize>() / 4;
+
+    // every page fits in the budget and verifies on its own
+    let (mut from, mut paged, mut pages) = (None, Vec::new(), 0);
+    loop {
+        let page = tree
+            .get_merkle_proof_page(root.as_ref(), keys, budget, from.as_ref())?
+            .expect("page");
+        let page_leaves: Vec<Hash> = page.keys.iter().map(|key| *leaf_of[key]).collect();
+        assert!(page.proof.iter().map(|node| node.len()).sum::<usize>() <= budget);
+        assert!(verify_merkle_proofs(
+            hasher,
+            root.as_ref(),
+            &page.keys,
+            &page_leaves,
+            Some(&page.proof)
+        ));
+        paged.extend(page.keys);
+        pages += 1;
+        match page.next {
+            None => break,
+            next => from = next,
+        }
+    }
+    assert!(pages > 1);
+    paged.sort();
+    let mut sorted = keys.to_vec();
+    sorted.sort();
+    assert_eq!(paged, sorted);
+
+    // no single key fits in a tiny budget, and absent keys yield no page
+    assert!(tree
+        .get_merkle_proof_page(root.as_ref(), keys, 1, None)
+        .is_err());
+    let absent = [random_hash()];
+    assert_eq!(
+        tree.get_merkle_proof_page(root.as_ref(), &absent, budget, None)?,
+        None
+    );
+    Ok(())
+}
+
+fn soft_remove_keys_then_restore<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    tree.set_soft_delete(true)?;
+    assert!(tree.soft_delete());
+    let metadata = tree.db.get(&tree::METADATA_KEY)?.expect("metadata");
+    assert!(Metadata::from_bytes(&metadata)?.soft_delete);
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let inserted = root;
+    let half = keys.len() / 2;
+    let removed = tree.removes(root.as_ref(), &keys[..half])?;
+    assert_ne!(removed, root);
+    assert_eq!(tree.removes(removed.as_ref(), &keys[..half])?, removed);
+    for (key, leaf) in keys.iter().zip(leaves.iter()).take(half) {
+        assert_eq!(tree.get(removed.as_ref(), key)?, None);
+        // tombstones are leaves proven like any other
+        let tombstone = tree.iter(removed.as_ref()).find(|entry| match entry {
+            Ok((k, _)) => k == key,
+            Err(_) => true,
+        });
+        let tombstone = tombstone.expect("tombstone")?.1;
+        assert_eq!(tree.tombstoned(&tombstone)?, Some(*leaf));
+        let proof = tree.get_merkle_proof(removed.as_ref(), key)?;
+        assert!(verify_proof(
+            hasher,
+            removed.as_ref(),
+            &tombstone,
+            proof.as_ref()
+        ));
+    }
+    for (key, leaf) in keys.iter().zip(leaves.iter()).skip(half) {
+        assert_eq!(tree.get(removed.as_ref(), key)?, Some(*leaf));
+        assert_eq!(tree.tombstoned(leaf)?, None);
+    }
+
+    // the same removals give the same root
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    other.set_soft_delete(true)?;
+    let other_root = other.inserts(None, keys, leaves)?;
+    assert_eq!(other.removes(other_root.as_ref(), &keys[..half])?, removed);
+
+    // restoring every key brings the tree back, after pruning the rest
+    tree.prune(removed.as_slice())?;
+    root = removed;
+    for key in keys.iter() {
+        root = tree.restore(root.as_ref(), key)?;
+    }
+    assert_eq!(root, inserted);
+
+    tree.set_soft_delete(false)?;
+    assert_eq!(tree.removes(root.as_ref(), keys)?, None);
+    Ok(())
+}
+
+fn insert_keys_then_replay_and_verify_updates<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let (fresh, absent) = (random_hash(), random_hash());
+    let updates = vec![
+        (keys[0], Some(random_hash())),
+        (keys[1], None),
+        (fresh, Some(random_hash())),
+        (absent, None),
+        (keys[2], Some(leaves[2])),
+        (fresh, None),
+    ];
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    let mut expected = other.inserts(None, keys, leaves)?;
+    for (key, leaf) in updates.iter() {
+        expected = match leaf {
+            Some(leaf) => other.insert(expected.as_ref(), key, leaf)?,
+            None => other.remove(expected.as_ref(), key)?,
+        };
+    }
+
+    let replay = tree.replay_and_verify(root.as_ref(), &updates, expected.as_ref())?;
+    assert!(replay.is_match());
+    assert_eq!(replay.root, expected);
+    assert_eq!(replay.noops, vec![3, 4]);
+    assert_eq!(replay.diff, None);
+    // nothing of the replay is stored
+    assert!(tree.get(replay.root.as_ref(), &keys[3]).is_err());
+
+    // with the nodes of the expected tree at hand, entries differing are reported
+    let mut applied = root;
+    for (key, leaf) in updates.iter() {
+        applied = match leaf {
+            Some(leaf) => tree.insert(applied.as_ref(), key, leaf)?,
+            None => tree.remove(applied.as_ref(), key)?,
+        };
+    }
+    assert_eq!(applied, expected);
+    let mut forged = updates.clone();
+    forged[1] = (keys[1], Some(leaves[1]));
+    let replay = tree.replay_and_verify(root.as_ref(), &forged, expected.as_ref())?;
+    assert!(!replay.is_match());
+    let diff = replay.diff.expect("diff");
+    assert_eq!(diff.inserted, vec![(keys[1], leaves[1])]);
+    assert!(diff.removed.is_empty() && diff.modified.is_empty());
+
+    let replay = tree.replay_and_verify(root.as_ref(), &updates, Some(&random_hash()))?;
+    assert!(!replay.is_match() && replay.diff.is_none());
+    Ok(())
+}
+
+fn insert_keys_then_apply_updates_with_witness<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let fresh = random_hash();
+    let updates = vec![
+        (keys[0], Some(random_hash())),
+        (keys[1], None),
+        (fresh, Some(random_hash())),
+        (keys[2], None),
+        (fresh, None),
+    ];
+    let touched: Vec<Hash> = updates.iter().map(|(key, _)| *key).collect();
+    let witness = tree.collect_witness(root.as_ref(), &touched)?;
+    assert!(!witness.is_empty() && witness.len() < tree.db.keys()?.len());
+
+    let stateless = Monotree::<MemoryDB, H>::new("");
+    let applied = stateless.apply_with_witness(root.as_ref(), &witness, &updates)?;
+    let mut expected = root;
+    for (key, leaf) in updates.iter() {
+        expected = match leaf {
+            Some(leaf) => tree.insert(expected.as_ref(), key, leaf)?,
+            None => tree.remove(expected.as_ref(), key)?,
+        };
+    }
+    assert_eq!(applied, expected);
+
+    // a witness of other keys, or of tampered nodes, is refused
+    let other = tree.collect_witness(root.as_ref(), &keys[3..4])?;
+    assert!(matches!(
+        stateless.apply_with_witness(root.as_ref(), &other, &updates),
+        Err(Errors::ProofInvalid(_))
+    ));
+    let mut tampered = witness.clone();
+    tampered[0].1[0] ^= 1;
+    assert!(matches!(
+        stateless.apply_with_witness(root.as_ref(), &tampered, &updates),
+        Err(Errors::ProofInvalid(_))
+    ));
+    assert_eq!(tree.collect_witness(None, &touched)?, vec![]);
+    Ok(())
+}
+
+fn insert_keys_then_export_subtrees_by_prefix<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let root_hash = root.expect("root");
+    assert_eq!(
+        tree.subtree_root(root.as_ref(), &[])?,
+        Some((vec![], root_hash))
+    );
+    assert_eq!(tree.subtree_root(None, &[false])?, None);
+    let bits = bytes_to_slicebit(&keys[0], &(0..HASH_LEN * 8));
+    assert_eq!(
+        tree.subtree_root(root.as_ref(), &bits)?,
+        Some((bits.clone(), leaves[0]))
+    );
+    let (path, _) = tree
+        .subtree_root(root.as_ref(), &bits[..4])?
+        .expect("subtree");
+    assert!(path.len() >= 4 && path[..4] == bits[..4]);
+    assert!(tree
+        .subtree_root(root.as_ref(), &[bits.clone(), vec![false]].concat())
+        .is_err());
+
+    // a shard holding the keys under a prefix serves their proofs against the whole root
+    let mut shards = Vec::new();
+    for prefix in [vec![false, false], vec![false, true], vec![true]].iter() {
+        let mut shard = Monotree::<MemoryDB, H>::new("");
+        let nodes = tree.export_subtree(root.as_ref(), prefix)?;
+        for (hash, bytes) in nodes.iter() {
+            shard.db.put(hash, bytes.to_vec())?;
+        }
+        for (key, leaf) in keys.iter().zip(leaves.iter()) {
+            let bits = bytes_to_slicebit(key, &(0..prefix.len()));
+            if bits == *prefix {
+                assert_eq!(shard.get(root.as_ref(), key)?, Some(*leaf));
+                assert_eq!(
+                    shard.get_merkle_proof(root.as_ref(), key)?,
+                    tree.get_merkle_proof(root.as_ref(), key)?
+                );
+            }
+        }
+        shards.extend(nodes);
+    }
+
+    // the shards recombine into the whole tree
+    let mut whole = Monotree::<MemoryDB, H>::new("");
+    whole.apply_nodes(root.as_ref(), &shards)?;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(whole.get(root.as_ref(), key)?, Some(*leaf));
+    }
+    assert_eq!(
+        whole.db.keys()?.len(),
+        tree.diff_nodes(None, root.as_ref())?.len()
+    );
+    Ok(())
+}
+
+fn insert_keys_then_merge_disjoint_trees<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let expected = tree.inserts(root.as_ref(), keys, leaves)?;
+    let (low, high): (Vec<_>, Vec<_>) = keys
+        .iter()
+        .zip(leaves.iter())
+        .partition(|(key, _)| key[0] < 0x80);
+    let (low_keys, low_leaves): (Vec<Hash>, Vec<Hash>) = low.into_iter().unzip();
+    let (high_keys, high_leaves): (Vec<Hash>, Vec<Hash>) = high.into_iter().unzip();
+    let root_a = tree.inserts(root.as_ref(), &low_keys, &low_leaves)?;
+    let root_b = tree.inserts(root.as_ref(), &high_keys, &high_leaves)?;
+    // merged nodes are written anew, so of the same tree but not of the same root
+    let merged = tree.merge(root_a.as_ref(), root_b.as_ref())?;
+    assert!(tree.same_tree(merged.as_ref(), expected.as_ref())?);
+    assert_eq!(tree.merge(root_b.as_ref(), root_a.as_ref())?, merged);
+    assert_eq!(tree.merge(None, expected.as_ref())?, expected);
+    assert_eq!(tree.merge(expected.as_ref(), None)?, expected);
+    assert_eq!(tree.merge(None, None)?, None);
+
+    // keys interleaved, of every other one in each tree, merge the same
+    let (half, rest) = keys.split_at(keys.len() / 2);
+    let root_a = tree.inserts(root.as_ref(), half, &leaves[..half.len()])?;
+    let root_b = tree.inserts(root.as_ref(), rest, &leaves[half.len()..])?;
+    let merged = tree.merge(root_a.as_ref(), root_b.as_ref())?;
+    assert!(tree.same_tree(merged.as_ref(), expected.as_ref())?);
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(merged.as_ref(), key)?, Some(*leaf));
+    }
+
+    // keys in both trees merge only with the same leaves
+    let root_b = tree.inserts(root_b.as_ref(), &keys[..1], &leaves[..1])?;
+    let merged = tree.merge(root_a.as_ref(), root_b.as_ref())?;
+    assert!(tree.same_tree(merged.as_ref(), expected.as_ref())?);
+    let root_b = tree.insert(root_b.as_ref(), &keys[0], &random_hash())?;
+    assert!(tree.merge(root_a.as_ref(), root_b.as_ref()).is_err());
+    Ok(())
+}
+
+fn insert_keys_in_transaction_then_commit_or_discard<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    let stored = tree.db.keys()?.len();
+
+    // a failed transaction writes nothing, however far it got
+    let failed = tree.transaction(root.as_ref(), |txn| {
+        txn.inserts(&keys[half..], &leaves[half..])?;
+        txn.remove(&keys[0])?;
+        assert_eq!(txn.get(&keys[half])?, Some(leaves[half]));
+        let proof = txn.get_merkle_proof(&keys[half])?;
+        assert!(verify_proof(
+            hasher,
+            txn.root(),
+            &leaves[half],
+            proof.as_ref()
+        ));
+        Err(Errors::new("abort"))
+    });
+    assert!(failed.is_err());
+    assert_eq!(tree.db.keys()?.len(), stored);
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+
+    // a transaction succeeded gives the same root as the updates applied one by one
+    let mut within = None;
+    let committed = tree.transaction(root.as_ref(), |txn| {
+        txn.inserts(&keys[half..], &leaves[half..])?;
+        txn.insert(&keys[1], &leaves[0])?;
+        txn.removes(&keys[..1])?;
+        within = txn.root().cloned();
+        Ok(())
+    })?;
+    assert_eq!(committed, within);
+    let mut expected = tree.inserts(root.as_ref(), &keys[half..], &leaves[half..])?;
+    expected = tree.insert(expected.as_ref(), &keys[1], &leaves[0])?;
+    expected = tree.remove(expected.as_ref(), &keys[0])?;
+    assert_eq!(committed, expected);
+    for (key, leaf) in keys.iter().zip(leaves.iter()).skip(2) {
+        assert_eq!(tree.get(committed.as_ref(), key)?, Some(*leaf));
+    }
+    assert_eq!(tree.get(committed.as_ref(), &keys[1])?, Some(leaves[0]));
+    assert_eq!(tree.get(committed.as_ref(), &keys[0])?, None);
+    Ok(())
+}
+
+fn insert_and_remove_keys_in_single_batch<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    let mut expected = tree.inserts(root.as_ref(), &keys[half..], &leaves[half..])?;
+    expected = tree.insert(expected.as_ref(), &keys[1], &leaves[0])?;
+    expected = tree.remove(expected.as_ref(), &keys[0])?;
+
+    let roots = tree.watch_roots();
+    let mut batch = tree.batch(root.as_ref());
+    for (key, leaf) in keys[half..].iter().zip(leaves[half..].iter()).rev() {
+        batch = batch.insert(key, leaf);
+    }
+    // the last update of a key wins, however the keys are sorted
+    let mut batch = batch
+        .remove(&keys[1])
+        .insert(&keys[1], &leaves[0])
+        .insert(&keys[0], &leaves[1])
+        .remove(&keys[0]);
+    // lookups observe the updates gathered, before any is applied
+    assert_eq!(batch.get(&keys[0])?, None);
+    assert_eq!(batch.get(&keys[1])?, Some(leaves[0]));
+    if let Some((key, leaf)) = keys.iter().zip(leaves.iter()).skip(2).last() {
+        assert_eq!(batch.get(key)?, Some(*leaf));
+    }
+    assert_eq!(batch.len(), keys.len() - half + 4);
+    let committed = batch.commit()?;
+    assert!(tree.same_tree(committed.as_ref(), expected.as_ref())?);
+    // a single batch committed, producing a single new root
+    assert_eq!(
+        roots
+            .try_iter()
+            .map(|change| change.after)
+            .collect::<Vec<_>>(),
+        vec![committed]
+    );
+    assert_eq!(tree.get(committed.as_ref(), &keys[0])?, None);
+    assert_eq!(tree.get(committed.as_ref(), &keys[1])?, Some(leaves[0]));
+
+    let batch = tree.batch(committed.as_ref());
+    assert!(batch.is_empty());
+    assert_eq!(batch.commit()?, committed);
+    Ok(())
+}
+
+fn insert_keys_then_prove_default_leaves<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let default = [0u8; HASH_LEN];
+    tree.set_default_leaf(Some(&default))?;
+    assert_eq!(tree.default_leaf(), Some(&default));
+    let (absent, others) = keys.split_first().expect("keys");
+    root = tree.inserts(root.as_ref(), others, &leaves[1..])?;
+    assert_eq!(tree.get(root.as_ref(), absent)?, Some(default));
+    assert_eq!(tree.get(root.as_ref(), &others[0])?, Some(leaves[1]));
+
+    for (key, leaf) in [(absent, &default), (&others[0], &leaves[1])].iter() {
+        let proof = tree.get_leaf_proof(root.as_ref(), key)?;
+        assert!(verify_leaf_proof(
+            hasher,
+            root.as_ref(),
+            *key,
+            Some(*leaf),
+            Some(&default),
+            &proof
+        ));
+        // the default leaf is proven only along with the default leaf of the tree
+        assert_eq!(
+            verify_leaf_proof(hasher, root.as_ref(), *key, Some(*leaf), None, &proof),
+            *key != absent
+        );
+        assert!(!verify_leaf_proof(
+            hasher,
+            root.as_ref(),
+            *key,
+            Some(&random_hash()),
+            Some(&default),
+            &proof
+        ));
+    }
+
+    // inserting the default leaf removes the key
+    let removed = tree.remove(root.as_ref(), &others[0])?;
+    assert_eq!(tree.insert(root.as_ref(), &others[0], &default)?, removed);
+    assert_eq!(tree.insert(removed.as_ref(), absent, &default)?, removed);
+
+    // the default leaf is kept by the tree reopened, until unset
+    let mut tree = Monotree::<D, H>::with_db(tree.db)?;
+    assert_eq!(tree.get(removed.as_ref(), &others[0])?, Some(default));
+    tree.set_default_leaf(None)?;
+    assert_eq!(tree.get(removed.as_ref(), &others[0])?, None);
+    let proof = tree.get_leaf_proof(removed.as_ref(), &others[0])?;
+    assert!(verify_leaf_proof(
+        hasher,
+        removed.as_ref(),
+        &others[0],
+        None,
+        None,
+        &proof
+    ));
+    let tree = Monotree::<D, H>::with_db(tree.db)?;
+    assert_eq!(tree.default_leaf(), None);
+    Ok(())
+}
+
+fn insert_short_keys_then_gen_and_verify_proofs<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    const KEY_LEN: usize = 20;
+    assert!(tree.set_key_len(0).is_err() && tree.set_key_len(HASH_LEN + 1).is_err());
+    tree.set_key_len(KEY_LEN)?;
+    assert_eq!(tree.key_len(), KEY_LEN);
+    let metadata = tree.db.get(&tree::METADATA_KEY)?.expect("metadata");
+    assert_eq!(Metadata::from_bytes(&metadata)?.key_len, KEY_LEN);
+    let mut full = Monotree::<MemoryDB, H>::new("");
+    let full_root = full.inserts(None, keys, leaves)?;
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let size = |proof: &Option<Proof>| {
+        proof
+            .iter()
+            .flatten()
+            .map(|(_, cut)| cut.len())
+            .sum::<usize>()
+    };
+    assert!(tree.estimated_size(root.as_ref())? < full.estimated_size(full_root.as_ref())?);
+
+    let cut = |key: &Hash| {
+        let mut key = *key;
+        key[KEY_LEN..].fill(0);
+        key
+    };
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        // keys sharing the leading bytes are the same key
+        assert_eq!(tree.get(root.as_ref(), &cut(key))?, Some(*leaf));
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+        assert!(verify_proof(hasher, root.as_ref(), leaf, proof.as_ref()));
+        assert!(size(&proof) < size(&full.get_merkle_proof(full_root.as_ref(), key)?));
+    }
+    let mut expected: Vec<_> = keys
+        .iter()
+        .zip(leaves.iter())
+        .map(|(key, leaf)| (cut(key), *leaf))
+        .collect();
+    expected.sort();
+    let mut entries = tree.iter(root.as_ref()).collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    assert_eq!(entries, expected);
+
+    // free functions take keys cut to the length
+    let paths: Vec<&[u8]> = keys.iter().map(|key| &key[..KEY_LEN]).collect();
+    let proof = tree.get_merkle_proofs(root.as_ref(), keys)?;
+    assert!(verify_merkle_proofs(
+        hasher,
+        root.as_ref(),
+        &paths,
+        leaves,
+        proof.as_ref()
+    ));
+    let absent = random_hash();
+    let proof = tree.get_non_inclusion_proof(root.as_ref(), &absent)?;
+    assert!(verify_non_inclusion_proof(
+        hasher,
+        root.as_ref(),
+        &absent[..KEY_LEN],
+        proof.as_ref()
+    ));
+
+    let old = root;
+    root = tree.removes(root.as_ref(), &keys[..10])?;
+    let proof = tree.consistency_proof(old.as_ref(), root.as_ref())?;
+    assert_eq!(proof.updates.len(), 10);
+    assert!(verify_consistency(
+        hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &proof,
+        Consistency::Any
+    ));
+    Ok(())
+}
+
+fn open_batch_then_read_own_writes<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let (kept, pending) = (random_bytes(HASH_LEN), random_bytes(HASH_LEN));
+    tree.db.put(&kept, b"kept".to_vec())?;
+
+    // pending writes are observed within the batch, along with the tree committed before
+    tree.db.init_batch()?;
+    tree.db.put(&pending, b"pending".to_vec())?;
+    tree.db.delete(&kept)?;
+    assert_eq!(tree.db.get(&pending)?, Some(b"pending".to_vec()));
+    assert_eq!(tree.db.get(&kept)?, None);
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[0])?;
+    assert!(verify_proof(
+        hasher,
+        root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+
+    // a batch left open is discarded by the next one
+    tree.db.init_batch()?;
+    assert_eq!(tree.db.get(&pending)?, None);
+    assert_eq!(tree.db.get(&kept)?, Some(b"kept".to_vec()));
+    tree.db.finish_batch()?;
+    assert_eq!(tree.db.get(&pending)?, None);
+
+    tree.db.init_batch()?;
+    tree.db.put(&pending, b"pending".to_vec())?;
+    tree.db.delete(&kept)?;
+    tree.db.finish_batch()?;
+    assert_eq!(tree.db.get(&pending)?, Some(b"pending".to_vec()));
+    assert_eq!(tree.db.get(&kept)?, None);
+    Ok(())
+}
+
+fn insert_child_roots_then_verify_chained_proof<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    // split entries into child trees, then insert each child root into the parent tree
+    let ids = random_hashes(4);
+    let n = keys.len() / ids.len();
+    let mut children = Vec::new();
+    for (i, id) in ids.iter().enumerate() {
+        let child = tree.inserts(None, &keys[i * n..(i + 1) * n], &leaves[i * n..(i + 1) * n])?;
+        root = tree.insert(root.as_ref(), id, &child.expect("child"))?;
+        children.push(child);
+    }
+    for (i, id) in ids.iter().enumerate() {
+        for j in i * n..(i + 1) * n {
+            let proof = tree.get_chained_proof(root.as_ref(), &[*id, keys[j]])?;
+            assert_eq!(proof.as_ref().map(|p| p.len()), Some(2));
+            assert!(tree::verify_chained_proof(
+                hasher,
+                root.as_ref(),
+                &leaves[j],
+                proof.as_ref()
+            ));
+            assert!(!tree::verify_chained_proof(
+                hasher,
+                children[i].as_ref(),
+                &leaves[j],
+                proof.as_ref()
+            ));
+            let wrong = (j + 1) % keys.len();
+            assert!(!tree::verify_chained_proof(
+                hasher,
+                root.as_ref(),
+                &leaves[wrong],
+                proof.as_ref()
+            ));
+        }
+    }
+    assert_eq!(
+        tree.get_chained_proof(root.as_ref(), &[keys[0], ids[0]])?,
+        None
+    );
+    Ok(())
+}
+
+fn insert_keys_into_nested_trees_then_remove<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    // two levels of child trees: ids[i % 2] -> ids[2 + i % 3] -> (key, leaf)
+    let ids = random_hashes(5);
+    let path = |i: usize| [ids[i % 2], ids[2 + i % 3]];
+    for (i, (k, v)) in keys.iter().zip(leaves.iter()).enumerate() {
+        root = tree.insert_nested(root.as_ref(), &path(i), k, v)?;
+    }
+    for (i, (k, v)) in keys.iter().zip(leaves.iter()).enumerate() {
+        assert_eq!(tree.get_nested(root.as_ref(), &path(i), k)?, Some(*v));
+        assert_eq!(tree.get_nested(root.as_ref(), &path(i + 1), k)?, None);
+        let chain = [&path(i)[..], &[*k]].concat();
+        let proof = tree.get_chained_proof(root.as_ref(), &chain)?;
+        assert!(tree::verify_chained_proof(
+            hasher,
+            root.as_ref(),
+            v,
+            proof.as_ref()
+        ));
+    }
+    for (i, k) in keys.iter().enumerate() {
+        root = tree.remove_nested(root.as_ref(), &path(i), k)?;
+        assert_eq!(tree.get_nested(root.as_ref(), &path(i), k)?, None);
+    }
+    assert_eq!(root, None);
+    Ok(())
+}
+
+/// Insert, prove and remove entries of any store, coded against `MerkleStore` alone.
+fn round_trip_store<S: MerkleStore<Key = Hash, Value = Hash>>(
+    store: &mut S,
+    mut root: Option<S::Root>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<Option<S::Root>> {
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        root = store.insert(root.as_ref(), k, v)?;
+    }
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(store.get(root.as_ref(), k)?, Some(*v));
+        let proof = store.prove(root.as_ref(), k)?;
+        assert!(store.verify(root.as_ref(), k, v, proof.as_ref()));
+        assert!(!store.verify(root.as_ref(), k, &random_hash(), proof.as_ref()));
+    }
+    for k in keys.iter().skip(1) {
+        root = store.remove(root.as_ref(), k)?;
+        assert_eq!(store.get(root.as_ref(), k)?, None);
+        assert!(store.prove(root.as_ref(), k)?.is_none());
+    }
+    Ok(root)
+}
+
+fn insert_keys_then_prove_through_merkle_store<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let root = round_trip_store(&mut tree, root, keys, leaves)?;
+    let single = tree.inserts(None, &keys[..1], &leaves[..1])?;
+    assert!(tree.same_tree(root.as_ref(), single.as_ref())?);
+    Ok(())
+}
+
+fn par_insert_keys_then_compare_roots<D: Database, H: Hasher + Sync>(
+    mut tree: Monotree<D, H>,
+    _hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    let expected = other.inserts(root.as_ref(), keys, leaves)?;
+    let root = tree.par_inserts(root.as_ref(), keys, leaves)?;
+    assert_eq!(root, expected);
+    assert_eq!(
+        tree.gets(root.as_ref(), keys)?,
+        leaves.iter().map(|l| Some(*l)).collect::<Vec<_>>()
+    );
+
+    // into an existing tree, updating some keys and with enough others to hash in parallel
+    let mut more = random_hashes(5000);
+    more.extend_from_slice(&keys[..keys.len() / 2]);
+    let values = random_hashes(more.len());
+    let expected = other.inserts(expected.as_ref(), &more, &values)?;
+    let root = tree.par_inserts(root.as_ref(), &more, &values)?;
+    assert_eq!(root, expected);
+    for (key, value) in more.iter().zip(values.iter()).step_by(97) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*value));
+    }
+
+    // of entries of the same key, the last one wins
+    let leaf = random_hash();
+    let twice = tree.par_inserts(root.as_ref(), &[keys[0], keys[0]], &[random_hash(), leaf])?;
+    assert_eq!(twice, tree.insert(root.as_ref(), &keys[0], &leaf)?);
+    assert_eq!(tree.par_inserts(root.as_ref(), &[], &[])?, root);
+    Ok(())
+}
+
+fn build_tree_from_sorted_leaves_then_compare_roots<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let mut entries: Vec<(Hash, Hash)> = keys.iter().cloned().zip(leaves.iter().cloned()).collect();
+    entries.sort();
+    let built = tree.build_from_sorted(&entries)?;
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    assert_eq!(built, other.inserts(root.as_ref(), keys, leaves)?);
+    for (key, leaf) in entries.iter() {
+        assert_eq!(tree.get(built.as_ref(), key)?, Some(*leaf));
+        let proof = tree.get_merkle_proof(built.as_ref(), key)?;
+        assert!(verify_proof(hasher, built.as_ref(), leaf, proof.as_ref()));
+    }
+    assert_eq!(
+        tree.build_from_sorted(&entries[..1])?,
+        tree.insert(None, &entries[0].0, &entries[0].1)?
+    );
+    assert_eq!(tree.build_from_sorted(&[])?, None);
+
+    // out of order, or of the same key twice
+    entries.swap(0, 1);
+    assert!(tree.build_from_sorted(&entries).is_err());
+    entries.swap(0, 1);
+    entries.insert(1, entries[0]);
+    assert!(tree.build_from_sorted(&entries).is_err());
+    Ok(())
+}
+
+fn export_tree_then_import_into_another_db<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    root = tree.inserts(root.as_ref(), keys, leaves)?;
+    let mut snapshot = Vec::new();
+    let count = tree.export(root.as_ref(), &mut snapshot)?;
+    assert_eq!(count as usize, tree.diff_nodes(None, root.as_ref())?.len());
+
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    assert_eq!(other.import(&snapshot[..])?, root);
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(other.get(root.as_ref(), key)?, Some(*leaf));
+        let proof = other.get_merkle_proof(root.as_ref(), key)?;
+        assert!(verify_proof(hasher, root.as_ref(), leaf, proof.as_ref()));
+    }
+    let mut empty = Vec::new();
+    tree.export(None, &mut empty)?;
+    assert_eq!(other.import(&empty[..])?, None);
+
+    // truncated, tampered with, or of a tree of other settings
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    assert!(other.import(&snapshot[..snapshot.len() - 1]).is_err());
+    let mut tampered = snapshot.clone();
+    let n = tampered.len();
+    tampered[n - 2] ^= 0x01;
+    assert!(other.import(&tampered[..]).is_err());
+    other.set_key_len(16)?;
+    assert!(other.import(&snapshot[..]).is_err());
+    Ok(())
+}
+
+fn copy_tree_into_another_db<D: Database, H: Hasher>(
+    mut tree: Monotree<D, H>,
+    hasher: &H,
+    mut root: Option<Hash>,
+    keys: &[Hash],
+    leaves: &[Hash],
+) -> Result<()> {
+    let half = keys.len() / 2;
+    root = tree.inserts(root.as_ref(), &keys[..half], &leaves[..half])?;
+    let mut target = Monotree::<MemoryDB, H>::new("");
+    assert_eq!(tree.copy_to(root.as_ref(), &mut target)?, root);
+
+    // copying a later root writes only the nodes the target lacks
+    let copied = root;
+    root = tree.inserts(root.as_ref(), &keys[half..], &leaves[half..])?;
+    let lacking = tree.diff_nodes(copied.as_ref(), root.as_ref())?;
+    let before = target.db.keys()?.len();
+    assert_eq!(tree.copy_to(root.as_ref(), &mut target)?, root);
+    assert_eq!(target.db.keys()?.len() - before, lacking.len());
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(target.get(root.as_ref(), key)?, Some(*leaf));
+        let proof = target.get_merkle_proof(root.as_ref(), key)?;
+        assert!(verify_proof(hasher, root.as_ref(), leaf, proof.as_ref()));
+    }
+    assert_eq!(tree.copy_to(None, &mut target)?, None);
+
+    let mut other = Monotree::<MemoryDB, H>::new("");
+    other.set_key_len(16)?;
+    assert!(tree.copy_to(root.as_ref(), &mut other).is_err());
+    Ok(())
+}
+
 macro_rules! impl_integration_test {
     ($fn:ident, ($d:expr, $db:ident), ($h:expr, $hasher:ident), $n:expr) => {
         paste::item_with_macros! {
@@ -185,63 +2310,3820 @@ macro_rules! impl_integration_test {
                     if fs::metadata(&dbname).is_ok() {
                         fs::remove_dir_all(&dbname).unwrap()
                     }
-                });
-                let keys = random_hashes($n);
-                let leaves = random_hashes($n);
-                let tree = Monotree::<$db, $hasher>::new(&dbname);
-                let hasher = $hasher::new();
-                let root: Option<Hash> = None;
-                $fn(tree, &hasher, root, &keys, &leaves)?;
-                Ok(())
+                });
+                let keys = random_hashes($n);
+                let leaves = random_hashes($n);
+                let tree = Monotree::<$db, $hasher>::new(&dbname);
+                let hasher = $hasher::new();
+                let root: Option<Hash> = None;
+                $fn(tree, &hasher, root, &keys, &leaves)?;
+                Ok(())
+            }
+        }
+    };
+}
+
+macro_rules! impl_test_with_params {
+    ([$($fn:tt)+], [$($db:tt)+], [$($hasher:tt)+], [$n:tt, $($ns:tt),*]) => {
+        impl_test_with_params!([$($fn)+], [$($db)+], [$($hasher)+], [$n]);
+        impl_test_with_params!([$($fn)+], [$($db)+], [$($hasher)+], [$($ns),*]);
+    };
+
+    ([$($fn:tt)+], [$($db:tt)+], [($($h:tt)+), $($hasher:tt),*], [$n:tt]) => {
+        impl_test_with_params!([$($fn)+], [$($db)+], [($($h)+)], [$n]);
+        impl_test_with_params!([$($fn)+], [$($db)+], [$($hasher),*], [$n]);
+    };
+
+    ([$($fn:tt)+], [($($d:tt)+), $($db:tt),*], [($($h:tt)+)], [$n:tt]) => {
+        impl_test_with_params!([$($fn)+], [($($d)+)], [($($h)+)], [$n]);
+        impl_test_with_params!([$($fn)+], [$($db),*], [($($h)+)], [$n]);
+    };
+
+    ([$f:tt, $($fn:tt),*], [($($d:tt)+)], [($($h:tt)+)], [$n:tt]) => {
+        impl_integration_test!($f, ($($d)+), ($($h)+), $n);
+        impl_test_with_params!([$($fn),*], [($($d)+)], [($($h)+)], [$n]);
+    };
+
+    ([$f:tt], [($($d:tt)+)], [($($h:tt)+)], [$n:tt]) => {
+        impl_integration_test!($f, ($($d)+), ($($h)+), $n);
+    };
+
+    ($($other:tt)*) => {};
+}
+
+impl_test_with_params!(
+    [
+        insert_keys_then_verify_values,
+        insert_keys_then_gen_and_verify_proof,
+        insert_keys_then_delete_keys_immediately,
+        insert_keys_then_delete_keys_in_order,
+        insert_keys_then_delete_keys_reversely,
+        insert_keys_then_delete_keys_randomly
+    ],
+    [("hashmap", MemoryDB), ("rocksdb", RocksDB), ("sled", Sled)],
+    [
+        ("blake3", Blake3),
+        ("blake2s", Blake2s),
+        ("blake2b", Blake2b),
+        ("sha2", Sha2),
+        ("sha3", Sha3)
+    ],
+    [100, 500, 1000]
+);
+
+type ShardedMemoryDB = ShardedDB<MemoryDB>;
+type ShardedRocksDB = ShardedDB<RocksDB>;
+type ShardedSled = ShardedDB<Sled>;
+type MirroredMemoryDB = MirroredDB<MemoryDB, MemoryDB>;
+type MirroredRocksSled = MirroredDB<RocksDB, Sled>;
+type CachedMemoryDB = CachedDb<MemoryDB>;
+type CachedRocksDB = CachedDb<RocksDB>;
+type CachedSled = CachedDb<Sled>;
+type TieredMemoryDB = TieredDb<MemoryDB, MemoryDB>;
+type TieredRocksDB = TieredDb<MemoryDB, RocksDB>;
+
+macro_rules! impl_durability_test {
+    ($d:expr, $db:ident) => {
+        paste::item_with_macros! {
+            #[test]
+            fn [<test_ $d _flush_on_finish_batch>]() -> Result<()> {
+                let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+                let _g = scopeguard::guard((), |_| {
+                    if fs::metadata(&dbname).is_ok() {
+                        fs::remove_dir_all(&dbname).unwrap()
+                    }
+                });
+                let keys = random_hashes(100);
+                let leaves = random_hashes(100);
+                let mut tree = Monotree::<$db, Blake3>::new(&dbname);
+                tree.db.set_durability(Durability::Flush);
+                let root = tree.inserts(None, &keys, &leaves)?;
+                for (k, v) in keys.iter().zip(leaves.iter()) {
+                    assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+                }
+                let root = tree.removes(root.as_ref(), &keys)?;
+                assert_eq!(root, None);
+
+                // flushes every few batches, or once a while has passed, leave batches as they are
+                for durability in [
+                    Durability::FlushEvery(3),
+                    Durability::FlushInterval(std::time::Duration::from_millis(1)),
+                ]
+                .iter()
+                {
+                    tree.db.set_durability(*durability);
+                    let mut root = None;
+                    for (k, v) in keys.chunks(10).zip(leaves.chunks(10)) {
+                        root = tree.inserts(root.as_ref(), k, v)?;
+                    }
+                    for (k, v) in keys.iter().zip(leaves.iter()) {
+                        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+                    }
+                }
+
+                // nodes synced by a batch are there once reopened, along with the root after them
+                tree.db.set_durability(Durability::Sync);
+                let root = tree.inserts(None, &keys, &leaves)?;
+                tree.swap_root(None, root.as_ref())?;
+                drop(tree);
+                let mut tree = Monotree::<$db, Blake3>::new(&dbname);
+                assert_eq!(tree.load_root()?, root);
+                for (k, v) in keys.iter().zip(leaves.iter()) {
+                    assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+                }
+                Ok(())
+            }
+        }
+    };
+}
+
+#[test]
+fn test_mem_cache_evicts_least_recently_used() -> Result<()> {
+    let keys = random_hashes(10);
+    let mut cache = MemCache::with_capacity(3, 1 << 10);
+    for key in keys[..3].iter() {
+        cache.fill(key, key.to_vec());
+    }
+    assert_eq!(cache.get(&keys[0])?, Some(keys[0].to_vec()));
+    cache.fill(&keys[3], keys[3].to_vec());
+    assert!(!cache.contains(&keys[1]));
+    assert!(cache.contains(&keys[0]) && cache.contains(&keys[2]) && cache.contains(&keys[3]));
+    assert_eq!(cache.usage(), (3, 3 * 64));
+
+    // writes within a batch are pinned over the capacity, and dropped unless committed
+    cache.init_batch();
+    for key in keys[4..].iter() {
+        cache.put(key, key.to_vec())?;
+    }
+    cache.delete(&keys[0])?;
+    assert!(keys[4..].iter().all(|key| cache.contains(key)));
+    assert!(cache.contains(&keys[0]) && cache.get(&keys[0])?.is_none());
+    cache.discard_batch();
+    assert!(keys[4..].iter().all(|key| !cache.contains(key)));
+    assert!(!cache.contains(&keys[0]));
+    cache.init_batch();
+    for key in keys[4..].iter() {
+        cache.put(key, key.to_vec())?;
+    }
+    cache.init_batch();
+    assert!(!cache.contains(&keys[4]));
+    for key in keys[4..].iter() {
+        cache.put(key, key.to_vec())?;
+    }
+    cache.finish_batch();
+    assert_eq!(cache.usage().0, 3);
+    assert!(keys[7..].iter().all(|key| cache.contains(key)));
+
+    cache.set_capacity(10, 100);
+    assert_eq!(cache.usage(), (1, 64));
+    assert!(cache.contains(&keys[9]));
+    Ok(())
+}
+
+#[test]
+fn test_mem_cache_tunes_capacity_by_hit_rate() -> Result<()> {
+    use std::sync::atomic::AtomicUsize;
+    let keys = random_hashes(20);
+    let budget = Arc::new(AtomicUsize::new(usize::MAX));
+    let mut cache = MemCache::with_capacity(1000, 10 * 64);
+    let available = Arc::clone(&budget);
+    cache.set_tuning(Some(CacheTuning {
+        min_bytes: 10 * 64,
+        max_bytes: 100 * 64,
+        target_hit_rate: 0.9,
+        window: 20,
+        budget: Some(Arc::new(move || available.load(Ordering::Relaxed))),
+    }));
+    let read_all = |cache: &mut MemCache| {
+        for key in keys.iter() {
+            if cache.lookup(key).is_none() {
+                cache.fill(key, key.to_vec());
+            }
+        }
+    };
+    read_all(&mut cache);
+    assert_eq!(cache.stats().max_bytes, 20 * 64);
+    for _ in 0..5 {
+        read_all(&mut cache);
+    }
+    let before = cache.stats();
+    read_all(&mut cache);
+    let stats = cache.stats();
+    assert_eq!((stats.entries, stats.bytes), (20, 20 * 64));
+    assert_eq!(stats.hits - before.hits, 20);
+    assert_eq!(stats.misses, before.misses);
+    assert!(stats.hit_rate() > 0.5);
+
+    // the budget shrinks the cache on the next lookup, even below the minimum
+    budget.store(5 * 64, Ordering::Relaxed);
+    cache.lookup(&keys[0]);
+    assert_eq!(cache.usage(), (5, 5 * 64));
+    budget.store(usize::MAX, Ordering::Relaxed);
+
+    // a cache mostly unused shrinks to twice what it holds, within the bounds
+    for _ in 0..20 {
+        cache.lookup(&keys[0]);
+    }
+    assert_eq!(cache.stats().max_bytes, 10 * 64);
+    cache.set_tuning(None);
+    cache.set_capacity(1000, 10 * 64);
+    for _ in 0..5 {
+        read_all(&mut cache);
+    }
+    assert_eq!(cache.stats().max_bytes, 10 * 64);
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_with_small_cache() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(500), random_hashes(500));
+    let mut tree = Monotree::<CachedDb<RocksDB>, Blake3>::new(&dbname);
+    tree.db.set_cache_capacity(16, 1 << 10);
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let root = tree.removes(root.as_ref(), &keys[..100])?;
+    assert_eq!(tree.gets(root.as_ref(), &keys[..100])?, vec![None; 100]);
+    for (key, leaf) in keys.iter().zip(leaves.iter()).skip(100) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_metrics_count_nodes_cache_proofs_and_batches() -> Result<()> {
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let counters = Arc::new(Counters::default());
+    let mut tree = Monotree::<CachedDb<MemoryDB>, Blake3>::new("");
+    tree.set_metrics(counters.clone());
+    tree.db.set_metrics(counters.clone());
+    let (root, report) = tree.inserts_with_report(None, &keys, &leaves)?;
+    assert_eq!(counters.batches.load(Ordering::Relaxed), 1);
+    assert_eq!(
+        counters.nodes_written.load(Ordering::Relaxed),
+        report.nodes_written
+    );
+    assert_eq!(
+        counters.bytes_written.load(Ordering::Relaxed),
+        report.bytes_written
+    );
+    assert_eq!(
+        counters.nodes_read.load(Ordering::Relaxed),
+        report.nodes_read
+    );
+
+    // nodes written by the batch are all read from the cache
+    let read = counters.nodes_read.load(Ordering::Relaxed);
+    let hits = counters.cache_hits.load(Ordering::Relaxed);
+    let proof = tree
+        .get_merkle_proof(root.as_ref(), &keys[0])?
+        .expect("proof");
+    assert_eq!(counters.proofs.load(Ordering::Relaxed), 1);
+    assert_eq!(
+        counters.proof_bytes.load(Ordering::Relaxed),
+        proof.to_bytes().len() as u64
+    );
+    let lookups = counters.nodes_read.load(Ordering::Relaxed) - read;
+    assert!(lookups > 0);
+    assert_eq!(counters.cache_hits.load(Ordering::Relaxed) - hits, lookups);
+    assert!(counters.hit_rate() > 0.0);
+
+    // proofs of keys missing are not counted
+    assert_eq!(tree.get_merkle_proof(None, &keys[0])?, None);
+    assert_eq!(counters.proofs.load(Ordering::Relaxed), 1);
+    tree.removes(root.as_ref(), &keys[..10])?;
+    assert_eq!(counters.batches.load(Ordering::Relaxed), 2);
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "metrics-prometheus")]
+fn test_prometheus_registers_tree_and_cache_metrics() -> Result<()> {
+    use prometheus::{Encoder, Registry, TextEncoder};
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let registry = Registry::new();
+    let prometheus = Arc::new(Prometheus::new("node", &registry)?);
+    // collectors of the same names are registered only once
+    assert!(Prometheus::new("node", &registry).is_err());
+    let mut tree = Monotree::<CachedDb<MemoryDB>, Blake3>::new("");
+    tree.set_metrics(prometheus.clone());
+    tree.db.set_metrics(prometheus.clone());
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let root = tree.removes(root.as_ref(), &keys[..10])?;
+    for key in keys[10..13].iter() {
+        assert!(tree.get_merkle_proof(root.as_ref(), key)?.is_some());
+    }
+    prometheus.set_tree_size(tree.db.total_size()?);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&registry.gather(), &mut buffer)
+        .expect("encode");
+    let text = String::from_utf8(buffer).expect("text");
+    let value = |name: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(&format!("{} ", name)))
+            .expect("metric")
+            .to_string()
+    };
+    assert_eq!(
+        value("node_tree_size_bytes"),
+        tree.db.total_size()?.to_string()
+    );
+    assert_eq!(value("node_proofs_total"), "3");
+    assert_eq!(
+        value("node_cache_hit_ratio"),
+        prometheus.counters().hit_rate().to_string()
+    );
+    assert!(text.contains("# TYPE node_batch_commit_seconds histogram\n"));
+    assert_eq!(value("node_batch_commit_seconds_count"), "2");
+    assert_eq!(value("node_batch_commit_seconds_bucket{le=\"+Inf\"}"), "2");
+    assert_eq!(value("node_proof_seconds_count"), "3");
+    // buckets are cumulative
+    let buckets: Vec<u64> = text
+        .lines()
+        .filter(|line| line.starts_with("node_proof_seconds_bucket"))
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(buckets.len(), LATENCY_BUCKETS.len() + 1);
+    assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]));
+    Ok(())
+}
+
+#[test]
+fn test_watch_roots_of_batches_committed() -> Result<()> {
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let roots = tree.watch_roots();
+    let dropped = tree.watch_roots();
+    drop(dropped);
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let removed = tree.removes(root.as_ref(), &keys[..10])?;
+    // batches leaving the root as it was send nothing
+    tree.removes(removed.as_ref(), &keys[..10])?;
+    let committed = tree.transaction(removed.as_ref(), |txn| {
+        txn.inserts(&keys[..10], &leaves[..10])
+    })?;
+    assert!(tree.same_tree(committed.as_ref(), root.as_ref())?);
+    let changes: Vec<RootChange> = roots.try_iter().collect();
+    assert_eq!(
+        changes,
+        vec![
+            RootChange {
+                before: None,
+                after: root
+            },
+            RootChange {
+                before: root,
+                after: removed
+            },
+            RootChange {
+                before: removed,
+                after: committed
+            },
+        ]
+    );
+
+    // batches vetoed send nothing
+    tree.set_commit_policy(Box::new(Thresholds {
+        max_keys: 1,
+        max_bytes: u64::MAX,
+        veto: true,
+    }));
+    assert!(tree.removes(root.as_ref(), &keys[..10]).is_err());
+    assert!(roots.try_recv().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_http_server_answers_roots_leaves_and_proofs() -> Result<()> {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let mut server = Server::new(&mut tree);
+    assert_eq!(
+        server.respond("GET", "/root"),
+        (200, serde_json::json!({ "root": null }))
+    );
+    // keys are looked up in the tree of the root given, or of the one recorded
+    let (status, body) = server.respond("GET", &format!("/leaf/{}", hex!(keys[0])));
+    assert_eq!((status, &body["leaf"]), (200, &serde_json::Value::Null));
+    let target = format!("/proof/{}?root={}", hex!(keys[0]), hex!(root.unwrap()));
+    let (status, body) = server.respond("GET", &target);
+    assert_eq!(status, 200);
+    assert_eq!(body["leaf"], hex!(leaves[0]));
+    let proof: Proof = body["proof"]
+        .as_array()
+        .expect("proof")
+        .iter()
+        .map(|step| {
+            let cut = hex::decode(step[1].as_str().unwrap()).unwrap();
+            (step[0].as_bool().unwrap(), cut)
+        })
+        .collect();
+    assert!(verify_proof(
+        &Blake3::new(),
+        root.as_ref(),
+        &leaves[0],
+        Some(&proof)
+    ));
+    assert_eq!(server.respond("GET", "/leaf/00").0, 400);
+    assert_eq!(server.respond("GET", "/keys").0, 404);
+    assert_eq!(server.respond("POST", "/root").0, 405);
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let mut client = TcpStream::connect(listener.local_addr()?)?;
+    write!(client, "GET /root HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+    let (stream, _) = listener.accept()?;
+    server.handle(stream)?;
+    let mut response = String::new();
+    client.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("{\"root\":null}"));
+    Ok(())
+}
+
+#[test]
+fn test_grpc_service_answers_updates_leaves_and_proofs() -> Result<()> {
+    use monotree::grpc;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let uri = format!("http://{}", listener.local_addr()?);
+    let service = grpc::Service::new(Monotree::<MemoryDB, Blake3>::new(""));
+    std::thread::spawn(move || service.serve(listener));
+    let mut client = grpc::Client::connect(&uri)?;
+
+    // updates give the same roots as those of a tree run in place
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let expected = tree.inserts(None, &keys[..50], &leaves[..50])?;
+    let root = client.inserts(None, &keys[..50], &leaves[..50])?;
+    assert_eq!(root, expected);
+    let expected = tree.insert(expected.as_ref(), &keys[50], &leaves[50])?;
+    let root = client.insert(root.as_ref(), &keys[50], &leaves[50])?;
+    assert_eq!(root, expected);
+    assert_eq!(client.get(root.as_ref(), &keys[50])?, Some(leaves[50]));
+    assert_eq!(
+        client.gets(root.as_ref(), &keys[49..52])?,
+        vec![Some(leaves[49]), Some(leaves[50]), None]
+    );
+
+    // roots are recorded only by swapping them
+    assert_eq!(client.load_root()?, None);
+    assert!(client.swap_root(None, root.as_ref())?);
+    assert!(!client.swap_root(None, root.as_ref())?);
+    assert_eq!(client.load_root()?, root);
+
+    // proofs in protobuf verify against the root
+    let (leaf, proof) = client
+        .get_merkle_proof(root.as_ref(), &keys[3])?
+        .expect("proof");
+    assert_eq!(leaf, leaves[3]);
+    assert!(verify_proof(
+        &Blake3::new(),
+        root.as_ref(),
+        &leaf,
+        Some(&proof)
+    ));
+    assert_eq!(client.get_merkle_proof(root.as_ref(), &keys[99])?, None);
+    let local = tree
+        .get_merkle_proof(root.as_ref(), &keys[3])?
+        .expect("proof");
+    assert_eq!(Proof::from(grpc::Proof::from(&local)), local);
+
+    let expected = tree.removes(root.as_ref(), &keys[..10])?;
+    let expected = tree.remove(expected.as_ref(), &keys[10])?;
+    let removed = client.removes(root.as_ref(), &keys[..10])?;
+    assert_eq!(client.remove(removed.as_ref(), &keys[10])?, expected);
+
+    // malformed batches and roots of no tree fail, while the service goes on
+    assert!(client
+        .inserts(root.as_ref(), &keys[..2], &leaves[..1])
+        .is_err());
+    assert!(client.get(Some(&keys[0]), &keys[0]).is_err());
+    assert_eq!(client.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert!(grpc::Client::connect("not a uri").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_overlay_db_commits_or_discards_candidates() -> Result<()> {
+    let (keys, leaves) = (random_hashes(200), random_hashes(200));
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys[..100], &leaves[..100])?;
+
+    // a candidate discarded leaves nothing in the database
+    let mut candidate = Monotree::<_, Blake3>::with_db(OverlayDB::with_db(&mut tree.db))?;
+    let discarded = candidate.inserts(root.as_ref(), &keys[100..], &leaves[100..])?;
+    let discarded = discarded.expect("root");
+    assert!(candidate.db.get(&discarded)?.is_some());
+    candidate.db.discard();
+    assert!(candidate.db.get(&discarded)?.is_none());
+    drop(candidate);
+    assert!(tree.db.get(&discarded)?.is_none());
+
+    // a candidate committed gives the same tree as the updates applied directly
+    let mut candidate = Monotree::<_, Blake3>::with_db(OverlayDB::with_db(&mut tree.db))?;
+    let committed = candidate.removes(root.as_ref(), &keys[..50])?;
+    candidate.db.commit()?;
+    assert_eq!(tree.gets(committed.as_ref(), &keys[..50])?, vec![None; 50]);
+    for (key, leaf) in keys.iter().zip(leaves.iter()).take(100).skip(50) {
+        assert_eq!(tree.get(committed.as_ref(), key)?, Some(*leaf));
+    }
+    assert_eq!(tree.removes(root.as_ref(), &keys[..50])?, committed);
+    Ok(())
+}
+
+#[test]
+fn test_tiered_db_flushes_to_cold_then_reads_through() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(500), random_hashes(500));
+    let db = TieredDb::with_dbs(MemoryDB::new(""), RocksDB::new(&dbname));
+    let mut tree = Monotree::<_, Blake3>::with_db(db)?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let root = tree.removes(root.as_ref(), &keys[..100])?;
+    tree.swap_root(None, root.as_ref())?;
+    tree.db.flush()?;
+    assert!(tree.db.total_size()? > 0);
+    drop(tree);
+
+    // the cold database alone has every batch flushed
+    let mut tree = Monotree::<RocksDB, Blake3>::new(&dbname);
+    assert_eq!(tree.load_root()?, root);
+    assert_eq!(tree.gets(root.as_ref(), &keys[..100])?, vec![None; 100]);
+    drop(tree);
+
+    // reads missing an empty hot database fall through to the cold one
+    let db = TieredDb::with_dbs(MemoryDB::new(""), RocksDB::new(&dbname));
+    let mut tree = Monotree::<_, Blake3>::with_db(db)?;
+    assert_eq!(tree.load_root()?, root);
+    for (key, leaf) in keys.iter().zip(leaves.iter()).skip(100) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+    }
+    // entries deleted are never read from the cold database, even before flushed
+    tree.db.put(&keys[0], keys[0].to_vec())?;
+    tree.db.flush()?;
+    tree.db.delete(&keys[0])?;
+    assert_eq!(tree.db.get(&keys[0])?, None);
+    Ok(())
+}
+
+#[test]
+fn test_cached_db_writes_back_batches() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<CachedDb<MemoryDB>, Blake3>::new("");
+    tree.db.init_batch()?;
+    tree.db.put(&keys[0], keys[0].to_vec())?;
+    tree.db.put(&keys[0], keys[1].to_vec())?;
+    assert_eq!(tree.db.get(&keys[0])?, Some(keys[1].to_vec()));
+    assert_eq!(tree.db.db_mut().get(&keys[0])?, None);
+    tree.db.finish_batch()?;
+    assert_eq!(tree.db.db_mut().get(&keys[0])?, Some(keys[1].to_vec()));
+    tree.db.delete(&keys[0])?;
+    assert_eq!(tree.db.db_mut().get(&keys[0])?, None);
+
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let before = tree.db.cache_stats();
+    assert_eq!(tree.gets(root.as_ref(), &keys)?.len(), 100);
+    let stats = tree.db.cache_stats();
+    assert!(stats.hits > before.hits && stats.misses == before.misses);
+
+    // writes of a batch failing to write back are never read
+    drop(Monotree::<RocksDB, Blake3>::new(&dbname));
+    let mut db = CachedDb::<RocksDB>::open_read_only(&dbname)?;
+    db.init_batch()?;
+    db.put(&keys[0], keys[0].to_vec())?;
+    assert!(db.finish_batch().is_err());
+    assert_eq!(db.get(&keys[0])?, None);
+    assert_eq!(db.cache_stats().entries, 0);
+    Ok(())
+}
+
+#[test]
+fn test_faulty_db_injects_faults() -> Result<()> {
+    use std::time::{Duration, Instant};
+    let (keys, leaves) = (random_hashes(50), random_hashes(50));
+    let mut tree = Monotree::<FaultyDB<MemoryDB>, Blake3>::new("");
+    tree.db.set_seed(7);
+    let root = tree.inserts(None, &keys[..10], &leaves[..10])?;
+
+    tree.db.set_faults(Faults {
+        error_rate: 1.0,
+        ..Default::default()
+    });
+    assert!(matches!(
+        tree.get(root.as_ref(), &keys[0]),
+        Err(Errors::Db(_))
+    ));
+    tree.db.set_faults(Faults {
+        latency: Duration::from_millis(2),
+        ..Default::default()
+    });
+    let started = Instant::now();
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert!(started.elapsed() >= Duration::from_millis(2));
+
+    // dropped batches succeed with nothing written, torn ones fail with a part written
+    tree.db.set_faults(Faults {
+        drop_rate: 1.0,
+        ..Default::default()
+    });
+    let dropped = tree.inserts(root.as_ref(), &keys[10..], &leaves[10..])?;
+    tree.db.set_faults(Faults::default());
+    assert!(matches!(
+        tree.get(dropped.as_ref(), &keys[10]),
+        Err(Errors::NotFound(_))
+    ));
+    tree.db.set_faults(Faults {
+        tear_rate: 1.0,
+        ..Default::default()
+    });
+    assert!(tree
+        .inserts(root.as_ref(), &keys[10..], &leaves[10..])
+        .is_err());
+    assert_eq!(tree.db.injected(), 3);
+    tree.db.set_faults(Faults::default());
+    assert_eq!(
+        tree.inserts(root.as_ref(), &keys[10..], &leaves[10..])?,
+        dropped
+    );
+
+    // a queue applying batches again on failures ends up at the same root
+    let mut expected = Monotree::<MemoryDB, Blake3>::new("");
+    let mut expected_root = expected.inserts(None, &keys[..10], &leaves[..10])?;
+    for (key, leaf) in keys[10..].iter().zip(leaves[10..].iter()) {
+        expected_root = expected.insert(expected_root.as_ref(), key, leaf)?;
+    }
+    let expected = expected_root;
+    tree.db.set_faults(Faults::default());
+    tree.swap_root(None, root.as_ref())?;
+    let updates: Vec<monotree::queue::Update> = keys[10..]
+        .iter()
+        .zip(leaves[10..].iter())
+        .map(|(k, l)| (*k, Some(*l)))
+        .collect();
+    for batch in updates.chunks(5) {
+        monotree::queue::submit(&mut tree, batch)?;
+    }
+    tree.db.set_faults(Faults {
+        error_rate: 0.02,
+        ..Default::default()
+    });
+    let mut failures = 0;
+    while monotree::queue::pending(&mut tree).unwrap_or(1) > 0 {
+        if monotree::queue::recover(&mut tree).is_err() {
+            failures += 1;
+            assert!(failures < 1000);
+        }
+    }
+    tree.db.set_faults(Faults::default());
+    assert!(failures > 0);
+    assert_eq!(tree.load_root()?, expected);
+    Ok(())
+}
+
+#[test]
+fn test_check_invariants_of_databases() -> Result<()> {
+    let checks = Checks {
+        seed: 7,
+        rounds: 4,
+        entries: 50,
+    };
+    check_invariants(&mut Monotree::<MemoryDB, Blake3>::new(""), &checks)?;
+    check_invariants(&mut Monotree::<MemoryDB, Sha2>::new(""), &checks)?;
+    check_invariants(
+        &mut Monotree::<CachedDb<MemoryDB>, Blake3>::new(""),
+        &checks,
+    )?;
+    // reads within batches observe their writes alike over backends staging them differently
+    check_invariants(&mut Monotree::<ShardedMemoryDB, Blake3>::new(""), &checks)?;
+    check_invariants(&mut Monotree::<TieredMemoryDB, Blake3>::new(""), &checks)?;
+
+    // a database dropping batches breaks them
+    let faulty = FaultyDB::with_db(
+        MemoryDB::try_new("")?,
+        Faults {
+            drop_rate: 1.0,
+            ..Default::default()
+        },
+    );
+    let mut tree = Monotree::<_, Blake3>::with_db(faulty)?;
+    assert!(check_invariants(&mut tree, &checks).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_errors_by_kind() -> Result<()> {
+    let keys = random_hashes(10);
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &keys)?.expect("root");
+    let unknown = random_hash();
+    assert!(matches!(
+        tree.get(Some(&unknown), &keys[0]),
+        Err(Errors::NotFound(hash)) if hash == unknown
+    ));
+
+    // corrupted nodes fail to decode instead of panicking
+    let bytes = tree.db.get(&root)?.expect("bytes");
+    let mut corrupted = bytes.clone();
+    *corrupted.last_mut().expect("indicator") = 0xee;
+    tree.db.put(&root, corrupted)?;
+    assert!(matches!(
+        tree.get(Some(&root), &keys[0]),
+        Err(Errors::Decode(_))
+    ));
+    let truncated = [&bytes[..HASH_LEN / 2], &bytes[bytes.len() - 1..]].concat();
+    assert!(matches!(
+        Node::<HASH_LEN>::from_bytes(&truncated),
+        Err(Errors::Decode(_))
+    ));
+    assert!(matches!(
+        Metadata::from_bytes(&[0x09, 0x00]),
+        Err(Errors::Decode(_))
+    ));
+    // key lengths beyond a byte are never recorded cut short
+    let metadata = Metadata {
+        key_len: 255,
+        ..Default::default()
+    };
+    assert_eq!(Metadata::from_bytes(&metadata.to_bytes()?)?, metadata);
+    let metadata = Metadata {
+        key_len: 256,
+        ..Default::default()
+    };
+    assert!(metadata.to_bytes().is_err());
+    assert!(matches!(MemoryDB::load(".tmp/none"), Err(Errors::Io(_))));
+    Ok(())
+}
+
+#[test]
+fn test_hashmap_save_then_load() -> Result<()> {
+    let path = std::env::temp_dir().join(hex!(random_bytes(4)));
+    let path = path.to_str().expect("path");
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path).unwrap()
+        }
+    });
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    tree.db.save(path)?;
+
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    tree.db = MemoryDB::load(path)?;
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+    }
+    assert!(MemoryDB::load(&format!("{}.none", path)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_hashmap_with_file_then_reopen() -> Result<()> {
+    let path = std::env::temp_dir().join(hex!(random_bytes(4)));
+    let path = path.to_str().expect("path");
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<MemoryDB, Blake3>::with_db(MemoryDB::with_file(path)?)?;
+    let root = tree.inserts(None, &keys[..50], &leaves[..50])?;
+    tree.swap_root(None, root.as_ref())?;
+    // nothing is in the file until flushed
+    assert!(fs::metadata(path).is_err());
+    tree.db.flush()?;
+
+    let mut tree = Monotree::<MemoryDB, Blake3>::with_db(MemoryDB::with_file(path)?)?;
+    let root = tree.load_root()?;
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    let updated = tree.inserts(root.as_ref(), &keys[50..], &leaves[50..])?;
+    tree.swap_root(root.as_ref(), updated.as_ref())?;
+    tree.db.flush()?;
+    assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+
+    let mut tree = Monotree::<MemoryDB, Blake3>::with_db(MemoryDB::load(path)?)?;
+    assert_eq!(tree.load_root()?, updated);
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(updated.as_ref(), k)?, Some(*v));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_hashmap_with_file_then_reopen_namespaces() -> Result<()> {
+    let path = std::env::temp_dir().join(hex!(random_bytes(4)));
+    let path = path.to_str().expect("path");
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(path).is_ok() {
+            fs::remove_file(path).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut utxo = Monotree::<NamespacedDB<MemoryDB>, Blake3>::with_namespace(
+        MemoryDB::with_file(path)?,
+        b"utxo",
+    )?;
+    let mut state = Monotree::<_, Blake3>::with_db(utxo.db.namespace(b"state"))?;
+    let spent = utxo.inserts(None, &keys[..50], &leaves[..50])?;
+    let root = state.inserts(None, &keys, &leaves)?;
+    utxo.swap_root(None, spent.as_ref())?;
+    state.swap_root(None, root.as_ref())?;
+    // keys prefixed by namespaces are longer than HASH_LEN, and saved all the same
+    utxo.db.flush()?;
+
+    let mut utxo = Monotree::<NamespacedDB<MemoryDB>, Blake3>::with_namespace(
+        MemoryDB::with_file(path)?,
+        b"utxo",
+    )?;
+    let mut state = Monotree::<_, Blake3>::with_db(utxo.db.namespace(b"state"))?;
+    assert_eq!(utxo.load_root()?, spent);
+    assert_eq!(state.load_root()?, root);
+    for (i, (k, v)) in keys.iter().zip(leaves.iter()).enumerate() {
+        assert_eq!(utxo.get(spent.as_ref(), k)?, Some(*v).filter(|_| i < 50));
+        assert_eq!(state.get(root.as_ref(), k)?, Some(*v));
+    }
+
+    // entries cut short fail to load
+    let bytes = fs::read(path)?;
+    for len in [3, 4 + 1, bytes.len() - 1].iter() {
+        fs::write(path, &bytes[..*len])?;
+        assert!(matches!(MemoryDB::load(path), Err(Errors::Decode(_))));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_bulk_load() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let keys = random_hashes(1000);
+    let leaves = random_hashes(1000);
+    let mut tree = Monotree::<RocksDB, Blake3>::new(&dbname);
+    tree.db.set_bulk_load(true);
+    let root = tree.inserts(None, &keys, &leaves)?;
+    tree.db.set_bulk_load(false);
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+    }
+    let root = tree.removes(root.as_ref(), &keys)?;
+    assert_eq!(root, None);
+    Ok(())
+}
+
+#[test]
+fn test_mirrored_cas_then_mirror_secondary() -> Result<()> {
+    let mut db = MirroredMemoryDB::new("");
+    let key = random_hash();
+    assert!(db.cas(&key, None, Some(b"first".to_vec()))?);
+    assert!(!db.cas(&key, Some(b"other"), Some(b"second".to_vec()))?);
+    db.promote()?;
+    assert_eq!(db.get(&key)?, Some(b"first".to_vec()));
+    assert!(db.cas(&key, Some(b"first"), None)?);
+    assert_eq!(db.get(&key)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_mirrored_lagging_then_promote() -> Result<()> {
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let (half, rest) = (&keys[..50], &keys[50..]);
+    let mut tree = Monotree::<MirroredMemoryDB, Blake3>::new("");
+    tree.db.set_lagging(true);
+    let root = tree.inserts(None, half, &leaves[..50])?;
+    let root = tree.inserts(root.as_ref(), rest, &leaves[50..])?;
+
+    // the primary is gone: the secondary must serve the same tree from now on
+    tree.db.promote()?;
+    assert!(tree.db.is_promoted());
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+    }
+    let root = tree.removes(root.as_ref(), &keys)?;
+    assert_eq!(root, None);
+    Ok(())
+}
+
+#[test]
+fn test_sharded_db_with_shards_then_route_keys() -> Result<()> {
+    assert!(ShardedMemoryDB::with_shards(Vec::new()).is_err());
+    let shards = (0..3)
+        .map(|_| MemoryDB::try_new(""))
+        .collect::<Result<_>>()?;
+    let mut tree = Monotree::<ShardedMemoryDB, Blake3>::with_db(ShardedDB::with_shards(shards)?)?;
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+    }
+    Ok(())
+}
+
+static PRIMARY_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// A `MemoryDB` failing all operations while `PRIMARY_DOWN` is set.
+struct FlakyDB(MemoryDB);
+
+impl FlakyDB {
+    fn check(&self) -> Result<()> {
+        match PRIMARY_DOWN.load(Ordering::SeqCst) {
+            true => Err(Errors::Db("primary down".to_string())),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Database for FlakyDB {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(FlakyDB(MemoryDB::try_new(dbpath)?))
+    }
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.check()?;
+        self.0.get(key)
+    }
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.check()?;
+        self.0.put(key, value)
+    }
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.check()?;
+        self.0.delete(key)
+    }
+    fn init_batch(&mut self) -> Result<()> {
+        self.0.init_batch()
+    }
+    fn finish_batch(&mut self) -> Result<()> {
+        self.0.finish_batch()
+    }
+    fn total_size(&self) -> Result<u64> {
+        self.0.total_size()
+    }
+}
+
+#[test]
+fn test_mirrored_failover_policies() -> Result<()> {
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let mut tree = Monotree::<MirroredDB<FlakyDB, MemoryDB>, Blake3>::new("");
+    let root = tree.inserts(None, &keys[..50], &leaves[..50])?;
+
+    PRIMARY_DOWN.store(true, Ordering::SeqCst);
+    assert!(tree.get(root.as_ref(), &keys[0]).is_err());
+
+    tree.db.set_failover(Failover::ReadSecondary);
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert!(tree.db.is_degraded());
+    assert!(tree.insert(root.as_ref(), &keys[50], &leaves[50]).is_err());
+
+    // keep the tree going with the secondary, then catch up the primary
+    tree.db.set_failover(Failover::QueueWrites(1 << 12));
+    let root = tree.inserts(root.as_ref(), &keys[50..], &leaves[50..])?;
+    assert!(!tree.db.probe());
+    PRIMARY_DOWN.store(false, Ordering::SeqCst);
+    assert!(tree.db.probe());
+    assert!(!tree.db.is_degraded());
+
+    tree.db.set_failover(Failover::FailFast);
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+    }
+    Ok(())
+}
+
+/// A `MemoryDB` counting round trips, a read of many keys at once counted as one,
+/// and reads of writes pending in a batch as none, as backends over a network serve them.
+struct RoundTripDB(MemoryDB, usize, std::collections::HashSet<Vec<u8>>);
+
+impl Database for RoundTripDB {
+    fn try_new(dbpath: &str) -> Result<Self> {
+        Ok(RoundTripDB(
+            MemoryDB::try_new(dbpath)?,
+            0,
+            Default::default(),
+        ))
+    }
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if !self.2.contains(key) {
+            self.1 += 1;
+        }
+        self.0.get(key)
+    }
+    fn get_many(&mut self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.1 += 1;
+        self.0.get_many(keys)
+    }
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.2.insert(key.to_vec());
+        self.0.put(key, value)
+    }
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.0.delete(key)
+    }
+    fn init_batch(&mut self) -> Result<()> {
+        self.0.init_batch()
+    }
+    fn finish_batch(&mut self) -> Result<()> {
+        self.2.clear();
+        self.0.finish_batch()
+    }
+}
+
+#[test]
+fn test_prefetch_nodes_of_batches() -> Result<()> {
+    let keys = random_hashes(400);
+    let leaves = random_hashes(400);
+    let mut plain = Monotree::<RoundTripDB, Blake3>::new("");
+    let mut prefetching = Monotree::<RoundTripDB, Blake3>::new("");
+    prefetching.set_prefetch(HASH_LEN * 8);
+    let root = plain.inserts(None, &keys[..200], &leaves[..200])?;
+    assert_eq!(
+        prefetching.inserts(None, &keys[..200], &leaves[..200])?,
+        root
+    );
+
+    // the same roots, in far fewer round trips
+    plain.db.1 = 0;
+    prefetching.db.1 = 0;
+    let inserted = plain.inserts(root.as_ref(), &keys[200..], &leaves[200..])?;
+    assert_eq!(
+        prefetching.inserts(root.as_ref(), &keys[200..], &leaves[200..])?,
+        inserted
+    );
+    assert!(prefetching.db.1 * 2 < plain.db.1);
+    let removed = plain.removes(inserted.as_ref(), &keys[..300])?;
+    assert_eq!(
+        prefetching.removes(inserted.as_ref(), &keys[..300])?,
+        removed
+    );
+    for (key, leaf) in keys.iter().zip(leaves.iter()).skip(300) {
+        assert_eq!(prefetching.get(removed.as_ref(), key)?, Some(*leaf));
+    }
+
+    // a few levels read ahead still give the same root
+    prefetching.set_prefetch(2);
+    assert_eq!(
+        prefetching.removes(inserted.as_ref(), &keys[..300])?,
+        removed
+    );
+
+    // backends not telling their size fail, while trees still estimate theirs
+    assert!(prefetching.db.total_size().is_err());
+    assert!(prefetching.estimated_size(removed.as_ref())? > 0);
+    Ok(())
+}
+
+impl_durability_test!("rocksdb", RocksDB);
+impl_durability_test!("sled", Sled);
+
+impl_test_with_params!(
+    [
+        insert_keys_then_estimate_size,
+        insert_keys_then_remove_keys_with_progress,
+        insert_keys_then_cancel_batch,
+        insert_keys_then_compute_root_from_proof,
+        read_legacy_nodes_then_insert_keys,
+        insert_keys_then_sync_replica_by_diff,
+        insert_keys_then_traverse_in_reverse_order,
+        insert_keys_in_any_order_then_compare_roots,
+        insert_keys_then_verify_consistency,
+        insert_keys_then_remove_with_proofs,
+        insert_keys_then_prove_updates,
+        insert_keys_then_verify_non_inclusion_proof,
+        update_key_then_gen_proofs_across_versions,
+        insert_keys_then_gen_and_verify_multiproof,
+        insert_keys_then_gets_with_proofs,
+        insert_keys_then_replace_leaves,
+        insert_keys_if_absent_then_compare_and_swap,
+        insert_keys_then_swap_root,
+        insert_keys_then_iterate_entries,
+        insert_keys_then_gen_and_verify_proof_pages,
+        insert_keys_then_diff_roots,
+        insert_keys_then_prune_stale_nodes,
+        insert_keys_then_scan_entries_by_prefix,
+        insert_keys_with_filter_then_reject_absent_keys,
+        insert_keys_then_report_costs,
+        insert_values_then_verify_by_leaf_policy,
+        insert_keys_then_check_commits_by_policy,
+        open_batch_then_read_own_writes,
+        soft_remove_keys_then_restore,
+        insert_keys_then_replay_and_verify_updates,
+        insert_keys_then_apply_updates_with_witness,
+        insert_keys_then_export_subtrees_by_prefix,
+        insert_keys_then_merge_disjoint_trees,
+        insert_and_remove_keys_in_single_batch,
+        insert_keys_then_prove_default_leaves,
+        insert_short_keys_then_gen_and_verify_proofs,
+        insert_child_roots_then_verify_chained_proof,
+        insert_keys_into_nested_trees_then_remove,
+        insert_keys_then_prove_through_merkle_store,
+        par_insert_keys_then_compare_roots,
+        build_tree_from_sorted_leaves_then_compare_roots,
+        export_tree_then_import_into_another_db,
+        copy_tree_into_another_db,
+        insert_keys_in_transaction_then_commit_or_discard,
+        insert_keys_then_verify_integrity,
+        insert_keys_then_get_stats
+    ],
+    [("hashmap", MemoryDB), ("rocksdb", RocksDB), ("sled", Sled)],
+    [
+        ("blake3", Blake3),
+        ("blake2s", Blake2s),
+        ("blake2b", Blake2b),
+        ("sha2", Sha2),
+        ("sha3", Sha3)
+    ],
+    [100]
+);
+
+impl_test_with_params!(
+    [
+        insert_keys_then_verify_values,
+        insert_keys_then_gen_and_verify_proof,
+        insert_keys_then_delete_keys_randomly,
+        insert_keys_then_estimate_size
+    ],
+    [
+        ("sharded_hashmap", ShardedMemoryDB),
+        ("sharded_rocksdb", ShardedRocksDB),
+        ("sharded_sled", ShardedSled),
+        ("mirrored_hashmap", MirroredMemoryDB),
+        ("mirrored_rocksdb_sled", MirroredRocksSled),
+        ("cached_hashmap", CachedMemoryDB),
+        ("cached_rocksdb", CachedRocksDB),
+        ("cached_sled", CachedSled),
+        ("tiered_hashmap", TieredMemoryDB),
+        ("tiered_rocksdb", TieredRocksDB)
+    ],
+    [("blake3", Blake3)],
+    [100]
+);
+
+impl_test_with_params!(
+    [
+        insert_keys_then_verify_values,
+        insert_keys_then_gen_and_verify_proof,
+        insert_keys_then_gen_and_verify_multiproof
+    ],
+    [("hashmap", MemoryDB)],
+    [
+        ("poseidon_bn254", PoseidonBn254),
+        ("poseidon_bls12_381", PoseidonBls12381)
+    ],
+    [100]
+);
+
+#[test]
+fn test_sha256d_as_bitcoin() -> Result<()> {
+    let hasher = Sha256d::new();
+    assert_eq!(
+        hex::encode(hasher.digest(b"hello")),
+        "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50"
+    );
+    assert_eq!(
+        hasher.digest(b"hello"),
+        Sha2::new().digest(&Sha2::new().digest(b"hello"))
+    );
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<MemoryDB, Sha256d>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[0])?;
+    assert!(verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+    assert!(!verify_proof(
+        &Sha2::new(),
+        root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_separated_and_keyed_hashers() -> Result<()> {
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let plain = Blake3::new();
+    let separated = Separated(Blake3::new());
+    let node = [0x01u8; HASH_LEN * 2];
+    assert_eq!(separated.digest(&node), plain.digest(&node));
+    assert_ne!(separated.digest_node(&node), separated.digest_leaf(&node));
+    assert_ne!(separated.digest_node(&node), plain.digest_node(&node));
+
+    // separated domains change roots but proofs verify the same way
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let mut tree = Monotree::<MemoryDB, Separated<Blake3>>::new("");
+    let separated_root = tree.inserts(None, &keys, &leaves)?;
+    assert_ne!(root, separated_root);
+    let proof = tree.get_merkle_proof(separated_root.as_ref(), &keys[0])?;
+    assert!(verify_proof(
+        &separated,
+        separated_root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+    assert!(!verify_proof(
+        &plain,
+        separated_root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+
+    // keys set on the tree are carried into the trees replaying its updates
+    let (key, other) = (random_hash(), random_hash());
+    let mut tree = Monotree::<MemoryDB, Separated<Blake3Keyed>>::new("");
+    tree.hasher = Separated(Blake3Keyed::with_key(&key));
+    let keyed_root = tree.inserts(None, &keys[..50], &leaves[..50])?;
+    let new_root = tree.inserts(keyed_root.as_ref(), &keys[50..], &leaves[50..])?;
+    assert_ne!(new_root, separated_root);
+    let proof = tree.consistency_proof(keyed_root.as_ref(), new_root.as_ref())?;
+    let consistent = |hasher| {
+        verify_consistency(
+            &hasher,
+            keyed_root.as_ref(),
+            new_root.as_ref(),
+            &proof,
+            Consistency::AppendOnly,
+        )
+    };
+    assert!(consistent(Separated(Blake3Keyed::with_key(&key))));
+    assert!(!consistent(Separated(Blake3Keyed::with_key(&other))));
+
+    let mut tree = Monotree::<MemoryDB, Blake2bKeyed>::new("");
+    tree.hasher = Blake2bKeyed::with_key(&key)?;
+    let keyed_root = tree.inserts(None, &keys, &leaves)?;
+    tree.hasher = Blake2bKeyed::with_key(&other)?;
+    assert_ne!(tree.inserts(None, &keys, &leaves)?, keyed_root);
+    assert!(Blake2bKeyed::with_key(&[0u8; 65]).is_err());
+    let mut tree = Monotree::<MemoryDB, Blake2bKeyed>::new("");
+    let unkeyed_root = tree.inserts(None, &keys, &leaves)?;
+    let mut tree = Monotree::<MemoryDB, Blake2b>::new("");
+    assert_eq!(tree.inserts(None, &keys, &leaves)?, unkeyed_root);
+    Ok(())
+}
+
+fn insert_keys_of_len_then_prove<H: Hasher<N>, const N: usize>() -> Result<()> {
+    let random = |n| {
+        (0..n)
+            .map(|_| slice_to_array::<N>(&random_bytes(N)))
+            .collect::<Vec<_>>()
+    };
+    let (keys, leaves) = (random(100), random(100));
+    let mut tree = Monotree::<MemoryDB, H, DefaultCodec, N>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+        assert!(verify_proof(
+            &tree.hasher,
+            root.as_ref(),
+            leaf,
+            proof.as_ref()
+        ));
+    }
+    assert_eq!(tree.iter(root.as_ref()).count(), keys.len());
+    let absent = random(1)[0];
+    let proof = tree.get_non_inclusion_proof(root.as_ref(), &absent)?;
+    assert!(verify_non_inclusion_proof(
+        &tree.hasher,
+        root.as_ref(),
+        &absent,
+        proof.as_ref()
+    ));
+
+    let removed = tree.removes(root.as_ref(), &keys[..50])?;
+    let proof = tree.consistency_proof(root.as_ref(), removed.as_ref())?;
+    assert!(verify_consistency(
+        &tree.hasher,
+        root.as_ref(),
+        removed.as_ref(),
+        &proof,
+        Consistency::Any
+    ));
+    assert_eq!(tree.removes(removed.as_ref(), &keys[50..])?, None);
+    Ok(())
+}
+
+#[test]
+fn test_hashers_of_other_lengths() -> Result<()> {
+    insert_keys_of_len_then_prove::<Sha512, 64>()?;
+    insert_keys_of_len_then_prove::<Blake2bVar<20>, 20>()?;
+    assert_eq!(
+        Blake2bVar::<32>::new().digest(b"monotree"),
+        Blake2b::new().digest(b"monotree")
+    );
+
+    // trees keep keys no longer than their hashes
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    tree.set_key_len(24)?;
+    let db = tree.db;
+    assert!(Monotree::<MemoryDB, Blake2bVar<20>, DefaultCodec, 20>::with_db(db).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_hexary_tree() -> Result<()> {
+    let (keys, leaves) = (random_hashes(500), random_hashes(500));
+    let hasher = Blake3::new();
+    let mut tree = HexaryTree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let mut walked = 0;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+        let proof = tree.get_proof(root.as_ref(), key)?;
+        walked += proof.as_ref().map_or(0, |proof| proof.len());
+        assert!(verify_hexary_proof(
+            &hasher,
+            root.as_ref(),
+            key,
+            Some(leaf),
+            proof.as_ref()
+        ));
+        assert!(!verify_hexary_proof(
+            &hasher,
+            root.as_ref(),
+            key,
+            None,
+            proof.as_ref()
+        ));
+    }
+    // keys are found in a few nodes, against the 9 or so of a binary tree
+    assert!(walked < keys.len() * 4);
+    let absent = random_hash();
+    assert_eq!(tree.get(root.as_ref(), &absent)?, None);
+    let proof = tree.get_proof(root.as_ref(), &absent)?;
+    assert!(verify_hexary_proof(
+        &hasher,
+        root.as_ref(),
+        &absent,
+        None,
+        proof.as_ref()
+    ));
+    assert!(!verify_hexary_proof(
+        &hasher,
+        root.as_ref(),
+        &absent,
+        Some(&leaves[0]),
+        proof.as_ref()
+    ));
+
+    // roots depend on the entries only, however they were inserted or removed
+    let mut shuffled: Vec<_> = keys.iter().cloned().zip(leaves.iter().cloned()).collect();
+    shuffle(&mut shuffled);
+    let (shuffled_keys, shuffled_leaves): (Vec<Hash>, Vec<Hash>) = shuffled.into_iter().unzip();
+    let mut other = HexaryTree::<MemoryDB, Blake3>::new("");
+    assert_eq!(other.inserts(None, &shuffled_keys, &shuffled_leaves)?, root);
+    let removed = tree.removes(root.as_ref(), &keys[250..])?;
+    let mut half = HexaryTree::<MemoryDB, Blake3>::new("");
+    assert_eq!(half.inserts(None, &keys[..250], &leaves[..250])?, removed);
+    assert_eq!(tree.remove(removed.as_ref(), &absent)?, removed);
+    assert_eq!(tree.removes(removed.as_ref(), &keys[..250])?, None);
+    assert!(verify_hexary_proof(&hasher, None, &absent, None, None));
+    Ok(())
+}
+
+#[test]
+fn test_sparse_merkle_tree() -> Result<()> {
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let hasher = Blake3::new();
+    let mut tree = SparseMerkleTree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+        let proof = tree.get_proof(root.as_ref(), key)?;
+        assert!(verify_smt_proof(
+            &hasher,
+            root.as_ref(),
+            key,
+            Some(leaf),
+            &proof
+        ));
+        assert!(!verify_smt_proof(&hasher, root.as_ref(), key, None, &proof));
+    }
+    let absent = random_hash();
+    assert_eq!(tree.get(root.as_ref(), &absent)?, None);
+    let proof = tree.get_proof(root.as_ref(), &absent)?;
+    assert!(verify_smt_proof(
+        &hasher,
+        root.as_ref(),
+        &absent,
+        None,
+        &proof
+    ));
+    assert!(!verify_smt_proof(
+        &hasher,
+        root.as_ref(),
+        &absent,
+        Some(&leaves[0]),
+        &proof
+    ));
+
+    // roots depend on the entries only, however they were inserted or removed
+    let mut shuffled: Vec<_> = keys.iter().cloned().zip(leaves.iter().cloned()).collect();
+    shuffle(&mut shuffled);
+    let (shuffled_keys, shuffled_leaves): (Vec<Hash>, Vec<Hash>) = shuffled.into_iter().unzip();
+    let mut other = SparseMerkleTree::<MemoryDB, Blake3>::new("");
+    assert_eq!(other.inserts(None, &shuffled_keys, &shuffled_leaves)?, root);
+    let removed = tree.removes(root.as_ref(), &keys[50..])?;
+    let mut half = SparseMerkleTree::<MemoryDB, Blake3>::new("");
+    assert_eq!(half.inserts(None, &keys[..50], &leaves[..50])?, removed);
+    assert_eq!(tree.remove(removed.as_ref(), &absent)?, removed);
+    assert_eq!(tree.removes(removed.as_ref(), &keys[..50])?, None);
+    assert!(verify_smt_proof(
+        &hasher,
+        None,
+        &absent,
+        None,
+        &tree.get_proof(None, &absent)?
+    ));
+
+    // a key of all bits zero is hashed up with empty subtrees, H(0..0) = 0, on the right
+    let mut expected = leaves[0];
+    for _ in 0..HASH_LEN * 8 {
+        expected = hasher.digest(&[&expected[..], &[0u8; HASH_LEN][..]].concat());
+    }
+    let root = tree.insert(None, &[0u8; HASH_LEN], &leaves[0])?;
+    assert_eq!(root, Some(expected));
+    Ok(())
+}
+
+#[test]
+fn test_partial_tree_from_proofs() -> Result<()> {
+    let mut full = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = full.inserts(None, &keys, &leaves)?;
+    let mut partial = PartialTree::<Blake3>::new(root);
+    for (key, leaf) in keys.iter().zip(leaves.iter()).take(10) {
+        let proof = full.get_merkle_proof(root.as_ref(), key)?.expect("proof");
+        partial.absorb_proof(root.as_ref(), key, leaf, &proof)?;
+    }
+    assert_eq!(partial.get(&keys[3])?, Some(leaves[3]));
+
+    // proofs of another key, leaf or root are refused
+    let proof = full
+        .get_merkle_proof(root.as_ref(), &keys[50])?
+        .expect("proof");
+    let absorb = |partial: &mut PartialTree<Blake3>, root: Option<Hash>, key, leaf| {
+        matches!(
+            partial.absorb_proof(root.as_ref(), key, leaf, &proof),
+            Err(Errors::ProofInvalid(_))
+        )
+    };
+    assert!(absorb(&mut partial, root, &keys[51], &leaves[50]));
+    assert!(absorb(&mut partial, root, &keys[50], &leaves[51]));
+    assert!(absorb(
+        &mut partial,
+        Some(random_hash()),
+        &keys[50],
+        &leaves[50]
+    ));
+
+    // updates of keys absorbed give the roots of the full tree
+    let leaf = random_hash();
+    let mut expected = full.insert(root.as_ref(), &keys[0], &leaf)?;
+    assert_eq!(partial.insert(&keys[0], &leaf)?, expected);
+    expected = full.remove(expected.as_ref(), &keys[1])?;
+    assert_eq!(partial.remove(&keys[1])?, expected);
+    let proof = partial.get_merkle_proof(&keys[2])?;
+    assert!(verify_proof(
+        &full.hasher,
+        expected.as_ref(),
+        &leaves[2],
+        proof.as_ref()
+    ));
+
+    // keys elsewhere need proofs of their own
+    let mut last = Ok(None);
+    for key in keys[10..].iter() {
+        last = partial.get(key);
+        if last.is_err() {
+            break;
+        }
+    }
+    assert!(matches!(last, Err(Errors::NotFound(_))));
+    Ok(())
+}
+
+#[test]
+fn test_raw_keys_of_any_length() -> Result<()> {
+    let keys: Vec<Vec<u8>> = (0..100).map(|i| random_bytes(1 + i * 3)).collect();
+    let leaves = random_hashes(100);
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let mut root = None;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        root = tree.insert_raw_key(root.as_ref(), key, leaf)?;
+    }
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get_raw_key(root.as_ref(), key)?, Some(*leaf));
+        let proof = tree.get_merkle_proof(root.as_ref(), &tree.raw_key(key))?;
+        assert!(verify_proof(
+            &tree.hasher,
+            root.as_ref(),
+            leaf,
+            proof.as_ref()
+        ));
+        assert_eq!(tree.original_key(&tree.raw_key(key))?, None);
+    }
+
+    // stored keys are given back, and change no root
+    let mut stored = Monotree::<MemoryDB, Blake3>::new("");
+    stored.set_raw_keys(true)?;
+    let mut stored_root = None;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        stored_root = stored.insert_raw_key(stored_root.as_ref(), key, leaf)?;
+    }
+    assert_eq!(stored_root, root);
+    let entries: Vec<(Hash, Hash)> = stored.iter(root.as_ref()).collect::<Result<_>>()?;
+    for (key, _) in entries.iter() {
+        let original = stored.original_key(key)?.expect("original_key()");
+        assert!(keys.contains(&original));
+    }
+    let removed = stored.remove_raw_key(root.as_ref(), &keys[0])?;
+    assert_eq!(stored.get_raw_key(removed.as_ref(), &keys[0])?, None);
+    assert_eq!(
+        stored.original_key(&stored.raw_key(&keys[0]))?,
+        Some(keys[0].clone())
+    );
+    let db = stored.db;
+    assert!(Monotree::<MemoryDB, Blake3>::with_db(db)?.raw_keys());
+    Ok(())
+}
+
+#[test]
+fn test_poseidon_hashers_as_circomlib() {
+    let be = |bytes: &[u8]| {
+        let mut hash = [0u8; HASH_LEN];
+        hash[HASH_LEN - bytes.len()..].copy_from_slice(bytes);
+        hash
+    };
+    // Poseidon([1, 2]) of circomlib
+    let (one, two) = (be(&[1]), be(&[2]));
+    assert_eq!(
+        hex::encode(PoseidonBn254::new().hash2(&one, &two)),
+        "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"
+    );
+    let hasher = PoseidonBn254::new();
+    let bytes = random_bytes(70);
+    let mut acc = be(&[70]);
+    for chunk in bytes.chunks(POSEIDON_CHUNK_LEN) {
+        acc = hasher.hash2(&acc, &be(chunk));
+    }
+    assert_eq!(hasher.digest(&bytes), acc);
+    assert_ne!(hasher.digest(&[]), hasher.digest(&[0]));
+    assert_ne!(
+        PoseidonBls12381::new().hash2(&one, &two),
+        hasher.hash2(&one, &two)
+    );
+}
+
+#[test]
+fn test_merkle_map_insert_get_prove() -> Result<()> {
+    // values are (owner, balance) pairs
+    let mut map = MerkleMap::<String, (String, u64)>::new("");
+    let names: Vec<String> = (0..100).map(|i| format!("user-{}", i)).collect();
+    for (i, name) in names.iter().enumerate() {
+        map.insert(name, &(name.clone(), i as u64))?;
+    }
+    let hasher = Blake3::new();
+    for (i, name) in names.iter().enumerate() {
+        let account = map.get(name)?.expect("account");
+        assert_eq!(account, (name.clone(), i as u64));
+        let proof = map.prove(name)?;
+        let leaf = map.leaf(&account)?;
+        assert!(verify_proof(
+            &hasher,
+            map.root().as_ref(),
+            &leaf,
+            proof.as_ref()
+        ));
+    }
+    assert_eq!(map.get(&"nobody".to_string())?, None);
+    for name in names.iter() {
+        map.remove(name)?;
+    }
+    assert_eq!(map.root(), None);
+    Ok(())
+}
+
+#[test]
+fn test_import_csv_and_json_lines() -> Result<()> {
+    let csv = (0..100).fold("name,score,note\n".to_string(), |s, i| {
+        s + &format!("user-{},{},-\n", i, i * 10)
+    });
+    let mut importer = Importer::new(Format::Csv, "name", "score");
+    importer.set_chunk_size(30);
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let mut reports = Vec::new();
+    let root = importer.import_with_progress(&mut tree, None, csv.as_bytes(), &mut |n, read| {
+        reports.push((n, read))
+    })?;
+    assert_eq!(reports, [(30, 30), (60, 60), (90, 90), (100, 100)]);
+    let mut map = MerkleMap::<String, String>::with_tree(tree, root);
+    for i in 0..100 {
+        let score = map.get(&format!("user-{}", i))?;
+        assert_eq!(score, Some(format!("{}", i * 10)));
+    }
+
+    let jsonl = (0..100).fold(String::new(), |s, i| {
+        s + &format!("{{\"id\":{},\"data\":{{\"score\":{}}}}}\n", i, i * 10)
+    });
+    let importer = Importer::new(Format::JsonLines, "id", "data");
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = importer.import(&mut tree, None, jsonl.as_bytes())?;
+    let mut map = MerkleMap::<serde_json::Value, serde_json::Value>::with_tree(tree, root);
+    for i in 0..100 {
+        let data = map.get(&serde_json::json!(i))?;
+        assert_eq!(data, Some(serde_json::json!({ "score": i * 10 })));
+    }
+
+    let importer = Importer::new(Format::Csv, "name", "missing");
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    assert!(importer.import(&mut tree, None, csv.as_bytes()).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_reopen_with_traversal() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let traversal = Traversal {
+        bits: BitOrder::LsbFirst,
+        bytes: ByteOrder::BigEndian,
+    };
+    let root = {
+        let mut tree = Monotree::<RocksDB, Blake3>::new(&dbname);
+        tree.set_traversal(traversal)?;
+        tree.inserts(None, &keys, &leaves)?
+    };
+    let mut tree = Monotree::<RocksDB, Blake3>::new(&dbname);
+    assert_eq!(tree.traversal(), traversal);
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_default_codec_then_reproduce_roots_of_0_1() -> Result<()> {
+    // roots of the same tree as written by monotree 0.1
+    let hasher = Blake3::new();
+    let keys: Vec<Hash> = (0u32..100)
+        .map(|i| hasher.digest(&i.to_be_bytes()))
+        .collect();
+    let leaves: Vec<Hash> = (1000u32..1100)
+        .map(|i| hasher.digest(&i.to_be_bytes()))
+        .collect();
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert_eq!(
+        root_to_hex(root.as_ref()),
+        "14a881a745b2e588f54db535cb389175b8886a4b202b15c253b78b1ed3e82fcf"
+    );
+    let mut root = None;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        root = tree.insert(root.as_ref(), key, leaf)?;
+    }
+    assert_eq!(
+        root_to_hex(root.as_ref()),
+        "79c0251b1b5a4a1001cc1f5543e9d4f61a1f462cbe80d3f090a232403d9e9d32"
+    );
+
+    // removing keys, in a batch or one by one in reverse, then inserting them back
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let removed = tree.removes(root.as_ref(), &keys[..30])?;
+    assert_eq!(
+        root_to_hex(removed.as_ref()),
+        "78f03fcff8c69d11264ce100cccbf2ba992a7dfe4b3096b1bce062f553e8759d"
+    );
+    let mut one = root;
+    for key in keys[..30].iter().rev() {
+        one = tree.remove(one.as_ref(), key)?;
+    }
+    assert_eq!(one, removed);
+    let reinserted = tree.inserts(removed.as_ref(), &keys[..30], &leaves[..30])?;
+    assert_eq!(reinserted, root);
+    Ok(())
+}
+
+#[test]
+fn test_decode_nodes_of_0_1_then_rederive_root() -> Result<()> {
+    // every node written by monotree 0.1 inserting 4 keys one by one
+    let nodes = [
+        "26d6197be92d3c70f8ee6452bec1801cee307f150fecb6973c22618bfd772bc2000000026000000001ecd295738bcdcca4a938d814c7864ca58ba26d38270c915f485f39b940c08ff62b01",
+        "68bfe183043ceffaac85f4fd8a7c4a03c107cee8153212072d1597f52c4b15a7000201005882f75066fd0398619cdfe6fcfa463ad254ebdecc381c10dd328cb07b4984860002010060604abfd7695c05c911fd1ba39654b8381bcee3797692bb863134aa16b68a2cab220dc4125936a8c162e115399cf38e3d9ae6379247d4c521f2b7bf2c4f855201",
+        "d295738bcdcca4a938d814c7864ca58ba26d38270c915f485f39b940c08ff62b00000001ec00",
+        "5fe8d595435a65ecafef5bab0ed1846b42c7430c3819c51fa02d4430cfb6040b00000100ec2bd03bf86b935fa34d71ad7ebb049f1f10f87d343e521511d8f9e6625620cd00",
+        "cd02b98e1444027d6d7aa99fadb9d51a21fc67ba9bc7e0038635026c487e595e00010100a4b6064b23dbaa408b171b0fed5628afa267ef40a4f5a806ae2405e85fa6f1c400010100ec2bd03bf86b935fa34d71ad7ebb049f1f10f87d343e521511d8f9e6625620cd5fe8d595435a65ecafef5bab0ed1846b42c7430c3819c51fa02d4430cfb6040b01",
+        "ab220dc4125936a8c162e115399cf38e3d9ae6379247d4c521f2b7bf2c4f85520000010060604abfd7695c05c911fd1ba39654b8381bcee3797692bb863134aa16b68a2c00000001ecd295738bcdcca4a938d814c7864ca58ba26d38270c915f485f39b940c08ff62b01",
+    ];
+    let root = hex_to_root("1ed52f1890b15814a04a9b3ab689a786be010c0d51c8ae156b9315585e960d0c")?;
+    let hasher = Blake3::new();
+    let keys: Vec<Hash> = (0u32..4).map(|i| hasher.digest(&i.to_be_bytes())).collect();
+    let leaves: Vec<Hash> = (1000u32..1004)
+        .map(|i| hasher.digest(&i.to_be_bytes()))
+        .collect();
+
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    for node in nodes.iter() {
+        let bytes = hex::decode(node).unwrap();
+        assert_eq!(Node::<HASH_LEN>::version(&bytes)?, 0);
+        let node = Node::<HASH_LEN>::from_bytes(&bytes)?;
+        assert_eq!(DefaultCodec::encode(&node)?, bytes);
+        tree.db.put(&hasher.digest(&bytes), bytes)?;
+    }
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+        assert!(verify_proof(&hasher, root.as_ref(), leaf, proof.as_ref()));
+    }
+
+    // the same keys inserted in the same order give the root of 0.1
+    let mut fresh = Monotree::<MemoryDB, Blake3>::new("");
+    let mut rederived = None;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        rederived = fresh.insert(rederived.as_ref(), key, leaf)?;
+    }
+    assert_eq!(rederived, root);
+    Ok(())
+}
+
+#[test]
+fn test_node_versions_then_reject_later_ones() -> Result<()> {
+    let (keys, leaves) = (random_hashes(2), random_hashes(2));
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?.expect("root");
+    let bytes = tree.db.get(&root)?.expect("node");
+    assert_eq!(Node::<HASH_LEN>::version(&bytes)?, 0);
+    let mut compact = Monotree::<MemoryDB, Blake3, CompactCodec>::new("");
+    let root = compact.inserts(None, &keys, &leaves)?.expect("root");
+    let bytes = compact.db.get(&root)?.expect("node");
+    assert_eq!(Node::<HASH_LEN>::version(&bytes)?, NODE_VERSION);
+
+    // a node of a later version is detected rather than misread
+    let mut later = bytes.clone();
+    *later.last_mut().unwrap() = ((NODE_VERSION + 1) << 1) | 1;
+    assert!(matches!(
+        Node::<HASH_LEN>::from_bytes(&later),
+        Err(Errors::Decode(_))
+    ));
+    let hash = Blake3::new().digest(&later);
+    compact.db.put(&hash, later)?;
+    assert!(matches!(
+        compact.get(Some(&hash), &keys[0]),
+        Err(Errors::Decode(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_migrate_nodes_between_versions() -> Result<()> {
+    let (keys, leaves) = (random_hashes(500), random_hashes(500));
+    // a tree of nodes of version 0, as written by earlier releases without metadata
+    let mut fixed = Monotree::<MemoryDB, Blake3>::new("");
+    let old = fixed.inserts(None, &keys, &leaves)?;
+    assert_eq!(fixed.db.get(&tree::METADATA_KEY)?, None);
+    let mut tree = Monotree::<MemoryDB, Blake3, CompactCodec>::with_db(fixed.db)?;
+    assert!(tree.migrate(old.as_ref(), 0, 0).is_err());
+
+    let mut reported = Vec::new();
+    let root =
+        tree.migrate_with_progress(old.as_ref(), 0, NODE_VERSION, &mut |n| reported.push(n))?;
+    let mut compact = Monotree::<MemoryDB, Blake3, CompactCodec>::new("");
+    assert_eq!(root, compact.inserts(None, &keys, &leaves)?);
+    assert_eq!(reported.last(), Some(&(keys.len() - 1)));
+    let hasher = Blake3::new();
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), key)?, Some(*leaf));
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?;
+        assert!(verify_proof(&hasher, root.as_ref(), leaf, proof.as_ref()));
+    }
+
+    // nodes migrated already are kept as they are
+    let mut reported = Vec::new();
+    let again =
+        tree.migrate_with_progress(root.as_ref(), 0, NODE_VERSION, &mut |n| reported.push(n))?;
+    assert_eq!((again, reported), (root, vec![0]));
+    assert_eq!(tree.migrate(None, 0, NODE_VERSION)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_compact_codec_then_verify_proofs() -> Result<()> {
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let hasher = Blake3::new();
+    let mut tree = Monotree::<MemoryDB, Blake3, CompactCodec>::new("");
+    let metadata = tree.db.get(&tree::METADATA_KEY)?.expect("metadata");
+    assert_eq!(Metadata::from_bytes(&metadata)?.codec, CompactCodec::ID);
+
+    let half = keys.len() / 2;
+    let old = tree.inserts(None, &keys[..half], &leaves[..half])?;
+    let root = tree.inserts(old.as_ref(), &keys[half..], &leaves[half..])?;
+    let mut fixed = Monotree::<MemoryDB, Blake3>::new("");
+    assert_ne!(fixed.inserts(None, &keys, &leaves)?, root);
+    for (k, v) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(tree.get(root.as_ref(), k)?, Some(*v));
+        let proof = tree.get_merkle_proof(root.as_ref(), k)?;
+        assert!(verify_proof(&hasher, root.as_ref(), v, proof.as_ref()));
+    }
+    let absent = random_hash();
+    let proof = tree.get_non_inclusion_proof(root.as_ref(), &absent)?;
+    assert!(verify_non_inclusion_proof(
+        &hasher,
+        root.as_ref(),
+        &absent,
+        proof.as_ref()
+    ));
+    let proof = tree.consistency_proof(old.as_ref(), root.as_ref())?;
+    assert!(verify_consistency(
+        &hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &proof,
+        Consistency::AppendOnly
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_malformed_compact_nodes_then_fail_to_decode() -> Result<()> {
+    let decode = |bytes: &[u8]| Node::<HASH_LEN>::from_bytes(bytes).map(|_| ());
+    // ranges of varints over `BitsLen`, of a soft node and of either side of a hard node
+    let over = [0xf0, 0xa2, 0x04, 0xf1, 0xa2, 0x04];
+    let hash = [0u8; HASH_LEN];
+    let soft = [&hash[..], &over, &[0x02]].concat();
+    assert!(matches!(decode(&soft), Err(Errors::Decode(_))));
+    let left = [&hash[..], &over, &[0x00, 0x08, 0xff], &hash, &[0x03]].concat();
+    assert!(matches!(decode(&left), Err(Errors::Decode(_))));
+    let right = [&hash[..], &[0x00, 0x08, 0xff], &over, &hash, &[0x03]].concat();
+    assert!(matches!(decode(&right), Err(Errors::Decode(_))));
+
+    // varints unterminated, ranges reversed and paths shorter than their ranges
+    let unterminated = [&hash[..], &[0x80, 0x80, 0x02]].concat();
+    assert!(matches!(decode(&unterminated), Err(Errors::Decode(_))));
+    let reversed = [&hash[..], &[0x08, 0x00, 0xff, 0x02]].concat();
+    assert!(matches!(decode(&reversed), Err(Errors::Decode(_))));
+    let short = [&hash[..], &[0x00, 0x10, 0xff, 0x02]].concat();
+    assert!(matches!(decode(&short), Err(Errors::Decode(_))));
+
+    assert!(matches!(
+        Bits::from_compact_bytes(&over),
+        Err(Errors::Decode(_))
+    ));
+    assert!(matches!(
+        Bits::from_compact_bytes(&[0x80]),
+        Err(Errors::Decode(_))
+    ));
+    let bits = Bits::new(&[0xab, 0xcd]).shift(3, false);
+    assert_eq!(Bits::from_compact_bytes(&bits.to_compact_bytes()?)?, bits);
+    Ok(())
+}
+
+#[test]
+fn test_verifiers_on_malformed_proofs() -> Result<()> {
+    let hasher = Blake3::new();
+    let (keys, leaves) = (random_hashes(50), random_hashes(50));
+    let mut tree = Monotree::<MemoryDB, Blake3, CompactCodec>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let (key, leaf) = (&keys[0], &leaves[0]);
+
+    // ranges of varints over `BitsLen`, and cells of no bits
+    let over: MultiProof = vec![vec![0x01, 0xf0, 0xa2, 0x04, 0xf1, 0xa2, 0x04, 0x02]];
+    assert!(!verify_merkle_proofs(
+        &hasher,
+        root.as_ref(),
+        &[key],
+        &[*leaf],
+        Some(&over)
+    ));
+    let empty: MultiProof = vec![vec![0x01, 0x00, 0x00, 0x02]; 4];
+    assert!(!verify_merkle_proofs(
+        &hasher,
+        root.as_ref(),
+        &[key],
+        &[*leaf],
+        Some(&empty)
+    ));
+    let node = [
+        &[0u8; HASH_LEN][..],
+        &[0xf0, 0xa2, 0x04, 0xf1, 0xa2, 0x04, 0x02],
+    ]
+    .concat();
+    let root_of_node = hasher.digest(&node);
+    assert!(!verify_non_inclusion_proof(
+        &hasher,
+        Some(&root_of_node),
+        key,
+        Some(&vec![node])
+    ));
+
+    // empty keys, and steps on the right with nothing to fold the hash into
+    let proof = tree.get_merkle_proofs(root.as_ref(), &keys[..1])?;
+    assert!(!verify_merkle_proofs(
+        &hasher,
+        root.as_ref(),
+        &[&[][..]],
+        &[*leaf],
+        proof.as_ref()
+    ));
+    let proof = tree.get_non_inclusion_proof(root.as_ref(), &random_hash())?;
+    assert!(!verify_non_inclusion_proof(
+        &hasher,
+        root.as_ref(),
+        &[],
+        proof.as_ref()
+    ));
+    let right: Proof = vec![(true, vec![])];
+    assert_eq!(compute_root(&hasher, leaf, Some(&right)), None);
+    assert!(!verify_proof(&hasher, root.as_ref(), leaf, Some(&right)));
+
+    // proofs truncated, flipped or of random bytes fail to verify, without panicking
+    let proof = tree.get_merkle_proof(root.as_ref(), key)?.expect("proof");
+    let multiproof = tree
+        .get_merkle_proofs(root.as_ref(), &keys)?
+        .expect("proof");
+    let absent = random_hash();
+    let non_inclusion = tree
+        .get_non_inclusion_proof(root.as_ref(), &absent)?
+        .expect("proof");
+    for _ in 0..500 {
+        let mut proof = proof.clone();
+        let (mut multiproof, mut non_inclusion) = (multiproof.clone(), non_inclusion.clone());
+        let i = random_byte() as usize;
+        let (p, m, n) = (
+            i % proof.len(),
+            i % multiproof.len(),
+            i % non_inclusion.len(),
+        );
+        let mut cuts = [&mut proof[p].1, &mut multiproof[m], &mut non_inclusion[n]];
+        match random_byte() % 3 {
+            0 => cuts
+                .iter_mut()
+                .for_each(|cut| cut.truncate(i % (cut.len() + 1))),
+            1 => cuts
+                .iter_mut()
+                .filter(|cut| !cut.is_empty())
+                .for_each(|cut| {
+                    let j = random_byte() as usize % cut.len();
+                    cut[j] ^= 1 << (random_byte() % 8);
+                }),
+            _ => {
+                let random = || random_bytes(random_byte() as usize % 80);
+                proof = (0..i % 8)
+                    .map(|_| (random_byte() & 1 == 1, random()))
+                    .collect();
+                multiproof = (0..i % 8).map(|_| random()).collect();
+                non_inclusion = (0..i % 8).map(|_| random()).collect();
+            }
+        }
+        assert!(!verify_proof(
+            &hasher,
+            root.as_ref(),
+            &random_hash(),
+            Some(&proof)
+        ));
+        compute_root(&hasher, leaf, Some(&proof));
+        verify_merkle_proofs(&hasher, root.as_ref(), &keys, &leaves, Some(&multiproof));
+        verify_non_inclusion_proof(&hasher, root.as_ref(), &absent, Some(&non_inclusion));
+    }
+    Ok(())
+}
+
+#[test]
+#[should_panic(expected = "codec mismatch")]
+fn test_rocksdb_reopen_with_other_codec() {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    {
+        let mut tree = Monotree::<RocksDB, Blake3, CompactCodec>::new(&dbname);
+        tree.insert(None, &random_hash(), &random_hash()).unwrap();
+    }
+    Monotree::<RocksDB, Blake3>::new(&dbname);
+}
+
+#[test]
+fn test_open_backends_under_a_file_then_fail() -> Result<()> {
+    fs::create_dir_all(".tmp")?;
+    let file = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&file).is_ok() {
+            fs::remove_file(&file).unwrap()
+        }
+    });
+    fs::write(&file, b"not a directory")?;
+    let dbname = format!("{}/db", file);
+    assert!(RocksDB::try_new(&dbname).is_err());
+    assert!(Sled::try_new(&dbname).is_err());
+    assert!(ShardedMemoryDB::try_new(&dbname).is_err());
+    assert!(MirroredMemoryDB::try_new(&dbname).is_err());
+    assert!(Monotree::<RocksDB, Blake3>::try_new(&dbname).is_err());
+    assert!(MerkleMap::<String, String, Sled>::try_new(&dbname).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_reopen_with_other_codec_then_fail() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    {
+        let mut tree = Monotree::<RocksDB, Blake3, CompactCodec>::try_new(&dbname)?;
+        tree.insert(None, &random_hash(), &random_hash())?;
+    }
+    assert!(Monotree::<RocksDB, Blake3>::try_new(&dbname).is_err());
+    assert!(Monotree::<RocksDB, Blake3, CompactCodec>::try_new(&dbname).is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_reopen_with_filter() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = {
+        let mut tree = Monotree::<RocksDB, Blake3>::try_new(&dbname)?;
+        tree.enable_filter(&[], 200, 0.01)?;
+        let root = tree.inserts(None, &keys[..50], &leaves[..50])?;
+        tree.insert(root.as_ref(), &keys[50], &leaves[50])?
+    };
+    // the filter is loaded along with the tree and keeps every key inserted
+    let mut tree = Monotree::<RocksDB, Blake3>::try_new(&dbname)?;
+    assert_eq!(
+        tree.gets(root.as_ref(), &keys[..51])?,
+        leaves[..51].iter().cloned().map(Some).collect::<Vec<_>>()
+    );
+    let unknown = random_hash();
+    assert!(keys[51..]
+        .iter()
+        .any(|key| matches!(tree.get(Some(&unknown), key), Ok(None))));
+    assert!(BloomFilter::from_bytes(&[0x00; 4]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_with_options() -> Result<()> {
+    use monotree::database::rocksdb::{BlockBasedOptions, DBCompressionType, Options};
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        for path in [dbname.clone(), format!("{}-missing", dbname)].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_dir_all(path).unwrap()
+            }
+        }
+    });
+    let options = || {
+        let mut table = BlockBasedOptions::default();
+        table.set_bloom_filter(10, false);
+        table.set_lru_cache(1 << 20);
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.set_block_based_table_factory(&table);
+        opts.set_compression_type(DBCompressionType::None);
+        opts
+    };
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = {
+        let db = RocksDB::with_options(&dbname, options())?;
+        let mut tree = Monotree::<RocksDB, Blake3>::with_db(db)?;
+        tree.inserts(None, &keys, &leaves)?
+    };
+    let mut tree =
+        Monotree::<RocksDB, Blake3>::with_db(RocksDB::with_options(&dbname, options())?)?;
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    drop(tree);
+
+    let db = RocksDB::with_cf_options(&dbname, "monotree", options())?;
+    let mut tree = Monotree::<RocksDB, Blake3>::with_db(db)?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert_eq!(tree.get(root.as_ref(), &keys[99])?, Some(leaves[99]));
+
+    let mut opts = Options::default();
+    opts.create_if_missing(false);
+    assert!(RocksDB::with_options(&format!("{}-missing", dbname), opts).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_rocksdb_with_column_family() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    {
+        // data of an application in the default column family
+        let mut db = RocksDB::try_new(&dbname)?;
+        db.put(&keys[0], b"app".to_vec())?;
+    }
+    let root = {
+        let mut tree =
+            Monotree::<RocksDB, Blake3>::with_db(RocksDB::with_cf(&dbname, "monotree")?)?;
+        let root = tree.inserts(None, &keys, &leaves)?;
+        assert_eq!(tree.db.get(&keys[0])?, None);
+        assert!(!tree.db.keys()?.contains(&keys[0].to_vec()));
+        root
+    };
+    {
+        let mut tree =
+            Monotree::<RocksDB, Blake3>::with_db(RocksDB::with_cf(&dbname, "monotree")?)?;
+        assert_eq!(
+            tree.gets(root.as_ref(), &keys)?,
+            leaves.iter().cloned().map(Some).collect::<Vec<_>>()
+        );
+        assert!(tree.db.total_size()? > 0);
+    }
+    {
+        let mut other = Monotree::<RocksDB, Blake3>::with_db(RocksDB::with_cf(&dbname, "other")?)?;
+        assert!(matches!(
+            other.get(root.as_ref(), &keys[0]),
+            Err(Errors::NotFound(_))
+        ));
+    }
+    // every column family has to be opened once created, the default one included
+    let mut db = RocksDB::with_cf(&dbname, "default")?;
+    assert_eq!(db.get(&keys[0])?, Some(b"app".to_vec()));
+    assert!(db.get(&root.expect("root"))?.is_none());
+    Ok(())
+}
+
+#[test]
+fn test_share_db_handles_of_application() -> Result<()> {
+    use monotree::database::rocksdb::{Options, DB};
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut opts = Options::default();
+    opts.create_if_missing(true);
+    opts.create_missing_column_families(true);
+    let db = Arc::new(DB::open_cf(&opts, &dbname, ["monotree"])?);
+    db.put(b"app", b"data")?;
+
+    let mut tree = Monotree::<RocksDB, Blake3>::with_db(RocksDB::from_db(Arc::clone(&db)))?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let cf = RocksDB::from_db_cf(Arc::clone(&db), "monotree")?;
+    let mut other = Monotree::<RocksDB, Blake3>::with_db(cf)?;
+    let other_root = other.inserts(None, &keys[..50], &leaves[..50])?;
+    assert_eq!(tree.get(root.as_ref(), &keys[99])?, Some(leaves[99]));
+    assert_eq!(other.get(other_root.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert!(other.get(root.as_ref(), &keys[0]).is_err());
+    assert!(db.get(root.expect("root"))?.is_some());
+    assert_eq!(db.get(b"app")?.as_deref(), Some(&b"data"[..]));
+    assert!(RocksDB::from_db_cf(Arc::clone(&db), "missing").is_err());
+    drop((tree, other));
+
+    let sled = sled::open(format!("{}-sled", dbname))?;
+    let _s = scopeguard::guard((), |_| {
+        fs::remove_dir_all(format!("{}-sled", dbname)).ok();
+    });
+    let mut tree = Monotree::<Sled, Blake3>::with_db(Sled::from_db(sled.clone()))?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let mut other = Monotree::<Sled, Blake3>::with_db(Sled::from_db(sled.clone()))?;
+    assert_eq!(other.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert!(sled.get(root.expect("root"))?.is_some());
+
+    // a tree of its own in the database of the application is apart from the default one
+    let mut tree = Monotree::<Sled, Blake3>::with_db(Sled::from_tree(sled.open_tree("tree")?))?;
+    assert!(matches!(
+        tree.get(root.as_ref(), &keys[0]),
+        Err(Errors::NotFound(_))
+    ));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert!(sled.open_tree("tree")?.get(root.expect("root"))?.is_some());
+    assert!(tree.db.total_size()? > 0);
+
+    let config = sled::Config::new()
+        .temporary(true)
+        .cache_capacity(1 << 20)
+        .use_compression(false)
+        .flush_every_ms(None);
+    let mut tree = Monotree::<Sled, Blake3>::with_db(Sled::with_config(config)?)?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    Ok(())
+}
+
+#[test]
+fn test_open_read_only_databases() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        for path in [dbname.clone(), format!("{}-sled", dbname)].iter() {
+            if fs::metadata(path).is_ok() {
+                fs::remove_dir_all(path).unwrap()
+            }
+        }
+    });
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    fn check<D: Database>(dbpath: &str, keys: &[Hash], leaves: &[Hash]) -> Result<()> {
+        assert!(D::open_read_only(dbpath).is_err());
+        let root = {
+            let mut tree = Monotree::<D, Blake3>::try_new(dbpath)?;
+            let root = tree.inserts(None, keys, leaves)?;
+            tree.swap_root(None, root.as_ref())?;
+            tree.db.flush()?;
+            root
+        };
+        // `Sled` releases its lock in the background once dropped
+        let mut opened = D::open_read_only(dbpath);
+        for _ in 0..100 {
+            if opened.is_ok() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            opened = D::open_read_only(dbpath);
+        }
+        let mut tree = Monotree::<D, Blake3>::with_db(opened?)?;
+        assert_eq!(tree.load_root()?, root);
+        assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+        let proof = tree.get_merkle_proof(root.as_ref(), &keys[1])?;
+        assert!(verify_proof(
+            &tree.hasher,
+            root.as_ref(),
+            &leaves[1],
+            proof.as_ref()
+        ));
+        assert!(matches!(
+            tree.insert(root.as_ref(), &random_hash(), &random_hash()),
+            Err(Errors::Db(_))
+        ));
+        assert!(tree.db.delete(&keys[0]).is_err());
+        assert!(tree.swap_root(root.as_ref(), None).is_err());
+        assert_eq!(tree.load_root()?, root);
+        Ok(())
+    }
+    check::<RocksDB>(&dbname, &keys, &leaves)?;
+    check::<Sled>(&format!("{}-sled", dbname), &keys, &leaves)?;
+    assert!(MemoryDB::open_read_only("").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_within_open_batch() -> Result<()> {
+    let mut db = SnapshotDB::with_db(MemoryDB::new(""));
+    let (before, after) = (random_bytes(HASH_LEN), random_bytes(HASH_LEN));
+    db.init_batch()?;
+    db.put(&before, b"before".to_vec())?;
+    let id = db.snapshot();
+    db.put(&after, b"after".to_vec())?;
+    // the batch opened before the snapshot is committed, not left open to be discarded
+    db.finish_batch()?;
+    db.rollback(id)?;
+    db.init_batch()?;
+    db.finish_batch()?;
+    assert_eq!(db.get(&before)?, Some(b"before".to_vec()));
+    assert_eq!(db.get(&after)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_then_rollback_or_release() -> Result<()> {
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let hasher = Blake3::new();
+    let (keys, leaves) = (random_hashes(300), random_hashes(300));
+    let (root, speculative, released) = {
+        let mut tree = Monotree::<SnapshotDB<RocksDB>, Blake3>::try_new(&dbname)?;
+        let root = tree.inserts(None, &keys[..100], &leaves[..100])?;
+
+        // speculative updates are proven, then discarded
+        let id = tree.snapshot();
+        let speculative = tree.inserts(root.as_ref(), &keys[100..200], &leaves[100..200])?;
+        let proof = tree.get_merkle_proof(speculative.as_ref(), &keys[150])?;
+        assert!(verify_proof(
+            &hasher,
+            speculative.as_ref(),
+            &leaves[150],
+            proof.as_ref()
+        ));
+        tree.rollback(id)?;
+        assert!(matches!(
+            tree.get(speculative.as_ref(), &keys[150]),
+            Err(Errors::NotFound(_))
+        ));
+        assert_eq!(tree.get(root.as_ref(), &keys[50])?, Some(leaves[50]));
+        assert!(tree.rollback(id).is_err());
+
+        // nested snapshots are rolled back or released independently
+        let outer = tree.snapshot();
+        let released = tree.inserts(root.as_ref(), &keys[200..250], &leaves[200..250])?;
+        let inner = tree.snapshot();
+        tree.inserts(released.as_ref(), &keys[250..], &leaves[250..])?;
+        tree.rollback(inner)?;
+        assert_eq!(tree.db.depth(), 1);
+        tree.release(outer)?;
+        assert_eq!(tree.db.depth(), 0);
+        (root, speculative, released)
+    };
+    // only what was released has been written to disk
+    let mut tree = Monotree::<RocksDB, Blake3>::try_new(&dbname)?;
+    assert_eq!(tree.get(root.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert_eq!(tree.get(released.as_ref(), &keys[200])?, Some(leaves[200]));
+    assert!(tree.get(speculative.as_ref(), &keys[100]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_namespaces_share_db_then_isolate_trees() -> Result<()> {
+    let (keys, leaves) = (random_hashes(300), random_hashes(300));
+    let mut utxo =
+        Monotree::<NamespacedDB<MemoryDB>, Blake3>::with_namespace(MemoryDB::new(""), b"utxo")?;
+    let mut state = Monotree::<_, Blake3>::with_db(utxo.db.namespace(b"state"))?;
+    let spent = utxo.inserts(None, &keys[..100], &leaves[..100])?;
+    let unspent = utxo.inserts(spent.as_ref(), &keys[100..200], &leaves[100..200])?;
+    let root = state.inserts(None, &keys, &leaves)?;
+    utxo.swap_root(None, unspent.as_ref())?;
+
+    // keys of one namespace are never those of another
+    let (nodes, all) = (utxo.db.keys()?.len(), state.db.keys()?.len());
+    assert!(nodes > 0 && all > nodes);
+    assert_eq!(state.load_root()?, None);
+    assert_eq!(utxo.load_root()?, unspent);
+
+    // pruning a tree leaves others as they were
+    assert!(utxo.prune(unspent.as_slice())? > 0);
+    assert!(utxo.get(spent.as_ref(), &keys[0]).is_err());
+    assert_eq!(utxo.get(unspent.as_ref(), &keys[150])?, Some(leaves[150]));
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        assert_eq!(state.get(root.as_ref(), key)?, Some(*leaf));
+    }
+    assert_eq!(state.db.keys()?.len(), all);
+
+    // the same then within batches, written to the shared database apart
+    state.db.init_batch()?;
+    state.db.put(&keys[0], leaves[0].to_vec())?;
+    assert_eq!(state.db.get(&keys[0])?, Some(leaves[0].to_vec()));
+    assert_eq!(utxo.db.namespace(b"state").get(&keys[0])?, None);
+    state.db.finish_batch()?;
+    assert_eq!(
+        utxo.db.namespace(b"state").get(&keys[0])?,
+        Some(leaves[0].to_vec())
+    );
+    assert_eq!(utxo.db.get(&keys[0])?, None);
+    assert!(NamespacedDB::<MemoryDB>::try_new("").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_namespaces_hold_writes_then_commit_at_once() -> Result<()> {
+    let (keys, leaves) = (random_hashes(200), random_hashes(200));
+    let db = NamespacedDB::with_db(MemoryDB::new(""), b"utxo");
+    let mut nullifiers = Monotree::<_, Blake3>::with_db(db.namespace(b"nullifier"))?;
+    let mut utxo = Monotree::<_, Blake3>::with_db(db)?;
+    utxo.db.hold();
+    nullifiers.db.hold();
+    let spent = utxo.inserts(None, &keys[..100], &leaves[..100])?;
+    let nullified = nullifiers.inserts(None, &keys[100..], &leaves[100..])?;
+    utxo.swap_root(None, spent.as_ref())?;
+    nullifiers.swap_root(None, nullified.as_ref())?;
+
+    // writes held are observed by their own handles only
+    assert_eq!(utxo.get(spent.as_ref(), &keys[0])?, Some(leaves[0]));
+    assert_eq!(utxo.load_root()?, spent);
+    let mut observer = Monotree::<_, Blake3>::with_db(utxo.db.namespace(b"utxo"))?;
+    assert_eq!(observer.load_root()?, None);
+    assert!(observer.db.keys()?.is_empty());
+
+    NamespacedDB::commit_held(&mut [&mut utxo.db, &mut nullifiers.db])?;
+    assert!(!utxo.db.is_holding() && !nullifiers.db.is_holding());
+    assert_eq!(observer.load_root()?, spent);
+    assert_eq!(observer.get(spent.as_ref(), &keys[50])?, Some(leaves[50]));
+    let mut observer = Monotree::<_, Blake3>::with_db(utxo.db.namespace(b"nullifier"))?;
+    assert_eq!(observer.load_root()?, nullified);
+    assert_eq!(
+        observer.get(nullified.as_ref(), &keys[150])?,
+        Some(leaves[150])
+    );
+
+    // handles of other databases are never committed together
+    let mut other = NamespacedDB::with_db(MemoryDB::new(""), b"utxo");
+    other.hold();
+    other.put(&keys[0], leaves[0].to_vec())?;
+    assert!(NamespacedDB::commit_held(&mut [&mut utxo.db, &mut other]).is_err());
+    assert!(other.is_holding());
+    Ok(())
+}
+
+#[test]
+fn test_shell_walk_tree_then_prove() -> Result<()> {
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?.expect("root");
+    tree.swap_root(None, Some(&root))?;
+    let mut shell = Shell::new(&mut tree)?;
+    assert_eq!(shell.execute("root")?, format!("{}\n", hex::encode(root)));
+    assert_eq!(shell.execute("stats")?.lines().nth(1), Some("leaves: 100"));
+    let leaf = shell.execute(&format!("get {}", hex::encode(keys[7])))?;
+    assert_eq!(leaf, format!("{}\n", hex::encode(leaves[7])));
+    let proof = shell.execute(&format!("proof {}", hex::encode(keys[7])))?;
+    assert!(proof.ends_with("verified: true\n"));
+
+    // walk down into the first cell, then back up
+    let ls = shell.execute("ls")?;
+    assert_eq!(ls.lines().count(), 2);
+    let cell: Vec<&str> = ls.lines().next().unwrap().split(' ').collect();
+    assert_eq!(cell[0], "node");
+    shell.execute(&format!("cd {}", cell[2]))?;
+    assert_eq!(shell.execute("pwd")?, format!("/{} {}\n", cell[2], cell[1]));
+    shell.execute("cd ..")?;
+    assert!(shell.execute("pwd")?.starts_with("/ "));
+    assert!(matches!(shell.execute("cd 012"), Err(Errors::Other(_))));
+    assert!(shell.execute("bogus").is_err());
+
+    // a session goes on after errors, until quit
+    let mut output = Vec::new();
+    shell.run(
+        std::io::Cursor::new("bogus\nroot -\nroot\nquit\nls\n"),
+        &mut output,
+    )?;
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.matches("monotree> ").count(), 4);
+    assert!(output.contains("error: unknown command: bogus"));
+    assert!(output.contains("> -\n"));
+    Ok(())
+}
+
+/// Serve enough of the protocol of `Redis` over a `HashMap`, standing in for a real server.
+fn spawn_fake_redis() -> String {
+    use std::io::{BufRead, BufReader, Write};
+    type Store = Arc<std::sync::Mutex<std::collections::HashMap<Vec<u8>, Vec<u8>>>>;
+    fn execute(store: &Store, args: &[Vec<u8>]) -> Vec<u8> {
+        let bulk =
+            |bytes: &[u8]| [format!("${}\r\n", bytes.len()).as_bytes(), bytes, b"\r\n"].concat();
+        let mut store = store.lock().unwrap();
+        match args[0].as_slice() {
+            b"GET" => store.get(&args[1]).map_or(b"$-1\r\n".to_vec(), |v| bulk(v)),
+            b"MGET" => {
+                let values = args[1..]
+                    .iter()
+                    .flat_map(|key| store.get(key).map_or(b"$-1\r\n".to_vec(), |v| bulk(v)));
+                [
+                    format!("*{}\r\n", args.len() - 1).into_bytes(),
+                    values.collect(),
+                ]
+                .concat()
+            }
+            b"SET" => {
+                store.insert(args[1].to_vec(), args[2].to_vec());
+                b"+OK\r\n".to_vec()
+            }
+            b"DEL" => format!(":{}\r\n", store.remove(&args[1]).map_or(0, |_| 1)).into_bytes(),
+            b"SCAN" => {
+                let keys: Vec<u8> = store.keys().flat_map(|key| bulk(key)).collect();
+                [
+                    format!("*2\r\n$1\r\n0\r\n*{}\r\n", store.len()).as_bytes(),
+                    &keys,
+                ]
+                .concat()
+            }
+            b"INFO" => bulk(format!("# Memory\r\nused_memory:{}\r\n", 1 << 20).as_bytes()),
+            b"PING" | b"SELECT" | b"WATCH" | b"UNWATCH" => b"+OK\r\n".to_vec(),
+            _ => b"-ERR unknown command\r\n".to_vec(),
+        }
+    }
+    fn read_command(reader: &mut impl BufRead) -> Option<Vec<Vec<u8>>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let n: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::new();
+        for _ in 0..n {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            let len: usize = line.trim().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0u8; len + 2];
+            reader.read_exact(&mut arg).ok()?;
+            arg.truncate(len);
+            args.push(arg);
+        }
+        Some(args)
+    }
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let store = Store::default();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let (store, mut writer) = (store.clone(), stream.unwrap());
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                let mut queue: Option<Vec<Vec<Vec<u8>>>> = None;
+                while let Some(args) = read_command(&mut reader) {
+                    let reply = match (&mut queue, args[0].as_slice()) {
+                        (Some(_), b"EXEC") => {
+                            let queued = queue.take().unwrap();
+                            let replies = queued.iter().flat_map(|args| execute(&store, args));
+                            [
+                                format!("*{}\r\n", queued.len()).into_bytes(),
+                                replies.collect(),
+                            ]
+                            .concat()
+                        }
+                        (Some(queued), _) => {
+                            queued.push(args);
+                            b"+QUEUED\r\n".to_vec()
+                        }
+                        (None, b"MULTI") => {
+                            queue = Some(Vec::new());
+                            b"+OK\r\n".to_vec()
+                        }
+                        (None, _) => execute(&store, &args),
+                    };
+                    if writer.write_all(&reply).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    address
+}
+
+#[test]
+fn test_redis_shared_by_instances() -> Result<()> {
+    let address = spawn_fake_redis();
+    let hasher = Blake3::new();
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let mut tree = Monotree::<Redis, Blake3>::try_new(&format!("redis://{}/0", address))?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert!(tree.swap_root(None, root.as_ref())?);
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[3])?;
+    assert!(verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[3],
+        proof.as_ref()
+    ));
+
+    // another instance sees the same tree, and updates it in turn
+    let mut other = Monotree::<Redis, Blake3>::try_new(&address)?;
+    assert_eq!(other.load_root()?, root);
+    let found = other.gets(root.as_ref(), &keys)?;
+    assert_eq!(found, leaves.iter().cloned().map(Some).collect::<Vec<_>>());
+    // reads within a batch see its pending writes, the rest read by a single MGET
+    other.db.init_batch()?;
+    other.db.put(&keys[0], b"pending".to_vec())?;
+    let values = other
+        .db
+        .get_many(&[&keys[0], &root.expect("root"), &random_hash()])?;
+    assert_eq!(values[0].as_deref(), Some(&b"pending"[..]));
+    assert!(values[1].is_some() && values[2].is_none());
+    let newer = other.removes(root.as_ref(), &keys[..10])?;
+    assert!(other.swap_root(root.as_ref(), newer.as_ref())?);
+    assert!(!tree.swap_root(root.as_ref(), None)?);
+    assert_eq!(tree.load_root()?, newer);
+
+    assert!(tree.db.total_size()? > 0);
+    assert!(tree.prune(&[])? > 0);
+    assert!(tree.get(root.as_ref(), &keys[0]).is_err());
+    assert_eq!(tree.get(newer.as_ref(), &keys[50])?, Some(leaves[50]));
+
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .to_string();
+    assert!(matches!(Redis::try_new(&closed), Err(Errors::Io(_))));
+    Ok(())
+}
+
+/// Serve enough of the S3 API over a `HashMap`, standing in for an object store,
+/// paging listings by 40 objects and refusing requests unsigned if `signed`.
+fn spawn_fake_object_store(signed: bool) -> String {
+    use std::io::{BufRead, BufReader, Read, Write};
+    type Store = Arc<std::sync::Mutex<std::collections::BTreeMap<String, Vec<u8>>>>;
+    fn respond(store: &Store, method: &str, target: &str, body: Vec<u8>) -> (u16, Vec<u8>) {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut store = store.lock().unwrap();
+        match (method, path.trim_start_matches('/').split_once('/')) {
+            ("HEAD", None) if path == "/archive" => (200, vec![]),
+            ("GET", None) if path == "/archive" => {
+                let param = |name: &str| {
+                    query
+                        .split('&')
+                        .find_map(|pair| pair.strip_prefix(&format!("{}=", name)))
+                        .unwrap_or_default()
+                        .replace("%2F", "/")
+                };
+                let (prefix, after) = (param("prefix"), param("continuation-token"));
+                let listed: Vec<_> = store
+                    .range(after.clone()..)
+                    .filter(|(name, _)| name.starts_with(&prefix) && **name != after)
+                    .take(41)
+                    .collect();
+                let contents: String = listed
+                    .iter()
+                    .take(40)
+                    .map(|(name, value)| {
+                        format!(
+                            "<Contents><Key>{}</Key><Size>{}</Size>\
+                             <LastModified>2020-01-01T00:00:00.000Z</LastModified></Contents>",
+                            name,
+                            value.len()
+                        )
+                    })
+                    .collect();
+                let next = match listed.len() > 40 {
+                    true => format!(
+                        "<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+                        listed[39].0
+                    ),
+                    false => "<IsTruncated>false</IsTruncated>".to_string(),
+                };
+                let xml = format!("<ListBucketResult>{}{}</ListBucketResult>", contents, next);
+                (200, xml.into_bytes())
+            }
+            ("GET", Some(("archive", name))) => match store.get(name) {
+                Some(value) => (200, value.to_vec()),
+                None => (404, b"<Error><Code>NoSuchKey</Code></Error>".to_vec()),
+            },
+            ("PUT", Some(("archive", name))) => {
+                store.insert(name.to_string(), body);
+                (200, vec![])
             }
+            ("POST", None) if path == "/archive" && query == "delete" => {
+                let body = String::from_utf8(body).unwrap();
+                let deleted: String = body
+                    .split("<Key>")
+                    .skip(1)
+                    .filter_map(|part| part.split("</Key>").next())
+                    .map(|name| {
+                        store.remove(name);
+                        format!("<Deleted><Key>{}</Key></Deleted>", name)
+                    })
+                    .collect();
+                let xml = format!("<DeleteResult>{}</DeleteResult>", deleted);
+                (200, xml.into_bytes())
+            }
+            _ => (404, b"<Error><Code>NoSuchBucket</Code></Error>".to_vec()),
         }
-    };
+    }
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let store = Store::default();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let (store, mut writer) = (store.clone(), stream.unwrap());
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let request: Vec<String> = line.split_whitespace().map(String::from).collect();
+                let (mut length, mut authorized) = (0, !signed);
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let header = line.trim().to_lowercase();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = header.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    authorized |= header.starts_with("authorization: aws4-hmac-sha256 ");
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                let (status, body) = match authorized {
+                    true => respond(&store, &request[0], &request[1], body),
+                    false => (403, b"<Error><Code>AccessDenied</Code></Error>".to_vec()),
+                };
+                let reply = format!(
+                    "HTTP/1.1 {} OK\r\nContent-Length: {}\r\nETag: \"0\"\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                writer.write_all(&[reply.as_bytes(), &body].concat()).ok();
+            });
+        }
+    });
+    address
 }
 
-macro_rules! impl_test_with_params {
-    ([$($fn:tt)+], [$($db:tt)+], [$($hasher:tt)+], [$n:tt, $($ns:tt),*]) => {
-        impl_test_with_params!([$($fn)+], [$($db)+], [$($hasher)+], [$n]);
-        impl_test_with_params!([$($fn)+], [$($db)+], [$($hasher)+], [$($ns),*]);
+#[test]
+fn test_object_store_archiving_roots() -> Result<()> {
+    let address = spawn_fake_object_store(true);
+    let dbpath = format!("http://{}/archive/trees/main", address);
+    let credentials = Credentials {
+        access_key: "AKIDEXAMPLE".to_string(),
+        secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        region: "us-east-1".to_string(),
     };
+    assert!(ObjectStoreDb::with_credentials(&dbpath, None).is_err());
+    let db = ObjectStoreDb::with_credentials(&dbpath, Some(credentials.to_owned()))?;
+    let mut tree = Monotree::<_, Blake3>::with_db(db)?;
+    let hasher = Blake3::new();
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert!(tree.swap_root(None, root.as_ref())?);
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[7])?;
+    assert!(verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[7],
+        proof.as_ref()
+    ));
 
-    ([$($fn:tt)+], [$($db:tt)+], [($($h:tt)+), $($hasher:tt),*], [$n:tt]) => {
-        impl_test_with_params!([$($fn)+], [$($db)+], [($($h)+)], [$n]);
-        impl_test_with_params!([$($fn)+], [$($db)+], [$($hasher),*], [$n]);
+    // another instance serves proofs of the archived root, reading nodes once
+    let db = ObjectStoreDb::with_credentials(&dbpath, Some(credentials.to_owned()))?;
+    let mut archive = Monotree::<_, Blake3>::with_db(db)?;
+    assert_eq!(archive.load_root()?, root);
+    assert_eq!(archive.get_merkle_proof(root.as_ref(), &keys[7])?, proof);
+    let misses = archive.db.cache_stats().misses;
+    assert_eq!(archive.get_merkle_proof(root.as_ref(), &keys[7])?, proof);
+    assert_eq!(archive.db.cache_stats().misses, misses);
+    assert_eq!(archive.get(root.as_ref(), &random_hash())?, None);
+
+    // listings span several pages, within the prefix only
+    let other = format!("http://{}/archive/trees/other", address);
+    let mut other = ObjectStoreDb::with_credentials(&other, Some(credentials.to_owned()))?;
+    other.put(b"elsewhere", b"value".to_vec())?;
+    let stored = tree.db.keys()?;
+    assert!(stored.len() > 100 && !stored.contains(&b"elsewhere".to_vec()));
+    assert!(tree.db.total_size()? > 0);
+    let newer = tree.removes(root.as_ref(), &keys[..10])?;
+    assert!(tree.prune(&[newer.expect("root")])? > 0);
+    assert!(tree.db.keys()?.len() < stored.len());
+    assert_eq!(tree.get(newer.as_ref(), &keys[50])?, Some(leaves[50]));
+    assert_eq!(other.get(b"elsewhere")?, Some(b"value".to_vec()));
+
+    // as the cold tier, archiving what a hot database takes in
+    let cold = ObjectStoreDb::with_credentials(&dbpath, Some(credentials))?;
+    let mut tiered = Monotree::<_, Blake3>::with_db(TieredDb::with_dbs(MemoryDB::new(""), cold))?;
+    let more = random_hashes(10);
+    let latest = tiered.inserts(newer.as_ref(), &more, &more)?;
+    tiered.db.flush()?;
+    assert_eq!(archive.get(latest.as_ref(), &more[3])?, Some(more[3]));
+
+    assert!(ObjectStoreDb::try_new(&format!("http://{}/missing", address)).is_err());
+    assert!(ObjectStoreDb::try_new(&format!("https://{}/archive", address)).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_networked_backends_time_out() -> Result<()> {
+    use std::time::{Duration, Instant};
+    let timeout = Duration::from_millis(200);
+    let (keys, leaves) = (random_hashes(20), random_hashes(20));
+
+    // within the timeouts, backends serve trees as usual
+    let db = Redis::with_timeout(&spawn_fake_redis(), timeout)?;
+    let mut tree = Monotree::<_, Blake3>::with_db(db)?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert_eq!(tree.get(root.as_ref(), &keys[5])?, Some(leaves[5]));
+    let dbpath = format!("http://{}/archive/trees", spawn_fake_object_store(false));
+    let db = ObjectStoreDb::with_timeout(&dbpath, None, timeout)?;
+    let mut tree = Monotree::<_, Blake3>::with_db(db)?;
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert_eq!(tree.get(root.as_ref(), &keys[5])?, Some(leaves[5]));
+
+    // a server accepting connections but never answering fails them in time
+    let hung = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let address = hung.local_addr()?.to_string();
+    let started = Instant::now();
+    let redis = Redis::with_timeout(&address, timeout).and_then(|mut db| db.get(&keys[0]));
+    assert!(matches!(redis, Err(Errors::Timeout(_))));
+    let dbpath = format!("http://{}/archive", address);
+    let store = ObjectStoreDb::with_timeout(&dbpath, None, timeout);
+    assert!(matches!(store, Err(Errors::Timeout(_))));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    Ok(())
+}
+
+/// An `AsyncDatabase` over `HashMap`, standing in for a backend over network.
+struct AsyncMemoryDB {
+    db: std::collections::HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl AsyncDatabase for AsyncMemoryDB {
+    fn try_new(_dbpath: &str) -> Result<Self> {
+        Ok(AsyncMemoryDB {
+            db: std::collections::HashMap::new(),
+        })
+    }
+
+    fn get<'a>(&'a mut self, key: &'a [u8]) -> DbFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move { Ok(self.db.get(key).cloned()) })
+    }
+
+    fn put<'a>(&'a mut self, key: &'a [u8], value: Vec<u8>) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            self.db.insert(key.to_vec(), value);
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a mut self, key: &'a [u8]) -> DbFuture<'a, ()> {
+        Box::pin(async move {
+            self.db.remove(key);
+            Ok(())
+        })
+    }
+
+    fn init_batch(&mut self) -> DbFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn finish_batch(&mut self) -> DbFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn total_size(&self) -> DbFuture<'_, u64> {
+        let size = self
+            .db
+            .iter()
+            .map(|(k, v)| k.len() + v.len())
+            .sum::<usize>();
+        Box::pin(async move { Ok(size as u64) })
+    }
+}
+
+#[test]
+fn test_async_monotree_with_async_database() -> Result<()> {
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let mut sync = Monotree::<MemoryDB, Blake3>::new("");
+    let half = keys.len() / 2;
+    let mut expected = sync.inserts(None, &keys[..half], &leaves[..half])?;
+    for (key, leaf) in keys[half..].iter().zip(leaves[half..].iter()) {
+        expected = sync.insert(expected.as_ref(), key, leaf)?;
+    }
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        let tree = AsyncMonotree::<BlockingDB<AsyncMemoryDB>, Blake3>::new("").await?;
+        let root = tree
+            .inserts(None, keys[..half].to_vec(), leaves[..half].to_vec())
+            .await?;
+
+        let mut root = root;
+        for (key, leaf) in keys[half..].iter().zip(leaves[half..].iter()) {
+            root = tree.insert(root, *key, *leaf).await?;
+        }
+        assert_eq!(root, expected);
+
+        // clones share the tree, so spawned tasks can read it concurrently
+        let handles: Vec<_> = keys
+            .iter()
+            .map(|key| {
+                let (tree, key) = (tree.clone(), *key);
+                tokio::spawn(async move { tree.get(root, key).await })
+            })
+            .collect();
+        for (handle, leaf) in handles.into_iter().zip(leaves.iter()) {
+            assert_eq!(handle.await??, Some(*leaf));
+        }
+        assert_eq!(
+            tree.gets(root, keys.clone()).await?,
+            leaves.iter().map(|l| Some(*l)).collect::<Vec<_>>()
+        );
+
+        let hasher = Blake3::new();
+        let proof = tree.get_merkle_proof(root, keys[0]).await?;
+        assert!(verify_proof(
+            &hasher,
+            root.as_ref(),
+            &leaves[0],
+            proof.as_ref()
+        ));
+        let root = tree.remove(root, keys[0]).await?;
+        assert_eq!(tree.get(root, keys[0]).await?, None);
+        let root = tree.removes(root, keys[1..].to_vec()).await?;
+        assert_eq!(root, None);
+        Ok(())
+    })
+}
+
+#[test]
+fn test_async_monotree_stream_leaves() -> Result<()> {
+    use futures::StreamExt;
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let mut sync = Monotree::<MemoryDB, Blake3>::new("");
+    let root = sync.inserts(None, &keys, &leaves)?;
+    let expected = sync.iter(root.as_ref()).collect::<Result<Vec<_>>>()?;
+
+    let mut rt = tokio::runtime::Runtime::new()?;
+    rt.block_on(async {
+        let tree = AsyncMonotree::with_tree(sync);
+        let mut stream = Box::pin(tree.leaves(root, 8)?);
+        let first = stream.next().await.expect("first entry")?;
+        assert_eq!(first, expected[0]);
+
+        // the tree is not held by the stream while it waits to be polled
+        assert_eq!(tree.get(root, keys[0]).await?, Some(leaves[0]));
+        let mut entries = vec![first];
+        while let Some(entry) = stream.next().await {
+            entries.push(entry?);
+        }
+        assert_eq!(entries, expected);
+
+        assert!(Box::pin(tree.leaves(None, 8)?).next().await.is_none());
+        let missing = random_hash();
+        let mut stream = Box::pin(tree.leaves(Some(missing), 8)?);
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(Errors::NotFound(_)))
+        ));
+        assert!(stream.next().await.is_none());
+        Ok(())
+    })
+}
+
+/// An `Anchor` appending roots to a log, standing in for a chain.
+struct LogAnchor {
+    log: Vec<Hash>,
+}
+
+impl Anchor for LogAnchor {
+    fn anchor(&mut self, root: &Hash) -> Result<Vec<u8>> {
+        self.log.push(*root);
+        Ok((self.log.len() as u64 - 1).to_be_bytes().to_vec())
+    }
+
+    fn verify(&self, root: &Hash, data: &[u8]) -> Result<bool> {
+        let index: u64 = bytes_to_int(data);
+        Ok(self.log.get(index as usize) == Some(root))
+    }
+}
+
+#[test]
+fn test_root_store_records_roots_by_version() -> Result<()> {
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    assert_eq!(RootStore::new(&mut tree).latest()?, None);
+    let (keys, leaves) = (random_hashes(50), random_hashes(50));
+    let mut roots = vec![None];
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        let root = tree.insert(roots[roots.len() - 1].as_ref(), key, leaf)?;
+        roots.push(root);
+    }
+    for (version, root) in roots.iter().enumerate() {
+        assert_eq!(
+            RootStore::new(&mut tree).commit(root.as_ref())?,
+            version as u64
+        );
+    }
+
+    let mut store = RootStore::new(&mut tree);
+    assert_eq!(store.len()?, roots.len() as u64);
+    assert_eq!(store.latest()?, Some((50, roots[50])));
+    assert_eq!(store.root_at(0)?, None);
+    assert_eq!(store.root_at(25)?, roots[25]);
+    assert!(store.root_at(51).is_err());
+
+    // roots are found again after restart, and survive pruning of stale nodes
+    let mut tree = Monotree::<MemoryDB, Blake3>::with_db(tree.db)?;
+    tree.prune(&[roots[50].expect("root")])?;
+    let mut store = RootStore::new(&mut tree);
+    assert_eq!(store.root_at(25)?, roots[25]);
+    assert_eq!(store.commit(None)?, 51);
+    assert_eq!(store.latest()?, Some((51, None)));
+    Ok(())
+}
+
+#[test]
+fn test_anchor_roots_every_n_commits() -> Result<()> {
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let mut anchoring = Anchoring::new(LogAnchor { log: Vec::new() }, Schedule::Commits(10));
+    let mut root = None;
+    let mut roots = Vec::new();
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        root = tree.insert(root.as_ref(), key, leaf)?;
+        roots.push(root.expect("root"));
+        if let Some(anchored) = anchoring.commit(&mut tree, root.as_ref())? {
+            assert_eq!(Some(anchored), receipt(&mut tree, &root.expect("root"))?);
+        }
+    }
+    for (i, root) in roots.iter().enumerate() {
+        assert_eq!(anchoring.verify(&mut tree, root)?, i % 10 == 9);
+    }
+
+    // a receipt not matching the service fails
+    let root = roots[9];
+    let mut forged = receipt(&mut tree, &root)?.expect("receipt");
+    forged.data = 1u64.to_be_bytes().to_vec();
+    let key = receipt_key(&tree, &root);
+    tree.db.put(&key, forged.to_bytes())?;
+    assert!(!anchoring.verify(&mut tree, &root)?);
+    Ok(())
+}
+
+#[test]
+fn test_anchor_roots_at_interval() -> Result<()> {
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let schedule = Schedule::Interval(std::time::Duration::from_secs(3600));
+    let mut anchoring = Anchoring::new(LogAnchor { log: Vec::new() }, schedule);
+    assert_eq!(anchoring.commit(&mut tree, None)?, None);
+    let first = tree.insert(None, &random_hash(), &random_hash())?;
+    assert!(anchoring.commit(&mut tree, first.as_ref())?.is_some());
+    let second = tree.insert(first.as_ref(), &random_hash(), &random_hash())?;
+    assert_eq!(anchoring.commit(&mut tree, second.as_ref())?, None);
+    let second = second.expect("root");
+    assert!(!anchoring.verify(&mut tree, &second)?);
+    anchoring.anchor_now(&mut tree, &second)?;
+    assert!(anchoring.verify(&mut tree, &second)?);
+    Ok(())
+}
+
+#[test]
+fn test_verify_proof_with_quorum() -> Result<()> {
+    let keys = random_hashes(100);
+    let leaves = random_hashes(100);
+    let hasher = Blake3::new();
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let forged = Some(random_hash());
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[0])?;
+    let verify = |roots: &[Option<Hash>], quorum| {
+        verify_proof_with_quorum(&hasher, roots, &leaves[0], proof.as_ref(), quorum)
     };
 
-    ([$($fn:tt)+], [($($d:tt)+), $($db:tt),*], [($($h:tt)+)], [$n:tt]) => {
-        impl_test_with_params!([$($fn)+], [($($d)+)], [($($h)+)], [$n]);
-        impl_test_with_params!([$($fn)+], [$($db),*], [($($h)+)], [$n]);
+    let roots = [root, root, forged, None];
+    assert!(verify(&roots, Quorum::AtLeast(2)));
+    assert!(!verify(&roots, Quorum::AtLeast(3)));
+    assert!(!verify(&roots, Quorum::Majority));
+    assert!(verify(&roots[..3], Quorum::Majority));
+    assert!(!verify(&roots[..3], Quorum::All));
+    assert!(verify(&roots[..2], Quorum::All));
+
+    // conflicting roots reaching the quorum are not trusted
+    assert_eq!(agreed_root(&[root, forged], Quorum::AtLeast(1)), None);
+    assert_eq!(agreed_root(&[root, forged, root], Quorum::Majority), root);
+    assert!(!verify(&[forged, forged, root], Quorum::Majority));
+    assert!(!verify(&[], Quorum::All));
+    Ok(())
+}
+
+#[test]
+fn test_label_keys_then_serve_views() -> Result<()> {
+    use monotree::view::*;
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    tree.set_traversal(Traversal {
+        bits: BitOrder::LsbFirst,
+        bytes: ByteOrder::LittleEndian,
+    })?;
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    for key in keys[..30].iter() {
+        set_labels(&mut tree, key, &["alice", "alice"])?;
+    }
+    for key in keys[20..50].iter() {
+        set_labels(&mut tree, key, &["bob", "alice"])?;
+    }
+    assert_eq!(labels(&mut tree, &keys[25])?, vec!["alice", "bob"]);
+    assert_eq!(labels(&mut tree, &keys[60])?, Vec::<String>::new());
+    assert!(set_labels(&mut tree, &keys[0], &[""]).is_err());
+    // labels change no root
+    assert_eq!(tree.inserts(None, &keys, &leaves)?, root);
+
+    let hasher = Blake3::new();
+    let view_of = |tree: &mut Monotree<MemoryDB, Blake3>, labels: &[&str]| -> Result<View> {
+        let view = view(tree, root.as_ref(), labels)?;
+        assert!(verify_view(&hasher, &view));
+        Ok(view)
     };
+    let alice = view_of(&mut tree, &["alice"])?;
+    assert_eq!(alice.entries.len(), 50);
+    assert_eq!(view_of(&mut tree, &["bob"])?.entries.len(), 30);
+    assert_eq!(view_of(&mut tree, &["bob", "carol"])?.entries.len(), 30);
+    for (key, leaf) in alice.entries.iter() {
+        let i = keys.iter().position(|k| k == key).expect("key");
+        assert!(i < 50 && leaves[i] == *leaf);
+    }
 
-    ([$f:tt, $($fn:tt),*], [($($d:tt)+)], [($($h:tt)+)], [$n:tt]) => {
-        impl_integration_test!($f, ($($d)+), ($($h)+), $n);
-        impl_test_with_params!([$($fn),*], [($($d)+)], [($($h)+)], [$n]);
+    set_labels(&mut tree, &keys[0], &[])?;
+    assert_eq!(
+        view(&mut tree, root.as_ref(), &["alice"])?.entries.len(),
+        49
+    );
+    assert_eq!(view(&mut tree, None, &["alice"])?.proof, None);
+
+    let mut forged = alice.clone();
+    forged.entries[0].1 = random_hash();
+    assert!(!verify_view(&hasher, &forged));
+    let mut forged = alice.clone();
+    forged.traversal = Traversal::default();
+    assert!(!verify_view(&hasher, &forged));
+    let mut forged = alice;
+    forged.entries.pop();
+    forged.root = Some(random_hash());
+    assert!(!verify_view(&hasher, &forged));
+    Ok(())
+}
+
+#[test]
+fn test_journal_batches_then_recover_exactly_once() -> Result<()> {
+    use monotree::queue::*;
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        if fs::metadata(&dbname).is_ok() {
+            fs::remove_dir_all(&dbname).unwrap()
+        }
+    });
+    let (keys, leaves) = (random_hashes(60), random_hashes(60));
+    let batches: Vec<Vec<Update>> = vec![
+        keys[..30]
+            .iter()
+            .zip(leaves[..30].iter())
+            .map(|(k, l)| (*k, Some(*l)))
+            .collect(),
+        keys[30..]
+            .iter()
+            .zip(leaves[30..].iter())
+            .map(|(k, l)| (*k, Some(*l)))
+            .collect(),
+        keys[..10].iter().map(|k| (*k, None)).collect(),
+    ];
+    // batches are applied an update after another
+    let mut expected = Monotree::<MemoryDB, Blake3>::new("");
+    let mut root = None;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        root = expected.insert(root.as_ref(), key, leaf)?;
+    }
+    for key in keys[..10].iter() {
+        root = expected.remove(root.as_ref(), key)?;
+    }
+    let expected = root;
+
+    {
+        let mut tree = Monotree::<RocksDB, Blake3>::new(&dbname);
+        for (seq, batch) in batches.iter().enumerate() {
+            assert_eq!(submit(&mut tree, batch)?, seq as u64);
+        }
+        assert_eq!(pending(&mut tree)?, 3);
+        assert_eq!(batch(&mut tree, 2)?.as_ref(), Some(&batches[2]));
+        assert!(apply_next(&mut tree)?.is_some());
+        // crash after writing the nodes of the next batch, before recording its root
+        let root = tree.load_root()?;
+        tree.inserts(root.as_ref(), &keys[30..], &leaves[30..])?;
+    }
+    let mut tree = Monotree::<RocksDB, Blake3>::new(&dbname);
+    assert_eq!(pending(&mut tree)?, 2);
+    assert_eq!(bounds(&mut tree)?, (1, 3));
+    assert_eq!(batch(&mut tree, 0)?, None);
+    assert_eq!(recover(&mut tree)?, 2);
+    assert_eq!(tree.load_root()?, expected);
+    assert_eq!(recover(&mut tree)?, 0);
+    assert_eq!(apply_next(&mut tree)?, None);
+    assert_eq!(tree.load_root()?, expected);
+
+    // batches emptying the tree clear the recorded root
+    submit(
+        &mut tree,
+        &keys[10..].iter().map(|k| (*k, None)).collect::<Vec<_>>(),
+    )?;
+    assert_eq!(apply_next(&mut tree)?, Some(None));
+    assert_eq!(tree.load_root()?, None);
+    Ok(())
+}
+
+#[test]
+fn test_journal_updates_then_replay() -> Result<()> {
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(30), random_hashes(30));
+    let mut journal = Journal::new(&mut tree);
+    assert!(journal.is_empty()?);
+    let mut root = None;
+    for (key, leaf) in keys[..20].iter().zip(leaves[..20].iter()) {
+        root = journal.insert(root.as_ref(), key, leaf)?;
+    }
+    for key in keys[..5].iter() {
+        root = journal.remove(root.as_ref(), key)?;
+    }
+    assert_eq!(journal.len()?, 25);
+    let entry = journal.entry(20)?;
+    assert_eq!((entry.key, entry.leaf), (keys[0], None));
+    assert_eq!(journal.entry(19)?.leaf, Some(leaves[19]));
+    assert_eq!(journal.entry(24)?.root, root);
+    assert_eq!(journal.root_before(0)?, None);
+    assert!(matches!(journal.entry(25), Err(Errors::NotFound(_))));
+    assert_eq!(journal.replay(0)?, root);
+    assert_eq!(journal.replay(10)?, root);
+    assert_eq!(journal.replay(25)?, root);
+    assert!(matches!(journal.replay(26), Err(Errors::NotFound(_))));
+
+    // catch up another tree, then again after more updates
+    let mut other = Monotree::<MemoryDB, Blake3>::new("");
+    assert_eq!(journal.replay_into(0, &mut other)?, root);
+    for (key, leaf) in keys[20..].iter().zip(leaves[20..].iter()) {
+        root = journal.insert(root.as_ref(), key, leaf)?;
+    }
+    assert_eq!(journal.replay_into(25, &mut other)?, root);
+    let mut expected = Monotree::<MemoryDB, Blake3>::new("");
+    let mut expected_root = None;
+    for (key, leaf) in keys[..20].iter().zip(leaves[..20].iter()) {
+        expected_root = expected.insert(expected_root.as_ref(), key, leaf)?;
+    }
+    for key in keys[..5].iter() {
+        expected_root = expected.remove(expected_root.as_ref(), key)?;
+    }
+    for (key, leaf) in keys[20..].iter().zip(leaves[20..].iter()) {
+        expected_root = expected.insert(expected_root.as_ref(), key, leaf)?;
+    }
+    assert_eq!(root, expected_root);
+    for (key, leaf) in keys[5..].iter().zip(leaves[5..].iter()) {
+        assert_eq!(other.get(root.as_ref(), key)?, Some(*leaf));
+    }
+
+    // a tree not holding the root before the version replayed from fails
+    let mut fresh = Monotree::<MemoryDB, Blake3>::new("");
+    assert!(journal.replay_into(10, &mut fresh).is_err());
+
+    // entries are found under the prefix of metadata, and kept by pruning
+    let records = |tree: &mut Monotree<MemoryDB, Blake3>| -> Result<usize> {
+        let entries = tree.db.iter_prefix(&tree::METADATA_KEY)?;
+        Ok(entries
+            .filter(|entry| matches!(entry, Ok((key, _)) if key.len() > HASH_LEN))
+            .count())
     };
+    assert_eq!(records(&mut tree)?, 36);
+    assert!(tree.prune(root.as_slice())? > 0);
+    assert_eq!(records(&mut tree)?, 36);
+    let mut journal = Journal::new(&mut tree);
+    let mut fresh = Monotree::<MemoryDB, Blake3>::new("");
+    assert_eq!(journal.replay_into(0, &mut fresh)?, root);
 
-    ([$f:tt], [($($d:tt)+)], [($($h:tt)+)], [$n:tt]) => {
-        impl_integration_test!($f, ($($d)+), ($($h)+), $n);
+    // the same for trees of other lengths of hashes
+    let mut tree = Monotree::<MemoryDB, Sha512, DefaultCodec, 64>::new("");
+    let mut journal = Journal::new(&mut tree);
+    let (key, leaf) = ([0x01; 64], [0x02; 64]);
+    let root = journal.insert(None, &key, &leaf)?;
+    assert_eq!(journal.entry(0)?.leaf, Some(leaf));
+    assert_eq!(journal.remove(root.as_ref(), &key)?, None);
+    assert_eq!(journal.replay(0)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_generate_vectors_then_verify() -> Result<()> {
+    let vectors = generate_all(20)?;
+    assert_eq!(vectors.len(), TRAVERSALS.len() * 8);
+    assert_eq!(vectors, generate_all(20)?);
+    let hasher = Blake3::new();
+    for vector in vectors.iter().filter(|vector| vector.hasher == "blake3") {
+        let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+        tree.set_traversal(vector.traversal)?;
+        let root = tree.inserts(None, &vector.keys, &vector.leaves)?;
+        assert_eq!(root, vector.root);
+        for (leaf, proof) in vector.leaves.iter().zip(&vector.proofs) {
+            assert!(verify_proof(&hasher, root.as_ref(), leaf, Some(proof)));
+        }
+        assert_eq!(vector.removes.len(), 7);
+        let mut root = root;
+        for (key, removed) in vector.removes.iter().zip(&vector.removed_roots) {
+            root = tree.remove(root.as_ref(), key)?;
+            assert_eq!(root, *removed);
+        }
+    }
+
+    // hashers and orders of traversal all give different roots
+    let mut roots: Vec<_> = vectors.iter().map(|vector| vector.root).collect();
+    roots.sort();
+    roots.dedup();
+    assert_eq!(roots.len(), vectors.len());
+
+    let json = vectors[0].to_json();
+    assert_eq!(json["hasher"], "blake3");
+    assert_eq!(json["traversal"]["bits"], "MsbFirst");
+    assert_eq!(json["root"], hex::encode(vectors[0].root.expect("root")));
+    assert_eq!(
+        json["proofs"].as_array().map(|proofs| proofs.len()),
+        Some(20)
+    );
+    assert!(generate::<Blake3>("blake3", TRAVERSALS[0], 0)?.to_json()["root"].is_null());
+    Ok(())
+}
+
+#[test]
+fn test_export_vectors_then_load_and_check() -> Result<()> {
+    let vectors = generate_all(10)?;
+    let json = export(&vectors).to_string();
+    let loaded = load(&json)?;
+    assert_eq!(loaded, vectors);
+    for vector in loaded.iter() {
+        check(vector)?;
+    }
+    assert_eq!(
+        load(&vectors[0].to_json().to_string())?,
+        vec![vectors[0].clone()]
+    );
+    check(&generate::<Blake3>("blake3", TRAVERSALS[1], 0)?)?;
+
+    // fixtures of other hashers, orders or bytes are all rejected
+    let mut forged = vectors[0].clone();
+    forged.hasher = "blake2s".to_string();
+    assert!(matches!(check(&forged), Err(Errors::ProofInvalid(_))));
+    let mut forged = vectors[0].clone();
+    forged.traversal = TRAVERSALS[3];
+    assert!(matches!(check(&forged), Err(Errors::ProofInvalid(_))));
+    let mut forged = vectors[0].clone();
+    forged.proofs[3][0].1[0] ^= 1;
+    assert!(matches!(check(&forged), Err(Errors::ProofInvalid(_))));
+    let mut forged = vectors[0].clone();
+    forged.removed_roots.swap(0, 1);
+    assert!(matches!(check(&forged), Err(Errors::ProofInvalid(_))));
+    forged.removed_roots.pop();
+    assert!(matches!(check(&forged), Err(Errors::ProofInvalid(_))));
+    let mut forged = vectors[0].clone();
+    forged.leaves.pop();
+    assert!(check(&forged).is_err());
+    forged.hasher = "md5".to_string();
+    assert!(matches!(check(&forged), Err(Errors::Other(_))));
+
+    let mut json = vectors[0].to_json();
+    json["traversal"]["bits"] = "Msb".into();
+    assert!(matches!(
+        TestVector::from_json(&json),
+        Err(Errors::Decode(_))
+    ));
+    json = vectors[0].to_json();
+    json["keys"][0] = "00ff".into();
+    assert!(TestVector::from_json(&json).is_err());
+
+    // fixtures without removals are read as removing no key
+    json = vectors[0].to_json();
+    let fields = json.as_object_mut().expect("object");
+    fields.remove("removes");
+    fields.remove("removed_roots");
+    let vector = TestVector::from_json(&json)?;
+    assert!(vector.removes.is_empty() && vector.removed_roots.is_empty());
+    check(&vector)?;
+    assert!(load("{").is_err());
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn test_serde_proofs_round_trip() -> Result<()> {
+    use monotree::view::*;
+    let hasher = Blake3::new();
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let old = tree.inserts(None, &keys[..50], &leaves[..50])?;
+    let root = tree.inserts(old.as_ref(), &keys[50..], &leaves[50..])?;
+
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[0])?;
+    let json = serde_json::to_string(&proof)?;
+    let proof: Option<Proof> = serde_json::from_str(&json)?;
+    assert!(verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+
+    let proof = tree.consistency_proof(old.as_ref(), root.as_ref())?;
+    let decoded: ConsistencyProof = serde_json::from_slice(&serde_json::to_vec(&proof)?)?;
+    assert_eq!(decoded, proof);
+    assert!(verify_consistency(
+        &hasher,
+        old.as_ref(),
+        root.as_ref(),
+        &decoded,
+        Consistency::AppendOnly
+    ));
+
+    let page = tree
+        .get_merkle_proof_page(root.as_ref(), &keys, usize::MAX, None)?
+        .expect("page");
+    let decoded: ProofPage = serde_json::from_str(&serde_json::to_string(&page)?)?;
+    assert_eq!(decoded, page);
+
+    set_labels(&mut tree, &keys[0], &["alice"])?;
+    let view = view(&mut tree, root.as_ref(), &["alice"])?;
+    let decoded: View = serde_json::from_str(&serde_json::to_string(&view)?)?;
+    assert!(verify_view(&hasher, &decoded));
+
+    // hashes of other lengths are rejected, not truncated
+    let json = serde_json::to_string(&page)?.replacen("[", "[[0],", 2);
+    assert!(serde_json::from_str::<ProofPage>(&json).is_err());
+
+    // nodes are serialized as their bytes, and borrow them back
+    let unit = Unit {
+        hash: &keys[1],
+        bits: Bits::new(&leaves[1]),
     };
+    let bytes = Node::<HASH_LEN>::new(Some(unit), None).to_bytes()?;
+    let node = Node::<HASH_LEN>::from_bytes(&bytes)?;
+    assert_eq!(
+        serde_json::to_string(&node)?,
+        serde_json::to_string(&bytes)?
+    );
+    let deserializer =
+        serde::de::value::BorrowedBytesDeserializer::<serde::de::value::Error>::new(&bytes[..]);
+    let decoded: Node = serde::Deserialize::deserialize(deserializer).expect("node");
+    assert_eq!(decoded.to_bytes()?, bytes);
+    Ok(())
+}
 
-    ($($other:tt)*) => {};
+#[test]
+fn test_encode_proofs_then_decode() -> Result<()> {
+    let hasher = Blake3::new();
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?.expect("proof");
+        let bytes = proof.to_bytes();
+        assert_eq!(bytes[0], monotree::wire::WIRE_VERSION);
+        let decoded = Proof::from_bytes(&bytes)?;
+        assert_eq!(decoded, proof);
+        assert!(verify_proof(&hasher, root.as_ref(), leaf, Some(&decoded)));
+    }
+
+    // byte for byte, as documented
+    let proof: Proof = vec![(false, vec![0xaa; 2]), (true, vec![0xbb; 130])];
+    let bytes = proof.to_bytes();
+    let expected = [
+        &[0x01, 0x02, 0x00, 0x02, 0xaa, 0xaa, 0x01, 0x82, 0x01][..],
+        &[0xbb; 130][..],
+    ]
+    .concat();
+    assert_eq!(bytes, expected);
+    assert_eq!(Proof::new().to_bytes(), vec![0x01, 0x00]);
+
+    // anything but the one encoding of a proof is rejected
+    let malformed = |bytes: &[u8]| Proof::from_bytes(bytes).is_err();
+    assert!(malformed(&[]));
+    assert!(malformed(&[&[0x03], &bytes[1..]].concat()));
+    assert!(malformed(&bytes[..bytes.len() - 1]));
+    assert!(malformed(&[&bytes[..], &[0x00]].concat()));
+    assert!(malformed(&[0x01, 0x01, 0x02, 0x00]));
+    assert!(malformed(&[0x01, 0x01, 0x01, 0x00]));
+    assert!(malformed(&[0x01, 0x81, 0x00]));
+    // counts and lengths too large for any proof, or for `usize` of 32-bit targets
+    let huge = int_to_varint(u64::MAX);
+    assert!(malformed(&[&[0x01][..], &huge].concat()));
+    assert!(malformed(&[&[0x01, 0x01, 0x00][..], &huge].concat()));
+    assert!(malformed(
+        &[&[0x01][..], &int_to_varint(1 << 32), &[0x00, 0x00]].concat()
+    ));
+    Ok(())
 }
 
-impl_test_with_params!(
-    [
-        insert_keys_then_verify_values,
-        insert_keys_then_gen_and_verify_proof,
-        insert_keys_then_delete_keys_immediately,
-        insert_keys_then_delete_keys_in_order,
-        insert_keys_then_delete_keys_reversely,
-        insert_keys_then_delete_keys_randomly
-    ],
-    [("hashmap", MemoryDB), ("rocksdb", RocksDB), ("sled", Sled)],
-    [
-        ("blake3", Blake3),
-        ("blake2s", Blake2s),
-        ("blake2b", Blake2b),
-        ("sha2", Sha2),
-        ("sha3", Sha3)
-    ],
-    [100, 500, 1000]
-);
+#[test]
+fn test_convert_roots_and_proofs_to_hex_and_json() -> Result<()> {
+    let hasher = Blake3::new();
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(50), random_hashes(50));
+    let root = tree.inserts(None, &keys, &leaves)?;
+
+    let hex = root_to_hex(root.as_ref());
+    assert_eq!(hex.len(), HASH_LEN * 2);
+    assert_eq!(hex_to_root(&hex)?, root);
+    assert_eq!(hex_to_root::<HASH_LEN>(&format!("0x{}", hex))?, root);
+    assert_eq!(root_to_hex::<HASH_LEN>(None), "");
+    assert_eq!(hex_to_root::<HASH_LEN>("")?, None);
+    for invalid in [&hex[2..], &hex[..HASH_LEN], "zz", &format!("{}00", hex)].iter() {
+        assert!(matches!(
+            hex_to_root::<HASH_LEN>(invalid),
+            Err(Errors::Decode(_))
+        ));
+    }
+    assert_eq!(hex_to_hash::<4>("0xdeadbeef")?, [0xde, 0xad, 0xbe, 0xef]);
+
+    let proof = tree
+        .get_merkle_proof(root.as_ref(), &keys[0])?
+        .expect("proof");
+    let json = serde_json::to_string(&proof.to_json())?;
+    let decoded = Proof::from_json(&serde_json::from_str(&json)?)?;
+    assert_eq!(decoded, proof);
+    assert!(verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[0],
+        Some(&decoded)
+    ));
+    let proof: Proof = vec![(false, vec![0xaa]), (true, vec![0xbb, 0xcc])];
+    assert_eq!(
+        proof.to_json(),
+        serde_json::json!([[false, "aa"], [true, "bbcc"]])
+    );
+    for invalid in [
+        serde_json::json!({}),
+        serde_json::json!([[false]]),
+        serde_json::json!([[1, "aa"]]),
+        serde_json::json!([[false, "zz"]]),
+        serde_json::json!([[true, ""]]),
+        serde_json::json!([[false, "aa", 0]]),
+    ]
+    .iter()
+    {
+        assert!(matches!(Proof::from_json(invalid), Err(Errors::Decode(_))));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sign_roots_then_verify_signed_proofs() -> Result<()> {
+    // vectors of RFC 8032, section 7.1
+    for (secret, public, message, signature) in [
+        (
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+        ),
+        (
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+        ),
+    ]
+    .iter()
+    {
+        let signer = Ed25519Signer::from_secret(&hex_to_hash(secret)?);
+        let message = hex::decode(message).expect("message");
+        assert_eq!(hex::encode(signer.public_key()), *public);
+        assert_eq!(hex::encode(signer.sign(&message)), *signature);
+        assert!(Ed25519Signer::verify(
+            &signer.public_key(),
+            &message,
+            &signer.sign(&message)
+        ));
+    }
+
+    let hasher = Blake3::new();
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(20), random_hashes(20));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let signer = Ed25519Signer::generate();
+    let public = signer.public_key();
+
+    let signature = signer.sign_root(root.as_ref());
+    assert!(Ed25519Signer::verify_root(
+        &public,
+        root.as_ref(),
+        &signature
+    ));
+    assert!(!Ed25519Signer::verify_root(&public, None, &signature));
+    assert!(!Ed25519Signer::verify(
+        &public,
+        &root.expect("root"),
+        &signature
+    ));
+
+    let signed = signed_proof(&mut tree, &signer, root.as_ref(), &keys[0])?.expect("signed");
+    assert!(verify_signed_proof::<Ed25519Signer, _>(
+        &hasher, &public, &leaves[0], &signed
+    ));
+    assert!(!verify_signed_proof::<Ed25519Signer, _>(
+        &hasher, &public, &leaves[1], &signed
+    ));
+    let other = Ed25519Signer::generate();
+    assert!(!verify_signed_proof::<Ed25519Signer, _>(
+        &hasher,
+        &other.public_key(),
+        &leaves[0],
+        &signed
+    ));
+    let mut tampered = signed.clone();
+    tampered.signature[0] ^= 0x01;
+    assert!(!verify_signed_proof::<Ed25519Signer, _>(
+        &hasher, &public, &leaves[0], &tampered
+    ));
+    tampered.signature.pop();
+    assert!(!verify_signed_proof::<Ed25519Signer, _>(
+        &hasher, &public, &leaves[0], &tampered
+    ));
+    assert_eq!(
+        signed_proof(&mut tree, &signer, root.as_ref(), &random_hash())?,
+        None
+    );
+    assert_eq!(signed_proof(&mut tree, &signer, None, &keys[0])?, None);
+    Ok(())
+}
+
+#[test]
+fn test_compress_proofs_then_decode() -> Result<()> {
+    let hasher = Blake3::new();
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(100), random_hashes(100));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        let proof = tree.get_merkle_proof(root.as_ref(), key)?.expect("proof");
+        let bytes = proof.to_compressed_bytes();
+        assert_eq!(bytes[0], monotree::wire::WIRE_VERSION_COMPRESSED);
+        assert_eq!(bytes.len(), proof.compressed_len());
+        assert_eq!(proof.to_bytes().len(), proof.encoded_len());
+        assert!(proof.compressed_len() < proof.encoded_len());
+        let decoded = Proof::from_bytes(&bytes)?;
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded.depth(), proof.len());
+        assert!(verify_proof(&hasher, root.as_ref(), leaf, Some(&decoded)));
+    }
+
+    // byte for byte, as documented
+    let proof: Proof = vec![(false, vec![0xaa; 2]), (true, vec![0xbb; 130])];
+    let bytes = proof.to_compressed_bytes();
+    let expected = [
+        &[0x02, 0x02, 0x02, 0x02, 0xaa, 0xaa, 0x82, 0x01][..],
+        &[0xbb; 130][..],
+    ]
+    .concat();
+    assert_eq!(bytes, expected);
+    assert_eq!(Proof::new().to_compressed_bytes(), vec![0x02, 0x00]);
+    let malformed = |bytes: &[u8]| Proof::from_bytes(bytes).is_err();
+    assert!(malformed(&[&[0x02, 0x02, 0x06], &bytes[3..]].concat()));
+    assert!(malformed(&[0x02, 0x01, 0x01, 0x00]));
+    assert!(malformed(&bytes[..bytes.len() - 1]));
+
+    // siblings of sparse Merkle trees are mostly empty, and left out
+    let mut smt = SparseMerkleTree::<MemoryDB, Blake3>::new("");
+    let root = smt.inserts(None, &keys, &leaves)?;
+    let proof = smt.get_proof(root.as_ref(), &keys[0])?;
+    let bytes = compress_smt_proof(&proof);
+    assert!(bytes.len() < HASH_LEN * 16);
+    assert_eq!(decompress_smt_proof(&bytes)?, proof);
+    assert!(decompress_smt_proof(&bytes[..bytes.len() - 1]).is_err());
+    assert!(decompress_smt_proof(&[&bytes[..], &[0u8; HASH_LEN]].concat()).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_verify_proof_without_tree() -> Result<()> {
+    let mut tree = Monotree::<MemoryDB, Blake3>::new("");
+    let (keys, leaves) = (random_hashes(50), random_hashes(50));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    let proof = tree.get_merkle_proof(root.as_ref(), &keys[0])?;
+    drop(tree);
+
+    // a light client holds nothing but the root, the leaf and the proof
+    let hasher = Blake3::new();
+    assert!(verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[0],
+        proof.as_ref()
+    ));
+    assert!(!verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[1],
+        proof.as_ref()
+    ));
+    assert!(!verify_proof(&hasher, None, &leaves[0], proof.as_ref()));
+    let malformed: Proof = vec![(true, vec![])];
+    assert!(!verify_proof(
+        &hasher,
+        root.as_ref(),
+        &leaves[0],
+        Some(&malformed)
+    ));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "wasm")]
+fn test_wasm_tree_then_verify_proof() -> Result<()> {
+    use monotree::wasm;
+    let mut tree = wasm::WasmTree::new();
+    let (keys, leaves) = (random_hashes(50), random_hashes(50));
+    for (key, leaf) in keys.iter().zip(leaves.iter()) {
+        tree.insert(key, leaf).expect("insert");
+    }
+    tree.remove(&keys[49]).expect("remove");
+    let root = tree.root();
+    let mut native = Monotree::<MemoryDB, DefaultHasher>::new("");
+    let expected = native.inserts(None, &keys[..49], &leaves[..49])?;
+    assert_eq!(root, expected.expect("root").to_vec());
+
+    assert_eq!(tree.get(&keys[0]).expect("get"), Some(leaves[0].to_vec()));
+    assert_eq!(tree.get(&keys[49]).expect("get"), None);
+    let proof = tree.prove(&keys[0]).expect("prove").expect("proof");
+    assert!(wasm::verify_proof(&root, &leaves[0], &proof));
+    assert!(!wasm::verify_proof(&root, &leaves[1], &proof));
+    assert!(!wasm::verify_proof(&[], &leaves[0], &proof));
+    assert!(!wasm::verify_proof(&root, &leaves[0], &proof[1..]));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "wasm")]
+fn test_indexed_db_without_handle_in_memory() -> Result<()> {
+    use monotree::wasm::IndexedDb;
+    // outside browsers, only the part kept in memory is at work
+    let mut tree = Monotree::<IndexedDb, DefaultHasher>::new("");
+    check_invariants(&mut tree, &Checks::default())?;
+    let (keys, leaves) = (random_hashes(20), random_hashes(20));
+    let root = tree.inserts(None, &keys, &leaves)?;
+    assert!(tree.swap_root(None, root.as_ref())?);
+    tree.db.flush()?;
+    assert_eq!(tree.load_root()?, root);
+    assert_eq!(tree.get(root.as_ref(), &keys[5])?, Some(leaves[5]));
+    Ok(())
+}
+
+// built over the primitives of `shuttle`, the handle only works within its tests
+#[cfg(not(feature = "model-checking"))]
+#[test]
+fn test_sync_monotree_reads_concurrently() -> Result<()> {
+    fn shared<T: Send + Sync>(_: &T) {}
+    let dbname = format!(".tmp/{}", hex!(random_bytes(4)));
+    let _g = scopeguard::guard((), |_| {
+        fs::remove_dir_all(&dbname).ok();
+    });
+    let db = sled::open(&dbname)?;
+    let tree: SyncMonotree<Sled, Blake3> =
+        SyncMonotree::new(move || Ok(Sled::from_db(db.clone())))?;
+    shared(&tree);
+    let (keys, leaves) = (random_hashes(400), random_hashes(400));
+    let root = tree.inserts(None, &keys[..200], &leaves[..200])?;
+
+    // readers prove the entries of the first root while a writer inserts the rest
+    let last = std::thread::scope(|scope| -> Result<Option<Hash>> {
+        let readers: Vec<_> = (0..4)
+            .map(|t| {
+                let (tree, keys, leaves) = (&tree, &keys, &leaves);
+                scope.spawn(move || -> Result<()> {
+                    let hasher = Blake3::new();
+                    for i in (t..200).step_by(4) {
+                        assert_eq!(tree.get(root.as_ref(), &keys[i])?, Some(leaves[i]));
+                        let proof = tree.get_merkle_proof(root.as_ref(), &keys[i])?;
+                        assert!(verify_proof(
+                            &hasher,
+                            root.as_ref(),
+                            &leaves[i],
+                            proof.as_ref()
+                        ));
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        let mut last = root;
+        for (key, leaf) in keys[200..].iter().zip(leaves[200..].iter()) {
+            last = tree.insert(last.as_ref(), key, leaf)?;
+        }
+        for reader in readers {
+            reader.join().expect("reader")?;
+        }
+        Ok(last)
+    })?;
+    assert_eq!(
+        tree.gets(last.as_ref(), &keys)?,
+        leaves.iter().map(|l| Some(*l)).collect::<Vec<_>>()
+    );
+
+    // readers observe the settings recorded by writers since they were opened
+    tree.write(|tree| tree.enable_filter(&[last.expect("root")], 1000, 0.01))?;
+    assert_eq!(tree.get(last.as_ref(), &keys[0])?, Some(leaves[0]));
+    let more = random_hash();
+    let last = tree.insert(last.as_ref(), &more, &more)?;
+    assert_eq!(tree.get(last.as_ref(), &more)?, Some(more));
+    let removed = tree.removes(last.as_ref(), &keys[..10])?;
+    assert_eq!(tree.get(removed.as_ref(), &keys[0])?, None);
+    Ok(())
+}
@@ -0,0 +1,226 @@
+//! Model-checking of `SyncMonotree` by `shuttle`, which runs the threads of each test
+//! over many schedules of their own, so that races show up regardless of timing.
+//!
+//! Run with `cargo test --test model_check --features model-checking`.
+use monotree::database::*;
+use monotree::hasher::*;
+use monotree::utils::*;
+use monotree::*;
+use shuttle::sync::{Arc, Mutex};
+use shuttle::thread;
+use std::collections::HashMap;
+
+/// Number of schedules explored by each test.
+const ITERATIONS: usize = 300;
+
+/// A database whose handles share their entries, as those of `RocksDB` and `Sled` do,
+/// locked by the primitives of `shuttle` so that every access is a point of preemption.
+/// Batches are kept by each handle and applied at once by `finish_batch()`.
+struct SharedDb {
+    entries: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    batch: Option<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+}
+
+impl SharedDb {
+    fn handle(&self) -> Self {
+        SharedDb {
+            entries: self.entries.clone(),
+            batch: None,
+        }
+    }
+
+    fn write(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Result<()> {
+        match self.batch.as_mut() {
+            Some(batch) => {
+                batch.insert(key.to_vec(), value);
+            }
+            None => {
+                let mut entries = self.entries.lock().expect("entries");
+                match value {
+                    Some(value) => entries.insert(key.to_vec(), value),
+                    None => entries.remove(key),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Database for SharedDb {
+    fn try_new(_dbpath: &str) -> Result<Self> {
+        Ok(SharedDb {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            batch: None,
+        })
+    }
+
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(value) = self.batch.as_ref().and_then(|batch| batch.get(key)) {
+            return Ok(value.clone());
+        }
+        Ok(self.entries.lock().expect("entries").get(key).cloned())
+    }
+
+    fn put(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.write(key, Some(value))
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<()> {
+        self.write(key, None)
+    }
+
+    fn init_batch(&mut self) -> Result<()> {
+        self.batch = Some(HashMap::new());
+        Ok(())
+    }
+
+    fn finish_batch(&mut self) -> Result<()> {
+        if let Some(batch) = self.batch.take() {
+            let mut entries = self.entries.lock().expect("entries");
+            for (key, value) in batch {
+                match value {
+                    Some(value) => entries.insert(key, value),
+                    None => entries.remove(&key),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn total_size(&self) -> Result<u64> {
+        let entries = self.entries.lock().expect("entries");
+        Ok(entries
+            .iter()
+            .map(|(k, v)| (k.len() + v.len()) as u64)
+            .sum())
+    }
+
+    fn cas(&mut self, key: &[u8], expected: Option<&[u8]>, new: Option<Vec<u8>>) -> Result<bool> {
+        let mut entries = self.entries.lock().expect("entries");
+        if entries.get(key).map(|value| &value[..]) != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => entries.insert(key.to_vec(), value),
+            None => entries.remove(key),
+        };
+        Ok(true)
+    }
+}
+
+/// Readers given a root published by a writer find every entry of it and prove them,
+/// whether they reuse a pooled tree or open another, while the writer inserts more
+/// with a `BloomFilter` enabled, which readers opened before the writes do not know of.
+fn readers_then_observe_whole_batches<D: Database + Send + 'static>(open: fn(&SharedDb) -> D) {
+    let (keys, leaves) = (random_hashes(6), random_hashes(6));
+    shuttle::check_random(
+        move || {
+            let db = SharedDb::try_new("").expect("try_new()");
+            let tree = {
+                let handle = db.handle();
+                SyncMonotree::<D, Blake3>::new(move || Ok(open(&handle))).expect("new()")
+            };
+            let tree = Arc::new(tree);
+            let first = tree
+                .inserts(None, &keys[..2], &leaves[..2])
+                .expect("inserts()");
+            tree.write(|tree| tree.enable_filter(&[first.expect("root")], 100, 0.01))
+                .expect("enable_filter()");
+            let published = Arc::new(Mutex::new((2, first)));
+
+            let readers: Vec<_> = (0..2)
+                .map(|_| {
+                    let (tree, published) = (tree.clone(), published.clone());
+                    let (keys, leaves) = (keys.clone(), leaves.clone());
+                    thread::spawn(move || {
+                        let hasher = Blake3::new();
+                        for _ in 0..2 {
+                            let (n, root) = *published.lock().expect("published");
+                            for (key, leaf) in keys.iter().zip(leaves.iter()).take(n) {
+                                assert_eq!(
+                                    tree.get(root.as_ref(), key).expect("get()"),
+                                    Some(*leaf)
+                                );
+                                let proof =
+                                    tree.get_merkle_proof(root.as_ref(), key).expect("proof");
+                                assert!(verify_proof(&hasher, root.as_ref(), leaf, proof.as_ref()));
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            let mut root = first;
+            for n in [4, 6].iter() {
+                root = tree
+                    .inserts(root.as_ref(), &keys[n - 2..*n], &leaves[n - 2..*n])
+                    .expect("inserts()");
+                *published.lock().expect("published") = (*n, root);
+            }
+            for reader in readers {
+                reader.join().expect("reader");
+            }
+        },
+        ITERATIONS,
+    );
+}
+
+/// Writers over handles of their own, swapping the recorded root in retry loops,
+/// never lose an update of each other.
+fn writers_then_lose_no_update<D: Database + Send + 'static>(open: fn(&SharedDb) -> D) {
+    let (keys, leaves) = (random_hashes(2), random_hashes(2));
+    shuttle::check_random(
+        move || {
+            let db = SharedDb::try_new("").expect("try_new()");
+            let writers: Vec<_> = (0..2)
+                .map(|i| {
+                    let handle = db.handle();
+                    let (key, leaf) = (keys[i], leaves[i]);
+                    thread::spawn(move || {
+                        let tree = SyncMonotree::<D, Blake3>::new(move || Ok(open(&handle)))
+                            .expect("new()");
+                        tree.write(|tree| loop {
+                            let root = tree.load_root()?;
+                            let new = tree.insert(root.as_ref(), &key, &leaf)?;
+                            if tree.swap_root(root.as_ref(), new.as_ref())? {
+                                return Ok(());
+                            }
+                        })
+                        .expect("write()");
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().expect("writer");
+            }
+
+            let mut tree = Monotree::<SharedDb, Blake3>::with_db(db.handle()).expect("with_db()");
+            let root = tree.load_root().expect("load_root()");
+            assert_eq!(
+                tree.gets(root.as_ref(), &keys).expect("gets()"),
+                leaves.iter().map(|leaf| Some(*leaf)).collect::<Vec<_>>()
+            );
+        },
+        ITERATIONS,
+    );
+}
+
+#[test]
+fn test_sync_monotree_readers_then_observe_whole_batches() {
+    readers_then_observe_whole_batches(SharedDb::handle);
+}
+
+#[test]
+fn test_sync_monotree_cached_readers_then_observe_whole_batches() {
+    readers_then_observe_whole_batches(|db| CachedDb::with_db(db.handle()));
+}
+
+#[test]
+fn test_sync_monotree_writers_then_lose_no_update() {
+    writers_then_lose_no_update(SharedDb::handle);
+}
+
+#[test]
+fn test_sync_monotree_cached_writers_then_lose_no_update() {
+    writers_then_lose_no_update(|db| CachedDb::with_db(db.handle()));
+}